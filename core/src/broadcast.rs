@@ -0,0 +1,139 @@
+use crate::{Event, EventResult, EventStore};
+use tokio::sync::broadcast;
+
+/// Default number of events a lagging subscriber can fall behind by
+pub const DEFAULT_BROADCAST_CAPACITY: usize = 1024;
+
+/// Event store wrapper that publishes every successful append
+///
+/// Lets other Rust code react to new events (secondary projections, webhooks)
+/// without polling. Subscribers only see events appended after they
+/// subscribe; one that falls more than the channel capacity behind receives
+/// `RecvError::Lagged` and should re-read the store.
+///
+/// The server broadcasts to WebSocket clients itself rather than through
+/// this, as it holds debounced source updates back from clients until they
+/// are applied to the projection.
+#[derive(Debug)]
+pub struct BroadcastingEventStore<S> {
+    inner: S,
+    sender: broadcast::Sender<Event>,
+}
+
+impl<S: EventStore> BroadcastingEventStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self::with_capacity(inner, DEFAULT_BROADCAST_CAPACITY)
+    }
+
+    pub fn with_capacity(inner: S, capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { inner, sender }
+    }
+
+    /// Receive each event appended from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    /// Get the wrapped store
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Unwrap the store, dropping the channel
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: EventStore> EventStore for BroadcastingEventStore<S> {
+    fn append_event(&mut self, event: Event) -> EventResult<()> {
+        self.inner.append_event(event.clone())?;
+        // Having no subscribers is not an error
+        let _ = self.sender.send(event);
+        Ok(())
+    }
+
+    fn append_events(&mut self, events: Vec<Event>) -> EventResult<()> {
+        self.inner.append_events(events.clone())?;
+        for event in events {
+            let _ = self.sender.send(event);
+        }
+        Ok(())
+    }
+
+    fn get_events(&self, aggregate_id: &str) -> EventResult<Vec<Event>> {
+        self.inner.get_events(aggregate_id)
+    }
+
+    fn get_events_since_version(
+        &self,
+        aggregate_id: &str,
+        after_version: i64,
+    ) -> EventResult<Vec<Event>> {
+        self.inner
+            .get_events_since_version(aggregate_id, after_version)
+    }
+
+    fn get_all_events(&self) -> EventResult<Vec<Event>> {
+        self.inner.get_all_events()
+    }
+
+    fn get_events_of_type(&self, event_type: &str) -> EventResult<Vec<Event>> {
+        self.inner.get_events_of_type(event_type)
+    }
+
+    fn get_events_between(&self, start_ts: i64, end_ts: i64) -> EventResult<Vec<Event>> {
+        self.inner.get_events_between(start_ts, end_ts)
+    }
+
+    fn get_latest_version(&self, aggregate_id: &str) -> i64 {
+        self.inner.get_latest_version(aggregate_id)
+    }
+
+    fn get_event_count(&self) -> usize {
+        self.inner.get_event_count()
+    }
+
+    fn get_aggregate_ids(&self) -> Vec<String> {
+        self.inner.get_aggregate_ids()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EventBuilder, InMemoryEventStore};
+
+    fn event(version: i64) -> Event {
+        EventBuilder::new()
+            .event_type("DocumentTitleUpdated")
+            .aggregate_id("doc-1")
+            .build(version)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_subscriber_receives_appends() {
+        let mut store = BroadcastingEventStore::new(InMemoryEventStore::new());
+        let mut receiver = store.subscribe();
+
+        let first = event(1);
+        let second = event(2);
+        store.append_event(first.clone()).unwrap();
+        store.append_event(second.clone()).unwrap();
+
+        assert_eq!(receiver.try_recv().unwrap(), first);
+        assert_eq!(receiver.try_recv().unwrap(), second);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_rejected_appends_are_not_published() {
+        let mut store = BroadcastingEventStore::new(InMemoryEventStore::new());
+        let mut receiver = store.subscribe();
+
+        assert!(store.append_event(event(2)).is_err());
+        assert!(receiver.try_recv().is_err());
+    }
+}
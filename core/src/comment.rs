@@ -0,0 +1,472 @@
+//! Threaded comments anchored to cells
+
+use crate::projections::ProcessedEvents;
+use crate::snapshot::restore_state;
+use crate::{Event, EventError, EventResult, Materializer, Projection};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// A comment on a cell, possibly replying to another comment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Comment {
+    pub id: String,
+    pub document_id: String,
+    pub cell_id: String,
+    pub author: String,
+    pub body: String,
+    /// Comment this one replies to; `None` starts a thread
+    pub parent_id: Option<String>,
+    pub resolved: bool,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// State for the comment projection
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommentProjectionState {
+    pub comments: HashMap<String, Comment>,
+    pub last_processed_timestamp: i64,
+    /// Comment events applied at `last_processed_timestamp`
+    #[serde(default)]
+    pub last_processed_event_ids: HashSet<String>,
+}
+
+impl ProcessedEvents for CommentProjectionState {
+    fn processed_mut(&mut self) -> (&mut i64, &mut HashSet<String>) {
+        (
+            &mut self.last_processed_timestamp,
+            &mut self.last_processed_event_ids,
+        )
+    }
+
+    fn processed(&self) -> (i64, &HashSet<String>) {
+        (
+            self.last_processed_timestamp,
+            &self.last_processed_event_ids,
+        )
+    }
+}
+
+impl CommentProjectionState {
+    /// Get a cell's comments in thread order
+    ///
+    /// Threads are ordered by when they were started, and each comment is
+    /// followed by its replies, oldest first.
+    pub fn get_comments_for_cell(&self, cell_id: &str) -> Vec<&Comment> {
+        let mut children: HashMap<Option<&str>, Vec<&Comment>> = HashMap::new();
+        for comment in self.comments.values().filter(|c| c.cell_id == cell_id) {
+            children
+                .entry(comment.parent_id.as_deref())
+                .or_default()
+                .push(comment);
+        }
+        for replies in children.values_mut() {
+            replies.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+        }
+
+        let mut ordered = Vec::new();
+        let mut stack: Vec<&Comment> = children
+            .get(&None)
+            .map(|roots| roots.iter().rev().copied().collect())
+            .unwrap_or_default();
+        while let Some(comment) = stack.pop() {
+            ordered.push(comment);
+            if let Some(replies) = children.get(&Some(comment.id.as_str())) {
+                stack.extend(replies.iter().rev());
+            }
+        }
+        ordered
+    }
+
+    /// Ids of a comment and every reply beneath it
+    fn thread_ids(&self, comment_id: &str) -> Vec<String> {
+        let mut ids = vec![comment_id.to_string()];
+        let mut i = 0;
+        while i < ids.len() {
+            let parent = ids[i].clone();
+            ids.extend(
+                self.comments
+                    .values()
+                    .filter(|c| c.parent_id.as_deref() == Some(parent.as_str()))
+                    .map(|c| c.id.clone()),
+            );
+            i += 1;
+        }
+        ids
+    }
+}
+
+fn payload_str<'a>(event: &'a Event, field: &str) -> EventResult<&'a str> {
+    event
+        .payload
+        .get(field)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| EventError::ValidationError(format!("Missing {}", field)))
+}
+
+/// Materializer for comment events
+pub struct CommentMaterializer;
+
+impl Materializer for CommentMaterializer {
+    type State = CommentProjectionState;
+    type Error = EventError;
+
+    fn initial_state() -> Self::State {
+        CommentProjectionState::default()
+    }
+
+    fn apply_event(state: &Self::State, event: &Event) -> Result<Self::State, Self::Error> {
+        let mut new_state = state.clone();
+        new_state.record_processed(event);
+
+        match event.event_type.as_str() {
+            "CommentAdded" => {
+                let comment_id = payload_str(event, "comment_id")?;
+                let parent_id = event
+                    .payload
+                    .get("parent_id")
+                    .and_then(|v| v.as_str())
+                    .map(String::from);
+
+                // Replies live on their parent's cell
+                let cell_id = match &parent_id {
+                    Some(parent_id) => new_state
+                        .comments
+                        .get(parent_id)
+                        .map(|parent| parent.cell_id.clone())
+                        .ok_or_else(|| {
+                            EventError::ValidationError(format!(
+                                "Unknown parent comment: {}",
+                                parent_id
+                            ))
+                        })?,
+                    None => payload_str(event, "cell_id")?.to_string(),
+                };
+
+                let comment = Comment {
+                    id: comment_id.to_string(),
+                    document_id: event.aggregate_id.clone(),
+                    cell_id,
+                    author: payload_str(event, "author")?.to_string(),
+                    body: payload_str(event, "body")?.to_string(),
+                    parent_id,
+                    resolved: false,
+                    created_at: event.timestamp,
+                    updated_at: event.timestamp,
+                };
+                new_state.comments.insert(comment.id.clone(), comment);
+            }
+
+            "CommentEdited" => {
+                let comment_id = payload_str(event, "comment_id")?;
+                // Last write wins by event time, as with document titles
+                if let Some(comment) = new_state
+                    .comments
+                    .get_mut(comment_id)
+                    .filter(|comment| event.timestamp >= comment.updated_at)
+                {
+                    comment.body = payload_str(event, "body")?.to_string();
+                    comment.updated_at = event.timestamp;
+                }
+            }
+
+            "CommentResolved" => {
+                let comment_id = payload_str(event, "comment_id")?;
+                if let Some(comment) = new_state.comments.get_mut(comment_id) {
+                    // `resolved: false` reopens a thread
+                    comment.resolved = event
+                        .payload
+                        .get("resolved")
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(true);
+                    comment.updated_at = event.timestamp;
+                }
+            }
+
+            "CommentDeleted" => {
+                // Replies go with the comment they answer
+                let comment_id = payload_str(event, "comment_id")?;
+                for id in new_state.thread_ids(comment_id) {
+                    new_state.comments.remove(&id);
+                }
+            }
+
+            "SnapshotCreated" => {
+                let mut restored: CommentProjectionState = restore_state(event, "comments")?;
+                restored.record_processed(event);
+                return Ok(restored);
+            }
+
+            _ => {}
+        }
+
+        Ok(new_state)
+    }
+
+    fn handles_event_type(event_type: &str) -> bool {
+        matches!(
+            event_type,
+            "CommentAdded"
+                | "CommentEdited"
+                | "CommentResolved"
+                | "CommentDeleted"
+                | "SnapshotCreated"
+        )
+    }
+}
+
+/// Comment projection implementation
+pub struct CommentProjection {
+    state: CommentProjectionState,
+}
+
+impl CommentProjection {
+    pub fn new() -> Self {
+        Self {
+            state: CommentMaterializer::initial_state(),
+        }
+    }
+
+    /// Get a specific comment by ID
+    pub fn get_comment(&self, comment_id: &str) -> Option<&Comment> {
+        self.state.comments.get(comment_id)
+    }
+
+    /// Get a cell's comments in thread order
+    pub fn get_comments_for_cell(&self, cell_id: &str) -> Vec<&Comment> {
+        self.state.get_comments_for_cell(cell_id)
+    }
+
+    /// Get the number of comments across all cells
+    pub fn comment_count(&self) -> usize {
+        self.state.comments.len()
+    }
+}
+
+impl Default for CommentProjection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Projection for CommentProjection {
+    type State = CommentProjectionState;
+
+    fn rebuild_from_events(&mut self, events: &[Event]) -> EventResult<()> {
+        let mut state = CommentMaterializer::initial_state();
+
+        for event in events {
+            // Only a snapshot marks events as seen before they're replayed
+            let seen = state.processed_at_last_timestamp(event);
+            if !seen && CommentMaterializer::handles_event_type(&event.event_type) {
+                state = CommentMaterializer::apply_event(&state, event).map_err(|e| {
+                    EventError::ValidationError(format!("Materialization failed: {}", e))
+                })?;
+            }
+        }
+
+        self.state = state;
+        Ok(())
+    }
+
+    fn get_state(&self) -> &Self::State {
+        &self.state
+    }
+
+    fn last_processed_timestamp(&self) -> i64 {
+        self.state.last_processed_timestamp
+    }
+
+    fn apply_new_events(&mut self, events: &[Event]) -> EventResult<()> {
+        for event in events {
+            if self.state.is_unprocessed(event)
+                && CommentMaterializer::handles_event_type(&event.event_type)
+            {
+                self.state = CommentMaterializer::apply_event(&self.state, event).map_err(|e| {
+                    EventError::ValidationError(format!("Materialization failed: {}", e))
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Utility functions for creating comment events
+
+/// Add a comment to a cell, or reply to an existing comment
+pub fn create_comment_event(
+    document_id: String,
+    comment_id: String,
+    cell_id: String,
+    author: String,
+    body: String,
+    parent_id: Option<String>,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    let mut payload = serde_json::json!({
+        "comment_id": comment_id,
+        "cell_id": cell_id,
+        "author": author,
+        "body": body
+    });
+
+    if let Some(parent_id) = parent_id {
+        payload["parent_id"] = serde_json::Value::String(parent_id);
+    }
+
+    EventBuilder::new()
+        .event_type("CommentAdded")
+        .aggregate_id(document_id)
+        .payload(payload)?
+        .build(version)
+}
+
+/// Replace a comment's body
+pub fn edit_comment_event(
+    document_id: String,
+    comment_id: String,
+    body: String,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    EventBuilder::new()
+        .event_type("CommentEdited")
+        .aggregate_id(document_id)
+        .payload(serde_json::json!({
+            "comment_id": comment_id,
+            "body": body
+        }))?
+        .build(version)
+}
+
+/// Resolve a comment, or reopen it with `resolved: false`
+pub fn resolve_comment_event(
+    document_id: String,
+    comment_id: String,
+    resolved: bool,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    EventBuilder::new()
+        .event_type("CommentResolved")
+        .aggregate_id(document_id)
+        .payload(serde_json::json!({
+            "comment_id": comment_id,
+            "resolved": resolved
+        }))?
+        .build(version)
+}
+
+/// Delete a comment along with its replies
+pub fn delete_comment_event(
+    document_id: String,
+    comment_id: String,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    EventBuilder::new()
+        .event_type("CommentDeleted")
+        .aggregate_id(document_id)
+        .payload(serde_json::json!({
+            "comment_id": comment_id
+        }))?
+        .build(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(comment_id: &str, parent_id: Option<&str>, timestamp: i64, version: i64) -> Event {
+        let mut event = create_comment_event(
+            "doc-1".to_string(),
+            comment_id.to_string(),
+            "cell-1".to_string(),
+            "alice".to_string(),
+            format!("Comment {}", comment_id),
+            parent_id.map(String::from),
+            version,
+        )
+        .unwrap();
+        event.timestamp = timestamp;
+        event
+    }
+
+    #[test]
+    fn test_comment_reply_threading() {
+        let mut projection = CommentProjection::new();
+        projection
+            .apply_new_events(&[
+                comment("c1", None, 100, 1),
+                comment("c2", None, 101, 2),
+                comment("c1-a", Some("c1"), 102, 3),
+                comment("c2-a", Some("c2"), 103, 4),
+                comment("c1-b", Some("c1"), 104, 5),
+                comment("c1-a-i", Some("c1-a"), 105, 6),
+            ])
+            .unwrap();
+
+        let thread: Vec<&str> = projection
+            .get_comments_for_cell("cell-1")
+            .iter()
+            .map(|c| c.id.as_str())
+            .collect();
+        assert_eq!(thread, vec!["c1", "c1-a", "c1-a-i", "c1-b", "c2", "c2-a"]);
+        assert!(projection.get_comments_for_cell("cell-2").is_empty());
+
+        // A reply to an unknown comment is rejected
+        assert!(projection
+            .apply_new_events(&[comment("c9", Some("missing"), 106, 7)])
+            .is_err());
+
+        // Deleting a comment removes the replies beneath it
+        let mut deleted = delete_comment_event("doc-1".to_string(), "c1-a".to_string(), 8).unwrap();
+        deleted.timestamp = 107;
+        projection.apply_new_events(&[deleted]).unwrap();
+        let thread: Vec<&str> = projection
+            .get_comments_for_cell("cell-1")
+            .iter()
+            .map(|c| c.id.as_str())
+            .collect();
+        assert_eq!(thread, vec!["c1", "c1-b", "c2", "c2-a"]);
+    }
+
+    #[test]
+    fn test_comment_edit_and_resolution() {
+        let mut edited = edit_comment_event(
+            "doc-1".to_string(),
+            "c1".to_string(),
+            "Fixed typo".to_string(),
+            2,
+        )
+        .unwrap();
+        edited.timestamp = 101;
+        let mut resolved =
+            resolve_comment_event("doc-1".to_string(), "c1".to_string(), true, 3).unwrap();
+        resolved.timestamp = 102;
+
+        let mut projection = CommentProjection::new();
+        projection
+            .rebuild_from_events(&[comment("c1", None, 100, 1), edited.clone(), resolved])
+            .unwrap();
+
+        let c1 = projection.get_comment("c1").unwrap();
+        assert_eq!(c1.body, "Fixed typo");
+        assert!(c1.resolved);
+        assert_eq!(c1.created_at, 100);
+        assert_eq!(c1.updated_at, 102);
+
+        let mut reopened =
+            resolve_comment_event("doc-1".to_string(), "c1".to_string(), false, 4).unwrap();
+        reopened.timestamp = 103;
+        // Replaying the earlier edit alongside is a no-op
+        projection.apply_new_events(&[edited, reopened]).unwrap();
+        assert!(!projection.get_comment("c1").unwrap().resolved);
+        assert_eq!(projection.get_comment("c1").unwrap().body, "Fixed typo");
+    }
+}
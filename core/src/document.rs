@@ -1,6 +1,10 @@
-use crate::{Event, EventError, EventResult, Materializer, Projection};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::fractional_index::FractionalIndex;
+use crate::projections::ProcessedEvents;
+use crate::snapshot::restore_state;
+use crate::{Event, EventError, EventResult, InMemoryEventStore, Materializer, Projection};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 /// Represents a single cell in a document, aligned with anode schema
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -8,13 +12,16 @@ pub struct Cell {
     pub id: String,
     pub cell_type: CellType,
     pub source: String,
-    pub fractional_index: Option<String>, // Fractional index for deterministic ordering
+    pub fractional_index: Option<FractionalIndex>, // Fractional index for deterministic ordering
 
     // Execution state
     pub execution_count: Option<u64>,
     pub execution_state: ExecutionState,
     pub assigned_runtime_session: Option<String>,
     pub last_execution_duration_ms: Option<u64>,
+    /// Error message or traceback from the last failed execution
+    #[serde(default)]
+    pub execution_error: Option<String>,
 
     // Cell type specific fields
     pub sql_connection_id: Option<String>,
@@ -34,6 +41,13 @@ pub struct Cell {
     pub document_id: String, // Track which document this cell belongs to
     pub created_at: i64,
     pub updated_at: i64,
+    /// Timestamp of the edit `source` came from, so a stale
+    /// `CellSourceUpdated` replayed after a newer one is ignored
+    #[serde(default)]
+    pub source_updated_at: i64,
+    /// Tombstone set by `CellDeleted` and cleared by `CellRestored`
+    #[serde(default)]
+    pub deleted: bool,
 }
 
 /// Cell types supported in the document engine, matching anode
@@ -116,6 +130,60 @@ pub struct CellOutput {
     pub created_at: i64,
 }
 
+impl CellOutput {
+    /// Build an output from a `CellOutputCreated` event
+    pub fn from_event(event: &Event) -> EventResult<Self> {
+        let output_data = &event.payload;
+        let output_id = output_data
+            .get("output_id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| EventError::ValidationError("Missing output_id".to_string()))?;
+
+        let cell_id = output_data
+            .get("cell_id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| EventError::ValidationError("Missing cell_id".to_string()))?;
+
+        let output_type: OutputType = parse_payload_enum(output_data, "output_type")?;
+
+        Ok(CellOutput {
+            id: output_id.to_string(),
+            cell_id: cell_id.to_string(),
+            output_type,
+            position: output_data
+                .get("position")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(0.0),
+            stream_name: output_data
+                .get("stream_name")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string()),
+            execution_count: output_data.get("execution_count").and_then(|v| v.as_u64()),
+            display_id: output_data
+                .get("display_id")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string()),
+            data: output_data
+                .get("data")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string()),
+            artifact_id: output_data
+                .get("artifact_id")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string()),
+            mime_type: output_data
+                .get("mime_type")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string()),
+            metadata: output_data.get("metadata").cloned(),
+            representations: output_data
+                .get("representations")
+                .and_then(|v| serde_json::from_value(v.clone()).ok()),
+            created_at: event.timestamp,
+        })
+    }
+}
+
 /// Document metadata matching anode's notebook metadata concept
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DocumentMetadata {
@@ -163,6 +231,10 @@ pub struct Document {
     pub metadata: DocumentMetadata,
     pub created_at: i64,
     pub updated_at: i64,
+    /// Timestamp of the edit `title` came from, so a stale
+    /// `DocumentTitleUpdated` replayed after a newer one is ignored
+    #[serde(default)]
+    pub title_updated_at: i64,
 }
 
 /// Runtime session for execution management
@@ -195,67 +267,336 @@ pub enum RuntimeStatus {
 }
 
 /// State for the Document projection
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct DocumentProjectionState {
     pub documents: HashMap<String, Document>,
     pub cells: HashMap<String, Cell>,
     pub outputs: HashMap<String, CellOutput>,
     pub runtime_sessions: HashMap<String, RuntimeSession>,
+    /// Actors who have edited each document, keyed by document ID
+    #[serde(default)]
+    pub collaborators: HashMap<String, HashSet<String>>,
     pub last_processed_timestamp: i64,
+    /// Ids of the applied events stamped `last_processed_timestamp`, so
+    /// incremental updates can tell a same-tick newcomer from a replay
+    #[serde(default)]
+    pub last_processed_event_ids: HashSet<String>,
+}
+
+impl ProcessedEvents for DocumentProjectionState {
+    fn processed_mut(&mut self) -> (&mut i64, &mut HashSet<String>) {
+        (
+            &mut self.last_processed_timestamp,
+            &mut self.last_processed_event_ids,
+        )
+    }
+
+    fn processed(&self) -> (i64, &HashSet<String>) {
+        (
+            self.last_processed_timestamp,
+            &self.last_processed_event_ids,
+        )
+    }
 }
 
 impl DocumentProjectionState {
     /// Get all cells for a specific document ordered by fractional index
+    ///
+    /// Deleted cells are left out.
     pub fn get_document_cells(&self, document_id: &str) -> Vec<&Cell> {
+        let mut cells = self.get_document_cells_including_deleted(document_id);
+        cells.retain(|cell| !cell.deleted);
+        cells
+    }
+
+    /// Get a document's cells in the order they were created, ignoring moves
+    ///
+    /// Cells created in the same second are ordered by id. Deleted cells are
+    /// left out.
+    pub fn get_document_cells_in_creation_order(&self, document_id: &str) -> Vec<&Cell> {
+        let mut cells: Vec<&Cell> = self
+            .cells
+            .values()
+            .filter(|cell| cell.document_id == document_id && !cell.deleted)
+            .collect();
+        cells.sort_by(|a, b| {
+            a.created_at
+                .cmp(&b.created_at)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        cells
+    }
+
+    /// Get up to `limit` of a document's cells, skipping the first `offset`
+    ///
+    /// Windows follow [`DocumentProjectionState::get_document_cells`] order,
+    /// so consecutive windows tile the full list; past the end they're empty.
+    pub fn get_document_cells_range(
+        &self,
+        document_id: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<&Cell> {
+        self.get_document_cells(document_id)
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .collect()
+    }
+
+    /// Count a document's cells, leaving out deleted ones
+    pub fn document_cell_count(&self, document_id: &str) -> usize {
+        self.cells
+            .values()
+            .filter(|cell| cell.document_id == document_id && !cell.deleted)
+            .count()
+    }
+
+    /// Get all cells for a document, deleted ones included, ordered by
+    /// fractional index
+    pub fn get_document_cells_including_deleted(&self, document_id: &str) -> Vec<&Cell> {
         let mut cells: Vec<&Cell> = self
             .cells
             .values()
             .filter(|cell| cell.document_id == document_id)
             .collect();
 
-        // Sort by fractional index
-        cells.sort_by(|a, b| match (&a.fractional_index, &b.fractional_index) {
-            (Some(a_idx), Some(b_idx)) => a_idx.cmp(b_idx),
-            (Some(_), None) => std::cmp::Ordering::Less,
-            (None, Some(_)) => std::cmp::Ordering::Greater,
-            (None, None) => a.created_at.cmp(&b.created_at),
+        // Sort by fractional index, then creation time, then id so that
+        // colliding indices and timestamps still yield a stable order
+        cells.sort_by(|a, b| {
+            let index_order = match (&a.fractional_index, &b.fractional_index) {
+                (Some(a_idx), Some(b_idx)) => a_idx.cmp(b_idx),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            };
+            index_order
+                .then_with(|| a.created_at.cmp(&b.created_at))
+                .then_with(|| a.id.cmp(&b.id))
         });
 
         cells
     }
 
-    /// Get outputs for a specific cell
+    /// Get outputs for a specific cell; a deleted cell has none
     pub fn get_cell_outputs(&self, cell_id: &str) -> Vec<&CellOutput> {
+        if self.is_cell_deleted(cell_id) {
+            return Vec::new();
+        }
         let mut outputs: Vec<&CellOutput> = self
             .outputs
             .values()
             .filter(|output| output.cell_id == cell_id)
             .collect();
 
+        // Break position collisions by creation time, then id, for a stable order
         outputs.sort_by(|a, b| {
             a.position
                 .partial_cmp(&b.position)
                 .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.created_at.cmp(&b.created_at))
+                .then_with(|| a.id.cmp(&b.id))
         });
         outputs
     }
+
+    /// Get outputs a cell produced during one execution
+    pub fn get_cell_outputs_for_execution(
+        &self,
+        cell_id: &str,
+        execution_count: u64,
+    ) -> Vec<&CellOutput> {
+        let mut outputs = self.get_cell_outputs(cell_id);
+        outputs.retain(|output| output.execution_count == Some(execution_count));
+        outputs
+    }
+
+    /// Get a cell unless it has been deleted
+    pub fn get_live_cell(&self, cell_id: &str) -> Option<&Cell> {
+        self.cells.get(cell_id).filter(|cell| !cell.deleted)
+    }
+
+    /// Copy of the state holding only the documents `include` accepts
+    ///
+    /// Cells, outputs and collaborators go with their document. Runtime
+    /// sessions aren't tied to a document, so all of them are kept.
+    pub fn retain_documents(&self, include: impl Fn(&str) -> bool) -> Self {
+        let cells: HashMap<String, Cell> = self
+            .cells
+            .iter()
+            .filter(|(_, cell)| include(&cell.document_id))
+            .map(|(id, cell)| (id.clone(), cell.clone()))
+            .collect();
+        Self {
+            documents: self
+                .documents
+                .iter()
+                .filter(|(id, _)| include(id))
+                .map(|(id, document)| (id.clone(), document.clone()))
+                .collect(),
+            outputs: self
+                .outputs
+                .iter()
+                .filter(|(_, output)| cells.contains_key(&output.cell_id))
+                .map(|(id, output)| (id.clone(), output.clone()))
+                .collect(),
+            collaborators: self
+                .collaborators
+                .iter()
+                .filter(|(id, _)| include(id))
+                .map(|(id, actors)| (id.clone(), actors.clone()))
+                .collect(),
+            cells,
+            ..self.clone()
+        }
+    }
+
+    fn is_cell_deleted(&self, cell_id: &str) -> bool {
+        self.cells.get(cell_id).is_some_and(|cell| cell.deleted)
+    }
 }
 
-/// Materializer for Document events
-pub struct DocumentMaterializer;
+/// Parse an enum field from an event payload using its serde wire format
+///
+/// Going through serde keeps the materializer in lockstep with the
+/// `rename_all` derives instead of duplicating the tag strings by hand.
+fn parse_payload_enum<T: DeserializeOwned>(
+    payload: &serde_json::Value,
+    field: &str,
+) -> EventResult<T> {
+    let value = payload
+        .get(field)
+        .ok_or_else(|| EventError::ValidationError(format!("Missing {}", field)))?;
 
-impl Materializer for DocumentMaterializer {
-    type State = DocumentProjectionState;
-    type Error = EventError;
+    serde_json::from_value(value.clone()).map_err(|_| {
+        let raw = value
+            .as_str()
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| value.to_string());
+        EventError::ValidationError(format!("Invalid {}: {}", field, raw))
+    })
+}
 
-    fn initial_state() -> Self::State {
-        DocumentProjectionState::default()
+/// Check a payload's fractional index so a bad one can't quietly scramble
+/// cell order
+fn parse_fractional_index(index: &str) -> EventResult<FractionalIndex> {
+    FractionalIndex::new(index).map_err(|e| EventError::ValidationError(e.to_string()))
+}
+
+/// Identify who made an edit
+///
+/// Prefers the event's own `actor`, which the server sets from the
+/// authenticated caller, then an `actor` or (on creation events) `created_by`
+/// in the payload.
+fn event_actor(event: &Event) -> Option<&str> {
+    event.actor.as_deref().or_else(|| {
+        event
+            .payload
+            .get("actor")
+            .or_else(|| event.payload.get("created_by"))
+            .and_then(|v| v.as_str())
+    })
+}
+
+/// Check whether an event type reads required fields from its payload
+///
+/// Such events are useless without an object payload, so builders reject
+/// them up front rather than letting the materializer fail later with a
+/// vaguer "Missing ..." error.
+pub(crate) fn requires_payload_fields(event_type: &str) -> bool {
+    matches!(
+        event_type,
+        "CellCreated"
+            | "CellSourceUpdated"
+            | "CellTypeChanged"
+            | "CellVisibilityChanged"
+            | "CellAiConfigUpdated"
+            | "CellExecutionStateChanged"
+            | "CellOutputCreated"
+            | "CellOutputAppended"
+            | "CellOutputRepositioned"
+            | "CellOutputsCleared"
+            | "CellMoved"
+            | "CellDeleted"
+            | "CellRestored"
+            | "DocumentTagAdded"
+            | "DocumentTagRemoved"
+            | "DocumentCustomFieldSet"
+            | "RuntimeSessionStarted"
+            | "RuntimeSessionStatusChanged"
+            | "RuntimeSessionTerminated"
+            | "SnapshotCreated"
+    )
+}
+
+/// Inclusive numeric bounds for an `ai_settings` field
+struct AiSettingRange {
+    field: &'static str,
+    min: f64,
+    max: f64,
+}
+
+/// Numeric bounds each AI provider accepts; unknown providers get the
+/// OpenAI-compatible ranges
+fn ai_setting_ranges(provider: Option<&str>) -> [AiSettingRange; 3] {
+    let max_temperature = match provider {
+        Some("anthropic") => 1.0,
+        _ => 2.0,
+    };
+    [
+        AiSettingRange {
+            field: "temperature",
+            min: 0.0,
+            max: max_temperature,
+        },
+        AiSettingRange {
+            field: "top_p",
+            min: 0.0,
+            max: 1.0,
+        },
+        AiSettingRange {
+            field: "max_tokens",
+            min: 1.0,
+            max: f64::MAX,
+        },
+    ]
+}
+
+/// Check `ai_settings` against the provider's schema
+///
+/// Settings must be a JSON object; known numeric fields must be numbers
+/// within range. Other fields pass through untouched.
+fn validate_ai_settings(provider: Option<&str>, settings: &serde_json::Value) -> EventResult<()> {
+    let settings = settings.as_object().ok_or_else(|| {
+        EventError::ValidationError("ai_settings must be a JSON object".to_string())
+    })?;
+
+    for range in ai_setting_ranges(provider) {
+        let Some(value) = settings.get(range.field) else {
+            continue;
+        };
+        let in_range = value
+            .as_f64()
+            .is_some_and(|n| n >= range.min && n <= range.max);
+        if !in_range {
+            return Err(EventError::ValidationError(format!(
+                "Invalid ai_settings.{}: {} (expected a number in [{}, {}])",
+                range.field, value, range.min, range.max
+            )));
+        }
     }
+    Ok(())
+}
 
-    fn apply_event(state: &Self::State, event: &Event) -> Result<Self::State, Self::Error> {
-        let mut new_state = state.clone();
-        new_state.last_processed_timestamp = event.timestamp;
+/// Materializer for Document events
+pub struct DocumentMaterializer;
 
+impl DocumentMaterializer {
+    /// Apply `event` to `state` in place, recording it as processed
+    ///
+    /// Each arm checks the payload before changing anything, so an error
+    /// leaves `state` as it was.
+    fn apply_in_place(state: &mut DocumentProjectionState, event: &Event) -> EventResult<()> {
         match event.event_type.as_str() {
             "DocumentCreated" => {
                 let document = Document {
@@ -272,23 +613,29 @@ impl Materializer for DocumentMaterializer {
                     .unwrap_or_default(),
                     created_at: event.timestamp,
                     updated_at: event.timestamp,
+                    title_updated_at: event.timestamp,
                 };
-                new_state
-                    .documents
-                    .insert(event.aggregate_id.clone(), document);
+                state.documents.insert(event.aggregate_id.clone(), document);
             }
 
             "DocumentTitleUpdated" => {
-                if let Some(document) = new_state.documents.get_mut(&event.aggregate_id) {
+                // Last write wins by event time: a stale update replayed after
+                // a newer title edit must not clobber it
+                if let Some(document) = state
+                    .documents
+                    .get_mut(&event.aggregate_id)
+                    .filter(|document| event.timestamp >= document.title_updated_at)
+                {
                     if let Some(title) = event.payload.get("title").and_then(|v| v.as_str()) {
                         document.title = title.to_string();
-                        document.updated_at = event.timestamp;
+                        document.title_updated_at = event.timestamp;
+                        document.updated_at = document.updated_at.max(event.timestamp);
                     }
                 }
             }
 
             "DocumentMetadataUpdated" => {
-                if let Some(document) = new_state.documents.get_mut(&event.aggregate_id) {
+                if let Some(document) = state.documents.get_mut(&event.aggregate_id) {
                     if let Some(metadata) = event.payload.get("metadata") {
                         document.metadata = serde_json::from_value(metadata.clone())
                             .unwrap_or_else(|_| document.metadata.clone());
@@ -297,6 +644,60 @@ impl Materializer for DocumentMaterializer {
                 }
             }
 
+            // The patch events touch one part of the metadata each, so
+            // concurrent edits to different parts all survive
+            "DocumentTagAdded" | "DocumentTagRemoved" => {
+                let tag = event
+                    .payload
+                    .get("tag")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| EventError::ValidationError("Missing tag".to_string()))?;
+                if let Some(document) = state.documents.get_mut(&event.aggregate_id) {
+                    let tags = &mut document.metadata.tags;
+                    if event.event_type == "DocumentTagAdded" {
+                        if !tags.iter().any(|t| t == tag) {
+                            tags.push(tag.to_string());
+                        }
+                    } else {
+                        tags.retain(|t| t != tag);
+                    }
+                    document.updated_at = event.timestamp;
+                }
+            }
+
+            "DocumentCustomFieldSet" => {
+                let key = event
+                    .payload
+                    .get("key")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| EventError::ValidationError("Missing key".to_string()))?;
+                // An explicit null value removes the field
+                let value = match event.payload.get("value") {
+                    Some(serde_json::Value::Null) => None,
+                    Some(serde_json::Value::String(value)) => Some(value),
+                    Some(_) => {
+                        return Err(EventError::ValidationError(
+                            "value must be a string or null".to_string(),
+                        ))
+                    }
+                    None => return Err(EventError::ValidationError("Missing value".to_string())),
+                };
+                if let Some(document) = state.documents.get_mut(&event.aggregate_id) {
+                    match value {
+                        Some(value) => {
+                            document
+                                .metadata
+                                .custom
+                                .insert(key.to_string(), value.clone());
+                        }
+                        None => {
+                            document.metadata.custom.remove(key);
+                        }
+                    }
+                    document.updated_at = event.timestamp;
+                }
+            }
+
             "CellCreated" => {
                 let cell_data = &event.payload;
                 let cell_id = cell_data
@@ -304,24 +705,14 @@ impl Materializer for DocumentMaterializer {
                     .and_then(|v| v.as_str())
                     .ok_or_else(|| EventError::ValidationError("Missing cell_id".to_string()))?;
 
-                let cell_type_str = cell_data
-                    .get("cell_type")
-                    .and_then(|v| v.as_str())
-                    .ok_or_else(|| EventError::ValidationError("Missing cell_type".to_string()))?;
-
-                let cell_type = match cell_type_str {
-                    "code" => CellType::Code,
-                    "markdown" => CellType::Markdown,
-                    "sql" => CellType::Sql,
-                    "ai" => CellType::Ai,
-                    "raw" => CellType::Raw,
-                    _ => {
-                        return Err(EventError::ValidationError(format!(
-                            "Invalid cell_type: {}",
-                            cell_type_str
-                        )))
-                    }
-                };
+                let cell_type: CellType = parse_payload_enum(cell_data, "cell_type")?;
+
+                if let Some(settings) = cell_data.get("ai_settings") {
+                    validate_ai_settings(
+                        cell_data.get("ai_provider").and_then(|v| v.as_str()),
+                        settings,
+                    )?;
+                }
 
                 let cell = Cell {
                     id: cell_id.to_string(),
@@ -334,11 +725,13 @@ impl Materializer for DocumentMaterializer {
                     fractional_index: cell_data
                         .get("fractional_index")
                         .and_then(|v| v.as_str())
-                        .map(|s| s.to_string()),
+                        .map(parse_fractional_index)
+                        .transpose()?,
                     execution_count: cell_data.get("execution_count").and_then(|v| v.as_u64()),
                     execution_state: ExecutionState::default(),
                     assigned_runtime_session: None,
                     last_execution_duration_ms: None,
+                    execution_error: None,
                     sql_connection_id: cell_data
                         .get("sql_connection_id")
                         .and_then(|v| v.as_str())
@@ -371,21 +764,55 @@ impl Materializer for DocumentMaterializer {
                     created_by: cell_data
                         .get("created_by")
                         .and_then(|v| v.as_str())
+                        .or(event.actor.as_deref())
                         .unwrap_or("system")
                         .to_string(),
                     document_id: event.aggregate_id.clone(), // Store document association
                     created_at: event.timestamp,
                     updated_at: event.timestamp,
+                    source_updated_at: event.timestamp,
+                    deleted: false,
                 };
 
-                new_state.cells.insert(cell_id.to_string(), cell);
+                state.cells.insert(cell_id.to_string(), cell);
 
                 // Update document timestamp
-                if let Some(document) = new_state.documents.get_mut(&event.aggregate_id) {
+                if let Some(document) = state.documents.get_mut(&event.aggregate_id) {
                     document.updated_at = event.timestamp;
                 }
             }
 
+            "CellAiConfigUpdated" => {
+                let cell_id = event
+                    .payload
+                    .get("cell_id")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| EventError::ValidationError("Missing cell_id".to_string()))?;
+
+                if let Some(cell) = state.cells.get_mut(cell_id) {
+                    let provider = event.payload.get("ai_provider").and_then(|v| v.as_str());
+                    let settings = event.payload.get("ai_settings");
+                    if let Some(settings) = settings {
+                        validate_ai_settings(provider.or(cell.ai_provider.as_deref()), settings)?;
+                    }
+                    if let Some(provider) = provider {
+                        cell.ai_provider = Some(provider.to_string());
+                    }
+                    if let Some(model) = event.payload.get("ai_model").and_then(|v| v.as_str()) {
+                        cell.ai_model = Some(model.to_string());
+                    }
+                    if let Some(settings) = settings {
+                        cell.ai_settings = Some(settings.clone());
+                    }
+                    cell.updated_at = event.timestamp;
+
+                    // Update document timestamp
+                    if let Some(document) = state.documents.get_mut(&event.aggregate_id) {
+                        document.updated_at = event.timestamp;
+                    }
+                }
+            }
+
             "CellSourceUpdated" => {
                 let cell_id = event
                     .payload
@@ -393,14 +820,80 @@ impl Materializer for DocumentMaterializer {
                     .and_then(|v| v.as_str())
                     .ok_or_else(|| EventError::ValidationError("Missing cell_id".to_string()))?;
 
-                if let Some(cell) = new_state.cells.get_mut(cell_id) {
+                // Last write wins by edit time, so two offline edits settle
+                // the same whichever replays first
+                let edited_at = source_edited_at(event);
+                if let Some(cell) = state
+                    .cells
+                    .get_mut(cell_id)
+                    .filter(|cell| edited_at >= cell.source_updated_at)
+                {
                     if let Some(source) = event.payload.get("source").and_then(|v| v.as_str()) {
                         cell.source = source.to_string();
+                        cell.source_updated_at = edited_at;
+                    }
+                    cell.updated_at = event.timestamp;
+
+                    // Update document timestamp
+                    if let Some(document) = state.documents.get_mut(&event.aggregate_id) {
+                        document.updated_at = event.timestamp;
+                    }
+                }
+            }
+
+            "CellTypeChanged" => {
+                let cell_id = event
+                    .payload
+                    .get("cell_id")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| EventError::ValidationError("Missing cell_id".to_string()))?;
+
+                let cell_type: CellType = parse_payload_enum(&event.payload, "cell_type")?;
+
+                if let Some(cell) = state.cells.get_mut(cell_id) {
+                    // Type-specific configuration doesn't carry over to other types
+                    if cell_type != CellType::Sql {
+                        cell.sql_connection_id = None;
+                        cell.sql_result_variable = None;
+                    }
+                    if cell_type != CellType::Ai {
+                        cell.ai_provider = None;
+                        cell.ai_model = None;
+                        cell.ai_settings = None;
+                    }
+                    cell.cell_type = cell_type;
+                    cell.updated_at = event.timestamp;
+
+                    // Update document timestamp
+                    if let Some(document) = state.documents.get_mut(&event.aggregate_id) {
+                        document.updated_at = event.timestamp;
+                    }
+                }
+            }
+
+            "CellVisibilityChanged" => {
+                let cell_id = event
+                    .payload
+                    .get("cell_id")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| EventError::ValidationError("Missing cell_id".to_string()))?;
+
+                if let Some(cell) = state.cells.get_mut(cell_id) {
+                    // Only the toggles present in the payload change
+                    let visible = |field: &str| event.payload.get(field).and_then(|v| v.as_bool());
+                    if let Some(source_visible) = visible("source_visible") {
+                        cell.source_visible = source_visible;
+                    }
+                    if let Some(output_visible) = visible("output_visible") {
+                        cell.output_visible = output_visible;
+                    }
+                    if let Some(ai_context_visible) = visible("ai_context_visible") {
+                        cell.ai_context_visible = ai_context_visible;
                     }
                     cell.updated_at = event.timestamp;
 
                     // Update document timestamp
-                    if let Some(document) = new_state.documents.get_mut(&event.aggregate_id) {
+                    if let Some(document) = state.documents.get_mut(&event.aggregate_id) {
                         document.updated_at = event.timestamp;
                     }
                 }
@@ -413,20 +906,30 @@ impl Materializer for DocumentMaterializer {
                     .and_then(|v| v.as_str())
                     .ok_or_else(|| EventError::ValidationError("Missing cell_id".to_string()))?;
 
-                if let Some(cell) = new_state.cells.get_mut(cell_id) {
-                    if let Some(state_str) = event
-                        .payload
-                        .get("execution_state")
-                        .and_then(|v| v.as_str())
-                    {
-                        cell.execution_state = match state_str {
-                            "idle" => ExecutionState::Idle,
-                            "queued" => ExecutionState::Queued,
-                            "running" => ExecutionState::Running,
-                            "completed" => ExecutionState::Completed,
-                            "error" => ExecutionState::Error,
-                            _ => cell.execution_state.clone(),
-                        };
+                if let Some(cell) = state.cells.get_mut(cell_id) {
+                    // Missing or unknown states leave the current state untouched
+                    if let Ok(state) = parse_payload_enum(&event.payload, "execution_state") {
+                        match state {
+                            ExecutionState::Completed => {
+                                if let Some(count) = event
+                                    .payload
+                                    .get("execution_count")
+                                    .and_then(|v| v.as_u64())
+                                {
+                                    cell.execution_count = Some(count);
+                                }
+                                cell.execution_error = None;
+                            }
+                            ExecutionState::Error => {
+                                cell.execution_error = event
+                                    .payload
+                                    .get("error")
+                                    .and_then(|v| v.as_str())
+                                    .map(|s| s.to_string());
+                            }
+                            _ => cell.execution_error = None,
+                        }
+                        cell.execution_state = state;
                     }
 
                     if let Some(runtime_session) = event
@@ -450,75 +953,63 @@ impl Materializer for DocumentMaterializer {
             }
 
             "CellOutputCreated" => {
-                let output_data = &event.payload;
-                let output_id = output_data
+                let output = CellOutput::from_event(event)?;
+                state.outputs.insert(output.id.clone(), output);
+            }
+
+            "CellOutputAppended" => {
+                let output_id = event
+                    .payload
                     .get("output_id")
                     .and_then(|v| v.as_str())
                     .ok_or_else(|| EventError::ValidationError("Missing output_id".to_string()))?;
 
-                let cell_id = output_data
-                    .get("cell_id")
-                    .and_then(|v| v.as_str())
-                    .ok_or_else(|| EventError::ValidationError("Missing cell_id".to_string()))?;
+                // Streams grow in place so one stdout stays one output
+                if let Some(output) = state.outputs.get_mut(output_id) {
+                    if let Some(chunk) = event.payload.get("data").and_then(|v| v.as_str()) {
+                        output.data.get_or_insert_with(String::new).push_str(chunk);
+                    }
+                    if let Some(representations) =
+                        event.payload.get("representations").and_then(|v| {
+                            serde_json::from_value::<HashMap<String, MediaRepresentation>>(
+                                v.clone(),
+                            )
+                            .ok()
+                        })
+                    {
+                        output
+                            .representations
+                            .get_or_insert_with(HashMap::new)
+                            .extend(representations);
+                    }
+                }
+            }
 
-                let output_type_str = output_data
-                    .get("output_type")
+            "CellOutputRepositioned" => {
+                let output_id = event
+                    .payload
+                    .get("output_id")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| {
-                        EventError::ValidationError("Missing output_type".to_string())
-                    })?;
+                    .ok_or_else(|| EventError::ValidationError("Missing output_id".to_string()))?;
+                let position = event
+                    .payload
+                    .get("position")
+                    .and_then(|v| v.as_f64())
+                    .ok_or_else(|| EventError::ValidationError("Missing position".to_string()))?;
 
-                let output_type = match output_type_str {
-                    "multimedia_display" => OutputType::MultimediaDisplay,
-                    "multimedia_result" => OutputType::MultimediaResult,
-                    "terminal" => OutputType::Terminal,
-                    "markdown" => OutputType::Markdown,
-                    "error" => OutputType::Error,
-                    _ => {
-                        return Err(EventError::ValidationError(format!(
-                            "Invalid output_type: {}",
-                            output_type_str
-                        )))
-                    }
-                };
+                if let Some(output) = state.outputs.get_mut(output_id) {
+                    output.position = position;
+                }
+            }
 
-                let output = CellOutput {
-                    id: output_id.to_string(),
-                    cell_id: cell_id.to_string(),
-                    output_type,
-                    position: output_data
-                        .get("position")
-                        .and_then(|v| v.as_f64())
-                        .unwrap_or(0.0),
-                    stream_name: output_data
-                        .get("stream_name")
-                        .and_then(|v| v.as_str())
-                        .map(|s| s.to_string()),
-                    execution_count: output_data.get("execution_count").and_then(|v| v.as_u64()),
-                    display_id: output_data
-                        .get("display_id")
-                        .and_then(|v| v.as_str())
-                        .map(|s| s.to_string()),
-                    data: output_data
-                        .get("data")
-                        .and_then(|v| v.as_str())
-                        .map(|s| s.to_string()),
-                    artifact_id: output_data
-                        .get("artifact_id")
-                        .and_then(|v| v.as_str())
-                        .map(|s| s.to_string()),
-                    mime_type: output_data
-                        .get("mime_type")
-                        .and_then(|v| v.as_str())
-                        .map(|s| s.to_string()),
-                    metadata: output_data.get("metadata").cloned(),
-                    representations: output_data
-                        .get("representations")
-                        .and_then(|v| serde_json::from_value(v.clone()).ok()),
-                    created_at: event.timestamp,
-                };
+            "CellOutputsCleared" => {
+                let cell_id = event
+                    .payload
+                    .get("cell_id")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| EventError::ValidationError("Missing cell_id".to_string()))?;
 
-                new_state.outputs.insert(output_id.to_string(), output);
+                state.outputs.retain(|_, output| output.cell_id != cell_id);
             }
 
             "CellMoved" => {
@@ -534,14 +1025,15 @@ impl Materializer for DocumentMaterializer {
                     .and_then(|v| v.as_str())
                     .ok_or_else(|| {
                         EventError::ValidationError("Missing fractional_index".to_string())
-                    })?;
+                    })
+                    .and_then(parse_fractional_index)?;
 
-                if let Some(cell) = new_state.cells.get_mut(cell_id) {
-                    cell.fractional_index = Some(new_fractional_index.to_string());
+                if let Some(cell) = state.cells.get_mut(cell_id) {
+                    cell.fractional_index = Some(new_fractional_index);
                     cell.updated_at = event.timestamp;
 
                     // Update document timestamp
-                    if let Some(document) = new_state.documents.get_mut(&event.aggregate_id) {
+                    if let Some(document) = state.documents.get_mut(&event.aggregate_id) {
                         document.updated_at = event.timestamp;
                     }
                 }
@@ -554,24 +1046,129 @@ impl Materializer for DocumentMaterializer {
                     .and_then(|v| v.as_str())
                     .ok_or_else(|| EventError::ValidationError("Missing cell_id".to_string()))?;
 
-                // Remove cell and its outputs
-                new_state.cells.remove(cell_id);
-                new_state
-                    .outputs
-                    .retain(|_, output| output.cell_id != cell_id);
+                // Tombstone the cell; it and its outputs stay around for
+                // `CellRestored` but are hidden from queries
+                if let Some(cell) = state.cells.get_mut(cell_id) {
+                    cell.deleted = true;
+                    cell.updated_at = event.timestamp;
+                }
 
                 // Update document timestamp
-                if let Some(document) = new_state.documents.get_mut(&event.aggregate_id) {
+                if let Some(document) = state.documents.get_mut(&event.aggregate_id) {
                     document.updated_at = event.timestamp;
                 }
             }
 
+            "CellRestored" => {
+                let cell_id = event
+                    .payload
+                    .get("cell_id")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| EventError::ValidationError("Missing cell_id".to_string()))?;
+
+                if let Some(cell) = state.cells.get_mut(cell_id) {
+                    cell.deleted = false;
+                    cell.updated_at = event.timestamp;
+
+                    // Update document timestamp
+                    if let Some(document) = state.documents.get_mut(&event.aggregate_id) {
+                        document.updated_at = event.timestamp;
+                    }
+                }
+            }
+
+            "RuntimeSessionStarted" => {
+                let payload = &event.payload;
+                let required = |field: &str| {
+                    payload
+                        .get(field)
+                        .and_then(|v| v.as_str())
+                        .map(|s| s.to_string())
+                        .ok_or_else(|| EventError::ValidationError(format!("Missing {}", field)))
+                };
+                let flag = |field: &str| {
+                    payload
+                        .get(field)
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(false)
+                };
+
+                let session = RuntimeSession {
+                    session_id: required("session_id")?,
+                    runtime_id: required("runtime_id")?,
+                    runtime_type: required("runtime_type")?,
+                    status: parse_payload_enum(payload, "status")
+                        .unwrap_or(RuntimeStatus::Starting),
+                    is_active: true,
+                    can_execute_code: flag("can_execute_code"),
+                    can_execute_sql: flag("can_execute_sql"),
+                    can_execute_ai: flag("can_execute_ai"),
+                    available_ai_models: payload
+                        .get("available_ai_models")
+                        .and_then(|v| serde_json::from_value(v.clone()).ok()),
+                    last_renewed_at: None,
+                    expires_at: payload.get("expires_at").and_then(|v| v.as_i64()),
+                };
+                state
+                    .runtime_sessions
+                    .insert(session.session_id.clone(), session);
+            }
+
+            "RuntimeSessionStatusChanged" => {
+                let session_id = event
+                    .payload
+                    .get("session_id")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| EventError::ValidationError("Missing session_id".to_string()))?;
+                let status: RuntimeStatus = parse_payload_enum(&event.payload, "status")?;
+
+                if let Some(session) = state.runtime_sessions.get_mut(session_id) {
+                    session.is_active = status != RuntimeStatus::Terminated;
+                    session.status = status;
+                    session.last_renewed_at = Some(event.timestamp);
+                    if let Some(expires_at) =
+                        event.payload.get("expires_at").and_then(|v| v.as_i64())
+                    {
+                        session.expires_at = Some(expires_at);
+                    }
+                }
+            }
+
+            "RuntimeSessionTerminated" => {
+                let session_id = event
+                    .payload
+                    .get("session_id")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| EventError::ValidationError("Missing session_id".to_string()))?;
+
+                state.runtime_sessions.remove(session_id);
+            }
+
             "DocumentDeleted" => {
                 // Remove document and all associated cells/outputs
-                new_state.documents.remove(&event.aggregate_id);
+                state.documents.remove(&event.aggregate_id);
+                state.collaborators.remove(&event.aggregate_id);
+
+                let mut removed_cells = HashSet::new();
+                state.cells.retain(|cell_id, cell| {
+                    if cell.document_id == event.aggregate_id {
+                        removed_cells.insert(cell_id.clone());
+                        false
+                    } else {
+                        true
+                    }
+                });
+                state
+                    .outputs
+                    .retain(|_, output| !removed_cells.contains(&output.cell_id));
+            }
 
-                // For proper cleanup, we'd need to track which cells belong to which document
-                // This could be done by storing document_id in cells or using aggregate relationships
+            "SnapshotCreated" => {
+                *state = restore_state(event, "documents")?;
+                // Events the snapshot already covers at its timestamp stay
+                // marked as seen, so a replay doesn't apply them twice
+                state.record_processed(event);
+                return Ok(());
             }
 
             _ => {
@@ -579,6 +1176,32 @@ impl Materializer for DocumentMaterializer {
             }
         }
 
+        if event.event_type != "DocumentDeleted" && Self::handles_event_type(&event.event_type) {
+            if let Some(actor) = event_actor(event) {
+                state
+                    .collaborators
+                    .entry(event.aggregate_id.clone())
+                    .or_default()
+                    .insert(actor.to_string());
+            }
+        }
+
+        state.record_processed(event);
+        Ok(())
+    }
+}
+
+impl Materializer for DocumentMaterializer {
+    type State = DocumentProjectionState;
+    type Error = EventError;
+
+    fn initial_state() -> Self::State {
+        DocumentProjectionState::default()
+    }
+
+    fn apply_event(state: &Self::State, event: &Event) -> Result<Self::State, Self::Error> {
+        let mut new_state = state.clone();
+        Self::apply_in_place(&mut new_state, event)?;
         Ok(new_state)
     }
 
@@ -588,32 +1211,90 @@ impl Materializer for DocumentMaterializer {
             "DocumentCreated"
                 | "DocumentTitleUpdated"
                 | "DocumentMetadataUpdated"
+                | "DocumentTagAdded"
+                | "DocumentTagRemoved"
+                | "DocumentCustomFieldSet"
                 | "CellCreated"
                 | "CellSourceUpdated"
+                | "CellTypeChanged"
+                | "CellVisibilityChanged"
+                | "CellAiConfigUpdated"
                 | "CellExecutionStateChanged"
                 | "CellOutputCreated"
+                | "CellOutputAppended"
+                | "CellOutputRepositioned"
+                | "CellOutputsCleared"
                 | "CellMoved"
                 | "CellDeleted"
+                | "CellRestored"
                 | "DocumentDeleted"
+                | "RuntimeSessionStarted"
+                | "RuntimeSessionStatusChanged"
+                | "RuntimeSessionTerminated"
+                | "SnapshotCreated"
         )
     }
 }
 
 /// Document projection implementation
+///
+/// The state is held behind an `Arc` and updated copy-on-write: while a
+/// snapshot handed out by [`DocumentProjection::snapshot`] is alive the next
+/// update copies the state once, so the snapshot stays consistent; otherwise
+/// updates apply in place.
 pub struct DocumentProjection {
-    state: DocumentProjectionState,
+    state: Arc<DocumentProjectionState>,
 }
 
 impl DocumentProjection {
     pub fn new() -> Self {
         Self {
-            state: DocumentMaterializer::initial_state(),
+            state: Arc::new(DocumentMaterializer::initial_state()),
         }
     }
 
-    /// Get all documents
-    pub fn get_documents(&self) -> Vec<&Document> {
-        self.state.documents.values().collect()
+    /// Rebuild the projection as it was at `cutoff_timestamp`
+    ///
+    /// Only events stamped at or before the cutoff are replayed, so anything
+    /// created later (a cell, an output, an edit) simply doesn't appear.
+    pub fn rebuild_as_of(&mut self, events: &[Event], cutoff_timestamp: i64) -> EventResult<()> {
+        self.rebuild_from_iter(
+            events
+                .iter()
+                .filter(|event| event.timestamp <= cutoff_timestamp),
+        )
+    }
+
+    fn rebuild_from_iter<'a>(
+        &mut self,
+        events: impl Iterator<Item = &'a Event>,
+    ) -> EventResult<()> {
+        let mut state = DocumentMaterializer::initial_state();
+
+        for event in events {
+            // Only a snapshot marks events as seen before they're replayed
+            let seen = state.processed_at_last_timestamp(event);
+            if !seen && DocumentMaterializer::handles_event_type(&event.event_type) {
+                DocumentMaterializer::apply_in_place(&mut state, event).map_err(|e| {
+                    EventError::ValidationError(format!("Materialization failed: {}", e))
+                })?;
+            }
+        }
+
+        self.state = Arc::new(state);
+        Ok(())
+    }
+
+    /// Get a cheap, read-only snapshot of the current state
+    ///
+    /// The snapshot is unaffected by later updates to the projection.
+    pub fn snapshot(&self) -> Arc<DocumentProjectionState> {
+        Arc::clone(&self.state)
+    }
+
+    /// Get all documents
+    pub fn get_documents(&self) -> Vec<&Document> {
+        self.state.documents.values().collect()
     }
 
     /// Get a specific document by ID
@@ -621,14 +1302,69 @@ impl DocumentProjection {
         self.state.documents.get(document_id)
     }
 
-    /// Get all cells for a document ordered by fractional index
+    /// Get all cells for a document ordered by fractional index, leaving
+    /// out deleted cells
     pub fn get_document_cells(&self, document_id: &str) -> Vec<&Cell> {
         self.state.get_document_cells(document_id)
     }
 
-    /// Get a specific cell by ID
+    /// Get a document's cells in creation order rather than display order
+    pub fn get_document_cells_in_creation_order(&self, document_id: &str) -> Vec<&Cell> {
+        self.state.get_document_cells_in_creation_order(document_id)
+    }
+
+    /// Get an ordered window of a document's cells, for paging through large
+    /// notebooks
+    pub fn get_document_cells_range(
+        &self,
+        document_id: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<&Cell> {
+        self.state
+            .get_document_cells_range(document_id, offset, limit)
+    }
+
+    /// Count a document's cells, leaving out deleted ones
+    pub fn document_cell_count(&self, document_id: &str) -> usize {
+        self.state.document_cell_count(document_id)
+    }
+
+    /// Get all cells for a document, deleted ones included, for undo and
+    /// admin views
+    pub fn get_document_cells_including_deleted(&self, document_id: &str) -> Vec<&Cell> {
+        self.state.get_document_cells_including_deleted(document_id)
+    }
+
+    /// Get documents that contain at least one cell of the given type
+    pub fn documents_with_cell_type(&self, cell_type: CellType) -> Vec<&Document> {
+        let document_ids: HashSet<&str> = self
+            .state
+            .cells
+            .values()
+            .filter(|cell| !cell.deleted && cell.cell_type == cell_type)
+            .map(|cell| cell.document_id.as_str())
+            .collect();
+
+        self.state
+            .documents
+            .values()
+            .filter(|document| document_ids.contains(document.id.as_str()))
+            .collect()
+    }
+
+    /// Get a specific cell by ID, unless it has been deleted
     pub fn get_cell(&self, cell_id: &str) -> Option<&Cell> {
-        self.state.cells.get(cell_id)
+        self.state.get_live_cell(cell_id)
+    }
+
+    /// Get several cells by ID, in request order, skipping unknown and
+    /// deleted IDs
+    pub fn get_cells(&self, cell_ids: &[&str]) -> Vec<&Cell> {
+        cell_ids
+            .iter()
+            .filter_map(|cell_id| self.state.get_live_cell(cell_id))
+            .collect()
     }
 
     /// Get outputs for a specific cell
@@ -636,14 +1372,56 @@ impl DocumentProjection {
         self.state.get_cell_outputs(cell_id)
     }
 
+    /// Get outputs for a specific cell from one execution
+    pub fn get_cell_outputs_for_execution(
+        &self,
+        cell_id: &str,
+        execution_count: u64,
+    ) -> Vec<&CellOutput> {
+        self.state
+            .get_cell_outputs_for_execution(cell_id, execution_count)
+    }
+
+    /// Get the total byte size of output `data` across a document's cells
+    pub fn document_output_bytes(&self, document_id: &str) -> usize {
+        self.state
+            .outputs
+            .values()
+            .filter(|output| {
+                self.state
+                    .get_live_cell(&output.cell_id)
+                    .is_some_and(|cell| cell.document_id == document_id)
+            })
+            .filter_map(|output| output.data.as_ref())
+            .map(|data| data.len())
+            .sum()
+    }
+
+    /// Get the runtime sessions that are currently running
+    pub fn get_runtime_sessions(&self) -> Vec<&RuntimeSession> {
+        let mut sessions: Vec<&RuntimeSession> = self.state.runtime_sessions.values().collect();
+        sessions.sort_by(|a, b| a.session_id.cmp(&b.session_id));
+        sessions
+    }
+
+    /// Get the actors who have edited a document
+    pub fn get_collaborators(&self, document_id: &str) -> Option<&HashSet<String>> {
+        self.state.collaborators.get(document_id)
+    }
+
     /// Get the number of documents
     pub fn document_count(&self) -> usize {
         self.state.documents.len()
     }
 
-    /// Get the total number of cells across all documents
+    /// Get the total number of cells across all documents, leaving out
+    /// deleted cells
     pub fn total_cell_count(&self) -> usize {
-        self.state.cells.len()
+        self.state
+            .cells
+            .values()
+            .filter(|cell| !cell.deleted)
+            .count()
     }
 }
 
@@ -657,18 +1435,7 @@ impl Projection for DocumentProjection {
     type State = DocumentProjectionState;
 
     fn rebuild_from_events(&mut self, events: &[Event]) -> EventResult<()> {
-        let mut state = DocumentMaterializer::initial_state();
-
-        for event in events {
-            if DocumentMaterializer::handles_event_type(&event.event_type) {
-                state = DocumentMaterializer::apply_event(&state, event).map_err(|e| {
-                    EventError::ValidationError(format!("Materialization failed: {}", e))
-                })?;
-            }
-        }
-
-        self.state = state;
-        Ok(())
+        self.rebuild_from_iter(events.iter())
     }
 
     fn get_state(&self) -> &Self::State {
@@ -681,11 +1448,12 @@ impl Projection for DocumentProjection {
 
     fn apply_new_events(&mut self, events: &[Event]) -> EventResult<()> {
         for event in events {
-            if event.timestamp > self.state.last_processed_timestamp
+            if self.state.is_unprocessed(event)
                 && DocumentMaterializer::handles_event_type(&event.event_type)
             {
-                self.state =
-                    DocumentMaterializer::apply_event(&self.state, event).map_err(|e| {
+                // Copies the state only if a snapshot of it is still out
+                DocumentMaterializer::apply_in_place(Arc::make_mut(&mut self.state), event)
+                    .map_err(|e| {
                         EventError::ValidationError(format!("Materialization failed: {}", e))
                     })?;
             }
@@ -694,6 +1462,502 @@ impl Projection for DocumentProjection {
     }
 }
 
+/// Collect every event that touched a cell, in log order
+///
+/// Starts at the cell's `CellCreated` and includes the later cell events
+/// whose payload references `cell_id` (source updates, moves, execution
+/// changes, outputs, deletion). Comments and presence mention cells too, but
+/// aren't part of the cell's own history.
+pub fn cell_history<'a>(events: &'a [Event], cell_id: &str) -> Vec<&'a Event> {
+    let references_cell =
+        |event: &Event| event.payload.get("cell_id").and_then(|v| v.as_str()) == Some(cell_id);
+    events
+        .iter()
+        .skip_while(|event| !(event.event_type == "CellCreated" && references_cell(event)))
+        .filter(|event| is_cell_event_type(&event.event_type) && references_cell(event))
+        .collect()
+}
+
+/// Check whether an event type changes a cell, as opposed to its document
+fn is_cell_event_type(event_type: &str) -> bool {
+    matches!(
+        event_type,
+        "CellCreated"
+            | "CellSourceUpdated"
+            | "CellTypeChanged"
+            | "CellVisibilityChanged"
+            | "CellAiConfigUpdated"
+            | "CellExecutionStateChanged"
+            | "CellOutputCreated"
+            | "CellOutputAppended"
+            | "CellOutputRepositioned"
+            | "CellOutputsCleared"
+            | "CellMoved"
+            | "CellDeleted"
+            | "CellRestored"
+    )
+}
+
+/// When a `CellSourceUpdated` edit was made, for last-writer-wins
+///
+/// The server stamps events in arrival order, so it carries the client's
+/// edit time in the payload's `edited_at`; without one the event time stands
+/// in.
+pub fn source_edited_at(event: &Event) -> i64 {
+    event
+        .payload
+        .get("edited_at")
+        .and_then(|v| v.as_i64())
+        .unwrap_or(event.timestamp)
+}
+
+/// Collect the ids of cells touched between two sync cursors
+///
+/// Covers events after `from_seq` up to and including `to_seq`, numbered as
+/// by [`InMemoryEventStore::latest_sequence`]. Events pruned by a compaction
+/// are gone, so a range reaching back before one only sees what's left.
+pub fn cells_affected_between(
+    store: &InMemoryEventStore,
+    from_seq: u64,
+    to_seq: u64,
+) -> HashSet<String> {
+    store
+        .iter_events_after_sequence(from_seq)
+        .take_while(|(seq, _)| *seq <= to_seq)
+        .filter_map(|(_, event)| event.payload.get("cell_id").and_then(|v| v.as_str()))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Collect the events belonging to a transaction, in log order
+pub fn events_in_transaction<'a>(events: &'a [Event], transaction_id: &str) -> Vec<&'a Event> {
+    events
+        .iter()
+        .filter(|event| event.transaction_id.as_deref() == Some(transaction_id))
+        .collect()
+}
+
+/// Build the events that undo a whole transaction
+///
+/// `events` is the full log the transaction was applied to. Inverses are
+/// returned newest-first, each numbered on from its aggregate's entry in
+/// `latest_versions` (see `InMemoryEventStore::latest_versions`), and grouped
+/// under a `{transaction_id}:undo` transaction so the undo is itself one
+/// unit. Execution state changes, outputs and runtime sessions come from the
+/// runtime and are left as they are.
+pub fn invert_transaction(
+    events: &[Event],
+    transaction_id: &str,
+    latest_versions: &HashMap<String, i64>,
+) -> EventResult<Vec<Event>> {
+    let mut state = DocumentMaterializer::initial_state();
+    let mut inverses = Vec::new();
+
+    for event in events {
+        if event.transaction_id.as_deref() == Some(transaction_id)
+            && !is_runtime_event(&event.event_type)
+        {
+            inverses.push(invert_event(&state, event)?);
+        }
+        // One owned state folded in place; cloning it per event would make
+        // undo quadratic in the log
+        if DocumentMaterializer::handles_event_type(&event.event_type) {
+            DocumentMaterializer::apply_in_place(&mut state, event)?;
+        }
+    }
+
+    let undo_transaction = format!("{}:undo", transaction_id);
+    let mut versions = latest_versions.clone();
+    inverses
+        .into_iter()
+        .rev()
+        .map(|(event_type, aggregate_id, payload)| {
+            let version = versions.entry(aggregate_id.clone()).or_default();
+            *version += 1;
+            crate::EventBuilder::new()
+                .event_type(event_type)
+                .aggregate_id(aggregate_id)
+                .payload(payload)?
+                .transaction(undo_transaction.clone())
+                .build(*version)
+        })
+        .collect()
+}
+
+/// Whether an event records what the runtime did rather than a user's edit
+///
+/// The code ran whether or not the edit that triggered it is undone.
+fn is_runtime_event(event_type: &str) -> bool {
+    matches!(
+        event_type,
+        "CellExecutionStateChanged"
+            | "CellOutputCreated"
+            | "CellOutputAppended"
+            | "CellOutputRepositioned"
+            | "CellOutputsCleared"
+            | "RuntimeSessionStarted"
+            | "RuntimeSessionStatusChanged"
+            | "RuntimeSessionTerminated"
+    )
+}
+
+/// Build the event that undoes `event`, given the state just before it was
+/// applied
+///
+/// Undo appends the inverse like any other event, so the log stays
+/// append-only. Invertible types are `CellCreated`, `CellDeleted`,
+/// `CellRestored`, `CellSourceUpdated`, `CellMoved`, `CellVisibilityChanged`,
+/// `DocumentTitleUpdated`, `DocumentMetadataUpdated` and the metadata patch
+/// events; anything else, or an event whose cell or document isn't in
+/// `state`, gives `None`. The inverse is numbered `event.version + 1`, which
+/// fits an immediate undo; rebuild it with the current version if the
+/// aggregate has moved on since.
+pub fn inverse_event(state: &DocumentProjectionState, event: &Event) -> Option<Event> {
+    let (event_type, aggregate_id, payload) = invert_event(state, event).ok()?;
+    crate::EventBuilder::new()
+        .event_type(event_type)
+        .aggregate_id(aggregate_id)
+        .payload(payload)
+        .and_then(|builder| builder.build(event.version + 1))
+        .ok()
+}
+
+/// Work out the event type and payload that reverse `event`, given the state
+/// just before it was applied
+fn invert_event(
+    state: &DocumentProjectionState,
+    event: &Event,
+) -> EventResult<(&'static str, String, serde_json::Value)> {
+    let cell_id = event.payload.get("cell_id").and_then(|v| v.as_str());
+    let previous_cell = cell_id.and_then(|id| state.cells.get(id));
+    let cannot_undo = || {
+        EventError::ValidationError(format!(
+            "{} event {} cannot be undone",
+            event.event_type, event.id
+        ))
+    };
+
+    let (event_type, payload) = match event.event_type.as_str() {
+        "CellCreated" => (
+            "CellDeleted",
+            serde_json::json!({ "cell_id": cell_id.ok_or_else(cannot_undo)? }),
+        ),
+        "CellDeleted" => {
+            // The tombstoned cell keeps everything, so restoring it is enough
+            let cell = previous_cell.ok_or_else(cannot_undo)?;
+            ("CellRestored", serde_json::json!({ "cell_id": cell.id }))
+        }
+        "CellRestored" => {
+            let cell = previous_cell.ok_or_else(cannot_undo)?;
+            ("CellDeleted", serde_json::json!({ "cell_id": cell.id }))
+        }
+        "CellSourceUpdated" => {
+            let cell = previous_cell.ok_or_else(cannot_undo)?;
+            (
+                "CellSourceUpdated",
+                serde_json::json!({ "cell_id": cell.id, "source": cell.source }),
+            )
+        }
+        "CellVisibilityChanged" => {
+            let cell = previous_cell.ok_or_else(cannot_undo)?;
+            (
+                "CellVisibilityChanged",
+                serde_json::json!({
+                    "cell_id": cell.id,
+                    "source_visible": cell.source_visible,
+                    "output_visible": cell.output_visible,
+                    "ai_context_visible": cell.ai_context_visible,
+                }),
+            )
+        }
+        "CellMoved" => {
+            let cell = previous_cell.ok_or_else(cannot_undo)?;
+            let fractional_index = cell.fractional_index.as_ref().ok_or_else(cannot_undo)?;
+            (
+                "CellMoved",
+                serde_json::json!({ "cell_id": cell.id, "fractional_index": fractional_index }),
+            )
+        }
+        "DocumentTitleUpdated" => {
+            let document = state
+                .documents
+                .get(&event.aggregate_id)
+                .ok_or_else(cannot_undo)?;
+            (
+                "DocumentTitleUpdated",
+                serde_json::json!({ "title": document.title }),
+            )
+        }
+        "DocumentMetadataUpdated" => {
+            let document = state
+                .documents
+                .get(&event.aggregate_id)
+                .ok_or_else(cannot_undo)?;
+            (
+                "DocumentMetadataUpdated",
+                serde_json::json!({ "metadata": document.metadata }),
+            )
+        }
+        "DocumentTagAdded" | "DocumentTagRemoved" => {
+            let document = state
+                .documents
+                .get(&event.aggregate_id)
+                .ok_or_else(cannot_undo)?;
+            let tag = event
+                .payload
+                .get("tag")
+                .and_then(|v| v.as_str())
+                .ok_or_else(cannot_undo)?;
+            let inverse = if document.metadata.tags.iter().any(|t| t == tag) {
+                "DocumentTagAdded"
+            } else {
+                "DocumentTagRemoved"
+            };
+            (inverse, serde_json::json!({ "tag": tag }))
+        }
+        "DocumentCustomFieldSet" => {
+            let document = state
+                .documents
+                .get(&event.aggregate_id)
+                .ok_or_else(cannot_undo)?;
+            let key = event
+                .payload
+                .get("key")
+                .and_then(|v| v.as_str())
+                .ok_or_else(cannot_undo)?;
+            (
+                "DocumentCustomFieldSet",
+                serde_json::json!({ "key": key, "value": document.metadata.custom.get(key) }),
+            )
+        }
+        _ => return Err(cannot_undo()),
+    };
+
+    Ok((event_type, event.aggregate_id.clone(), payload))
+}
+
+/// Build `CellMoved` events that give a document's cells a clean ordering
+///
+/// Does nothing if every cell already has a valid index in strictly
+/// increasing order. Otherwise the cells keep their current best-effort
+/// order (see [`DocumentProjection::get_document_cells`]) and are
+/// reassigned a fresh, evenly spread sequence; only cells whose index
+/// changes get an event, numbered from `next_version`.
+pub fn repair_indices(
+    projection: &DocumentProjection,
+    document_id: &str,
+    next_version: i64,
+) -> EventResult<Vec<Event>> {
+    let cells = projection.get_document_cells(document_id);
+    let indices: Option<Vec<&FractionalIndex>> = cells
+        .iter()
+        .map(|cell| cell.fractional_index.as_ref())
+        .collect();
+    if indices.is_some_and(|indices| indices.windows(2).all(|w| w[0] < w[1])) {
+        return Ok(Vec::new());
+    }
+
+    reindex_cells(&cells, document_id, next_version)
+}
+
+/// Build `CellMoved` events that replace a document's indices with short ones
+///
+/// Unlike [`repair_indices`] this always reindexes, keeping the current
+/// order; use it once indices have grown long from repeated inserts (see
+/// [`crate::fractional_index::rebalance`]). Only cells whose index changes
+/// get an event, numbered from `next_version`.
+pub fn rebalance_indices(
+    projection: &DocumentProjection,
+    document_id: &str,
+    next_version: i64,
+) -> EventResult<Vec<Event>> {
+    let cells = projection.get_document_cells(document_id);
+    reindex_cells(&cells, document_id, next_version)
+}
+
+/// Move `cells` onto a fresh, evenly spread sequence in their given order
+fn reindex_cells(cells: &[&Cell], document_id: &str, next_version: i64) -> EventResult<Vec<Event>> {
+    let indices: Vec<String> = cells
+        .iter()
+        .map(|cell| {
+            cell.fractional_index
+                .as_ref()
+                .map(|index| index.to_string())
+                .unwrap_or_default()
+        })
+        .collect();
+    cells
+        .iter()
+        .zip(crate::fractional_index::rebalance(&indices))
+        .filter(|(cell, index)| {
+            cell.fractional_index.as_ref().map(FractionalIndex::as_str) != Some(index.as_str())
+        })
+        .zip(next_version..)
+        .map(|((cell, index), version)| {
+            move_cell_event(document_id.to_string(), cell.id.clone(), index, version)
+        })
+        .collect()
+}
+
+/// Build the fewest `CellMoved` events that put cells in `desired_order`
+///
+/// `current` lists each cell with its index, in any order, and
+/// `desired_order` must name every one of them exactly once. The longest run
+/// of cells whose indices already increase in the desired order stays put;
+/// every other cell gets a short index between its new neighbours. Events
+/// follow the desired order and are numbered from `next_version`.
+pub fn reorder_cells(
+    document_id: &str,
+    current: &[(&str, Option<&FractionalIndex>)],
+    desired_order: &[&str],
+    next_version: i64,
+) -> EventResult<Vec<Event>> {
+    let indices: HashMap<&str, Option<&FractionalIndex>> = current.iter().copied().collect();
+    let mut seen = HashSet::new();
+    for cell_id in desired_order {
+        if !indices.contains_key(cell_id) {
+            return Err(EventError::ValidationError(format!(
+                "Unknown cell in new order: {}",
+                cell_id
+            )));
+        }
+        if !seen.insert(*cell_id) {
+            return Err(EventError::ValidationError(format!(
+                "Cell listed twice in new order: {}",
+                cell_id
+            )));
+        }
+    }
+    if let Some((missing, _)) = current.iter().find(|(id, _)| !seen.contains(id)) {
+        return Err(EventError::ValidationError(format!(
+            "Cell missing from new order: {}",
+            missing
+        )));
+    }
+
+    let ordered: Vec<Option<&FractionalIndex>> =
+        desired_order.iter().map(|id| indices[id]).collect();
+    let kept = longest_increasing_run(&ordered);
+
+    // Fill each gap between kept cells, tracking the index to its left
+    let mut new_indices = vec![None; ordered.len()];
+    let mut lower: Option<&FractionalIndex> = None;
+    let mut gap_start = 0;
+    for position in kept.iter().copied().chain([ordered.len()]) {
+        let upper = ordered.get(position).copied().flatten();
+        let fill = indices_between(lower, upper, position - gap_start)?;
+        for (slot, index) in new_indices[gap_start..position].iter_mut().zip(fill) {
+            *slot = Some(index);
+        }
+        lower = upper;
+        gap_start = position + 1;
+    }
+
+    desired_order
+        .iter()
+        .zip(new_indices)
+        .filter_map(|(cell_id, index)| Some((cell_id, index?)))
+        .zip(next_version..)
+        .map(|((cell_id, index), version)| {
+            move_cell_event(
+                document_id.to_string(),
+                cell_id.to_string(),
+                index.into(),
+                version,
+            )
+        })
+        .collect()
+}
+
+/// Positions of the longest strictly increasing run of indices, skipping
+/// missing ones
+fn longest_increasing_run(indices: &[Option<&FractionalIndex>]) -> Vec<usize> {
+    // tails[k] is the position ending the best run of length k + 1 found so far
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; indices.len()];
+    for (position, index) in indices.iter().enumerate() {
+        let Some(index) = index else { continue };
+        let length = tails.partition_point(|&tail| indices[tail].unwrap() < *index);
+        previous[position] = length.checked_sub(1).map(|k| tails[k]);
+        if length == tails.len() {
+            tails.push(position);
+        } else {
+            tails[length] = position;
+        }
+    }
+
+    let mut run = Vec::with_capacity(tails.len());
+    let mut position = tails.last().copied();
+    while let Some(p) = position {
+        run.push(p);
+        position = previous[p];
+    }
+    run.reverse();
+    run
+}
+
+/// `count` increasing indices strictly between `lower` and `upper`
+///
+/// Bisects rather than stepping from one end, so filling a gap with many
+/// cells keeps the indices short.
+fn indices_between(
+    lower: Option<&FractionalIndex>,
+    upper: Option<&FractionalIndex>,
+    count: usize,
+) -> EventResult<Vec<FractionalIndex>> {
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    let middle = match (lower, upper) {
+        (Some(lower), Some(upper)) => lower.between(upper),
+        (Some(lower), None) => lower.after(),
+        (None, Some(upper)) => upper.before(),
+        (None, None) => Ok(FractionalIndex::initial()),
+    }
+    .map_err(|e| EventError::ValidationError(e.to_string()))?;
+
+    let mut indices = indices_between(lower, Some(&middle), count / 2)?;
+    let after = indices_between(Some(&middle), upper, count - count / 2 - 1)?;
+    indices.push(middle);
+    indices.extend(after);
+    Ok(indices)
+}
+
+/// Build events that renumber a cell's outputs to positions 0, 1, 2, ...
+///
+/// Outputs keep their current order (see
+/// [`DocumentProjection::get_cell_outputs`]); only outputs whose position
+/// changes get a `CellOutputRepositioned` event, numbered from
+/// `next_version`. Returns nothing for an unknown cell.
+pub fn reposition_outputs(
+    projection: &DocumentProjection,
+    cell_id: &str,
+    next_version: i64,
+) -> EventResult<Vec<Event>> {
+    let Some(cell) = projection.get_cell(cell_id) else {
+        return Ok(Vec::new());
+    };
+
+    projection
+        .get_cell_outputs(cell_id)
+        .into_iter()
+        .enumerate()
+        .filter(|(position, output)| output.position != *position as f64)
+        .zip(next_version..)
+        .map(|((position, output), version)| {
+            crate::EventBuilder::new()
+                .event_type("CellOutputRepositioned")
+                .aggregate_id(cell.document_id.clone())
+                .payload(serde_json::json!({
+                    "cell_id": cell_id,
+                    "output_id": output.id,
+                    "position": position,
+                }))?
+                .build(version)
+        })
+        .collect()
+}
+
 /// Utility functions for creating document events
 
 /// Create a new document
@@ -715,6 +1979,52 @@ pub fn create_document_event(
         .build(version)
 }
 
+/// Tag a document, leaving the rest of its metadata alone
+pub fn add_document_tag_event(
+    document_id: String,
+    tag: String,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    EventBuilder::new()
+        .event_type("DocumentTagAdded")
+        .aggregate_id(document_id)
+        .payload(serde_json::json!({ "tag": tag }))?
+        .build(version)
+}
+
+/// Remove a tag from a document, leaving the rest of its metadata alone
+pub fn remove_document_tag_event(
+    document_id: String,
+    tag: String,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    EventBuilder::new()
+        .event_type("DocumentTagRemoved")
+        .aggregate_id(document_id)
+        .payload(serde_json::json!({ "tag": tag }))?
+        .build(version)
+}
+
+/// Set one custom metadata field on a document, or remove it with `None`
+pub fn set_document_custom_field_event(
+    document_id: String,
+    key: String,
+    value: Option<String>,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    EventBuilder::new()
+        .event_type("DocumentCustomFieldSet")
+        .aggregate_id(document_id)
+        .payload(serde_json::json!({ "key": key, "value": value }))?
+        .build(version)
+}
+
 /// Create a new cell with fractional indexing
 pub fn create_cell_event(
     document_id: String,
@@ -729,13 +2039,7 @@ pub fn create_cell_event(
 
     let mut payload = serde_json::json!({
         "cell_id": cell_id,
-        "cell_type": match cell_type {
-            CellType::Code => "code",
-            CellType::Markdown => "markdown",
-            CellType::Sql => "sql",
-            CellType::Ai => "ai",
-            CellType::Raw => "raw",
-        },
+        "cell_type": cell_type,
         "source": source,
         "created_by": created_by
     });
@@ -751,6 +2055,170 @@ pub fn create_cell_event(
         .build(version)
 }
 
+/// Clear a cell's outputs, e.g. before it is re-executed
+pub fn clear_cell_outputs_event(
+    document_id: String,
+    cell_id: String,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    EventBuilder::new()
+        .event_type("CellOutputsCleared")
+        .aggregate_id(document_id)
+        .payload(serde_json::json!({
+            "cell_id": cell_id
+        }))?
+        .build(version)
+}
+
+/// Mime types in the order we prefer them as an output's primary representation
+const MIME_PRIORITY: &[&str] = &[
+    "application/vnd.jupyter.widget-view+json",
+    "application/vnd.vegalite.v5+json",
+    "application/vnd.plotly.v1+json",
+    "text/html",
+    "image/svg+xml",
+    "image/png",
+    "image/jpeg",
+    "text/markdown",
+    "text/latex",
+    "application/json",
+    "text/plain",
+];
+
+/// Build a `CellOutputCreated` event from a Jupyter `{mimetype: data}` mimebundle
+///
+/// Every entry becomes an inline representation; the richest one (by
+/// `MIME_PRIORITY`, with unknown types ranked just above `text/plain`) also
+/// fills the flattened `data`/`mime_type` fields. Non-string data such as
+/// JSON payloads is stored serialized in `data`.
+pub fn output_event_from_mimebundle(
+    document_id: String,
+    cell_id: String,
+    bundle: &serde_json::Value,
+    output_type: OutputType,
+    execution_count: Option<u64>,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    let bundle = bundle
+        .as_object()
+        .ok_or_else(|| EventError::ValidationError("Mimebundle must be an object".to_string()))?;
+
+    let rank = |mime_type: &str| {
+        MIME_PRIORITY
+            .iter()
+            .position(|m| *m == mime_type)
+            .unwrap_or(MIME_PRIORITY.len() - 1)
+    };
+    let (primary_mime, primary_data) = bundle
+        .iter()
+        .min_by_key(|(mime_type, _)| (rank(mime_type), mime_type.as_str() == "text/plain"))
+        .ok_or_else(|| EventError::ValidationError("Mimebundle is empty".to_string()))?;
+
+    let data = match primary_data {
+        serde_json::Value::String(text) => text.clone(),
+        other => other.to_string(),
+    };
+    let representations: HashMap<String, MediaRepresentation> = bundle
+        .iter()
+        .map(|(mime_type, data)| {
+            (
+                mime_type.clone(),
+                MediaRepresentation::Inline {
+                    data: data.clone(),
+                    metadata: None,
+                },
+            )
+        })
+        .collect();
+
+    let mut payload = serde_json::json!({
+        "output_id": crate::generate_event_id(),
+        "cell_id": cell_id,
+        "output_type": output_type,
+        "data": data,
+        "mime_type": primary_mime,
+        "representations": representations,
+    });
+    if let Some(count) = execution_count {
+        payload["execution_count"] = serde_json::Value::from(count);
+    }
+
+    EventBuilder::new()
+        .event_type("CellOutputCreated")
+        .aggregate_id(document_id)
+        .payload(payload)?
+        .build(version)
+}
+
+/// Announce a runtime session that is attaching to a document
+///
+/// The session's `status`, capability flags, AI models and `expires_at` are
+/// recorded; `is_active` and `last_renewed_at` are maintained by the
+/// materializer.
+pub fn create_runtime_session_event(
+    document_id: String,
+    session: &RuntimeSession,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    EventBuilder::new()
+        .event_type("RuntimeSessionStarted")
+        .aggregate_id(document_id)
+        .payload(serde_json::json!({
+            "session_id": session.session_id,
+            "runtime_id": session.runtime_id,
+            "runtime_type": session.runtime_type,
+            "status": session.status,
+            "can_execute_code": session.can_execute_code,
+            "can_execute_sql": session.can_execute_sql,
+            "can_execute_ai": session.can_execute_ai,
+            "available_ai_models": session.available_ai_models,
+            "expires_at": session.expires_at,
+        }))?
+        .build(version)
+}
+
+/// Change a runtime session's status, e.g. `starting` -> `ready`
+pub fn update_runtime_session_status_event(
+    document_id: String,
+    session_id: String,
+    status: RuntimeStatus,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    EventBuilder::new()
+        .event_type("RuntimeSessionStatusChanged")
+        .aggregate_id(document_id)
+        .payload(serde_json::json!({
+            "session_id": session_id,
+            "status": status
+        }))?
+        .build(version)
+}
+
+/// Remove a runtime session that has shut down
+pub fn terminate_runtime_session_event(
+    document_id: String,
+    session_id: String,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    EventBuilder::new()
+        .event_type("RuntimeSessionTerminated")
+        .aggregate_id(document_id)
+        .payload(serde_json::json!({
+            "session_id": session_id
+        }))?
+        .build(version)
+}
+
 /// Update a cell's source code
 pub fn update_cell_source_event(
     document_id: String,
@@ -770,47 +2238,189 @@ pub fn update_cell_source_event(
         .build(version)
 }
 
-/// Move a cell using fractional indexing
-pub fn move_cell_event(
+/// Convert a cell to a different type
+pub fn change_cell_type_event(
     document_id: String,
     cell_id: String,
-    fractional_index: String,
+    cell_type: CellType,
     version: i64,
 ) -> EventResult<Event> {
     use crate::EventBuilder;
 
     EventBuilder::new()
-        .event_type("CellMoved")
+        .event_type("CellTypeChanged")
         .aggregate_id(document_id)
         .payload(serde_json::json!({
             "cell_id": cell_id,
-            "fractional_index": fractional_index
+            "cell_type": cell_type
         }))?
         .build(version)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_document_creation() {
-        let event = create_document_event(
-            "doc-123".to_string(),
-            "My Document".to_string(),
-            DocumentMetadata::default(),
-            1,
-        )
-        .unwrap();
+/// Patch an AI cell's provider, model and settings
+///
+/// `None` leaves that field as it is.
+pub fn update_cell_ai_config_event(
+    document_id: String,
+    cell_id: String,
+    ai_provider: Option<String>,
+    ai_model: Option<String>,
+    ai_settings: Option<serde_json::Value>,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
 
-        assert_eq!(event.event_type, "DocumentCreated");
-        assert_eq!(event.aggregate_id, "doc-123");
+    let mut payload = serde_json::json!({ "cell_id": cell_id });
+    if let Some(provider) = ai_provider {
+        payload["ai_provider"] = provider.into();
+    }
+    if let Some(model) = ai_model {
+        payload["ai_model"] = model.into();
+    }
+    if let Some(settings) = ai_settings {
+        payload["ai_settings"] = settings;
     }
 
-    #[test]
-    fn test_cell_creation() {
-        let event = create_cell_event(
-            "doc-123".to_string(),
+    EventBuilder::new()
+        .event_type("CellAiConfigUpdated")
+        .aggregate_id(document_id)
+        .payload(payload)?
+        .build(version)
+}
+
+/// Show or hide parts of a cell
+///
+/// `None` leaves that toggle as it is.
+pub fn change_cell_visibility_event(
+    document_id: String,
+    cell_id: String,
+    source_visible: Option<bool>,
+    output_visible: Option<bool>,
+    ai_context_visible: Option<bool>,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    let mut payload = serde_json::json!({ "cell_id": cell_id });
+    for (field, visible) in [
+        ("source_visible", source_visible),
+        ("output_visible", output_visible),
+        ("ai_context_visible", ai_context_visible),
+    ] {
+        if let Some(visible) = visible {
+            payload[field] = visible.into();
+        }
+    }
+
+    EventBuilder::new()
+        .event_type("CellVisibilityChanged")
+        .aggregate_id(document_id)
+        .payload(payload)?
+        .build(version)
+}
+
+/// Bring back a deleted cell along with its outputs
+pub fn restore_cell_event(
+    document_id: String,
+    cell_id: String,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    EventBuilder::new()
+        .event_type("CellRestored")
+        .aggregate_id(document_id)
+        .payload(serde_json::json!({ "cell_id": cell_id }))?
+        .build(version)
+}
+
+/// Append a chunk to an existing output, e.g. the next piece of stdout
+pub fn append_cell_output_event(
+    document_id: String,
+    cell_id: String,
+    output_id: String,
+    data: String,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    EventBuilder::new()
+        .event_type("CellOutputAppended")
+        .aggregate_id(document_id)
+        .payload(serde_json::json!({
+            "cell_id": cell_id,
+            "output_id": output_id,
+            "data": data
+        }))?
+        .build(version)
+}
+
+/// Move a cell using fractional indexing
+pub fn move_cell_event(
+    document_id: String,
+    cell_id: String,
+    fractional_index: String,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    EventBuilder::new()
+        .event_type("CellMoved")
+        .aggregate_id(document_id)
+        .payload(serde_json::json!({
+            "cell_id": cell_id,
+            "fractional_index": fractional_index
+        }))?
+        .build(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a terminal `CellOutputCreated` event; `fields` are merged into
+    /// the payload
+    fn output_event(
+        document_id: &str,
+        cell_id: &str,
+        output_id: &str,
+        fields: serde_json::Value,
+    ) -> crate::EventBuilder {
+        let mut payload = serde_json::json!({
+            "output_id": output_id,
+            "cell_id": cell_id,
+            "output_type": "terminal",
+        });
+        if let (Some(payload), serde_json::Value::Object(fields)) =
+            (payload.as_object_mut(), fields)
+        {
+            payload.extend(fields);
+        }
+        crate::EventBuilder::new()
+            .event_type("CellOutputCreated")
+            .aggregate_id(document_id)
+            .payload(payload)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_document_creation() {
+        let event = create_document_event(
+            "doc-123".to_string(),
+            "My Document".to_string(),
+            DocumentMetadata::default(),
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(event.event_type, "DocumentCreated");
+        assert_eq!(event.aggregate_id, "doc-123");
+    }
+
+    #[test]
+    fn test_cell_creation() {
+        let event = create_cell_event(
+            "doc-123".to_string(),
             "cell-1".to_string(),
             CellType::Code,
             "print('hello')".to_string(),
@@ -856,7 +2466,10 @@ mod tests {
 
         let cell = projection.get_cell("cell-1").unwrap();
         assert_eq!(cell.source, "print('hello')");
-        assert_eq!(cell.fractional_index, Some("a0".to_string()));
+        assert_eq!(
+            cell.fractional_index.as_ref().map(FractionalIndex::as_str),
+            Some("a0")
+        );
         assert_eq!(cell.document_id, "doc-123");
 
         // Test that document cells are properly associated
@@ -864,4 +2477,1980 @@ mod tests {
         assert_eq!(document_cells.len(), 1);
         assert_eq!(document_cells[0].id, "cell-1");
     }
+
+    #[test]
+    fn test_cell_history() {
+        let events = vec![
+            // Logged before the cell exists, e.g. by a client that raced ahead
+            update_cell_source_event(
+                "doc-123".to_string(),
+                "cell-1".to_string(),
+                "too soon".to_string(),
+                1,
+            )
+            .unwrap(),
+            create_document_event(
+                "doc-123".to_string(),
+                "Test Document".to_string(),
+                DocumentMetadata::default(),
+                1,
+            )
+            .unwrap(),
+            create_cell_event(
+                "doc-123".to_string(),
+                "cell-1".to_string(),
+                CellType::Code,
+                "print('hello')".to_string(),
+                Some("a0".to_string()),
+                "user-1".to_string(),
+                2,
+            )
+            .unwrap(),
+            create_cell_event(
+                "doc-123".to_string(),
+                "cell-2".to_string(),
+                CellType::Markdown,
+                "# Notes".to_string(),
+                Some("a1".to_string()),
+                "user-1".to_string(),
+                3,
+            )
+            .unwrap(),
+            update_cell_source_event(
+                "doc-123".to_string(),
+                "cell-1".to_string(),
+                "print('world')".to_string(),
+                4,
+            )
+            .unwrap(),
+            crate::comment::create_comment_event(
+                "doc-123".to_string(),
+                "comment-1".to_string(),
+                "cell-1".to_string(),
+                "user-2".to_string(),
+                "Why world?".to_string(),
+                None,
+                1,
+            )
+            .unwrap(),
+            move_cell_event(
+                "doc-123".to_string(),
+                "cell-1".to_string(),
+                "a2".to_string(),
+                5,
+            )
+            .unwrap(),
+        ];
+
+        let history = cell_history(&events, "cell-1");
+        let types: Vec<&str> = history.iter().map(|e| e.event_type.as_str()).collect();
+        assert_eq!(types, vec!["CellCreated", "CellSourceUpdated", "CellMoved"]);
+        assert_eq!(
+            history.iter().map(|e| e.version).collect::<Vec<_>>(),
+            vec![2, 4, 5]
+        );
+    }
+
+    #[test]
+    fn test_materializer_accepts_serialized_cell_types() {
+        let cell_types = [
+            CellType::Code,
+            CellType::Markdown,
+            CellType::Sql,
+            CellType::Ai,
+            CellType::Raw,
+        ];
+
+        for (i, cell_type) in cell_types.iter().enumerate() {
+            let event = Event {
+                id: format!("event-{}", i),
+                event_type: "CellCreated".to_string(),
+                aggregate_id: "doc-123".to_string(),
+                payload: serde_json::json!({
+                    "cell_id": "cell-1",
+                    "cell_type": serde_json::to_value(cell_type).unwrap(),
+                }),
+                timestamp: 1,
+                version: 1,
+                transaction_id: None,
+                actor: None,
+            };
+
+            let state =
+                DocumentMaterializer::apply_event(&DocumentMaterializer::initial_state(), &event)
+                    .unwrap();
+            assert_eq!(&state.cells["cell-1"].cell_type, cell_type);
+        }
+    }
+
+    #[test]
+    fn test_document_cells_tie_break_by_id() {
+        let mut events = vec![create_document_event(
+            "doc-123".to_string(),
+            "Test Document".to_string(),
+            DocumentMetadata::default(),
+            1,
+        )
+        .unwrap()];
+
+        // Same fractional index and (second-resolution) creation time
+        for (version, cell_id) in [(2, "cell-b"), (3, "cell-a")] {
+            let mut event = create_cell_event(
+                "doc-123".to_string(),
+                cell_id.to_string(),
+                CellType::Code,
+                String::new(),
+                Some("a0".to_string()),
+                "user-1".to_string(),
+                version,
+            )
+            .unwrap();
+            event.timestamp = 1000;
+            events.push(event);
+        }
+
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+
+        for _ in 0..10 {
+            let ids: Vec<&str> = projection
+                .get_document_cells("doc-123")
+                .iter()
+                .map(|c| c.id.as_str())
+                .collect();
+            assert_eq!(ids, vec!["cell-a", "cell-b"]);
+        }
+    }
+
+    #[test]
+    fn test_get_cells_skips_unknown_ids() {
+        let mut events = vec![create_document_event(
+            "doc-123".to_string(),
+            "Test Document".to_string(),
+            DocumentMetadata::default(),
+            1,
+        )
+        .unwrap()];
+        for (version, cell_id) in [(2, "cell-1"), (3, "cell-2")] {
+            events.push(
+                create_cell_event(
+                    "doc-123".to_string(),
+                    cell_id.to_string(),
+                    CellType::Code,
+                    String::new(),
+                    None,
+                    "user-1".to_string(),
+                    version,
+                )
+                .unwrap(),
+            );
+        }
+
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+
+        let cells = projection.get_cells(&["cell-2", "missing", "cell-1"]);
+        let ids: Vec<&str> = cells.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, vec!["cell-2", "cell-1"]);
+    }
+
+    #[test]
+    fn test_document_output_bytes() {
+        let events = vec![
+            create_document_event(
+                "doc-123".to_string(),
+                "Test Document".to_string(),
+                DocumentMetadata::default(),
+                1,
+            )
+            .unwrap(),
+            create_cell_event(
+                "doc-123".to_string(),
+                "cell-1".to_string(),
+                CellType::Code,
+                String::new(),
+                None,
+                "user-1".to_string(),
+                2,
+            )
+            .unwrap(),
+            output_event(
+                "doc-123",
+                "cell-1",
+                "output-1",
+                serde_json::json!({"data": "hello"}),
+            )
+            .build(3)
+            .unwrap(),
+            output_event(
+                "doc-123",
+                "cell-1",
+                "output-2",
+                serde_json::json!({"data": "wörld"}),
+            )
+            .build(4)
+            .unwrap(),
+            output_event(
+                "doc-123",
+                "cell-elsewhere",
+                "output-3",
+                serde_json::json!({"data": "ignored"}),
+            )
+            .build(5)
+            .unwrap(),
+        ];
+
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+
+        // "wörld" is 6 bytes in UTF-8
+        assert_eq!(projection.document_output_bytes("doc-123"), 11);
+        assert_eq!(projection.document_output_bytes("doc-other"), 0);
+    }
+
+    #[test]
+    fn test_snapshot_unaffected_by_later_updates() {
+        let mut projection = DocumentProjection::new();
+        let mut doc_event = create_document_event(
+            "doc-123".to_string(),
+            "Original".to_string(),
+            DocumentMetadata::default(),
+            1,
+        )
+        .unwrap();
+        doc_event.timestamp = 1000;
+        projection.apply_new_events(&[doc_event]).unwrap();
+
+        let snapshot = projection.snapshot();
+
+        let title_event = Event {
+            id: "event-title".to_string(),
+            event_type: "DocumentTitleUpdated".to_string(),
+            aggregate_id: "doc-123".to_string(),
+            payload: serde_json::json!({"title": "Renamed"}),
+            timestamp: 1001,
+            version: 2,
+            transaction_id: None,
+            actor: None,
+        };
+        projection.apply_new_events(&[title_event]).unwrap();
+
+        assert_eq!(snapshot.documents["doc-123"].title, "Original");
+        assert_eq!(projection.get_document("doc-123").unwrap().title, "Renamed");
+    }
+
+    #[test]
+    fn test_collaborators_tracked_per_document() {
+        let mut events = vec![
+            create_document_event(
+                "doc-1".to_string(),
+                "Shared".to_string(),
+                DocumentMetadata::default(),
+                1,
+            )
+            .unwrap(),
+            create_cell_event(
+                "doc-1".to_string(),
+                "cell-1".to_string(),
+                CellType::Code,
+                "print('hi')".to_string(),
+                None,
+                "alice".to_string(),
+                2,
+            )
+            .unwrap(),
+        ];
+        let mut update = update_cell_source_event(
+            "doc-1".to_string(),
+            "cell-1".to_string(),
+            "x".to_string(),
+            3,
+        )
+        .unwrap();
+        update.payload["actor"] = serde_json::json!("bob");
+        events.push(update);
+
+        // A stamped actor wins over whatever the payload claims
+        let mut stamped = update_cell_source_event(
+            "doc-1".to_string(),
+            "cell-1".to_string(),
+            "y".to_string(),
+            4,
+        )
+        .unwrap();
+        stamped.payload["actor"] = serde_json::json!("mallory");
+        stamped.actor = Some("carol".to_string());
+        events.push(stamped);
+
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+
+        let collaborators = projection.get_collaborators("doc-1").unwrap();
+        assert_eq!(collaborators.len(), 3);
+        assert!(collaborators.contains("alice"));
+        assert!(collaborators.contains("bob"));
+        assert!(collaborators.contains("carol"));
+        assert!(!collaborators.contains("mallory"));
+        assert!(projection.get_collaborators("doc-2").is_none());
+    }
+
+    #[test]
+    fn test_transaction_undo_reverts_as_unit() {
+        use crate::EventBuilder;
+
+        let mut events = vec![create_document_event(
+            "doc-1".to_string(),
+            "Notebook".to_string(),
+            DocumentMetadata::default(),
+            1,
+        )
+        .unwrap()];
+        events.push(
+            create_cell_event(
+                "doc-1".to_string(),
+                "cell-1".to_string(),
+                CellType::Code,
+                "before".to_string(),
+                Some("a0".to_string()),
+                "alice".to_string(),
+                2,
+            )
+            .unwrap(),
+        );
+
+        let in_transaction = |event_type: &str, payload: serde_json::Value, version: i64| {
+            EventBuilder::new()
+                .event_type(event_type)
+                .aggregate_id("doc-1")
+                .payload(payload)
+                .unwrap()
+                .transaction("tx-1")
+                .build(version)
+                .unwrap()
+        };
+        events.push(in_transaction(
+            "CellSourceUpdated",
+            serde_json::json!({ "cell_id": "cell-1", "source": "after" }),
+            3,
+        ));
+        events.push(in_transaction(
+            "CellMoved",
+            serde_json::json!({ "cell_id": "cell-1", "fractional_index": "b0" }),
+            4,
+        ));
+        events.push(in_transaction(
+            "CellCreated",
+            serde_json::json!({ "cell_id": "cell-2", "cell_type": "markdown", "source": "# hi" }),
+            5,
+        ));
+
+        let grouped = events_in_transaction(&events, "tx-1");
+        let versions: Vec<_> = grouped.iter().map(|e| e.version).collect();
+        assert_eq!(versions, vec![3, 4, 5]);
+
+        let latest_versions = HashMap::from([("doc-1".to_string(), 5)]);
+        let undo = invert_transaction(&events, "tx-1", &latest_versions).unwrap();
+        assert_eq!(undo.len(), 3);
+        assert!(undo
+            .iter()
+            .all(|e| e.transaction_id.as_deref() == Some("tx-1:undo")));
+        let undo_types: Vec<_> = undo.iter().map(|e| e.event_type.as_str()).collect();
+        assert_eq!(
+            undo_types,
+            vec!["CellDeleted", "CellMoved", "CellSourceUpdated"]
+        );
+
+        events.extend(undo);
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+
+        let cell = projection.get_cell("cell-1").unwrap();
+        assert_eq!(cell.source, "before");
+        assert_eq!(
+            cell.fractional_index.as_ref().map(FractionalIndex::as_str),
+            Some("a0")
+        );
+        assert!(projection.get_cell("cell-2").is_none());
+    }
+
+    #[test]
+    fn test_transaction_undo_skips_runtime_events() {
+        use crate::EventBuilder;
+
+        let in_transaction =
+            |event_type: &str, aggregate_id: &str, payload: serde_json::Value, version: i64| {
+                EventBuilder::new()
+                    .event_type(event_type)
+                    .aggregate_id(aggregate_id)
+                    .payload(payload)
+                    .unwrap()
+                    .transaction("run-all")
+                    .build(version)
+                    .unwrap()
+            };
+        let mut events = Vec::new();
+        for document_id in ["doc-1", "doc-2"] {
+            events.push(
+                create_document_event(
+                    document_id.to_string(),
+                    "Notebook".to_string(),
+                    DocumentMetadata::default(),
+                    1,
+                )
+                .unwrap(),
+            );
+            events.push(
+                create_cell_event(
+                    document_id.to_string(),
+                    format!("{}-cell", document_id),
+                    CellType::Code,
+                    "before".to_string(),
+                    None,
+                    "alice".to_string(),
+                    2,
+                )
+                .unwrap(),
+            );
+        }
+
+        // Edit and run a cell in each document as one action
+        for document_id in ["doc-1", "doc-2"] {
+            let cell_id = format!("{}-cell", document_id);
+            events.push(in_transaction(
+                "CellSourceUpdated",
+                document_id,
+                serde_json::json!({ "cell_id": cell_id, "source": "after" }),
+                3,
+            ));
+            events.push(in_transaction(
+                "CellExecutionStateChanged",
+                document_id,
+                serde_json::json!({ "cell_id": cell_id, "execution_state": "running" }),
+                4,
+            ));
+            events.push(in_transaction(
+                "CellOutputCreated",
+                document_id,
+                serde_json::json!({
+                    "output_id": format!("{}-out", document_id),
+                    "cell_id": cell_id,
+                    "output_type": "terminal",
+                    "data": "done",
+                }),
+                5,
+            ));
+        }
+        let latest_versions = HashMap::from([("doc-1".to_string(), 5), ("doc-2".to_string(), 5)]);
+        let undo = invert_transaction(&events, "run-all", &latest_versions).unwrap();
+        let undone: Vec<_> = undo
+            .iter()
+            .map(|e| (e.event_type.as_str(), e.aggregate_id.as_str(), e.version))
+            .collect();
+        assert_eq!(
+            undone,
+            vec![
+                ("CellSourceUpdated", "doc-2", 6),
+                ("CellSourceUpdated", "doc-1", 6),
+            ]
+        );
+
+        events.extend(undo);
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+        assert_eq!(projection.get_cell("doc-1-cell").unwrap().source, "before");
+        assert_eq!(projection.get_cell("doc-2-cell").unwrap().source, "before");
+        assert_eq!(projection.get_cell_outputs("doc-1-cell").len(), 1);
+    }
+
+    #[test]
+    fn test_ai_settings_validated_on_create() {
+        let ai_cell = |cell_id: &str, temperature: f64| Event {
+            id: format!("event-{}", cell_id),
+            event_type: "CellCreated".to_string(),
+            aggregate_id: "doc-1".to_string(),
+            payload: serde_json::json!({
+                "cell_id": cell_id,
+                "cell_type": "ai",
+                "ai_provider": "openai",
+                "ai_settings": { "temperature": temperature },
+            }),
+            timestamp: 1000,
+            version: 1,
+            transaction_id: None,
+            actor: None,
+        };
+
+        let state = DocumentMaterializer::initial_state();
+
+        let result = DocumentMaterializer::apply_event(&state, &ai_cell("too-hot", 3.5));
+        assert!(matches!(result, Err(EventError::ValidationError(_))));
+
+        let state = DocumentMaterializer::apply_event(&state, &ai_cell("ok", 0.7)).unwrap();
+        let settings = state.cells["ok"].ai_settings.as_ref().unwrap();
+        assert_eq!(settings["temperature"], 0.7);
+    }
+
+    #[test]
+    fn test_stale_title_update_ignored() {
+        let title_event = |title: &str, timestamp: i64, version: i64| Event {
+            id: format!("title-{}", version),
+            event_type: "DocumentTitleUpdated".to_string(),
+            aggregate_id: "doc-1".to_string(),
+            payload: serde_json::json!({ "title": title }),
+            timestamp,
+            version,
+            transaction_id: None,
+            actor: None,
+        };
+
+        let mut created = create_document_event(
+            "doc-1".to_string(),
+            "Original".to_string(),
+            DocumentMetadata::default(),
+            1,
+        )
+        .unwrap();
+        created.timestamp = 1000;
+
+        let state = DocumentMaterializer::initial_state();
+        let state = DocumentMaterializer::apply_event(&state, &created).unwrap();
+        let state =
+            DocumentMaterializer::apply_event(&state, &title_event("Newer", 3000, 2)).unwrap();
+        let state =
+            DocumentMaterializer::apply_event(&state, &title_event("Older", 2000, 3)).unwrap();
+
+        let document = &state.documents["doc-1"];
+        assert_eq!(document.title, "Newer");
+        assert_eq!(document.updated_at, 3000);
+
+        // A cell edit bumps the document, but only title edits guard the title
+        let mut cell = create_cell_event(
+            "doc-1".to_string(),
+            "cell-1".to_string(),
+            CellType::Code,
+            String::new(),
+            None,
+            "alice".to_string(),
+            4,
+        )
+        .unwrap();
+        cell.timestamp = 5000;
+        let state = DocumentMaterializer::apply_event(&state, &cell).unwrap();
+        let state =
+            DocumentMaterializer::apply_event(&state, &title_event("Latest", 4000, 5)).unwrap();
+
+        let document = &state.documents["doc-1"];
+        assert_eq!(document.title, "Latest");
+        assert_eq!(document.title_updated_at, 4000);
+        assert_eq!(document.updated_at, 5000);
+    }
+
+    #[test]
+    fn test_documents_with_cell_type() {
+        let mut events = Vec::new();
+        for (document_id, cell_type) in [
+            ("doc-ai", CellType::Ai),
+            ("doc-code", CellType::Code),
+            ("doc-mixed", CellType::Markdown),
+        ] {
+            events.push(
+                create_document_event(
+                    document_id.to_string(),
+                    document_id.to_string(),
+                    DocumentMetadata::default(),
+                    1,
+                )
+                .unwrap(),
+            );
+            events.push(
+                create_cell_event(
+                    document_id.to_string(),
+                    format!("{}-cell", document_id),
+                    cell_type,
+                    String::new(),
+                    None,
+                    "alice".to_string(),
+                    2,
+                )
+                .unwrap(),
+            );
+        }
+        events.push(
+            create_cell_event(
+                "doc-mixed".to_string(),
+                "doc-mixed-ai".to_string(),
+                CellType::Ai,
+                String::new(),
+                None,
+                "alice".to_string(),
+                3,
+            )
+            .unwrap(),
+        );
+
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+
+        let mut ids: Vec<_> = projection
+            .documents_with_cell_type(CellType::Ai)
+            .iter()
+            .map(|d| d.id.as_str())
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["doc-ai", "doc-mixed"]);
+        assert!(projection
+            .documents_with_cell_type(CellType::Sql)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_cell_outputs_for_execution() {
+        let run_output = |output_id: &str, execution_count: u64, position: f64| {
+            output_event(
+                "doc-1",
+                "cell-1",
+                output_id,
+                serde_json::json!({"execution_count": execution_count, "position": position}),
+            )
+            .build(1)
+            .unwrap()
+        };
+
+        let mut projection = DocumentProjection::new();
+        projection
+            .rebuild_from_events(&[
+                run_output("run1-a", 1, 0.0),
+                run_output("run1-b", 1, 1.0),
+                run_output("run2-a", 2, 0.0),
+            ])
+            .unwrap();
+
+        let ids = |execution_count| {
+            projection
+                .get_cell_outputs_for_execution("cell-1", execution_count)
+                .iter()
+                .map(|o| o.id.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(1), vec!["run1-a", "run1-b"]);
+        assert_eq!(ids(2), vec!["run2-a"]);
+        assert!(ids(3).is_empty());
+    }
+
+    #[test]
+    fn test_repair_duplicate_indices() {
+        let mut events = vec![create_document_event(
+            "doc-1".to_string(),
+            "Notebook".to_string(),
+            DocumentMetadata::default(),
+            1,
+        )
+        .unwrap()];
+        for (version, (cell_id, index)) in [("cell-a", "a0"), ("cell-b", "a0"), ("cell-c", "a1")]
+            .into_iter()
+            .enumerate()
+        {
+            events.push(
+                create_cell_event(
+                    "doc-1".to_string(),
+                    cell_id.to_string(),
+                    CellType::Code,
+                    String::new(),
+                    Some(index.to_string()),
+                    "alice".to_string(),
+                    version as i64 + 2,
+                )
+                .unwrap(),
+            );
+        }
+
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+        let order_before: Vec<_> = projection
+            .get_document_cells("doc-1")
+            .iter()
+            .map(|c| c.id.clone())
+            .collect();
+
+        let moves = repair_indices(&projection, "doc-1", 5).unwrap();
+        assert!(!moves.is_empty());
+        assert!(moves.iter().all(|e| e.event_type == "CellMoved"));
+
+        events.extend(moves);
+        projection.rebuild_from_events(&events).unwrap();
+
+        let cells = projection.get_document_cells("doc-1");
+        let order_after: Vec<_> = cells.iter().map(|c| c.id.clone()).collect();
+        let indices: Vec<_> = cells
+            .iter()
+            .map(|c| String::from(c.fractional_index.clone().unwrap()))
+            .collect();
+        assert_eq!(order_after, order_before);
+        assert!(crate::fractional_index::is_valid_order(&indices));
+        assert!(repair_indices(&projection, "doc-1", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_reposition_colliding_outputs() {
+        let positioned_output = |output_id: &str, position: f64, timestamp: i64| {
+            output_event(
+                "doc-1",
+                "cell-1",
+                output_id,
+                serde_json::json!({"position": position}),
+            )
+            .timestamp(timestamp)
+            .build(1)
+            .unwrap()
+        };
+
+        let mut events = vec![
+            create_cell_event(
+                "doc-1".to_string(),
+                "cell-1".to_string(),
+                CellType::Code,
+                String::new(),
+                None,
+                "alice".to_string(),
+                1,
+            )
+            .unwrap(),
+            positioned_output("out-a", 0.0, 100),
+            positioned_output("out-b", 0.0, 200),
+            positioned_output("out-c", 7.5, 300),
+        ];
+
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+
+        let moves = reposition_outputs(&projection, "cell-1", 5).unwrap();
+        assert_eq!(moves.len(), 2);
+
+        events.extend(moves);
+        projection.rebuild_from_events(&events).unwrap();
+
+        let outputs: Vec<_> = projection
+            .get_cell_outputs("cell-1")
+            .iter()
+            .map(|o| (o.id.as_str(), o.position))
+            .collect();
+        assert_eq!(
+            outputs,
+            vec![("out-a", 0.0), ("out-b", 1.0), ("out-c", 2.0)]
+        );
+        assert!(reposition_outputs(&projection, "cell-1", 7)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_cells_affected_between() {
+        let mut events = vec![create_document_event(
+            "doc-1".to_string(),
+            "Notebook".to_string(),
+            DocumentMetadata::default(),
+            1,
+        )
+        .unwrap()];
+        for (version, cell_id) in (2..).zip(["cell-1", "cell-2", "cell-3"]) {
+            events.push(
+                create_cell_event(
+                    "doc-1".to_string(),
+                    cell_id.to_string(),
+                    CellType::Code,
+                    String::new(),
+                    None,
+                    "alice".to_string(),
+                    version,
+                )
+                .unwrap(),
+            );
+        }
+        // Sequences 5-7: edits to two of the three cells
+        for (version, cell_id) in (5..).zip(["cell-1", "cell-3", "cell-1"]) {
+            events.push(
+                update_cell_source_event(
+                    "doc-1".to_string(),
+                    cell_id.to_string(),
+                    "edited".to_string(),
+                    version,
+                )
+                .unwrap(),
+            );
+        }
+
+        use crate::EventStore;
+
+        let mut store = InMemoryEventStore::new();
+        store.append_events(events).unwrap();
+
+        let affected = cells_affected_between(&store, 4, 7);
+        assert_eq!(
+            affected,
+            HashSet::from(["cell-1".to_string(), "cell-3".to_string()])
+        );
+        assert!(cells_affected_between(&store, 7, 10).is_empty());
+
+        // Sequences run on past a compaction
+        let snapshot = crate::snapshot_event(
+            &DocumentProjectionState::default(),
+            &crate::comment::CommentProjectionState::default(),
+            &store.latest_versions(),
+            store.latest_sequence(),
+            crate::current_timestamp(),
+        )
+        .unwrap();
+        store.compact(snapshot).unwrap();
+        store
+            .append_event(
+                update_cell_source_event(
+                    "doc-1".to_string(),
+                    "cell-2".to_string(),
+                    "x".to_string(),
+                    8,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(store.latest_sequence(), 9);
+        assert_eq!(
+            cells_affected_between(&store, 8, 9),
+            HashSet::from(["cell-2".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_clear_cell_outputs() {
+        let cell_output = |output_id: &str, cell_id: &str, version: i64| {
+            output_event(
+                "doc-1",
+                cell_id,
+                output_id,
+                serde_json::json!({"data": "hello\n"}),
+            )
+            .timestamp(version)
+            .build(version)
+            .unwrap()
+        };
+
+        let mut events = Vec::new();
+        for (cell_id, version) in [("cell-1", 1), ("cell-2", 2)] {
+            let mut event = create_cell_event(
+                "doc-1".to_string(),
+                cell_id.to_string(),
+                CellType::Code,
+                String::new(),
+                None,
+                "alice".to_string(),
+                version,
+            )
+            .unwrap();
+            event.timestamp = version;
+            events.push(event);
+        }
+        events.push(cell_output("out-a", "cell-1", 3));
+        events.push(cell_output("out-b", "cell-1", 4));
+        events.push(cell_output("out-c", "cell-2", 5));
+
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+        assert_eq!(projection.get_cell_outputs("cell-1").len(), 2);
+
+        let mut clear =
+            clear_cell_outputs_event("doc-1".to_string(), "cell-1".to_string(), 6).unwrap();
+        clear.timestamp = 6;
+        assert_eq!(clear.event_type, "CellOutputsCleared");
+        events.push(clear);
+        projection.rebuild_from_events(&events).unwrap();
+
+        assert!(projection.get_cell_outputs("cell-1").is_empty());
+        assert_eq!(projection.get_cell_outputs("cell-2").len(), 1);
+    }
+
+    #[test]
+    fn test_output_event_from_mimebundle() {
+        let bundle = serde_json::json!({
+            "text/plain": "<b>hi</b> as text",
+            "text/html": "<b>hi</b>",
+        });
+        let event = output_event_from_mimebundle(
+            "doc-1".to_string(),
+            "cell-1".to_string(),
+            &bundle,
+            OutputType::MultimediaResult,
+            Some(3),
+            1,
+        )
+        .unwrap();
+        assert_eq!(event.event_type, "CellOutputCreated");
+
+        let output = CellOutput::from_event(&event).unwrap();
+        assert_eq!(output.cell_id, "cell-1");
+        assert_eq!(output.output_type, OutputType::MultimediaResult);
+        assert_eq!(output.execution_count, Some(3));
+        assert_eq!(output.mime_type.as_deref(), Some("text/html"));
+        assert_eq!(output.data.as_deref(), Some("<b>hi</b>"));
+
+        let representations = output.representations.unwrap();
+        assert_eq!(representations.len(), 2);
+        assert_eq!(
+            representations["text/plain"],
+            MediaRepresentation::Inline {
+                data: serde_json::json!("<b>hi</b> as text"),
+                metadata: None,
+            }
+        );
+
+        assert!(output_event_from_mimebundle(
+            "doc-1".to_string(),
+            "cell-1".to_string(),
+            &serde_json::json!({}),
+            OutputType::MultimediaDisplay,
+            None,
+            1,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_document_deleted_cascades() {
+        let mut events = vec![create_document_event(
+            "doc-1".to_string(),
+            "Doomed".to_string(),
+            DocumentMetadata::default(),
+            1,
+        )
+        .unwrap()];
+        for (i, cell_id) in ["cell-1", "cell-2", "cell-3"].into_iter().enumerate() {
+            events.push(
+                create_cell_event(
+                    "doc-1".to_string(),
+                    cell_id.to_string(),
+                    CellType::Code,
+                    String::new(),
+                    None,
+                    "alice".to_string(),
+                    2 + i as i64 * 2,
+                )
+                .unwrap(),
+            );
+            events.push(
+                output_event_from_mimebundle(
+                    "doc-1".to_string(),
+                    cell_id.to_string(),
+                    &serde_json::json!({"text/plain": "ok"}),
+                    OutputType::MultimediaResult,
+                    None,
+                    3 + i as i64 * 2,
+                )
+                .unwrap(),
+            );
+        }
+        events.push(
+            create_document_event(
+                "doc-2".to_string(),
+                "Survivor".to_string(),
+                DocumentMetadata::default(),
+                1,
+            )
+            .unwrap(),
+        );
+        events.push(
+            create_cell_event(
+                "doc-2".to_string(),
+                "cell-4".to_string(),
+                CellType::Code,
+                String::new(),
+                None,
+                "alice".to_string(),
+                2,
+            )
+            .unwrap(),
+        );
+
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+        assert_eq!(projection.total_cell_count(), 4);
+        assert_eq!(projection.get_state().outputs.len(), 3);
+
+        events.push(
+            crate::EventBuilder::new()
+                .event_type("DocumentDeleted")
+                .aggregate_id("doc-1")
+                .build(8)
+                .unwrap(),
+        );
+        projection.rebuild_from_events(&events).unwrap();
+
+        assert_eq!(projection.total_cell_count(), 1);
+        assert!(projection.get_cell("cell-4").is_some());
+        assert!(projection.get_state().outputs.is_empty());
+    }
+
+    #[test]
+    fn test_apply_new_events_keeps_equal_timestamps() {
+        let mut projection = DocumentProjection::new();
+        let mut events = vec![
+            create_cell_event(
+                "doc-1".to_string(),
+                "cell-1".to_string(),
+                CellType::Code,
+                String::new(),
+                None,
+                "alice".to_string(),
+                1,
+            )
+            .unwrap(),
+            update_cell_source_event("doc-1".to_string(), "cell-1".to_string(), "x".into(), 2)
+                .unwrap(),
+        ];
+        for event in &mut events {
+            event.timestamp = 1_700_000_000;
+        }
+
+        // Submitted one at a time, as the server does
+        for event in &events {
+            projection
+                .apply_new_events(std::slice::from_ref(event))
+                .unwrap();
+        }
+        assert_eq!(projection.get_cell("cell-1").unwrap().source, "x");
+
+        // Replaying an already-applied event is still a no-op
+        let before = projection.get_cell("cell-1").unwrap().updated_at;
+        projection.apply_new_events(&events[..1]).unwrap();
+        assert_eq!(projection.get_cell("cell-1").unwrap().source, "x");
+        assert_eq!(projection.get_cell("cell-1").unwrap().updated_at, before);
+    }
+
+    #[test]
+    fn test_rebalance_long_indices() {
+        let long_index = format!("a0{}", "V".repeat(30));
+        let mut events = Vec::new();
+        for (version, (cell_id, index)) in [
+            ("cell-a", "a0".to_string()),
+            ("cell-b", long_index.clone()),
+            ("cell-c", "a1".to_string()),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            events.push(
+                create_cell_event(
+                    "doc-1".to_string(),
+                    cell_id.to_string(),
+                    CellType::Code,
+                    String::new(),
+                    Some(index),
+                    "alice".to_string(),
+                    version as i64 + 1,
+                )
+                .unwrap(),
+            );
+        }
+
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+
+        // Already in a valid order, so repair has nothing to do
+        assert!(repair_indices(&projection, "doc-1", 4).unwrap().is_empty());
+
+        let moves = rebalance_indices(&projection, "doc-1", 4).unwrap();
+        assert!(moves.iter().all(|e| e.event_type == "CellMoved"));
+        events.extend(moves);
+        projection.rebuild_from_events(&events).unwrap();
+
+        let cells = projection.get_document_cells("doc-1");
+        let order: Vec<_> = cells.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(order, vec!["cell-a", "cell-b", "cell-c"]);
+        assert!(cells
+            .iter()
+            .all(|c| c.fractional_index.as_ref().unwrap().as_str().len() < long_index.len()));
+    }
+
+    #[test]
+    fn test_runtime_session_lifecycle() {
+        let session = RuntimeSession {
+            session_id: "session-1".to_string(),
+            runtime_id: "runtime-1".to_string(),
+            runtime_type: "python3".to_string(),
+            status: RuntimeStatus::Starting,
+            is_active: true,
+            can_execute_code: true,
+            can_execute_sql: false,
+            can_execute_ai: true,
+            available_ai_models: Some(vec!["gpt-4o".to_string()]),
+            last_renewed_at: None,
+            expires_at: Some(2_000),
+        };
+
+        let mut events =
+            vec![create_runtime_session_event("doc-1".to_string(), &session, 1).unwrap()];
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+        assert_eq!(projection.get_runtime_sessions(), vec![&session]);
+
+        let mut ready = update_runtime_session_status_event(
+            "doc-1".to_string(),
+            "session-1".to_string(),
+            RuntimeStatus::Ready,
+            2,
+        )
+        .unwrap();
+        ready.timestamp = 1_500;
+        events.push(ready);
+        projection.rebuild_from_events(&events).unwrap();
+
+        let sessions = projection.get_runtime_sessions();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].status, RuntimeStatus::Ready);
+        assert!(sessions[0].is_active);
+        assert!(sessions[0].can_execute_code && !sessions[0].can_execute_sql);
+        assert_eq!(sessions[0].last_renewed_at, Some(1_500));
+        assert_eq!(sessions[0].expires_at, Some(2_000));
+
+        events.push(
+            terminate_runtime_session_event("doc-1".to_string(), "session-1".to_string(), 3)
+                .unwrap(),
+        );
+        projection.rebuild_from_events(&events).unwrap();
+        assert!(projection.get_runtime_sessions().is_empty());
+    }
+
+    #[test]
+    fn test_execution_count_and_error() {
+        let execution = |state: &str, extra: serde_json::Value, version: i64| {
+            let mut payload = serde_json::json!({"cell_id": "cell-1", "execution_state": state});
+            payload
+                .as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            crate::EventBuilder::new()
+                .event_type("CellExecutionStateChanged")
+                .aggregate_id("doc-1")
+                .payload(payload)
+                .unwrap()
+                .build(version)
+                .unwrap()
+        };
+
+        let mut events = vec![
+            create_cell_event(
+                "doc-1".to_string(),
+                "cell-1".to_string(),
+                CellType::Code,
+                "1 / 0".to_string(),
+                None,
+                "alice".to_string(),
+                1,
+            )
+            .unwrap(),
+            execution("running", serde_json::json!({}), 2),
+            execution(
+                "error",
+                serde_json::json!({"error": "ZeroDivisionError: division by zero"}),
+                3,
+            ),
+        ];
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+
+        let cell = projection.get_cell("cell-1").unwrap();
+        assert_eq!(cell.execution_state, ExecutionState::Error);
+        assert_eq!(
+            cell.execution_error.as_deref(),
+            Some("ZeroDivisionError: division by zero")
+        );
+        assert_eq!(cell.execution_count, None);
+
+        events.push(execution("running", serde_json::json!({}), 4));
+        events.push(execution(
+            "completed",
+            serde_json::json!({"execution_count": 3}),
+            5,
+        ));
+        projection.rebuild_from_events(&events).unwrap();
+
+        let cell = projection.get_cell("cell-1").unwrap();
+        assert_eq!(cell.execution_state, ExecutionState::Completed);
+        assert_eq!(cell.execution_count, Some(3));
+        assert_eq!(cell.execution_error, None);
+    }
+
+    #[test]
+    fn test_rebuild_as_of_matches_truncated_replay() {
+        let stamp = |mut event: Event, timestamp: i64| {
+            event.timestamp = timestamp;
+            event
+        };
+        let events = vec![
+            stamp(
+                create_document_event(
+                    "doc-1".to_string(),
+                    "Draft".to_string(),
+                    DocumentMetadata::default(),
+                    1,
+                )
+                .unwrap(),
+                100,
+            ),
+            stamp(
+                create_cell_event(
+                    "doc-1".to_string(),
+                    "cell-1".to_string(),
+                    CellType::Code,
+                    "x = 1".to_string(),
+                    None,
+                    "alice".to_string(),
+                    2,
+                )
+                .unwrap(),
+                110,
+            ),
+            stamp(
+                update_cell_source_event(
+                    "doc-1".to_string(),
+                    "cell-1".to_string(),
+                    "x = 2".to_string(),
+                    3,
+                )
+                .unwrap(),
+                120,
+            ),
+            stamp(
+                create_cell_event(
+                    "doc-1".to_string(),
+                    "cell-2".to_string(),
+                    CellType::Markdown,
+                    "# Later".to_string(),
+                    None,
+                    "bob".to_string(),
+                    4,
+                )
+                .unwrap(),
+                130,
+            ),
+        ];
+
+        let mut as_of = DocumentProjection::new();
+        as_of.rebuild_as_of(&events, 115).unwrap();
+        let mut truncated = DocumentProjection::new();
+        truncated.rebuild_from_events(&events[..2]).unwrap();
+
+        assert_eq!(as_of.get_cell("cell-1"), truncated.get_cell("cell-1"));
+        assert_eq!(as_of.get_cell("cell-1").unwrap().source, "x = 1");
+        // The cell created after the cutoff doesn't appear at all
+        assert!(as_of.get_cell("cell-2").is_none());
+        assert_eq!(as_of.get_document_cells("doc-1").len(), 1);
+        assert_eq!(as_of.last_processed_timestamp(), 110);
+
+        // A cutoff before the document existed shows nothing
+        as_of.rebuild_as_of(&events, 99).unwrap();
+        assert_eq!(as_of.document_count(), 0);
+    }
+
+    #[test]
+    fn test_cell_type_changed_clears_type_specific_fields() {
+        let created = |cell_id: &str, payload: serde_json::Value, version: i64| {
+            let mut payload = payload;
+            payload["cell_id"] = serde_json::json!(cell_id);
+            crate::EventBuilder::new()
+                .event_type("CellCreated")
+                .aggregate_id("doc-1")
+                .payload(payload)
+                .unwrap()
+                .timestamp(1000)
+                .build(version)
+                .unwrap()
+        };
+        let mut events = vec![
+            created(
+                "ai-cell",
+                serde_json::json!({
+                    "cell_type": "ai",
+                    "ai_provider": "openai",
+                    "ai_model": "gpt-4o",
+                    "ai_settings": { "temperature": 0.5 },
+                }),
+                1,
+            ),
+            created(
+                "sql-cell",
+                serde_json::json!({
+                    "cell_type": "sql",
+                    "sql_connection_id": "warehouse",
+                    "sql_result_variable": "df",
+                }),
+                2,
+            ),
+        ];
+        for (version, (cell_id, cell_type)) in
+            [("ai-cell", CellType::Code), ("sql-cell", CellType::Sql)]
+                .into_iter()
+                .enumerate()
+        {
+            let mut event = change_cell_type_event(
+                "doc-1".to_string(),
+                cell_id.to_string(),
+                cell_type,
+                version as i64 + 3,
+            )
+            .unwrap();
+            event.timestamp = 2000;
+            events.push(event);
+        }
+
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+
+        let ai_cell = projection.get_cell("ai-cell").unwrap();
+        assert_eq!(ai_cell.cell_type, CellType::Code);
+        assert_eq!(ai_cell.ai_provider, None);
+        assert_eq!(ai_cell.ai_model, None);
+        assert_eq!(ai_cell.ai_settings, None);
+        assert_eq!(ai_cell.updated_at, 2000);
+
+        // Converting to the same type keeps its configuration
+        let sql_cell = projection.get_cell("sql-cell").unwrap();
+        assert_eq!(sql_cell.sql_connection_id.as_deref(), Some("warehouse"));
+        assert_eq!(sql_cell.sql_result_variable.as_deref(), Some("df"));
+
+        let mut invalid = change_cell_type_event(
+            "doc-1".to_string(),
+            "ai-cell".to_string(),
+            CellType::Code,
+            5,
+        )
+        .unwrap();
+        invalid.payload["cell_type"] = serde_json::json!("notebook");
+        assert_eq!(
+            DocumentMaterializer::apply_event(projection.get_state(), &invalid).unwrap_err(),
+            EventError::ValidationError("Invalid cell_type: notebook".to_string())
+        );
+    }
+
+    #[test]
+    fn test_cell_visibility_changed_updates_only_given_toggles() {
+        let mut created = create_cell_event(
+            "doc-1".to_string(),
+            "cell-1".to_string(),
+            CellType::Code,
+            "print('hi')".to_string(),
+            None,
+            "alice".to_string(),
+            1,
+        )
+        .unwrap();
+        created.timestamp = 1000;
+        created.payload["ai_context_visible"] = serde_json::json!(false);
+        let mut hide_output = change_cell_visibility_event(
+            "doc-1".to_string(),
+            "cell-1".to_string(),
+            None,
+            Some(false),
+            None,
+            2,
+        )
+        .unwrap();
+        hide_output.timestamp = 2000;
+        assert_eq!(
+            hide_output.payload,
+            serde_json::json!({"cell_id": "cell-1", "output_visible": false})
+        );
+
+        let mut projection = DocumentProjection::new();
+        projection
+            .rebuild_from_events(&[created, hide_output])
+            .unwrap();
+
+        let cell = projection.get_cell("cell-1").unwrap();
+        assert!(!cell.output_visible);
+        assert!(cell.source_visible);
+        assert!(!cell.ai_context_visible);
+        assert_eq!(cell.updated_at, 2000);
+    }
+
+    #[test]
+    fn test_cell_ai_config_updated_patches_fields() {
+        let mut created = crate::EventBuilder::new()
+            .event_type("CellCreated")
+            .aggregate_id("doc-1")
+            .payload(serde_json::json!({
+                "cell_id": "cell-1",
+                "cell_type": "ai",
+                "ai_provider": "openai",
+                "ai_model": "gpt-4o",
+                "ai_settings": { "temperature": 0.2 },
+            }))
+            .unwrap()
+            .build(1)
+            .unwrap();
+        created.timestamp = 1000;
+        let mut update = update_cell_ai_config_event(
+            "doc-1".to_string(),
+            "cell-1".to_string(),
+            None,
+            Some("gpt-4o-mini".to_string()),
+            None,
+            2,
+        )
+        .unwrap();
+        update.timestamp = 2000;
+
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&[created]).unwrap();
+        assert_eq!(
+            projection.get_cell("cell-1").unwrap().ai_model.as_deref(),
+            Some("gpt-4o")
+        );
+
+        projection.apply_new_events(&[update]).unwrap();
+        let cell = projection.get_cell("cell-1").unwrap();
+        assert_eq!(cell.ai_model.as_deref(), Some("gpt-4o-mini"));
+        assert_eq!(cell.ai_provider.as_deref(), Some("openai"));
+        assert_eq!(
+            cell.ai_settings,
+            Some(serde_json::json!({ "temperature": 0.2 }))
+        );
+        assert_eq!(cell.updated_at, 2000);
+    }
+
+    #[test]
+    fn test_cell_soft_delete_and_restore() {
+        let cell = |cell_id: &str, fractional_index: &str, version: i64| {
+            create_cell_event(
+                "doc-1".to_string(),
+                cell_id.to_string(),
+                CellType::Code,
+                format!("# {}", cell_id),
+                Some(fractional_index.to_string()),
+                "alice".to_string(),
+                version,
+            )
+            .unwrap()
+        };
+        let mut events = vec![
+            cell("cell-1", "a0", 1),
+            cell("cell-2", "a1", 2),
+            output_event_from_mimebundle(
+                "doc-1".to_string(),
+                "cell-2".to_string(),
+                &serde_json::json!({"text/plain": "42"}),
+                OutputType::MultimediaResult,
+                Some(1),
+                3,
+            )
+            .unwrap(),
+            crate::EventBuilder::new()
+                .event_type("CellDeleted")
+                .aggregate_id("doc-1")
+                .payload(serde_json::json!({"cell_id": "cell-2"}))
+                .unwrap()
+                .build(4)
+                .unwrap(),
+        ];
+        for (offset, event) in events.iter_mut().enumerate() {
+            event.timestamp = 1000 + offset as i64;
+        }
+
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+
+        let ids =
+            |cells: Vec<&Cell>| -> Vec<String> { cells.iter().map(|c| c.id.clone()).collect() };
+        assert_eq!(ids(projection.get_document_cells("doc-1")), vec!["cell-1"]);
+        assert!(projection.get_cell("cell-2").is_none());
+        assert!(projection.get_cell_outputs("cell-2").is_empty());
+        assert_eq!(projection.total_cell_count(), 1);
+
+        let tombstones = projection.get_document_cells_including_deleted("doc-1");
+        assert_eq!(ids(tombstones.clone()), vec!["cell-1", "cell-2"]);
+        assert!(tombstones[1].deleted);
+        assert_eq!(tombstones[1].source, "# cell-2");
+
+        let mut restore = restore_cell_event("doc-1".to_string(), "cell-2".to_string(), 5).unwrap();
+        restore.timestamp = 2000;
+        projection.apply_new_events(&[restore]).unwrap();
+
+        let restored = projection.get_cell("cell-2").unwrap();
+        assert!(!restored.deleted);
+        assert_eq!(restored.updated_at, 2000);
+        assert_eq!(projection.get_cell_outputs("cell-2").len(), 1);
+        assert_eq!(
+            ids(projection.get_document_cells("doc-1")),
+            vec!["cell-1", "cell-2"]
+        );
+    }
+
+    #[test]
+    fn test_inverse_event_round_trips_edits_and_moves() {
+        let created = create_cell_event(
+            "doc-1".to_string(),
+            "cell-1".to_string(),
+            CellType::Code,
+            "before".to_string(),
+            Some("a0".to_string()),
+            "alice".to_string(),
+            1,
+        )
+        .unwrap();
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&[created]).unwrap();
+
+        let edits = [
+            update_cell_source_event(
+                "doc-1".to_string(),
+                "cell-1".to_string(),
+                "after".to_string(),
+                2,
+            )
+            .unwrap(),
+            move_cell_event(
+                "doc-1".to_string(),
+                "cell-1".to_string(),
+                "a5".to_string(),
+                2,
+            )
+            .unwrap(),
+        ];
+        for edit in edits {
+            let state = projection.get_state();
+            let undo = inverse_event(state, &edit).unwrap();
+            assert_eq!(undo.event_type, edit.event_type);
+            assert_eq!(undo.version, edit.version + 1);
+
+            let edited = DocumentMaterializer::apply_event(state, &edit).unwrap();
+            assert_ne!(edited.cells["cell-1"], state.cells["cell-1"]);
+            let undone = DocumentMaterializer::apply_event(&edited, &undo).unwrap();
+
+            let (before, after) = (&state.cells["cell-1"], &undone.cells["cell-1"]);
+            assert_eq!(after.source, before.source);
+            assert_eq!(after.fractional_index, before.fractional_index);
+        }
+
+        // Runtime-produced events have no inverse
+        let output =
+            clear_cell_outputs_event("doc-1".to_string(), "cell-1".to_string(), 2).unwrap();
+        assert!(inverse_event(projection.get_state(), &output).is_none());
+    }
+
+    #[test]
+    fn test_invalid_fractional_index_rejected() {
+        let moved = move_cell_event(
+            "doc-1".to_string(),
+            "cell-1".to_string(),
+            "a@".to_string(),
+            1,
+        )
+        .unwrap();
+        assert_eq!(
+            DocumentMaterializer::apply_event(&DocumentProjectionState::default(), &moved)
+                .unwrap_err(),
+            EventError::ValidationError("Invalid character in fractional index: @".to_string())
+        );
+    }
+
+    #[test]
+    fn test_cell_output_appended_concatenates_chunks() {
+        let created = crate::EventBuilder::new()
+            .event_type("CellOutputCreated")
+            .aggregate_id("doc-1")
+            .payload(serde_json::json!({
+                "output_id": "out-1",
+                "cell_id": "cell-1",
+                "output_type": "terminal",
+                "stream_name": "stdout",
+                "data": "",
+            }))
+            .unwrap()
+            .build(1)
+            .unwrap();
+        let chunk = |data: &str, version: i64| {
+            append_cell_output_event(
+                "doc-1".to_string(),
+                "cell-1".to_string(),
+                "out-1".to_string(),
+                data.to_string(),
+                version,
+            )
+            .unwrap()
+        };
+
+        let mut projection = DocumentProjection::new();
+        projection
+            .rebuild_from_events(&[created, chunk("hello ", 2), chunk("world\n", 3)])
+            .unwrap();
+
+        let outputs = projection.get_cell_outputs("cell-1");
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].data.as_deref(), Some("hello world\n"));
+        assert_eq!(outputs[0].stream_name.as_deref(), Some("stdout"));
+    }
+
+    #[test]
+    fn test_document_cells_range_tiles_full_order() {
+        let mut events = vec![create_document_event(
+            "doc-123".to_string(),
+            "Test Document".to_string(),
+            DocumentMetadata::default(),
+            1,
+        )
+        .unwrap()];
+        // Colliding and missing indices exercise every tie-break
+        let indices = ["m", "c", "c", "x", "a", "", "m", "b", "", "q", "c"];
+        for (i, index) in indices.iter().enumerate() {
+            events.push(
+                create_cell_event(
+                    "doc-123".to_string(),
+                    format!("cell-{:02}", i),
+                    CellType::Code,
+                    String::new(),
+                    (!index.is_empty()).then(|| index.to_string()),
+                    "user-1".to_string(),
+                    events.len() as i64 + 1,
+                )
+                .unwrap(),
+            );
+        }
+        events.push(
+            crate::EventBuilder::new()
+                .event_type("CellDeleted")
+                .aggregate_id("doc-123")
+                .payload(serde_json::json!({"cell_id": "cell-03"}))
+                .unwrap()
+                .build(events.len() as i64 + 1)
+                .unwrap(),
+        );
+
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+        let ids = |cells: Vec<&Cell>| -> Vec<String> {
+            cells.into_iter().map(|cell| cell.id.clone()).collect()
+        };
+        let full = ids(projection.get_document_cells("doc-123"));
+        assert_eq!(full.len(), 10);
+        assert_eq!(projection.document_cell_count("doc-123"), 10);
+
+        for limit in 1..=4 {
+            let mut tiled = Vec::new();
+            for offset in (0..full.len()).step_by(limit) {
+                let window = ids(projection.get_document_cells_range("doc-123", offset, limit));
+                assert!(window.len() <= limit);
+                tiled.extend(window);
+            }
+            assert_eq!(tiled, full, "limit {}", limit);
+        }
+        assert!(projection
+            .get_document_cells_range("doc-123", full.len(), 5)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_creation_order_ignores_moves() {
+        let mut events = vec![create_document_event(
+            "doc-123".to_string(),
+            "Test Document".to_string(),
+            DocumentMetadata::default(),
+            1,
+        )
+        .unwrap()];
+        for (i, (cell_id, index)) in [("cell-1", "a"), ("cell-2", "b"), ("cell-3", "c")]
+            .into_iter()
+            .enumerate()
+        {
+            let mut event = create_cell_event(
+                "doc-123".to_string(),
+                cell_id.to_string(),
+                CellType::Code,
+                String::new(),
+                Some(index.to_string()),
+                "user-1".to_string(),
+                i as i64 + 2,
+            )
+            .unwrap();
+            event.timestamp = 1000 + i as i64;
+            events.push(event);
+        }
+        // Move the last cell to the top
+        let mut moved = move_cell_event(
+            "doc-123".to_string(),
+            "cell-3".to_string(),
+            "0".to_string(),
+            5,
+        )
+        .unwrap();
+        moved.timestamp = 1010;
+        events.push(moved);
+
+        let mut projection = DocumentProjection::new();
+        projection.rebuild_from_events(&events).unwrap();
+        let ids = |cells: Vec<&Cell>| -> Vec<String> {
+            cells.into_iter().map(|cell| cell.id.clone()).collect()
+        };
+
+        assert_eq!(
+            ids(projection.get_document_cells("doc-123")),
+            vec!["cell-3", "cell-1", "cell-2"]
+        );
+        assert_eq!(
+            ids(projection.get_document_cells_in_creation_order("doc-123")),
+            vec!["cell-1", "cell-2", "cell-3"]
+        );
+    }
+
+    #[test]
+    fn test_reorder_cells_moves_fewest_cells() {
+        let index = |s: &str| FractionalIndex::new(s).unwrap();
+        let indices = [index("a0"), index("a1"), index("a2"), index("a3")];
+        let cell_ids = ["cell-0", "cell-1", "cell-2", "cell-3"];
+        let current: Vec<(&str, Option<&FractionalIndex>)> = cell_ids
+            .iter()
+            .copied()
+            .zip(indices.iter().map(Some))
+            .collect();
+
+        // Apply the moves and read the resulting order back
+        let reorder = |desired: &[&str]| {
+            let events = reorder_cells("doc-1", &current, desired, 10).unwrap();
+            let mut new_indices: HashMap<&str, FractionalIndex> = current
+                .iter()
+                .map(|(id, index)| (*id, (*index).unwrap().clone()))
+                .collect();
+            for (event, version) in events.iter().zip(10..) {
+                assert_eq!(event.event_type, "CellMoved");
+                assert_eq!(event.version, version);
+                let cell_id = event.payload["cell_id"].as_str().unwrap();
+                let index = event.payload["fractional_index"].as_str().unwrap();
+                new_indices.insert(cell_id, FractionalIndex::new(index).unwrap());
+            }
+            let mut order: Vec<&str> = cell_ids.to_vec();
+            order.sort_by_key(|id| new_indices[id].clone());
+            assert_eq!(order, desired);
+            events
+        };
+
+        let to_front = reorder(&["cell-3", "cell-0", "cell-1", "cell-2"]);
+        assert_eq!(to_front.len(), 1);
+        assert_eq!(to_front[0].payload["cell_id"], "cell-3");
+
+        let to_end = reorder(&["cell-1", "cell-2", "cell-3", "cell-0"]);
+        assert_eq!(to_end.len(), 1);
+        assert_eq!(to_end[0].payload["cell_id"], "cell-0");
+
+        let swapped = reorder(&["cell-0", "cell-2", "cell-1", "cell-3"]);
+        assert_eq!(swapped.len(), 1);
+
+        assert!(reorder(&cell_ids).is_empty());
+        assert_eq!(reorder(&["cell-3", "cell-2", "cell-1", "cell-0"]).len(), 3);
+
+        // The new order has to name every cell exactly once
+        for desired in [
+            vec!["cell-0", "cell-1", "cell-2"],
+            vec!["cell-0", "cell-1", "cell-2", "cell-2"],
+            vec!["cell-0", "cell-1", "cell-2", "cell-9"],
+        ] {
+            assert!(reorder_cells("doc-1", &current, &desired, 10).is_err());
+        }
+    }
+
+    #[test]
+    fn test_reorder_cells_keeps_indices_short() {
+        // Cells without indices all need one; bisecting keeps them short
+        let cell_ids: Vec<String> = (0..64).map(|i| format!("cell-{}", i)).collect();
+        let current: Vec<(&str, Option<&FractionalIndex>)> =
+            cell_ids.iter().map(|id| (id.as_str(), None)).collect();
+        let desired: Vec<&str> = cell_ids.iter().map(String::as_str).collect();
+
+        let events = reorder_cells("doc-1", &current, &desired, 1).unwrap();
+        assert_eq!(events.len(), 64);
+        let indices: Vec<String> = events
+            .iter()
+            .map(|e| e.payload["fractional_index"].as_str().unwrap().to_string())
+            .collect();
+        assert!(indices.windows(2).all(|w| w[0] < w[1]));
+        assert!(
+            indices.iter().all(|index| index.len() <= 4),
+            "{:?}",
+            indices
+        );
+    }
+
+    #[test]
+    fn test_metadata_patch_events_commute() {
+        let doc = || "doc-1".to_string();
+        let metadata = DocumentMetadata {
+            authors: vec!["alice".to_string()],
+            ..Default::default()
+        };
+        let created = create_document_event(doc(), "Notebook".to_string(), metadata, 1).unwrap();
+        let patches = vec![
+            add_document_tag_event(doc(), "draft".to_string(), 2).unwrap(),
+            add_document_tag_event(doc(), "ml".to_string(), 3).unwrap(),
+            set_document_custom_field_event(
+                doc(),
+                "owner".to_string(),
+                Some("data".to_string()),
+                4,
+            )
+            .unwrap(),
+        ];
+
+        // Two writers' edits land in either order and both survive
+        for order in [[0, 1, 2], [2, 1, 0]] {
+            let mut events = vec![created.clone()];
+            events.extend(order.iter().map(|&i| patches[i].clone()));
+            let mut projection = DocumentProjection::new();
+            projection.rebuild_from_events(&events).unwrap();
+
+            let metadata = &projection.get_document("doc-1").unwrap().metadata;
+            let mut tags = metadata.tags.clone();
+            tags.sort();
+            assert_eq!(tags, vec!["draft", "ml"]);
+            assert_eq!(metadata.custom["owner"], "data");
+            assert_eq!(metadata.authors, vec!["alice"]);
+        }
+
+        let mut projection = DocumentProjection::new();
+        let mut events = vec![created];
+        events.extend(patches);
+        projection.rebuild_from_events(&events).unwrap();
+
+        // Adding a tag twice keeps one copy, and undo puts things back
+        let again = add_document_tag_event(doc(), "ml".to_string(), 5).unwrap();
+        let state = DocumentMaterializer::apply_event(projection.get_state(), &again).unwrap();
+        assert_eq!(state.documents["doc-1"].metadata.tags, vec!["draft", "ml"]);
+        assert_eq!(
+            inverse_event(projection.get_state(), &again)
+                .unwrap()
+                .event_type,
+            "DocumentTagAdded"
+        );
+
+        let edits = [
+            remove_document_tag_event(doc(), "draft".to_string(), 5).unwrap(),
+            set_document_custom_field_event(doc(), "owner".to_string(), None, 5).unwrap(),
+            set_document_custom_field_event(doc(), "team".to_string(), Some("ai".to_string()), 5)
+                .unwrap(),
+        ];
+        for edit in edits {
+            let state = projection.get_state();
+            let undo = inverse_event(state, &edit).unwrap();
+            let edited = DocumentMaterializer::apply_event(state, &edit).unwrap();
+            assert_ne!(
+                edited.documents["doc-1"].metadata,
+                state.documents["doc-1"].metadata
+            );
+            let undone = DocumentMaterializer::apply_event(&edited, &undo).unwrap();
+            // A restored tag goes back on the end
+            let sorted = |state: &DocumentProjectionState| {
+                let mut metadata = state.documents["doc-1"].metadata.clone();
+                metadata.tags.sort();
+                metadata
+            };
+            assert_eq!(sorted(&undone), sorted(state));
+        }
+    }
+
+    #[test]
+    fn test_custom_field_value_must_be_string_or_null() {
+        let created = create_document_event(
+            "doc-1".to_string(),
+            "Notebook".to_string(),
+            DocumentMetadata::default(),
+            1,
+        )
+        .unwrap();
+        let state = DocumentMaterializer::apply_event(&Default::default(), &created).unwrap();
+        let set = |payload: serde_json::Value| {
+            let event = crate::EventBuilder::new()
+                .event_type("DocumentCustomFieldSet")
+                .aggregate_id("doc-1")
+                .payload(payload)
+                .unwrap()
+                .build(2)
+                .unwrap();
+            DocumentMaterializer::apply_event(&state, &event)
+        };
+
+        assert!(matches!(
+            set(serde_json::json!({"key": "owner"})),
+            Err(EventError::ValidationError(_))
+        ));
+        assert!(matches!(
+            set(serde_json::json!({"key": "owner", "value": 42})),
+            Err(EventError::ValidationError(_))
+        ));
+        let state = set(serde_json::json!({"key": "owner", "value": "data"})).unwrap();
+        assert_eq!(state.documents["doc-1"].metadata.custom["owner"], "data");
+    }
+
+    #[test]
+    fn test_stale_source_update_ignored() {
+        let source_event = |source: &str, timestamp: i64, version: i64| {
+            let mut event = update_cell_source_event(
+                "doc-1".to_string(),
+                "cell-1".to_string(),
+                source.to_string(),
+                version,
+            )
+            .unwrap();
+            event.timestamp = timestamp;
+            event
+        };
+        let mut created = create_cell_event(
+            "doc-1".to_string(),
+            "cell-1".to_string(),
+            CellType::Code,
+            "original".to_string(),
+            None,
+            "alice".to_string(),
+            1,
+        )
+        .unwrap();
+        created.timestamp = 1000;
+        let newer = source_event("newer", 3000, 2);
+        let older = source_event("older", 2000, 3);
+
+        // Either replay order settles on the newer edit
+        for edits in [[&newer, &older], [&older, &newer]] {
+            let mut state =
+                DocumentMaterializer::apply_event(&DocumentMaterializer::initial_state(), &created)
+                    .unwrap();
+            for edit in edits {
+                state = DocumentMaterializer::apply_event(&state, edit).unwrap();
+            }
+            let cell = &state.cells["cell-1"];
+            assert_eq!(cell.source, "newer");
+            assert_eq!(cell.source_updated_at, 3000);
+        }
+
+        // An explicit edit time beats the event time
+        let mut late = source_event("stale offline edit", 4000, 4);
+        late.payload["edited_at"] = 2500.into();
+        let mut state =
+            DocumentMaterializer::apply_event(&DocumentMaterializer::initial_state(), &created)
+                .unwrap();
+        for edit in [&newer, &late] {
+            state = DocumentMaterializer::apply_event(&state, edit).unwrap();
+        }
+        assert_eq!(state.cells["cell-1"].source, "newer");
+
+        // Edits to other fields don't count towards the source's age
+        let mut hidden = change_cell_visibility_event(
+            "doc-1".into(),
+            "cell-1".into(),
+            Some(false),
+            None,
+            None,
+            2,
+        )
+        .unwrap();
+        hidden.timestamp = 4000;
+        let state =
+            DocumentMaterializer::apply_event(&DocumentMaterializer::initial_state(), &created)
+                .unwrap();
+        let state = DocumentMaterializer::apply_event(&state, &hidden).unwrap();
+        let state = DocumentMaterializer::apply_event(&state, &older).unwrap();
+        assert_eq!(state.cells["cell-1"].source, "older");
+    }
 }
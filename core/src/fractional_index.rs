@@ -4,6 +4,8 @@
 //! lexicographic ordering and allow for conflict-free insertion of items at
 //! arbitrary positions by different clients.
 
+use serde::{Deserialize, Serialize};
+
 /// Characters used in fractional indices, ordered lexicographically
 const CHARS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
 const BASE: usize = CHARS.len();
@@ -36,6 +38,69 @@ impl std::error::Error for FractionalIndexError {}
 
 pub type Result<T> = std::result::Result<T, FractionalIndexError>;
 
+/// A fractional index checked to contain only valid characters
+///
+/// Orders the same way as the underlying string, which is the order of
+/// [`CHARS`]. Serializes as a plain string; deserializing trusts its input,
+/// so values from outside the materializer should go through
+/// [`FractionalIndex::new`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FractionalIndex(String);
+
+impl FractionalIndex {
+    /// Wrap a string, rejecting it if [`validate_index`] fails
+    pub fn new<S: Into<String>>(index: S) -> Result<Self> {
+        let index = index.into();
+        validate_index(&index)?;
+        Ok(Self(index))
+    }
+
+    /// The first index, see [`initial`]
+    pub fn initial() -> Self {
+        Self(initial())
+    }
+
+    /// An index strictly between `self` and `other`, see [`between`]
+    pub fn between(&self, other: &FractionalIndex) -> Result<Self> {
+        between(&self.0, &other.0).map(Self)
+    }
+
+    /// An index sorting before `self`, see [`before`]
+    pub fn before(&self) -> Result<Self> {
+        before(&self.0).map(Self)
+    }
+
+    /// An index sorting after `self`, see [`after`]
+    pub fn after(&self) -> Result<Self> {
+        after(&self.0).map(Self)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for FractionalIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::str::FromStr for FractionalIndex {
+    type Err = FractionalIndexError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::new(s)
+    }
+}
+
+impl From<FractionalIndex> for String {
+    fn from(index: FractionalIndex) -> Self {
+        index.0
+    }
+}
+
 /// Generate the first fractional index
 pub fn initial() -> String {
     "a0".to_string()
@@ -65,6 +130,41 @@ pub fn between(a: &str, b: &str) -> Result<String> {
     Ok(from_digits(&mid_digits))
 }
 
+/// Number of random characters `between_jittered` appends
+const JITTER_LEN: usize = 4;
+
+/// Generate a fractional index between two indices, with a random suffix
+///
+/// Two offline clients inserting between the same neighbours would get the
+/// same index from [`between`]; the suffix, derived from `rng_seed`, keeps
+/// their indices distinct. The result still satisfies `a < result < b`:
+/// `between` never returns a prefix of `b`, so extending it stays below `b`.
+pub fn between_jittered(a: &str, b: &str, rng_seed: u64) -> Result<String> {
+    let mut digits = to_digits(&between(a, b)?)?;
+
+    let mut state = rng_seed;
+    for i in 0..JITTER_LEN {
+        let value = (splitmix64(&mut state) % BASE as u64) as usize;
+        // Keep the last character non-zero so there's room below the result
+        digits.push(if i == JITTER_LEN - 1 && value == 0 {
+            1
+        } else {
+            value
+        });
+    }
+
+    Ok(from_digits(&digits))
+}
+
+/// Advance a SplitMix64 generator, returning the next value
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
 /// Generate a fractional index before the given index
 pub fn before(index: &str) -> Result<String> {
     validate_index(index)?;
@@ -73,21 +173,21 @@ pub fn before(index: &str) -> Result<String> {
         return Ok("a0".to_string());
     }
 
-    // If we can decrement the last character, do so
+    // If we can decrement the last character, do so, unless that would leave
+    // a trailing `0`; repeated prepends would otherwise bottom out at "0",
+    // which nothing sorts before
     let mut chars: Vec<char> = index.chars().collect();
     if let Some(last_char) = chars.last_mut() {
-        if let Some(prev_char) = get_previous_char(*last_char) {
+        if let Some(prev_char) = get_previous_char(*last_char).filter(|&c| c != char_at(0)) {
             *last_char = prev_char;
             return Ok(chars.into_iter().collect());
         }
     }
 
-    // If we can't decrement, we need to go to the previous "level"
-    // This is more complex, so we'll use a simpler approach
-    // by finding midpoint between empty string and current index
-    let empty_digits = vec![0]; // Represents empty/minimal index
+    // If we can't decrement, find something between the empty string
+    // (which sorts before every index) and the current index
     let index_digits = to_digits(index)?;
-    let mid_digits = midpoint(&empty_digits, &index_digits)?;
+    let mid_digits = midpoint(&[], &index_digits)?;
 
     Ok(from_digits(&mid_digits))
 }
@@ -181,39 +281,45 @@ fn from_digits(digits: &[usize]) -> String {
     digits.iter().map(|&pos| char_at(pos)).collect()
 }
 
-/// Find the midpoint between two digit arrays
+/// Find a digit array that sorts strictly between two others
+///
+/// Walks both arrays in step, copying `a`'s digits while there is no room
+/// between the bounds at that position. Once a copied digit is below `b`'s,
+/// the prefix is already less than `b` and only `a` constrains what follows.
+/// Past the end of `a` any digit works as a lower bound, since a longer
+/// string sorts after its prefix. The chosen digit is always above the lower
+/// bound, so results never end in `0` and always leave room for later inserts.
 fn midpoint(a: &[usize], b: &[usize]) -> Result<Vec<usize>> {
-    let max_len = a.len().max(b.len());
     let mut result = Vec::new();
-    let _carry = 0;
-
-    for i in 0..max_len {
-        let a_digit = if i < a.len() { a[i] } else { 0 };
-        let b_digit = if i < b.len() { b[i] } else { BASE - 1 };
+    let mut below_b = false;
 
-        if a_digit == b_digit {
-            result.push(a_digit);
-            continue;
-        }
-
-        if a_digit + 1 == b_digit {
-            // Adjacent digits - we need to go deeper
-            result.push(a_digit);
-            // Add a midpoint digit
-            let mid = (BASE - 1) / 2;
-            result.push(mid);
-            break;
+    for i in 0.. {
+        let lo = a.get(i).copied().unwrap_or(0);
+        let hi = if below_b {
+            BASE
         } else {
-            // Non-adjacent digits - we can find a midpoint
-            let mid = (a_digit + b_digit) / 2;
-            result.push(mid);
+            match b.get(i) {
+                Some(&digit) => digit,
+                // Everything we could produce from here starts with `b`
+                None => {
+                    return Err(FractionalIndexError::CannotGenerate(format!(
+                        "No index fits between '{}' and '{}'",
+                        from_digits(a),
+                        from_digits(b)
+                    )))
+                }
+            }
+        };
+
+        if hi > lo + 1 {
+            result.push((lo + hi) / 2);
             break;
         }
-    }
 
-    // Ensure we have a valid result
-    if result.is_empty() {
-        result.push(BASE / 2);
+        result.push(lo);
+        if hi == lo + 1 {
+            below_b = true;
+        }
     }
 
     Ok(result)
@@ -229,19 +335,59 @@ pub fn generate_sequence(count: usize) -> Vec<String> {
 
     for _ in 1..count {
         let last = result.last().unwrap();
-        match after(last) {
-            Ok(next) => result.push(next),
-            Err(_) => {
-                // Fallback: use base conversion
-                let index = result.len();
-                result.push(format!("z{}", index));
-            }
-        }
+        let next = after(last).unwrap_or_else(|_| {
+            // Fallback: lengthen the index, which always sorts after its prefix
+            let mut next = last.clone();
+            next.push(char_at(1));
+            next
+        });
+        result.push(next);
     }
 
     result
 }
 
+/// Generate `count` indices spread evenly across the key space
+///
+/// Unlike [`generate_sequence`], which packs indices next to each other, this
+/// leaves room between neighbours so later inserts stay short. Useful when
+/// reassigning the indices of an existing list.
+pub fn generate_sequence_spread(count: usize) -> Vec<String> {
+    if count == 0 {
+        return Vec::new();
+    }
+
+    // Pick a width with at least one free slot between neighbours
+    let slots = (count as u128 + 1) * 2;
+    let mut width = 2;
+    let mut space = (BASE as u128).pow(width);
+    while space < slots {
+        width += 1;
+        space *= BASE as u128;
+    }
+
+    (1..=count as u128)
+        .map(|i| {
+            let mut value = i * space / (count as u128 + 1);
+            let mut digits = vec![0; width as usize];
+            for digit in digits.iter_mut().rev() {
+                *digit = (value % BASE as u128) as usize;
+                value /= BASE as u128;
+            }
+            from_digits(&digits)
+        })
+        .collect()
+}
+
+/// Replace an ordered list of indices with short, evenly spaced ones
+///
+/// Repeated inserts at the same spot grow indices without bound; callers
+/// watching the longest key can swap in the result once it gets too long.
+/// The output has the same length and order as the input.
+pub fn rebalance(indices: &[String]) -> Vec<String> {
+    generate_sequence_spread(indices.len())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -321,6 +467,31 @@ mod tests {
         assert!(validate_index("@").is_err());
     }
 
+    #[test]
+    fn test_fractional_index_newtype() {
+        assert_eq!(
+            FractionalIndex::new("@"),
+            Err(FractionalIndexError::InvalidCharacter('@'))
+        );
+        assert!(matches!(
+            FractionalIndex::new(""),
+            Err(FractionalIndexError::InvalidIndex(_))
+        ));
+        assert!("a0@".parse::<FractionalIndex>().is_err());
+
+        let first = FractionalIndex::initial();
+        let later = first.after().unwrap();
+        let middle = first.between(&later).unwrap();
+        let earlier = first.before().unwrap();
+        assert!(earlier < first && first < middle && middle < later);
+        assert!(FractionalIndex::new("Z9").unwrap() < FractionalIndex::new("a0").unwrap());
+
+        // Wire format is the bare string
+        assert_eq!(serde_json::to_value(&first).unwrap(), "a0");
+        let decoded: FractionalIndex = serde_json::from_value("a0".into()).unwrap();
+        assert_eq!(decoded, first);
+    }
+
     #[test]
     fn test_complex_between() {
         // Test multiple levels of between operations
@@ -335,4 +506,104 @@ mod tests {
         assert!(is_valid_order(&indices));
         assert_eq!(indices.len(), 7);
     }
+
+    #[test]
+    fn test_generate_large_sequence() {
+        let indices = generate_sequence(10_000);
+        assert_eq!(indices.len(), 10_000);
+        assert!(is_valid_order(&indices));
+        assert!(indices.iter().all(|index| validate_index(index).is_ok()));
+    }
+
+    #[test]
+    fn test_generate_sequence_spread() {
+        for count in [1, 5, 100, 10_000] {
+            let sequence = generate_sequence_spread(count);
+            assert_eq!(sequence.len(), count);
+            assert!(is_valid_order(&sequence));
+            for pair in sequence.windows(2) {
+                assert!(validate_index(&pair[0]).is_ok());
+                assert!(between(&pair[0], &pair[1]).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn test_between_deep_insertions() {
+        let result = between("a0V", "a0W").unwrap();
+        assert!(result.as_str() > "a0V" && result.as_str() < "a0W");
+
+        let long_a = format!("a{}", "z".repeat(40));
+        let long_b = format!("b{}", "0".repeat(40));
+        let result = between(&long_a, &long_b).unwrap();
+        assert!(result > long_a && result < long_b);
+
+        // Nothing sorts strictly between an index and itself plus a trailing `0`
+        assert!(between("a0", "a00").is_err());
+    }
+
+    #[test]
+    fn test_repeated_insertion_keeps_order() {
+        // Always insert right after the lower bound
+        let mut indices = vec!["a0".to_string(), "a1".to_string()];
+        for _ in 0..1_000 {
+            let index = between(&indices[0], &indices[1]).unwrap();
+            indices.insert(1, index);
+            assert!(is_valid_order(&indices[..3]));
+        }
+        assert!(is_valid_order(&indices));
+
+        // Always insert right before the upper bound
+        let mut indices = vec!["a0".to_string(), "a1".to_string()];
+        for _ in 0..1_000 {
+            let last = indices.len() - 1;
+            let index = between(&indices[last - 1], &indices[last]).unwrap();
+            indices.insert(last, index);
+            assert!(is_valid_order(&indices[last - 1..]));
+        }
+        assert!(is_valid_order(&indices));
+
+        // Keep prepending
+        let mut indices = vec!["a0".to_string()];
+        for _ in 0..1_000 {
+            let index = before(&indices[0]).unwrap();
+            indices.insert(0, index);
+        }
+        assert!(is_valid_order(&indices));
+    }
+
+    #[test]
+    fn test_between_jittered() {
+        let indices: std::collections::HashSet<String> = (0..100)
+            .map(|seed| between_jittered("a0", "a1", seed).unwrap())
+            .collect();
+        assert_eq!(indices.len(), 100);
+        for index in &indices {
+            assert!(index.as_str() > "a0" && index.as_str() < "a1");
+            assert!(validate_index(index).is_ok());
+        }
+
+        // The same seed reproduces the same index
+        assert_eq!(
+            between_jittered("a0", "b0", 7).unwrap(),
+            between_jittered("a0", "b0", 7).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_rebalance() {
+        // Pathological input: every insert lands just after the first index
+        let mut indices = vec!["a0".to_string(), "a1".to_string()];
+        for _ in 0..200 {
+            let index = between(&indices[0], &indices[1]).unwrap();
+            indices.insert(1, index);
+        }
+        let longest = |indices: &[String]| indices.iter().map(String::len).max().unwrap();
+        assert!(longest(&indices) > 10);
+
+        let rebalanced = rebalance(&indices);
+        assert_eq!(rebalanced.len(), indices.len());
+        assert!(is_valid_order(&rebalanced));
+        assert!(longest(&rebalanced) < longest(&indices));
+    }
 }
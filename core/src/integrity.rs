@@ -0,0 +1,207 @@
+//! Consistency checks for event logs received from elsewhere
+//!
+//! [`InMemoryEventStore`](crate::InMemoryEventStore) refuses gaps and
+//! duplicates on append, but a log synced from a peer or read off disk may
+//! not have gone through it. [`check_integrity`] reports what's wrong with
+//! such a log without rejecting it outright.
+
+use crate::snapshot::{snapshot_versions, SNAPSHOT_EVENT_TYPE};
+use crate::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// One problem found in an event log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum IntegrityIssue {
+    /// Versions `expected..found` are missing from the aggregate
+    VersionGap {
+        aggregate_id: String,
+        expected: i64,
+        found: i64,
+    },
+    /// Several events claim the same version of an aggregate
+    DuplicateVersion {
+        aggregate_id: String,
+        version: i64,
+        event_ids: Vec<String>,
+    },
+    /// An id already used by an earlier event in the log
+    DuplicateEventId {
+        aggregate_id: String,
+        event_id: String,
+    },
+    /// A version stamped earlier than the version before it
+    TimestampOutOfOrder {
+        aggregate_id: String,
+        version: i64,
+        timestamp: i64,
+        previous_timestamp: i64,
+    },
+    /// The log couldn't be read at all
+    Unreadable { error: String },
+}
+
+/// Find every gap, duplicate and out-of-order timestamp in a log
+///
+/// Each aggregate is checked in version order, starting from version 1 or,
+/// if a snapshot replaced its earlier events, from the version after the one
+/// the snapshot records. Issues are grouped by aggregate, in order of each
+/// aggregate's first appearance in `events`.
+pub fn check_integrity<'a>(events: impl IntoIterator<Item = &'a Event>) -> Vec<IntegrityIssue> {
+    let mut issues = Vec::new();
+
+    let mut seen_ids = HashSet::new();
+    let mut base_versions: HashMap<&str, i64> = HashMap::new();
+    let mut aggregate_ids = Vec::new();
+    let mut by_aggregate: HashMap<&str, Vec<&Event>> = HashMap::new();
+    for event in events {
+        if !seen_ids.insert(event.id.as_str()) {
+            issues.push(IntegrityIssue::DuplicateEventId {
+                aggregate_id: event.aggregate_id.clone(),
+                event_id: event.id.clone(),
+            });
+        }
+        if event.event_type == SNAPSHOT_EVENT_TYPE {
+            for (aggregate_id, version) in snapshot_versions(event) {
+                let base = base_versions.entry(aggregate_id).or_default();
+                *base = (*base).max(version);
+            }
+        }
+        by_aggregate
+            .entry(&event.aggregate_id)
+            .or_insert_with(|| {
+                aggregate_ids.push(event.aggregate_id.as_str());
+                Vec::new()
+            })
+            .push(event);
+    }
+
+    for aggregate_id in aggregate_ids {
+        let mut aggregate_events = by_aggregate.remove(aggregate_id).unwrap_or_default();
+        aggregate_events.sort_by_key(|event| event.version);
+
+        let first_version = aggregate_events[0].version;
+        let mut expected = match base_versions.get(aggregate_id) {
+            Some(&base) if first_version > base => base + 1,
+            _ => 1,
+        };
+        let mut previous: Option<&Event> = None;
+        for group in aggregate_events.chunk_by(|a, b| a.version == b.version) {
+            let event = group[0];
+            if event.version > expected {
+                issues.push(IntegrityIssue::VersionGap {
+                    aggregate_id: aggregate_id.to_string(),
+                    expected,
+                    found: event.version,
+                });
+            }
+            if group.len() > 1 {
+                issues.push(IntegrityIssue::DuplicateVersion {
+                    aggregate_id: aggregate_id.to_string(),
+                    version: event.version,
+                    event_ids: group.iter().map(|e| e.id.clone()).collect(),
+                });
+            }
+            if let Some(previous) = previous.filter(|p| event.timestamp < p.timestamp) {
+                issues.push(IntegrityIssue::TimestampOutOfOrder {
+                    aggregate_id: aggregate_id.to_string(),
+                    version: event.version,
+                    timestamp: event.timestamp,
+                    previous_timestamp: previous.timestamp,
+                });
+            }
+            expected = event.version + 1;
+            previous = Some(event);
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EventBuilder;
+
+    fn event(aggregate_id: &str, version: i64, timestamp: i64) -> Event {
+        EventBuilder::new()
+            .event_type("DocumentTitleUpdated")
+            .aggregate_id(aggregate_id)
+            .payload(serde_json::json!({"title": "Title"}))
+            .unwrap()
+            .timestamp(timestamp)
+            .build(version)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_clean_log_has_no_issues() {
+        let events = vec![
+            event("doc-1", 1, 10),
+            event("doc-2", 1, 11),
+            event("doc-1", 2, 12),
+        ];
+        assert!(check_integrity(&events).is_empty());
+    }
+
+    #[test]
+    fn test_gapped_log() {
+        let events = vec![
+            event("doc-1", 1, 10),
+            event("doc-1", 2, 11),
+            event("doc-1", 4, 12),
+            event("doc-2", 3, 13),
+        ];
+        assert_eq!(
+            check_integrity(&events),
+            vec![
+                IntegrityIssue::VersionGap {
+                    aggregate_id: "doc-1".to_string(),
+                    expected: 3,
+                    found: 4,
+                },
+                IntegrityIssue::VersionGap {
+                    aggregate_id: "doc-2".to_string(),
+                    expected: 1,
+                    found: 3,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicated_log() {
+        let first = event("doc-1", 1, 10);
+        let rival = event("doc-1", 2, 11);
+        let second = event("doc-1", 2, 12);
+        let mut late = event("doc-1", 3, 5);
+        late.id = first.id.clone();
+        let events = vec![first.clone(), rival.clone(), second.clone(), late];
+
+        assert_eq!(
+            check_integrity(&events),
+            vec![
+                IntegrityIssue::DuplicateEventId {
+                    aggregate_id: "doc-1".to_string(),
+                    event_id: first.id.clone(),
+                },
+                IntegrityIssue::DuplicateVersion {
+                    aggregate_id: "doc-1".to_string(),
+                    version: 2,
+                    event_ids: vec![rival.id, second.id],
+                },
+                IntegrityIssue::TimestampOutOfOrder {
+                    aggregate_id: "doc-1".to_string(),
+                    version: 3,
+                    timestamp: 5,
+                    previous_timestamp: 11,
+                },
+            ]
+        );
+
+        let json = serde_json::to_value(&check_integrity(&events)[0]).unwrap();
+        assert_eq!(json["kind"], "duplicate_event_id");
+        assert_eq!(json["aggregate_id"], "doc-1");
+    }
+}
@@ -1,8 +1,19 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+#[cfg(feature = "tokio")]
+pub mod broadcast;
+pub mod comment;
 pub mod document;
 pub mod fractional_index;
+pub mod integrity;
+pub mod presence;
+pub mod projections;
+pub mod schema;
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod upcast;
 
 /// Core event structure for event sourcing
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -13,6 +24,13 @@ pub struct Event {
     pub payload: serde_json::Value,
     pub timestamp: i64,
     pub version: i64,
+    /// Groups events from one logical user action into a single undo unit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction_id: Option<String>,
+    /// Who created the event, when known; events stored before this field
+    /// existed deserialize with `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
 }
 
 /// Result type for event operations
@@ -21,12 +39,18 @@ pub type EventResult<T> = Result<T, EventError>;
 /// Errors that can occur in event operations
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum EventError {
-    InvalidVersion { expected: i64, got: i64 },
+    InvalidVersion {
+        expected: i64,
+        got: i64,
+    },
     DuplicateEventId(String),
     InvalidEventType(String),
     InvalidAggregateId(String),
     SerializationError(String),
     ValidationError(String),
+    /// Several problems found at once, e.g. every missing field of an event
+    MultipleValidationErrors(Vec<String>),
+    StorageError(String),
 }
 
 impl std::fmt::Display for EventError {
@@ -40,6 +64,10 @@ impl std::fmt::Display for EventError {
             EventError::InvalidAggregateId(id) => write!(f, "Invalid aggregate ID: {}", id),
             EventError::SerializationError(msg) => write!(f, "Serialization error: {}", msg),
             EventError::ValidationError(msg) => write!(f, "Validation error: {}", msg),
+            EventError::MultipleValidationErrors(msgs) => {
+                write!(f, "Validation errors: {}", msgs.join("; "))
+            }
+            EventError::StorageError(msg) => write!(f, "Storage error: {}", msg),
         }
     }
 }
@@ -51,17 +79,69 @@ pub trait EventStore {
     /// Append an event to the store
     fn append_event(&mut self, event: Event) -> EventResult<()>;
 
+    /// Append a batch of events, all or nothing
+    ///
+    /// Each event's version is checked against its aggregate's latest version
+    /// including earlier events in the batch. If any event is rejected, none
+    /// are stored and the first rejection is returned.
+    fn append_events(&mut self, events: Vec<Event>) -> EventResult<()>;
+
+    /// Append a batch of events one by one, keeping those that succeed
+    ///
+    /// Returns each rejected event's index in the batch with its error. Later
+    /// events are checked against the store as left by earlier ones, so one
+    /// version gap also rejects the rest of that aggregate's events.
+    fn append_events_best_effort(&mut self, events: Vec<Event>) -> Vec<(usize, EventError)> {
+        events
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, event)| self.append_event(event).err().map(|e| (index, e)))
+            .collect()
+    }
+
     /// Get all events for a specific aggregate
     fn get_events(&self, aggregate_id: &str) -> EventResult<Vec<Event>>;
 
+    /// Get an aggregate's events with `version > after_version`, ordered by version
+    fn get_events_since_version(
+        &self,
+        aggregate_id: &str,
+        after_version: i64,
+    ) -> EventResult<Vec<Event>>;
+
     /// Get all events in the store
     fn get_all_events(&self) -> EventResult<Vec<Event>>;
 
+    /// Get every event of one type, ordered by `(timestamp, version)`
+    fn get_events_of_type(&self, event_type: &str) -> EventResult<Vec<Event>>;
+
+    /// Get events with `start_ts <= timestamp <= end_ts`, ordered by `(timestamp, version)`
+    ///
+    /// Both bounds are inclusive, so `start_ts == end_ts` selects a single
+    /// second and a range with `start_ts > end_ts` is empty.
+    fn get_events_between(&self, start_ts: i64, end_ts: i64) -> EventResult<Vec<Event>>;
+
     /// Get the latest version for an aggregate
     fn get_latest_version(&self, aggregate_id: &str) -> i64;
 
     /// Get total event count
     fn get_event_count(&self) -> usize;
+
+    /// Get every aggregate id with events in the store, sorted
+    fn get_aggregate_ids(&self) -> Vec<String>;
+
+    /// Look for version gaps, duplicates and out-of-order timestamps
+    ///
+    /// See [`check_integrity`]; a store that can't be read reports a single
+    /// [`IntegrityIssue::Unreadable`].
+    fn verify_integrity(&self) -> Vec<IntegrityIssue> {
+        match self.get_all_events() {
+            Ok(events) => check_integrity(&events),
+            Err(e) => vec![IntegrityIssue::Unreadable {
+                error: e.to_string(),
+            }],
+        }
+    }
 }
 
 /// Trait for materializing events into projections/views
@@ -102,6 +182,10 @@ pub struct EventBuilder {
     event_type: Option<String>,
     aggregate_id: Option<String>,
     payload: serde_json::Value,
+    timestamp: Option<i64>,
+    transaction_id: Option<String>,
+    actor: Option<String>,
+    deterministic_id: bool,
 }
 
 impl EventBuilder {
@@ -110,6 +194,10 @@ impl EventBuilder {
             event_type: None,
             aggregate_id: None,
             payload: serde_json::Value::Null,
+            timestamp: None,
+            transaction_id: None,
+            actor: None,
+            deterministic_id: false,
         }
     }
 
@@ -129,36 +217,108 @@ impl EventBuilder {
         Ok(self)
     }
 
+    /// Use an explicit timestamp instead of the current time
+    pub fn timestamp(mut self, timestamp: i64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Tag the event as part of a transaction
+    pub fn transaction<S: Into<String>>(mut self, transaction_id: S) -> Self {
+        self.transaction_id = Some(transaction_id.into());
+        self
+    }
+
+    /// Record who created the event
+    pub fn actor<S: Into<String>>(mut self, actor: S) -> Self {
+        self.actor = Some(actor.into());
+        self
+    }
+
+    /// Derive the id from the event's content instead of picking a random one
+    ///
+    /// Building the same `(aggregate_id, version, event_type, payload)` again
+    /// gives the same id, so appending a rebuilt copy to a store that already
+    /// holds the event fails with [`EventError::DuplicateEventId`] instead of
+    /// storing it twice. Timestamp, transaction and actor don't count towards
+    /// the id: a copy stamped later is the same event. This only helps where
+    /// the id is kept; the server assigns its own ids to submitted events.
+    pub fn deterministic_id(mut self) -> Self {
+        self.deterministic_id = true;
+        self
+    }
+
+    /// Build the event, checking every field before giving up
+    ///
+    /// A single problem comes back as its own error; several come back
+    /// together as [`EventError::MultipleValidationErrors`].
     pub fn build(self, version: i64) -> EventResult<Event> {
-        let event_type = self
-            .event_type
-            .ok_or_else(|| EventError::ValidationError("Event type is required".to_string()))?;
-        let aggregate_id = self
-            .aggregate_id
-            .ok_or_else(|| EventError::ValidationError("Aggregate ID is required".to_string()))?;
-
-        // Basic validation
-        if event_type.trim().is_empty() {
-            return Err(EventError::InvalidEventType(event_type));
+        let mut errors = Vec::new();
+
+        match &self.event_type {
+            None => errors.push(EventError::ValidationError(
+                "Event type is required".to_string(),
+            )),
+            Some(event_type) if event_type.trim().is_empty() => {
+                errors.push(EventError::InvalidEventType(event_type.clone()))
+            }
+            Some(event_type)
+                if document::requires_payload_fields(event_type) && !self.payload.is_object() =>
+            {
+                errors.push(EventError::ValidationError(format!(
+                    "{} requires a JSON object payload, got {}",
+                    event_type,
+                    if self.payload.is_null() {
+                        "null"
+                    } else {
+                        "a non-object value"
+                    }
+                )))
+            }
+            Some(_) => {}
         }
-        if aggregate_id.trim().is_empty() {
-            return Err(EventError::InvalidAggregateId(aggregate_id));
+        match &self.aggregate_id {
+            None => errors.push(EventError::ValidationError(
+                "Aggregate ID is required".to_string(),
+            )),
+            Some(aggregate_id) if aggregate_id.trim().is_empty() => {
+                errors.push(EventError::InvalidAggregateId(aggregate_id.clone()))
+            }
+            Some(_) => {}
         }
         if version < 1 {
-            return Err(EventError::InvalidVersion {
+            errors.push(EventError::InvalidVersion {
                 expected: 1,
                 got: version,
             });
         }
 
-        Ok(Event {
-            id: generate_event_id(),
-            event_type,
-            aggregate_id,
-            payload: self.payload,
-            timestamp: current_timestamp(),
-            version,
-        })
+        match (self.event_type, self.aggregate_id) {
+            (Some(event_type), Some(aggregate_id)) if errors.is_empty() => Ok(Event {
+                id: if self.deterministic_id {
+                    content_event_id(&aggregate_id, version, &event_type, &self.payload)
+                } else {
+                    generate_event_id()
+                },
+                event_type,
+                aggregate_id,
+                payload: self.payload,
+                timestamp: self.timestamp.unwrap_or_else(current_timestamp),
+                version,
+                transaction_id: self.transaction_id,
+                actor: self.actor,
+            }),
+            _ if errors.len() == 1 => Err(errors.remove(0)),
+            _ => Err(EventError::MultipleValidationErrors(
+                errors
+                    .iter()
+                    .map(|e| match e {
+                        EventError::ValidationError(msg) => msg.clone(),
+                        e => e.to_string(),
+                    })
+                    .collect(),
+            )),
+        }
     }
 }
 
@@ -169,88 +329,460 @@ impl Default for EventBuilder {
 }
 
 /// In-memory event store implementation for testing and simple use cases
+///
+/// Events are kept in append order, with indexes maintained on append so
+/// per-aggregate reads and duplicate checks don't scan the whole log.
 #[derive(Debug, Clone)]
 pub struct InMemoryEventStore {
     events: Vec<Event>,
-    version_map: HashMap<String, i64>,
+    /// Positions in `events` of each aggregate's events, in version order
+    aggregate_index: HashMap<String, Vec<usize>>,
+    /// Positions in `events` ordered by `(timestamp, version)`, except that
+    /// a snapshot sorts after every event sharing its timestamp; other ties
+    /// in append order
+    time_order: Vec<usize>,
+    event_ids: HashSet<String>,
+    /// Versions recorded by snapshots, for aggregates whose events may have
+    /// been compacted away
+    base_versions: HashMap<String, i64>,
+    /// Sequence numbers taken by events compacted away, so numbering carries
+    /// on past them
+    base_sequence: u64,
+    /// Timestamp of the newest snapshot
+    snapshot_timestamp: Option<i64>,
+    strict: bool,
+    registered_event_types: HashSet<String>,
 }
 
 impl InMemoryEventStore {
     pub fn new() -> Self {
         Self {
             events: Vec::new(),
-            version_map: HashMap::new(),
+            aggregate_index: HashMap::new(),
+            time_order: Vec::new(),
+            event_ids: HashSet::new(),
+            base_versions: HashMap::new(),
+            base_sequence: 0,
+            snapshot_timestamp: None,
+            strict: false,
+            registered_event_types: HashSet::new(),
         }
     }
-}
 
-impl Default for InMemoryEventStore {
-    fn default() -> Self {
-        Self::new()
+    /// Create a store that rejects event types it does not know about
+    ///
+    /// Known types are those the document, presence and comment materializers
+    /// handle, plus any added with [`InMemoryEventStore::register_event_type`].
+    pub fn strict() -> Self {
+        Self {
+            strict: true,
+            ..Self::new()
+        }
     }
-}
 
-impl EventStore for InMemoryEventStore {
-    fn append_event(&mut self, event: Event) -> EventResult<()> {
+    /// Allow an additional event type in strict mode
+    pub fn register_event_type<S: Into<String>>(&mut self, event_type: S) {
+        self.registered_event_types.insert(event_type.into());
+    }
+
+    /// Check whether an event type would be accepted by this store
+    pub fn accepts_event_type(&self, event_type: &str) -> bool {
+        !self.strict
+            || DocumentMaterializer::handles_event_type(event_type)
+            || PresenceMaterializer::handles_event_type(event_type)
+            || CommentMaterializer::handles_event_type(event_type)
+            || self.registered_event_types.contains(event_type)
+    }
+
+    /// Get the global sequence number of the most recently appended event
+    ///
+    /// Events are numbered from 1 in append order across all aggregates, so a
+    /// store with no events is at sequence 0. Compacting doesn't reuse
+    /// numbers: the snapshot takes the next one.
+    pub fn latest_sequence(&self) -> u64 {
+        self.base_sequence + self.events.len() as u64
+    }
+
+    /// Get up to `limit` events with sequence numbers after `after_seq`, in
+    /// append order
+    ///
+    /// Unlike offset pagination, a cursor taken from one page still points
+    /// at the same place after more events are appended.
+    pub fn get_events_after_sequence(
+        &self,
+        after_seq: u64,
+        limit: usize,
+    ) -> EventResult<Vec<Event>> {
+        Ok(self
+            .iter_events_after_sequence(after_seq)
+            .take(limit)
+            .map(|(_, event)| event.clone())
+            .collect())
+    }
+
+    /// Iterate the events with sequence numbers after `after_seq`, in append
+    /// order, paired with their sequence numbers
+    ///
+    /// A cursor from before a compaction resumes at the snapshot, which
+    /// stands in for the pruned events.
+    pub fn iter_events_after_sequence(
+        &self,
+        after_seq: u64,
+    ) -> impl Iterator<Item = (u64, &Event)> + '_ {
+        let start = (after_seq.saturating_sub(self.base_sequence) as usize).min(self.events.len());
+        (self.base_sequence + start as u64 + 1..).zip(&self.events[start..])
+    }
+
+    /// Get all events newest-first, the reverse of [`EventStore::get_all_events`]
+    pub fn get_all_events_desc(&self) -> EventResult<Vec<Event>> {
+        Ok(self.iter_events().rev().cloned().collect())
+    }
+
+    /// Iterate every event in `(timestamp, version)` order without cloning
+    ///
+    /// The same order as [`EventStore::get_all_events`], kept up to date on
+    /// append so reading it costs nothing extra.
+    pub fn iter_events(&self) -> impl DoubleEndedIterator<Item = &Event> + ExactSizeIterator {
+        self.time_order.iter().map(|&index| &self.events[index])
+    }
+
+    /// Get an aggregate's events with `from <= version <= to`, ordered by version
+    pub fn get_events_in_version_range(
+        &self,
+        aggregate_id: &str,
+        from: i64,
+        to: i64,
+    ) -> EventResult<Vec<Event>> {
+        Ok(self
+            .aggregate_events(aggregate_id)
+            .filter(|e| (from..=to).contains(&e.version))
+            .cloned()
+            .collect())
+    }
+
+    /// Timestamp of the newest snapshot in the store, if any
+    ///
+    /// Replays apply a snapshot after every event stamped at or before it,
+    /// and the snapshot replaces the projections wholesale. An event appended
+    /// after the snapshot but stamped no later than it is lost on the next
+    /// replay, so writers should stamp new events after this.
+    pub fn snapshot_timestamp(&self) -> Option<i64> {
+        self.snapshot_timestamp
+    }
+
+    /// Latest version of every aggregate, including ones compacted away
+    pub fn latest_versions(&self) -> HashMap<String, i64> {
+        let mut versions = self.base_versions.clone();
+        for aggregate_id in self.aggregate_index.keys() {
+            versions.insert(aggregate_id.clone(), self.get_latest_version(aggregate_id));
+        }
+        versions
+    }
+
+    /// Replace every stored event with `snapshot`
+    ///
+    /// `snapshot` must be a [`SNAPSHOT_EVENT_TYPE`] event built from this
+    /// store's projections. Aggregates keep numbering from their latest
+    /// version and sequence numbers carry on after the pruned events, so
+    /// writers and cursors carry on as if nothing was dropped.
+    pub fn compact(&mut self, snapshot: Event) -> EventResult<()> {
+        if snapshot.event_type != SNAPSHOT_EVENT_TYPE {
+            return Err(EventError::InvalidEventType(snapshot.event_type));
+        }
+        self.check_append(&snapshot, self.get_latest_version(&snapshot.aggregate_id))?;
+
+        *self = Self {
+            base_versions: self.latest_versions(),
+            base_sequence: self.latest_sequence(),
+            strict: self.strict,
+            registered_event_types: std::mem::take(&mut self.registered_event_types),
+            ..Self::new()
+        };
+        self.push_event(snapshot);
+        Ok(())
+    }
+
+    /// Drop every event after the first `len`, in append order
+    ///
+    /// Undoes appends that couldn't be made durable elsewhere. Versions
+    /// recorded by a dropped snapshot are kept; they never exceed versions
+    /// the store already held.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.events.len() {
+            return;
+        }
+        let mut dropped_snapshot = false;
+        for event in self.events.drain(len..) {
+            dropped_snapshot |= event.event_type == SNAPSHOT_EVENT_TYPE;
+            self.event_ids.remove(&event.id);
+            if let Some(indices) = self.aggregate_index.get_mut(&event.aggregate_id) {
+                indices.retain(|&index| index < len);
+                if indices.is_empty() {
+                    self.aggregate_index.remove(&event.aggregate_id);
+                }
+            }
+        }
+        self.time_order.retain(|&index| index < len);
+        if dropped_snapshot {
+            self.snapshot_timestamp = self
+                .events
+                .iter()
+                .filter(|e| e.event_type == SNAPSHOT_EVENT_TYPE)
+                .map(|e| e.timestamp)
+                .max();
+        }
+    }
+
+    /// Append a batch of events, all or nothing, naming the rejected one
+    ///
+    /// Same as [`EventStore::append_events`], but a rejection comes back with
+    /// the index of the event that caused it.
+    pub fn append_batch(&mut self, events: Vec<Event>) -> Result<(), (usize, EventError)> {
+        // Check the whole batch before storing any of it
+        let mut batch_versions: HashMap<&str, i64> = HashMap::new();
+        let mut batch_ids: HashSet<&str> = HashSet::new();
+        for (index, event) in events.iter().enumerate() {
+            let current_version = batch_versions
+                .get(event.aggregate_id.as_str())
+                .copied()
+                .unwrap_or_else(|| self.get_latest_version(&event.aggregate_id));
+            self.check_append(event, current_version)
+                .map_err(|e| (index, e))?;
+            if !batch_ids.insert(&event.id) {
+                return Err((index, EventError::DuplicateEventId(event.id.clone())));
+            }
+            batch_versions.insert(&event.aggregate_id, event.version);
+            // A reloaded log can start with a snapshot that later events
+            // number on from
+            if event.event_type == SNAPSHOT_EVENT_TYPE {
+                for (aggregate_id, version) in snapshot_versions(event) {
+                    let current = batch_versions
+                        .entry(aggregate_id)
+                        .or_insert_with(|| self.get_latest_version(aggregate_id));
+                    *current = (*current).max(version);
+                }
+            }
+        }
+
+        for event in events {
+            self.push_event(event);
+        }
+        Ok(())
+    }
+
+    /// Check that an event may follow `current_version` for its aggregate
+    fn check_append(&self, event: &Event, current_version: i64) -> EventResult<()> {
+        if !self.accepts_event_type(&event.event_type) {
+            return Err(EventError::InvalidEventType(event.event_type.clone()));
+        }
+
         // Check for duplicate event ID
-        if self.events.iter().any(|e| e.id == event.id) {
-            return Err(EventError::DuplicateEventId(event.id));
+        if self.event_ids.contains(&event.id) {
+            return Err(EventError::DuplicateEventId(event.id.clone()));
         }
 
         // Check version ordering
-        let current_version = self.get_latest_version(&event.aggregate_id);
         let expected_version = current_version + 1;
-
         if event.version != expected_version {
             return Err(EventError::InvalidVersion {
                 expected: expected_version,
                 got: event.version,
             });
         }
+        Ok(())
+    }
 
-        // Update version map
-        self.version_map
-            .insert(event.aggregate_id.clone(), event.version);
-
-        // Store event
+    /// Store an event that has passed [`InMemoryEventStore::check_append`]
+    fn push_event(&mut self, event: Event) {
+        // Versions only grow, so each aggregate's positions stay in version order
+        self.aggregate_index
+            .entry(event.aggregate_id.clone())
+            .or_default()
+            .push(self.events.len());
+        // Usually lands at the end, as events mostly arrive in time order
+        let key = time_order_key(&event);
+        let position = self
+            .time_order
+            .partition_point(|&index| time_order_key(&self.events[index]) <= key);
+        self.time_order.insert(position, self.events.len());
+        self.event_ids.insert(event.id.clone());
+        if event.event_type == SNAPSHOT_EVENT_TYPE {
+            // A reloaded log that starts with a snapshot numbers on from it
+            if self.events.is_empty() {
+                self.base_sequence = self.base_sequence.max(snapshot_sequence(&event));
+            }
+            self.snapshot_timestamp = Some(
+                self.snapshot_timestamp
+                    .map_or(event.timestamp, |newest| newest.max(event.timestamp)),
+            );
+            for (aggregate_id, version) in snapshot_versions(&event) {
+                let base = self
+                    .base_versions
+                    .entry(aggregate_id.to_string())
+                    .or_default();
+                *base = (*base).max(version);
+            }
+        }
         self.events.push(event);
+    }
+
+    /// Iterate an aggregate's events in version order
+    fn aggregate_events<'a>(&'a self, aggregate_id: &str) -> impl Iterator<Item = &'a Event> {
+        self.aggregate_index
+            .get(aggregate_id)
+            .into_iter()
+            .flatten()
+            .map(|&index| &self.events[index])
+    }
+}
+
+/// Sort key of an event in [`InMemoryEventStore::iter_events`]
+///
+/// A snapshot covers everything stamped at or before it, so it sorts after
+/// the events sharing its timestamp whatever their versions.
+fn time_order_key(event: &Event) -> (i64, bool, i64) {
+    (
+        event.timestamp,
+        event.event_type == SNAPSHOT_EVENT_TYPE,
+        event.version,
+    )
+}
+
+impl Default for InMemoryEventStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventStore for InMemoryEventStore {
+    fn append_event(&mut self, event: Event) -> EventResult<()> {
+        self.check_append(&event, self.get_latest_version(&event.aggregate_id))?;
+        self.push_event(event);
         Ok(())
     }
 
+    fn append_events(&mut self, events: Vec<Event>) -> EventResult<()> {
+        self.append_batch(events).map_err(|(_, e)| e)
+    }
+
     fn get_events(&self, aggregate_id: &str) -> EventResult<Vec<Event>> {
-        let mut events: Vec<Event> = self
-            .events
-            .iter()
-            .filter(|e| e.aggregate_id == aggregate_id)
+        Ok(self.aggregate_events(aggregate_id).cloned().collect())
+    }
+
+    fn get_events_since_version(
+        &self,
+        aggregate_id: &str,
+        after_version: i64,
+    ) -> EventResult<Vec<Event>> {
+        Ok(self
+            .aggregate_events(aggregate_id)
+            .filter(|e| e.version > after_version)
             .cloned()
-            .collect();
-        events.sort_by_key(|e| e.version);
-        Ok(events)
+            .collect())
     }
 
     fn get_all_events(&self) -> EventResult<Vec<Event>> {
-        let mut events = self.events.clone();
-        events.sort_by_key(|e| (e.timestamp, e.version));
-        Ok(events)
+        Ok(self.iter_events().cloned().collect())
+    }
+
+    fn get_events_of_type(&self, event_type: &str) -> EventResult<Vec<Event>> {
+        Ok(self
+            .iter_events()
+            .filter(|e| e.event_type == event_type)
+            .cloned()
+            .collect())
+    }
+
+    fn get_events_between(&self, start_ts: i64, end_ts: i64) -> EventResult<Vec<Event>> {
+        Ok(self
+            .iter_events()
+            .filter(|e| (start_ts..=end_ts).contains(&e.timestamp))
+            .cloned()
+            .collect())
     }
 
     fn get_latest_version(&self, aggregate_id: &str) -> i64 {
-        self.version_map.get(aggregate_id).copied().unwrap_or(0)
+        let logged = self
+            .aggregate_index
+            .get(aggregate_id)
+            .and_then(|indices| indices.last())
+            .map_or(0, |&index| self.events[index].version);
+        logged.max(self.base_versions.get(aggregate_id).copied().unwrap_or(0))
     }
 
     fn get_event_count(&self) -> usize {
         self.events.len()
     }
+
+    /// Aggregates compacted into a snapshot are included too, since writers
+    /// keep numbering them from their recorded version
+    fn get_aggregate_ids(&self) -> Vec<String> {
+        let mut aggregate_ids: Vec<String> = self
+            .aggregate_index
+            .keys()
+            .chain(
+                self.base_versions
+                    .keys()
+                    .filter(|id| !self.aggregate_index.contains_key(*id)),
+            )
+            .cloned()
+            .collect();
+        aggregate_ids.sort();
+        aggregate_ids
+    }
+
+    fn verify_integrity(&self) -> Vec<IntegrityIssue> {
+        check_integrity(&self.events)
+    }
 }
 
 /// Generate a unique event ID
+///
+/// Random (UUID v4) rather than clock-based, so events created in the same
+/// tick can't collide.
 pub fn generate_event_id() -> String {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_nanos();
-    format!("event-{}", timestamp)
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Namespace for [`content_event_id`]'s name-based UUIDs
+const EVENT_ID_NAMESPACE: uuid::Uuid = uuid::Uuid::from_u128(0x331d47371f7449d88cf1d2812db9ba60);
+
+/// Event ID derived from an event's content, see [`EventBuilder::deterministic_id`]
+///
+/// A UUID v5, so it looks like any other id and is the same on every
+/// platform. Payload object keys are serialized sorted, so key order
+/// doesn't matter.
+pub fn content_event_id(
+    aggregate_id: &str,
+    version: i64,
+    event_type: &str,
+    payload: &serde_json::Value,
+) -> String {
+    let mut name = Vec::new();
+    name.extend(aggregate_id.bytes().chain([0]));
+    name.extend(version.to_le_bytes());
+    name.extend(event_type.bytes().chain([0]));
+    name.extend(payload.to_string().bytes());
+    uuid::Uuid::new_v5(&EVENT_ID_NAMESPACE, &name).to_string()
+}
+
+/// Fingerprint a sequence of events for cheap change detection
+///
+/// Stable across processes (FNV-1a over ids and versions), so it can back
+/// HTTP ETags. Order matters.
+pub fn log_hash<'a>(events: impl IntoIterator<Item = &'a Event>) -> u64 {
+    const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let mut hash = FNV_OFFSET;
+    for event in events {
+        let version = event.version.to_le_bytes();
+        for byte in event.id.bytes().chain([0]).chain(version) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+    hash
 }
 
 /// Get current timestamp as Unix epoch seconds
@@ -261,6 +793,30 @@ pub fn current_timestamp() -> i64 {
         .as_secs() as i64
 }
 
+/// Default tolerance for event timestamps ahead of the local clock, in seconds
+pub const DEFAULT_MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// Validate that a timestamp (Unix epoch seconds) is non-negative and at most
+/// `max_skew_secs` ahead of `now`
+///
+/// Far-future timestamps from a misconfigured client clock would otherwise
+/// dominate ordering and block incremental projection updates.
+pub fn validate_timestamp(timestamp: i64, now: i64, max_skew_secs: i64) -> EventResult<()> {
+    if timestamp < 0 {
+        return Err(EventError::ValidationError(format!(
+            "Timestamp {} is negative",
+            timestamp
+        )));
+    }
+    if timestamp > now.saturating_add(max_skew_secs) {
+        return Err(EventError::ValidationError(format!(
+            "Timestamp {} is more than {}s ahead of current time {}",
+            timestamp, max_skew_secs, now
+        )));
+    }
+    Ok(())
+}
+
 /// Validate event structure
 pub fn validate_event(event: &Event) -> EventResult<()> {
     if event.event_type.trim().is_empty() {
@@ -275,23 +831,59 @@ pub fn validate_event(event: &Event) -> EventResult<()> {
             got: event.version,
         });
     }
+    // How far ahead is too far depends on the writer's clock and unit, so
+    // only the sign is checked here; see `validate_timestamp`
+    if event.timestamp < 0 {
+        return Err(EventError::ValidationError(format!(
+            "Timestamp {} is negative",
+            event.timestamp
+        )));
+    }
     Ok(())
 }
 
+#[cfg(feature = "tokio")]
+pub use broadcast::BroadcastingEventStore;
+pub use comment::{
+    create_comment_event, delete_comment_event, edit_comment_event, resolve_comment_event, Comment,
+    CommentMaterializer, CommentProjection, CommentProjectionState,
+};
+pub use integrity::{check_integrity, IntegrityIssue};
+pub use presence::{
+    presence_aggregate_id, presence_document_id, prune_presence_events, update_presence_event,
+    PresenceMaterializer, PresenceProjection, PresenceState, UserPresence,
+    DEFAULT_PRESENCE_TTL_SECS, PRESENCE_AGGREGATE_PREFIX,
+};
+pub use projections::{AnyProjection, ProjectionRegistry};
+pub use schema::{EventSchemaRegistry, FieldKind, PayloadSchema};
+pub use snapshot::{snapshot_event, snapshot_sequence, snapshot_versions, SNAPSHOT_EVENT_TYPE};
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteEventStore;
+pub use upcast::{schema_version, EventUpcaster, UpcasterRegistry, SCHEMA_VERSION_FIELD};
+
 // Re-export document types
 pub use document::{
-    create_cell_event, create_document_event, move_cell_event, update_cell_source_event, Cell,
-    CellOutput, CellType, Document, DocumentMaterializer, DocumentMetadata, DocumentProjection,
-    DocumentProjectionState, ExecutionState, KernelSpec, LanguageInfo, MediaRepresentation,
-    OutputType, RuntimeSession, RuntimeStatus,
+    add_document_tag_event, append_cell_output_event, cell_history, cells_affected_between,
+    change_cell_type_event, change_cell_visibility_event, clear_cell_outputs_event,
+    create_cell_event, create_document_event, create_runtime_session_event, events_in_transaction,
+    inverse_event, invert_transaction, move_cell_event, output_event_from_mimebundle,
+    rebalance_indices, remove_document_tag_event, reorder_cells, repair_indices,
+    reposition_outputs, restore_cell_event, set_document_custom_field_event, source_edited_at,
+    terminate_runtime_session_event, update_cell_ai_config_event, update_cell_source_event,
+    update_runtime_session_status_event, Cell, CellOutput, CellType, Document,
+    DocumentMaterializer, DocumentMetadata, DocumentProjection, DocumentProjectionState,
+    ExecutionState, KernelSpec, LanguageInfo, MediaRepresentation, OutputType, RuntimeSession,
+    RuntimeStatus,
 };
 
 // Re-export fractional index utilities
 pub use fractional_index::{
     after as fractional_after, before as fractional_before, between as fractional_between,
-    generate_sequence as fractional_generate_sequence, initial as fractional_initial,
-    is_valid_order as fractional_is_valid_order, validate_index as fractional_validate_index,
-    FractionalIndexError,
+    between_jittered as fractional_between_jittered,
+    generate_sequence as fractional_generate_sequence,
+    generate_sequence_spread as fractional_generate_sequence_spread, initial as fractional_initial,
+    is_valid_order as fractional_is_valid_order, rebalance as fractional_rebalance,
+    validate_index as fractional_validate_index, FractionalIndex, FractionalIndexError,
 };
 
 #[cfg(test)]
@@ -365,4 +957,621 @@ mod tests {
             })
         ));
     }
+
+    #[test]
+    fn test_timestamp_validation() {
+        let mut event = EventBuilder::new()
+            .event_type("DocumentCreated")
+            .aggregate_id("cell-123")
+            .build(1)
+            .unwrap();
+        assert!(validate_event(&event).is_ok());
+
+        event.timestamp = -1;
+        assert!(matches!(
+            validate_event(&event),
+            Err(EventError::ValidationError(_))
+        ));
+
+        assert!(validate_timestamp(1_060, 1_000, 60).is_ok());
+        assert!(validate_timestamp(1_061, 1_000, 60).is_err());
+    }
+
+    #[test]
+    fn test_events_in_version_range() {
+        let mut store = InMemoryEventStore::new();
+        for version in 1..=5 {
+            for aggregate_id in ["doc-a", "doc-b"] {
+                let mut event = EventBuilder::new()
+                    .event_type("DocumentTitleUpdated")
+                    .aggregate_id(aggregate_id)
+                    .build(version)
+                    .unwrap();
+                event.id = format!("{}-{}", aggregate_id, version);
+                store.append_event(event).unwrap();
+            }
+        }
+
+        let versions = |events: Vec<Event>| events.iter().map(|e| e.version).collect::<Vec<_>>();
+
+        let events = store.get_events_in_version_range("doc-a", 2, 4).unwrap();
+        assert!(events.iter().all(|e| e.aggregate_id == "doc-a"));
+        assert_eq!(versions(events), vec![2, 3, 4]);
+
+        let events = store.get_events_in_version_range("doc-a", 5, 5).unwrap();
+        assert_eq!(versions(events), vec![5]);
+
+        assert!(store
+            .get_events_in_version_range("doc-a", 6, 10)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_strict_store_rejects_unknown_event_types() {
+        let event = |event_type: &str, version: i64| {
+            EventBuilder::new()
+                .event_type(event_type)
+                .aggregate_id("doc-1")
+                .build(version)
+                .unwrap()
+        };
+
+        let mut store = InMemoryEventStore::strict();
+        store.register_event_type("CommentAdded");
+
+        assert_eq!(
+            store.append_event(event("CellCreted", 1)),
+            Err(EventError::InvalidEventType("CellCreted".to_string()))
+        );
+        assert_eq!(store.get_event_count(), 0);
+
+        store.append_event(event("DocumentCreated", 1)).unwrap();
+        store.append_event(event("CommentAdded", 2)).unwrap();
+
+        let mut lenient = InMemoryEventStore::new();
+        assert!(lenient.append_event(event("CellCreted", 1)).is_ok());
+    }
+
+    #[test]
+    fn test_get_all_events_desc() {
+        let mut store = InMemoryEventStore::new();
+        for (version, timestamp) in [(1, 100), (2, 200), (3, 300)] {
+            let event = EventBuilder::new()
+                .event_type("DocumentTitleUpdated")
+                .aggregate_id("doc-1")
+                .timestamp(timestamp)
+                .build(version)
+                .unwrap();
+            store.append_event(event).unwrap();
+        }
+
+        let versions: Vec<_> = store
+            .get_all_events_desc()
+            .unwrap()
+            .iter()
+            .map(|e| e.version)
+            .collect();
+        assert_eq!(versions, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_null_payload_rejected_early() {
+        let result = EventBuilder::new()
+            .event_type("CellCreated")
+            .aggregate_id("doc-1")
+            .build(1);
+
+        match result {
+            Err(EventError::ValidationError(message)) => {
+                assert!(message.contains("CellCreated"));
+                assert!(message.contains("null"));
+            }
+            other => panic!("expected validation error, got {:?}", other),
+        }
+
+        // Event types without required fields still accept an empty payload
+        assert!(EventBuilder::new()
+            .event_type("DocumentDeleted")
+            .aggregate_id("doc-1")
+            .build(1)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_generate_event_id_is_unique() {
+        let ids: std::collections::HashSet<String> =
+            (0..10_000).map(|_| generate_event_id()).collect();
+        assert_eq!(ids.len(), 10_000);
+    }
+
+    #[test]
+    fn test_events_since_version() {
+        let mut store = InMemoryEventStore::new();
+        for version in 1..=3 {
+            for aggregate_id in ["doc-a", "doc-b"] {
+                let event = EventBuilder::new()
+                    .event_type("DocumentTitleUpdated")
+                    .aggregate_id(aggregate_id)
+                    .build(version)
+                    .unwrap();
+                store.append_event(event).unwrap();
+            }
+        }
+
+        let versions = |events: Vec<Event>| events.iter().map(|e| e.version).collect::<Vec<_>>();
+
+        let events = store.get_events_since_version("doc-a", 0).unwrap();
+        assert!(events.iter().all(|e| e.aggregate_id == "doc-a"));
+        assert_eq!(versions(events), vec![1, 2, 3]);
+
+        let events = store.get_events_since_version("doc-a", 1).unwrap();
+        assert_eq!(versions(events), vec![2, 3]);
+
+        assert!(store
+            .get_events_since_version("doc-a", 3)
+            .unwrap()
+            .is_empty());
+        assert!(store
+            .get_events_since_version("doc-a", 10)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_events_after_sequence() {
+        let mut store = InMemoryEventStore::new();
+        let append = |store: &mut InMemoryEventStore, aggregate_id: &str, version| {
+            let mut event = EventBuilder::new()
+                .event_type("DocumentTitleUpdated")
+                .aggregate_id(aggregate_id)
+                .build(version)
+                .unwrap();
+            event.id = format!("{}-{}", aggregate_id, version);
+            store.append_event(event).unwrap();
+        };
+        append(&mut store, "doc-a", 1);
+        append(&mut store, "doc-b", 1);
+        append(&mut store, "doc-a", 2);
+
+        let ids = |events: Vec<Event>| events.into_iter().map(|e| e.id).collect::<Vec<_>>();
+
+        assert_eq!(
+            ids(store.get_events_after_sequence(0, 2).unwrap()),
+            vec!["doc-a-1", "doc-b-1"]
+        );
+
+        // Appending doesn't shift what a cursor points at
+        append(&mut store, "doc-b", 2);
+        assert_eq!(
+            ids(store.get_events_after_sequence(2, 10).unwrap()),
+            vec!["doc-a-2", "doc-b-2"]
+        );
+        assert!(store.get_events_after_sequence(4, 10).unwrap().is_empty());
+        assert!(store.get_events_after_sequence(99, 10).unwrap().is_empty());
+
+        // Compacting doesn't reuse sequence numbers, even across a reload
+        let snapshot = snapshot_event(
+            &document::DocumentProjectionState::default(),
+            &comment::CommentProjectionState::default(),
+            &store.latest_versions(),
+            store.latest_sequence(),
+            100,
+        )
+        .unwrap();
+        store.compact(snapshot.clone()).unwrap();
+        assert_eq!(store.latest_sequence(), 5);
+        append(&mut store, "doc-a", 3);
+        assert_eq!(store.latest_sequence(), 6);
+        // A cursor from before the compaction resumes at the snapshot
+        assert_eq!(
+            ids(store.get_events_after_sequence(2, 10).unwrap()),
+            vec![snapshot.id.clone(), "doc-a-3".to_string()]
+        );
+        assert_eq!(
+            ids(store.get_events_after_sequence(5, 10).unwrap()),
+            vec!["doc-a-3"]
+        );
+
+        let mut reloaded = InMemoryEventStore::new();
+        reloaded
+            .append_events(store.get_all_events().unwrap())
+            .unwrap();
+        assert_eq!(reloaded.latest_sequence(), 6);
+    }
+
+    #[test]
+    fn test_events_between() {
+        let mut store = InMemoryEventStore::new();
+        // Appended out of timestamp order across two aggregates
+        for (aggregate_id, version, timestamp) in [
+            ("doc-a", 1, 300),
+            ("doc-b", 1, 100),
+            ("doc-a", 2, 200),
+            ("doc-b", 2, 200),
+        ] {
+            let event = EventBuilder::new()
+                .event_type("DocumentTitleUpdated")
+                .aggregate_id(aggregate_id)
+                .timestamp(timestamp)
+                .build(version)
+                .unwrap();
+            store.append_event(event).unwrap();
+        }
+
+        let stamps = |events: Vec<Event>| {
+            events
+                .iter()
+                .map(|e| (e.timestamp, e.version))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            stamps(store.get_events_between(0, i64::MAX).unwrap()),
+            vec![(100, 1), (200, 2), (200, 2), (300, 1)]
+        );
+        // Both bounds are inclusive
+        assert_eq!(
+            stamps(store.get_events_between(200, 200).unwrap()),
+            vec![(200, 2), (200, 2)]
+        );
+        assert_eq!(stamps(store.get_events_between(100, 200).unwrap()).len(), 3);
+        assert!(store.get_events_between(101, 199).unwrap().is_empty());
+        assert!(store.get_events_between(300, 100).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_events_of_type() {
+        let mut store = InMemoryEventStore::new();
+        for (version, event_type, timestamp) in [
+            (1, "CellCreated", 200),
+            (2, "CellSourceUpdated", 150),
+            (3, "CellCreated", 100),
+        ] {
+            let event = EventBuilder::new()
+                .event_type(event_type)
+                .aggregate_id("doc-a")
+                .payload(serde_json::json!({"cell_id": "cell-1"}))
+                .unwrap()
+                .timestamp(timestamp)
+                .build(version)
+                .unwrap();
+            store.append_event(event).unwrap();
+        }
+
+        let created = store.get_events_of_type("CellCreated").unwrap();
+        let versions: Vec<i64> = created.iter().map(|e| e.version).collect();
+        assert_eq!(versions, vec![3, 1]);
+        assert_eq!(
+            store.get_events_of_type("CellSourceUpdated").unwrap().len(),
+            1
+        );
+        assert!(store.get_events_of_type("CellDeleted").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_indexes_stay_correct_across_many_appends() {
+        let mut store = InMemoryEventStore::new();
+        let aggregates = 50;
+        let per_aggregate = 40;
+        // Interleave aggregates so each one's events are spread through the log
+        for version in 1..=per_aggregate {
+            for aggregate in 0..aggregates {
+                let event = EventBuilder::new()
+                    .event_type("DocumentTitleUpdated")
+                    .aggregate_id(format!("doc-{}", aggregate))
+                    .build(version)
+                    .unwrap();
+                store.append_event(event).unwrap();
+            }
+        }
+
+        assert_eq!(
+            store.get_event_count(),
+            (aggregates * per_aggregate) as usize
+        );
+        for aggregate in 0..aggregates {
+            let aggregate_id = format!("doc-{}", aggregate);
+            let events = store.get_events(&aggregate_id).unwrap();
+            assert_eq!(events.len(), per_aggregate as usize);
+            assert!(events.iter().all(|e| e.aggregate_id == aggregate_id));
+            assert!(events
+                .iter()
+                .zip(1..)
+                .all(|(event, version)| event.version == version));
+            assert_eq!(store.get_latest_version(&aggregate_id), per_aggregate);
+            assert_eq!(
+                store
+                    .get_events_since_version(&aggregate_id, per_aggregate - 5)
+                    .unwrap()
+                    .len(),
+                5
+            );
+        }
+        assert!(store.get_events("missing").unwrap().is_empty());
+        assert_eq!(store.get_latest_version("missing"), 0);
+
+        // Every stored id is still rejected as a duplicate
+        let existing = store.get_events("doc-7").unwrap().remove(3);
+        assert_eq!(
+            store.append_event(existing.clone()),
+            Err(EventError::DuplicateEventId(existing.id))
+        );
+    }
+
+    #[test]
+    fn test_append_events_with_version_gap() {
+        let batch = || {
+            let mut events: Vec<Event> = [("doc-a", 1), ("doc-b", 1), ("doc-a", 2), ("doc-a", 4)]
+                .into_iter()
+                .map(|(aggregate_id, version)| {
+                    EventBuilder::new()
+                        .event_type("DocumentTitleUpdated")
+                        .aggregate_id(aggregate_id)
+                        .build(version)
+                        .unwrap()
+                })
+                .collect();
+            // A well-formed event after the gap
+            events.push(
+                EventBuilder::new()
+                    .event_type("DocumentTitleUpdated")
+                    .aggregate_id("doc-b")
+                    .build(2)
+                    .unwrap(),
+            );
+            events
+        };
+
+        // All or nothing: the gap rejects the whole batch
+        let mut store = InMemoryEventStore::new();
+        assert_eq!(
+            store.append_events(batch()),
+            Err(EventError::InvalidVersion {
+                expected: 3,
+                got: 4
+            })
+        );
+        assert_eq!(store.get_event_count(), 0);
+        assert_eq!(store.get_latest_version("doc-a"), 0);
+        assert_eq!(store.append_batch(batch()).unwrap_err().0, 3);
+        assert_eq!(store.get_event_count(), 0);
+
+        // Versions run on from earlier events in the same batch
+        let mut events = batch();
+        events[3].version = 3;
+        store.append_events(events).unwrap();
+        assert_eq!(store.get_latest_version("doc-a"), 3);
+        assert_eq!(store.get_latest_version("doc-b"), 2);
+
+        // Best effort: everything but the gapped event is kept
+        let mut store = InMemoryEventStore::new();
+        let errors = store.append_events_best_effort(batch());
+        assert_eq!(
+            errors,
+            vec![(
+                3,
+                EventError::InvalidVersion {
+                    expected: 3,
+                    got: 4
+                }
+            )]
+        );
+        assert_eq!(store.get_event_count(), 4);
+        assert_eq!(store.get_latest_version("doc-a"), 2);
+        assert_eq!(store.get_latest_version("doc-b"), 2);
+
+        // A batch can't smuggle in the same id twice
+        let mut store = InMemoryEventStore::new();
+        let mut events = batch();
+        events.truncate(2);
+        events[1].id = events[0].id.clone();
+        assert!(matches!(
+            store.append_events(events),
+            Err(EventError::DuplicateEventId(_))
+        ));
+        assert_eq!(store.get_event_count(), 0);
+    }
+
+    #[test]
+    fn test_actor_round_trips_and_defaults_to_none() {
+        let legacy = serde_json::json!({
+            "id": "event-1",
+            "event_type": "DocumentCreated",
+            "aggregate_id": "doc-1",
+            "payload": {"title": "Old"},
+            "timestamp": 1,
+            "version": 1
+        });
+        let event: Event = serde_json::from_value(legacy.clone()).unwrap();
+        assert_eq!(event.actor, None);
+        // Unattributed events serialize exactly as before
+        assert_eq!(serde_json::to_value(&event).unwrap(), legacy);
+
+        let attributed = EventBuilder::new()
+            .event_type("DocumentCreated")
+            .aggregate_id("doc-1")
+            .payload(serde_json::json!({"title": "New"}))
+            .unwrap()
+            .actor("alice")
+            .build(1)
+            .unwrap();
+        let json = serde_json::to_string(&attributed).unwrap();
+        let decoded: Event = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.actor.as_deref(), Some("alice"));
+        assert_eq!(decoded, attributed);
+    }
+
+    #[test]
+    fn test_iter_events_matches_get_all_events() {
+        let mut store = InMemoryEventStore::new();
+        // Out-of-order timestamps exercise the sorted insert
+        for (aggregate_id, version, timestamp) in [
+            ("doc-1", 1, 300),
+            ("doc-2", 1, 100),
+            ("doc-1", 2, 100),
+            ("doc-3", 1, 200),
+            ("doc-2", 2, 300),
+        ] {
+            let event = EventBuilder::new()
+                .event_type("DocumentTitleUpdated")
+                .aggregate_id(aggregate_id)
+                .payload(serde_json::json!({"title": "t"}))
+                .unwrap()
+                .timestamp(timestamp)
+                .build(version)
+                .unwrap();
+            store.append_event(event).unwrap();
+        }
+
+        let iterated: Vec<&Event> = store.iter_events().collect();
+        let all = store.get_all_events().unwrap();
+        assert_eq!(iterated.len(), store.get_event_count());
+        assert!(iterated.iter().copied().eq(all.iter()));
+        assert_eq!(
+            iterated
+                .iter()
+                .map(|e| (e.timestamp, e.version))
+                .collect::<Vec<_>>(),
+            vec![(100, 1), (100, 2), (200, 1), (300, 1), (300, 2)]
+        );
+        assert!(store
+            .iter_events()
+            .rev()
+            .eq(store.get_all_events_desc().unwrap().iter()));
+    }
+
+    #[test]
+    fn test_build_reports_every_missing_field() {
+        let err = EventBuilder::new().build(1).unwrap_err();
+        assert_eq!(
+            err,
+            EventError::MultipleValidationErrors(vec![
+                "Event type is required".to_string(),
+                "Aggregate ID is required".to_string(),
+            ])
+        );
+        assert_eq!(
+            err.to_string(),
+            "Validation errors: Event type is required; Aggregate ID is required"
+        );
+
+        // A single problem keeps its own variant
+        let err = EventBuilder::new()
+            .event_type("DocumentCreated")
+            .aggregate_id("doc-1")
+            .build(0)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            EventError::InvalidVersion {
+                expected: 1,
+                got: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_deterministic_id_is_stable_across_retries() {
+        let build = |timestamp: i64, payload: serde_json::Value| {
+            EventBuilder::new()
+                .event_type("CellSourceUpdated")
+                .aggregate_id("doc-1")
+                .payload(payload)
+                .unwrap()
+                .timestamp(timestamp)
+                .deterministic_id()
+                .build(3)
+                .unwrap()
+        };
+        let payload = serde_json::json!({"cell_id": "cell-1", "source": "x = 1"});
+
+        let first = build(100, payload.clone());
+        // A retry a moment later is the same logical event
+        let retry = build(101, payload);
+        assert_eq!(first.id, retry.id);
+        assert!(uuid::Uuid::parse_str(&first.id).is_ok());
+
+        let other = build(
+            100,
+            serde_json::json!({"cell_id": "cell-1", "source": "x = 2"}),
+        );
+        assert_ne!(first.id, other.id);
+
+        // The store turns the retry away instead of storing it twice
+        let mut store = InMemoryEventStore::new();
+        for version in 1..=2 {
+            store
+                .append_event(
+                    EventBuilder::new()
+                        .event_type("DocumentTitleUpdated")
+                        .aggregate_id("doc-1")
+                        .build(version)
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+        store.append_event(first).unwrap();
+        assert_eq!(
+            store.append_event(retry.clone()),
+            Err(EventError::DuplicateEventId(retry.id))
+        );
+    }
+
+    #[test]
+    fn test_aggregate_ids_are_distinct() {
+        let event = |aggregate_id: &str, version: i64| {
+            EventBuilder::new()
+                .event_type("DocumentTitleUpdated")
+                .aggregate_id(aggregate_id)
+                .payload(serde_json::json!({"title": "Title"}))
+                .unwrap()
+                .build(version)
+                .unwrap()
+        };
+        let mut store = InMemoryEventStore::new();
+        assert!(store.get_aggregate_ids().is_empty());
+
+        store
+            .append_events(vec![
+                event("doc-b", 1),
+                event("doc-a", 1),
+                event("doc-b", 2),
+                event("doc-c", 1),
+                event("doc-a", 2),
+            ])
+            .unwrap();
+        assert_eq!(store.get_aggregate_ids(), vec!["doc-a", "doc-b", "doc-c"]);
+    }
+
+    #[test]
+    fn test_truncate_undoes_appends() {
+        let event = |aggregate_id: &str, version: i64| {
+            EventBuilder::new()
+                .event_type("DocumentTitleUpdated")
+                .aggregate_id(aggregate_id)
+                .payload(serde_json::json!({"title": "Title"}))
+                .unwrap()
+                .build(version)
+                .unwrap()
+        };
+        let mut store = InMemoryEventStore::new();
+        store
+            .append_events(vec![event("doc-a", 1), event("doc-b", 1)])
+            .unwrap();
+        let dropped = event("doc-a", 2);
+        store
+            .append_events(vec![dropped.clone(), event("doc-c", 1)])
+            .unwrap();
+
+        store.truncate(2);
+        assert_eq!(store.get_event_count(), 2);
+        assert_eq!(store.get_latest_version("doc-a"), 1);
+        assert_eq!(store.get_aggregate_ids(), vec!["doc-a", "doc-b"]);
+        assert_eq!(store.iter_events().count(), 2);
+
+        // The dropped event can be appended again
+        store.append_event(dropped).unwrap();
+        assert_eq!(store.get_latest_version("doc-a"), 2);
+    }
 }
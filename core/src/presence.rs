@@ -0,0 +1,438 @@
+//! Who is viewing or editing which cell, for collaborative editing
+//!
+//! Presence is ephemeral: entries expire once a user hasn't reported in for
+//! the projection's TTL, and stale entries are pruned rather than kept
+//! around like document state. Updates are recorded on a per-document
+//! presence aggregate so they don't advance the document's own version.
+
+use crate::{current_timestamp, Event, EventError, EventResult, Materializer, Projection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Default time after a user's last update before they're considered gone
+pub const DEFAULT_PRESENCE_TTL_SECS: i64 = 60;
+
+/// Prefix of the aggregates presence updates are recorded on
+pub const PRESENCE_AGGREGATE_PREFIX: &str = "presence-";
+
+/// The aggregate a document's presence updates are recorded on
+pub fn presence_aggregate_id(document_id: &str) -> String {
+    format!("{}{}", PRESENCE_AGGREGATE_PREFIX, document_id)
+}
+
+/// The document a presence aggregate belongs to, or `None` for any other aggregate
+pub fn presence_document_id(aggregate_id: &str) -> Option<&str> {
+    aggregate_id.strip_prefix(PRESENCE_AGGREGATE_PREFIX)
+}
+
+/// A user's last reported position in a document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserPresence {
+    pub user_id: String,
+    pub document_id: String,
+    /// Cell the user is focused on, if any
+    pub cell_id: Option<String>,
+    /// Cursor position within the cell's source
+    pub cursor_offset: Option<u64>,
+    /// When the user last reported in, in seconds since the epoch
+    pub last_seen: i64,
+}
+
+/// State for the presence projection
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PresenceState {
+    /// Presence per document, keyed by document ID then user ID
+    pub documents: HashMap<String, HashMap<String, UserPresence>>,
+    pub last_processed_timestamp: i64,
+}
+
+impl PresenceState {
+    /// Drop entries last seen before `cutoff`, returning how many were removed
+    fn remove_seen_before(&mut self, cutoff: i64) -> usize {
+        let mut removed = 0;
+        self.documents.retain(|_, users| {
+            let before = users.len();
+            users.retain(|_, presence| presence.last_seen >= cutoff);
+            removed += before - users.len();
+            !users.is_empty()
+        });
+        removed
+    }
+}
+
+/// Materializer for `UserPresenceUpdated` events
+///
+/// Each user keeps only their latest update per document. An update older
+/// than the one already held is ignored, so replays and out-of-order
+/// delivery can't move a cursor backwards.
+pub struct PresenceMaterializer;
+
+impl PresenceMaterializer {
+    /// Read the presence an event reports
+    ///
+    /// `last_seen` is the event's timestamp; a payload `timestamp` from the
+    /// client's clock is ignored. Updates stored on the document aggregate
+    /// itself, from before presence had its own, still count for the document.
+    fn presence_from_event(event: &Event) -> EventResult<UserPresence> {
+        let payload = &event.payload;
+        let user_id = payload
+            .get("user_id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| EventError::ValidationError("Missing user_id".to_string()))?;
+
+        Ok(UserPresence {
+            user_id: user_id.to_string(),
+            document_id: presence_document_id(&event.aggregate_id)
+                .unwrap_or(&event.aggregate_id)
+                .to_string(),
+            cell_id: payload
+                .get("cell_id")
+                .and_then(|v| v.as_str())
+                .map(String::from),
+            cursor_offset: payload.get("cursor_offset").and_then(|v| v.as_u64()),
+            last_seen: event.timestamp,
+        })
+    }
+}
+
+impl Materializer for PresenceMaterializer {
+    type State = PresenceState;
+    type Error = EventError;
+
+    fn initial_state() -> Self::State {
+        PresenceState::default()
+    }
+
+    fn apply_event(state: &Self::State, event: &Event) -> Result<Self::State, Self::Error> {
+        let mut new_state = state.clone();
+        new_state.last_processed_timestamp =
+            new_state.last_processed_timestamp.max(event.timestamp);
+
+        if event.event_type == "UserPresenceUpdated" {
+            let presence = Self::presence_from_event(event)?;
+            let users = new_state
+                .documents
+                .entry(presence.document_id.clone())
+                .or_default();
+            let is_newer = users
+                .get(&presence.user_id)
+                .is_none_or(|current| presence.last_seen >= current.last_seen);
+            if is_newer {
+                users.insert(presence.user_id.clone(), presence);
+            }
+        }
+
+        Ok(new_state)
+    }
+
+    fn handles_event_type(event_type: &str) -> bool {
+        event_type == "UserPresenceUpdated"
+    }
+}
+
+/// Presence projection implementation
+///
+/// Entries last seen more than `ttl_secs` before the newest processed event
+/// are pruned as events arrive, so the state stays bounded by the number of
+/// recently active users.
+pub struct PresenceProjection {
+    state: PresenceState,
+    ttl_secs: i64,
+}
+
+impl PresenceProjection {
+    pub fn new() -> Self {
+        Self::with_ttl(DEFAULT_PRESENCE_TTL_SECS)
+    }
+
+    /// Create a projection that expires users after `ttl_secs` of silence
+    pub fn with_ttl(ttl_secs: i64) -> Self {
+        Self {
+            state: PresenceMaterializer::initial_state(),
+            ttl_secs,
+        }
+    }
+
+    pub fn ttl_secs(&self) -> i64 {
+        self.ttl_secs
+    }
+
+    /// Get the users active in a document now, ordered by user ID
+    pub fn get_active_users(&self, document_id: &str) -> Vec<&UserPresence> {
+        self.get_active_users_at(document_id, current_timestamp())
+    }
+
+    /// Get the users active in a document as of `now`, ordered by user ID
+    pub fn get_active_users_at(&self, document_id: &str, now: i64) -> Vec<&UserPresence> {
+        let mut users: Vec<&UserPresence> = self
+            .state
+            .documents
+            .get(document_id)
+            .into_iter()
+            .flat_map(|users| users.values())
+            .filter(|presence| self.is_active(presence, now))
+            .collect();
+        users.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+        users
+    }
+
+    /// Get the users focused on a cell now, ordered by user ID
+    pub fn get_cell_editors(&self, cell_id: &str) -> Vec<&UserPresence> {
+        self.get_cell_editors_at(cell_id, current_timestamp())
+    }
+
+    /// Get the users focused on a cell as of `now`, ordered by user ID
+    pub fn get_cell_editors_at(&self, cell_id: &str, now: i64) -> Vec<&UserPresence> {
+        let mut editors: Vec<&UserPresence> = self
+            .state
+            .documents
+            .values()
+            .flat_map(|users| users.values())
+            .filter(|presence| presence.cell_id.as_deref() == Some(cell_id))
+            .filter(|presence| self.is_active(presence, now))
+            .collect();
+        editors.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+        editors
+    }
+
+    /// Remove entries that have expired as of `now`, returning how many were removed
+    ///
+    /// Events only prune relative to each other, so call this periodically to
+    /// clear out users who left a document nobody else is updating.
+    pub fn prune_expired(&mut self, now: i64) -> usize {
+        self.state
+            .remove_seen_before(now.saturating_sub(self.ttl_secs))
+    }
+
+    fn is_active(&self, presence: &UserPresence, now: i64) -> bool {
+        presence.last_seen >= now.saturating_sub(self.ttl_secs)
+    }
+}
+
+impl Default for PresenceProjection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Projection for PresenceProjection {
+    type State = PresenceState;
+
+    fn rebuild_from_events(&mut self, events: &[Event]) -> EventResult<()> {
+        self.state = PresenceMaterializer::initial_state();
+        self.apply_new_events(events)
+    }
+
+    fn get_state(&self) -> &Self::State {
+        &self.state
+    }
+
+    fn last_processed_timestamp(&self) -> i64 {
+        self.state.last_processed_timestamp
+    }
+
+    fn apply_new_events(&mut self, events: &[Event]) -> EventResult<()> {
+        // Updates are last-write-wins by time, so reapplying an event is harmless
+        for event in events {
+            if PresenceMaterializer::handles_event_type(&event.event_type) {
+                self.state =
+                    PresenceMaterializer::apply_event(&self.state, event).map_err(|e| {
+                        EventError::ValidationError(format!("Materialization failed: {}", e))
+                    })?;
+            }
+        }
+        let cutoff = self
+            .state
+            .last_processed_timestamp
+            .saturating_sub(self.ttl_secs);
+        self.state.remove_seen_before(cutoff);
+        Ok(())
+    }
+}
+
+/// Drop presence events that no longer affect presence as of `now`
+///
+/// Keeps every other event, plus the latest unexpired presence update per
+/// user and document, so a log can be compacted before it's replayed.
+pub fn prune_presence_events(events: &[Event], now: i64, ttl_secs: i64) -> Vec<Event> {
+    let mut projection = PresenceProjection::with_ttl(ttl_secs);
+    // Events that fail to materialize are dropped along with the stale ones
+    let _ = projection.rebuild_from_events(events);
+    projection.prune_expired(now);
+
+    events
+        .iter()
+        .filter(|event| {
+            if !PresenceMaterializer::handles_event_type(&event.event_type) {
+                return true;
+            }
+            let Ok(presence) = PresenceMaterializer::presence_from_event(event) else {
+                return false;
+            };
+            projection
+                .state
+                .documents
+                .get(&presence.document_id)
+                .and_then(|users| users.get(&presence.user_id))
+                .is_some_and(|latest| *latest == presence)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Report a user's position in a document
+///
+/// The event goes on the document's presence aggregate, see
+/// [`presence_aggregate_id`]; `version` is that aggregate's next version.
+pub fn update_presence_event(
+    document_id: String,
+    user_id: String,
+    cell_id: Option<String>,
+    cursor_offset: Option<u64>,
+    version: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    EventBuilder::new()
+        .event_type("UserPresenceUpdated")
+        .aggregate_id(presence_aggregate_id(&document_id))
+        .payload(serde_json::json!({
+            "user_id": user_id,
+            "cell_id": cell_id,
+            "cursor_offset": cursor_offset,
+        }))?
+        .build(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EventBuilder;
+
+    fn presence(user_id: &str, cell_id: &str, cursor_offset: u64, timestamp: i64) -> Event {
+        EventBuilder::new()
+            .event_type("UserPresenceUpdated")
+            .aggregate_id(presence_aggregate_id("doc-1"))
+            .payload(serde_json::json!({
+                "user_id": user_id,
+                "cell_id": cell_id,
+                "cursor_offset": cursor_offset,
+            }))
+            .unwrap()
+            .timestamp(timestamp)
+            .build(1)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_presence_join_and_update() {
+        let mut projection = PresenceProjection::with_ttl(30);
+        projection
+            .apply_new_events(&[
+                presence("alice", "cell-1", 0, 1_000),
+                presence("bob", "cell-1", 4, 1_001),
+            ])
+            .unwrap();
+
+        let users = projection.get_active_users_at("doc-1", 1_005);
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].user_id, "alice");
+        assert_eq!(projection.get_cell_editors_at("cell-1", 1_005).len(), 2);
+
+        // Alice moves to another cell; a stale update can't move her back
+        projection
+            .apply_new_events(&[
+                presence("alice", "cell-2", 7, 1_010),
+                presence("alice", "cell-1", 3, 1_002),
+            ])
+            .unwrap();
+
+        let editors = projection.get_cell_editors_at("cell-2", 1_010);
+        assert_eq!(editors.len(), 1);
+        assert_eq!(editors[0].cursor_offset, Some(7));
+        let editors = projection.get_cell_editors_at("cell-1", 1_010);
+        assert_eq!(editors.len(), 1);
+        assert_eq!(editors[0].user_id, "bob");
+        assert!(projection.get_active_users_at("doc-2", 1_010).is_empty());
+    }
+
+    #[test]
+    fn test_presence_expires_after_ttl() {
+        let mut projection = PresenceProjection::with_ttl(30);
+        projection
+            .apply_new_events(&[
+                presence("alice", "cell-1", 0, 1_000),
+                presence("bob", "cell-2", 0, 1_020),
+            ])
+            .unwrap();
+
+        // Alice has gone quiet for longer than the TTL
+        let users = projection.get_active_users_at("doc-1", 1_040);
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].user_id, "bob");
+        assert!(projection.get_cell_editors_at("cell-1", 1_040).is_empty());
+
+        assert_eq!(projection.prune_expired(1_040), 1);
+        assert_eq!(projection.prune_expired(1_100), 1);
+        assert!(projection.get_state().documents.is_empty());
+
+        // Newer events prune stale entries as they arrive
+        projection
+            .apply_new_events(&[presence("carol", "cell-1", 0, 2_000)])
+            .unwrap();
+        projection
+            .apply_new_events(&[presence("dave", "cell-1", 0, 2_100)])
+            .unwrap();
+        assert_eq!(projection.get_state().documents["doc-1"].len(), 1);
+    }
+
+    #[test]
+    fn test_prune_presence_events() {
+        let created = EventBuilder::new()
+            .event_type("DocumentCreated")
+            .aggregate_id("doc-1")
+            .build(1)
+            .unwrap();
+        let events = vec![
+            created.clone(),
+            presence("alice", "cell-1", 0, 1_000),
+            presence("alice", "cell-1", 5, 1_010),
+            presence("bob", "cell-1", 0, 900),
+        ];
+
+        let kept = prune_presence_events(&events, 1_020, 30);
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0], created);
+        assert_eq!(kept[1].payload["cursor_offset"], 5);
+    }
+
+    #[test]
+    fn test_presence_has_its_own_aggregate() {
+        let event = update_presence_event(
+            "doc-1".to_string(),
+            "alice".to_string(),
+            Some("cell-1".to_string()),
+            Some(3),
+            1,
+        )
+        .unwrap();
+        assert_eq!(event.aggregate_id, "presence-doc-1");
+        assert_eq!(presence_document_id(&event.aggregate_id), Some("doc-1"));
+        assert_eq!(presence_document_id("doc-1"), None);
+
+        // The client's clock doesn't decide when the user was last seen
+        let mut skewed = presence("bob", "cell-1", 0, 1_000);
+        skewed.payload["timestamp"] = serde_json::json!(9_999);
+        // Updates from before presence had its own aggregate still count
+        let mut legacy = presence("carol", "cell-2", 0, 1_005);
+        legacy.aggregate_id = "doc-1".to_string();
+
+        let mut projection = PresenceProjection::with_ttl(30);
+        projection.apply_new_events(&[skewed, legacy]).unwrap();
+        let users = projection.get_active_users_at("doc-1", 1_010);
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].user_id, "bob");
+        assert_eq!(users[0].last_seen, 1_000);
+        assert_eq!(users[1].user_id, "carol");
+    }
+}
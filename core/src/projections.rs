@@ -0,0 +1,234 @@
+use crate::{Event, EventResult, Projection, UpcasterRegistry};
+use std::any::Any;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+/// Projection state that remembers how far into the log it has applied
+///
+/// Timestamps only have clock granularity, so besides the newest applied
+/// timestamp the state keeps the ids of the events applied at it; that tells
+/// a same-tick newcomer from a replay during incremental updates.
+pub(crate) trait ProcessedEvents {
+    /// The newest applied timestamp and the ids applied at it
+    fn processed_mut(&mut self) -> (&mut i64, &mut HashSet<String>);
+
+    fn processed(&self) -> (i64, &HashSet<String>);
+
+    /// Record `event` as the newest event applied
+    fn record_processed(&mut self, event: &Event) {
+        let (timestamp, event_ids) = self.processed_mut();
+        if event.timestamp != *timestamp {
+            event_ids.clear();
+        }
+        *timestamp = event.timestamp;
+        event_ids.insert(event.id.clone());
+    }
+
+    /// Whether `event` was applied at the newest timestamp, e.g. because a
+    /// snapshot already covers it
+    fn processed_at_last_timestamp(&self, event: &Event) -> bool {
+        let (timestamp, event_ids) = self.processed();
+        event.timestamp == timestamp && event_ids.contains(&event.id)
+    }
+
+    /// Whether `event` comes after everything applied so far
+    fn is_unprocessed(&self, event: &Event) -> bool {
+        let (timestamp, event_ids) = self.processed();
+        match event.timestamp.cmp(&timestamp) {
+            Ordering::Greater => true,
+            Ordering::Equal => !event_ids.contains(&event.id),
+            Ordering::Less => false,
+        }
+    }
+}
+
+/// Object-safe subset of [`Projection`], so projections with different
+/// state types can sit side by side in a [`ProjectionRegistry`]
+pub trait AnyProjection: Send + Sync {
+    fn rebuild_from_events(&mut self, events: &[Event]) -> EventResult<()>;
+
+    fn apply_new_events(&mut self, events: &[Event]) -> EventResult<()>;
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<P: Projection + Send + Sync + 'static> AnyProjection for P {
+    fn rebuild_from_events(&mut self, events: &[Event]) -> EventResult<()> {
+        Projection::rebuild_from_events(self, events)
+    }
+
+    fn apply_new_events(&mut self, events: &[Event]) -> EventResult<()> {
+        Projection::apply_new_events(self, events)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Several independent projections fed from the same event log
+///
+/// Events are fanned out to every registered projection in registration
+/// order. Each projection skips event types its materializer doesn't
+/// handle, so adding one for, say, presence costs the others nothing.
+#[derive(Default)]
+pub struct ProjectionRegistry {
+    projections: Vec<Box<dyn AnyProjection>>,
+    upcasters: UpcasterRegistry,
+}
+
+impl ProjectionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a projection, replacing any existing one of the same type
+    pub fn register<P: Projection + Send + Sync + 'static>(&mut self, projection: P) {
+        match self.projections.iter_mut().find(|p| p.as_any().is::<P>()) {
+            Some(existing) => *existing = Box::new(projection),
+            None => self.projections.push(Box::new(projection)),
+        }
+    }
+
+    /// Builder-style [`ProjectionRegistry::register`]
+    pub fn with<P: Projection + Send + Sync + 'static>(mut self, projection: P) -> Self {
+        self.register(projection);
+        self
+    }
+
+    /// Upcast old payload shapes before any projection sees them
+    pub fn with_upcasters(mut self, upcasters: UpcasterRegistry) -> Self {
+        self.upcasters = upcasters;
+        self
+    }
+
+    /// Get the registered projection of type `P`
+    pub fn get<P: Projection + 'static>(&self) -> Option<&P> {
+        self.projections
+            .iter()
+            .find_map(|p| p.as_any().downcast_ref::<P>())
+    }
+
+    /// Get the registered projection of type `P` mutably
+    pub fn get_mut<P: Projection + 'static>(&mut self) -> Option<&mut P> {
+        self.projections
+            .iter_mut()
+            .find_map(|p| p.as_any_mut().downcast_mut::<P>())
+    }
+
+    /// Number of registered projections
+    pub fn len(&self) -> usize {
+        self.projections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.projections.is_empty()
+    }
+
+    /// Apply new events to every projection
+    ///
+    /// A failing projection doesn't stop the others from being updated; the
+    /// first error is returned once all have run.
+    pub fn apply_new_events(&mut self, events: &[Event]) -> EventResult<()> {
+        let events = self.upcasters.upcast_all(events);
+        let mut result = Ok(());
+        for projection in &mut self.projections {
+            if let Err(e) = projection.apply_new_events(&events) {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+
+    /// Replay the whole log into every projection
+    pub fn rebuild_all(&mut self, events: &[Event]) -> EventResult<()> {
+        let events = self.upcasters.upcast_all(events);
+        let mut result = Ok(());
+        for projection in &mut self.projections {
+            if let Err(e) = projection.rebuild_from_events(&events) {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+}
+
+impl std::fmt::Debug for ProjectionRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProjectionRegistry")
+            .field("projections", &self.projections.len())
+            .field("upcasters", &self.upcasters)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DocumentProjection, EventBuilder};
+
+    /// Counts every event it's given, whatever the type
+    #[derive(Debug, Clone, Default)]
+    struct CountingProjection {
+        count: usize,
+    }
+
+    impl Projection for CountingProjection {
+        type State = usize;
+
+        fn rebuild_from_events(&mut self, events: &[Event]) -> EventResult<()> {
+            self.count = events.len();
+            Ok(())
+        }
+
+        fn get_state(&self) -> &usize {
+            &self.count
+        }
+
+        fn last_processed_timestamp(&self) -> i64 {
+            0
+        }
+
+        fn apply_new_events(&mut self, events: &[Event]) -> EventResult<()> {
+            self.count += events.len();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_registry_fans_out_events() {
+        let mut registry = ProjectionRegistry::new()
+            .with(DocumentProjection::new())
+            .with(CountingProjection::default());
+        assert_eq!(registry.len(), 2);
+
+        let created = EventBuilder::new()
+            .event_type("DocumentCreated")
+            .aggregate_id("doc-1")
+            .payload(serde_json::json!({"title": "Shared log"}))
+            .unwrap()
+            .build(1)
+            .unwrap();
+        registry
+            .apply_new_events(std::slice::from_ref(&created))
+            .unwrap();
+
+        let documents = registry.get::<DocumentProjection>().unwrap();
+        assert_eq!(documents.get_document("doc-1").unwrap().title, "Shared log");
+        assert_eq!(registry.get::<CountingProjection>().unwrap().count, 1);
+
+        registry.rebuild_all(&[created.clone(), created]).unwrap();
+        assert_eq!(registry.get::<CountingProjection>().unwrap().count, 2);
+
+        // Registering the same type again replaces it
+        registry.register(CountingProjection::default());
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.get_mut::<CountingProjection>().unwrap().count, 0);
+    }
+}
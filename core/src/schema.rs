@@ -0,0 +1,424 @@
+use crate::{Event, EventError, EventResult};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// JSON type a payload field must have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    String,
+    Number,
+    Bool,
+    Object,
+    Array,
+    /// A string that is a valid fractional index, so a bad one is refused
+    /// before it's stored rather than failing every later replay
+    FractionalIndex,
+}
+
+impl FieldKind {
+    fn matches(self, value: &serde_json::Value) -> bool {
+        match self {
+            FieldKind::String => value.is_string(),
+            FieldKind::Number => value.is_number(),
+            FieldKind::Bool => value.is_boolean(),
+            FieldKind::Object => value.is_object(),
+            FieldKind::Array => value.is_array(),
+            FieldKind::FractionalIndex => value
+                .as_str()
+                .is_some_and(|index| crate::fractional_index::validate_index(index).is_ok()),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            FieldKind::String => "a string",
+            FieldKind::Number => "a number",
+            FieldKind::Bool => "a boolean",
+            FieldKind::Object => "an object",
+            FieldKind::Array => "an array",
+            FieldKind::FractionalIndex => "a valid fractional index",
+        }
+    }
+}
+
+/// Fields an event type's payload must or may carry
+///
+/// Fields not listed are allowed; listed optional fields are only checked
+/// when present and not null, and nullable fields must be present but may
+/// be null.
+#[derive(Debug, Clone, Default)]
+pub struct PayloadSchema {
+    required: Vec<(String, FieldKind)>,
+    optional: Vec<(String, FieldKind)>,
+    nullable: Vec<(String, FieldKind)>,
+}
+
+impl PayloadSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn required<S: Into<String>>(mut self, field: S, kind: FieldKind) -> Self {
+        self.required.push((field.into(), kind));
+        self
+    }
+
+    pub fn optional<S: Into<String>>(mut self, field: S, kind: FieldKind) -> Self {
+        self.optional.push((field.into(), kind));
+        self
+    }
+
+    pub fn nullable<S: Into<String>>(mut self, field: S, kind: FieldKind) -> Self {
+        self.nullable.push((field.into(), kind));
+        self
+    }
+
+    /// Check a payload, describing the first problem found
+    pub fn validate(&self, payload: &serde_json::Value) -> Result<(), String> {
+        let Some(object) = payload.as_object() else {
+            return Err("payload must be a JSON object".to_string());
+        };
+
+        for (field, kind) in &self.required {
+            match object.get(field) {
+                None | Some(serde_json::Value::Null) => {
+                    return Err(format!("missing {}", field));
+                }
+                Some(value) if !kind.matches(value) => {
+                    return Err(format!("{} must be {}", field, kind.name()));
+                }
+                Some(_) => {}
+            }
+        }
+        for (field, kind) in &self.optional {
+            match object.get(field) {
+                Some(value) if !value.is_null() && !kind.matches(value) => {
+                    return Err(format!("{} must be {}", field, kind.name()));
+                }
+                _ => {}
+            }
+        }
+        for (field, kind) in &self.nullable {
+            match object.get(field) {
+                None => return Err(format!("missing {}", field)),
+                Some(value) if !value.is_null() && !kind.matches(value) => {
+                    return Err(format!("{} must be {} or null", field, kind.name()));
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+type PayloadValidator = Arc<dyn Fn(&serde_json::Value) -> Result<(), String> + Send + Sync>;
+
+/// Payload validation per event type, checked before events are stored
+///
+/// The materializer only notices a malformed payload when it applies the
+/// event, by which point the event is already durable. Checking against a
+/// registry first keeps such events out of the log. Event types without a
+/// registered validator are accepted as-is.
+#[derive(Clone, Default)]
+pub struct EventSchemaRegistry {
+    validators: HashMap<String, PayloadValidator>,
+}
+
+impl EventSchemaRegistry {
+    /// An empty registry that accepts every payload
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry with schemas for the document, cell, presence and comment event types
+    pub fn with_builtin_schemas() -> Self {
+        use FieldKind::*;
+
+        let cell = || PayloadSchema::new().required("cell_id", String);
+        let session = || PayloadSchema::new().required("session_id", String);
+
+        let mut registry = Self::new();
+        registry.register_schema(
+            "DocumentCreated",
+            PayloadSchema::new()
+                .optional("title", String)
+                .optional("metadata", Object),
+        );
+        registry.register_schema(
+            "DocumentTitleUpdated",
+            PayloadSchema::new().required("title", String),
+        );
+        registry.register_schema(
+            "DocumentMetadataUpdated",
+            PayloadSchema::new().required("metadata", Object),
+        );
+        registry.register_schema(
+            "DocumentTagAdded",
+            PayloadSchema::new().required("tag", String),
+        );
+        registry.register_schema(
+            "DocumentTagRemoved",
+            PayloadSchema::new().required("tag", String),
+        );
+        registry.register_schema(
+            "DocumentCustomFieldSet",
+            PayloadSchema::new()
+                .required("key", String)
+                .nullable("value", String),
+        );
+        registry.register_schema(
+            "CellCreated",
+            cell()
+                .required("cell_type", String)
+                .optional("source", String)
+                .optional("fractional_index", FractionalIndex)
+                .optional("execution_count", Number)
+                .optional("ai_settings", Object)
+                .optional("source_visible", Bool)
+                .optional("output_visible", Bool),
+        );
+        registry.register_schema("CellSourceUpdated", cell().required("source", String));
+        registry.register_schema("CellTypeChanged", cell().required("cell_type", String));
+        registry.register_schema(
+            "CellVisibilityChanged",
+            cell()
+                .optional("source_visible", Bool)
+                .optional("output_visible", Bool)
+                .optional("ai_context_visible", Bool),
+        );
+        registry.register_schema(
+            "CellAiConfigUpdated",
+            cell()
+                .optional("ai_provider", String)
+                .optional("ai_model", String)
+                .optional("ai_settings", Object),
+        );
+        registry.register_schema(
+            "CellExecutionStateChanged",
+            cell()
+                .required("execution_state", String)
+                .optional("execution_count", Number),
+        );
+        registry.register_schema(
+            "CellOutputCreated",
+            cell()
+                .required("output_id", String)
+                .required("output_type", String)
+                .optional("position", Number)
+                .optional("representations", Object),
+        );
+        registry.register_schema(
+            "CellOutputAppended",
+            PayloadSchema::new()
+                .required("output_id", String)
+                .optional("cell_id", String)
+                .optional("data", String)
+                .optional("representations", Object),
+        );
+        registry.register_schema(
+            "CellOutputRepositioned",
+            PayloadSchema::new()
+                .required("output_id", String)
+                .required("position", Number),
+        );
+        registry.register_schema("CellOutputsCleared", cell());
+        registry.register_schema(
+            "CellMoved",
+            cell().required("fractional_index", FractionalIndex),
+        );
+        registry.register_schema("CellDeleted", cell());
+        registry.register_schema("CellRestored", cell());
+        registry.register_schema(
+            "RuntimeSessionStarted",
+            session()
+                .required("runtime_id", String)
+                .required("runtime_type", String)
+                .optional("available_ai_models", Array),
+        );
+        registry.register_schema(
+            "RuntimeSessionStatusChanged",
+            session().required("status", String),
+        );
+        registry.register_schema("RuntimeSessionTerminated", session());
+        registry.register_schema(
+            "UserPresenceUpdated",
+            PayloadSchema::new()
+                .required("user_id", String)
+                .optional("cell_id", String)
+                .optional("cursor_offset", Number)
+                .optional("timestamp", Number),
+        );
+        let comment = || PayloadSchema::new().required("comment_id", String);
+        registry.register_schema(
+            "CommentAdded",
+            comment()
+                .required("cell_id", String)
+                .required("author", String)
+                .required("body", String)
+                .optional("parent_id", String),
+        );
+        registry.register_schema("CommentEdited", comment().required("body", String));
+        registry.register_schema("CommentResolved", comment().optional("resolved", Bool));
+        registry.register_schema("CommentDeleted", comment());
+        registry.register_schema(
+            "SnapshotCreated",
+            PayloadSchema::new()
+                .required("documents", Object)
+                .required("comments", Object)
+                .required("versions", Object),
+        );
+        registry
+    }
+
+    /// Validate an event type's payloads against a schema
+    pub fn register_schema<S: Into<String>>(&mut self, event_type: S, schema: PayloadSchema) {
+        self.register_validator(event_type, move |payload| schema.validate(payload));
+    }
+
+    /// Validate an event type's payloads with a custom check
+    ///
+    /// Replaces any schema or validator already registered for the type.
+    pub fn register_validator<S, F>(&mut self, event_type: S, validator: F)
+    where
+        S: Into<String>,
+        F: Fn(&serde_json::Value) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.validators
+            .insert(event_type.into(), Arc::new(validator));
+    }
+
+    /// Check whether an event type has a validator
+    pub fn has_schema(&self, event_type: &str) -> bool {
+        self.validators.contains_key(event_type)
+    }
+
+    /// Check a payload for an event type
+    pub fn validate_payload(
+        &self,
+        event_type: &str,
+        payload: &serde_json::Value,
+    ) -> EventResult<()> {
+        match self.validators.get(event_type) {
+            Some(validator) => validator(payload).map_err(|reason| {
+                EventError::ValidationError(format!("Invalid {} payload: {}", event_type, reason))
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Check an event's payload before it is appended
+    pub fn validate(&self, event: &Event) -> EventResult<()> {
+        self.validate_payload(&event.event_type, &event.payload)
+    }
+}
+
+impl std::fmt::Debug for EventSchemaRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut event_types: Vec<&String> = self.validators.keys().collect();
+        event_types.sort();
+        f.debug_struct("EventSchemaRegistry")
+            .field("event_types", &event_types)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_builtin_schemas_accept_valid_payloads() {
+        let registry = EventSchemaRegistry::with_builtin_schemas();
+
+        registry
+            .validate_payload(
+                "CellCreated",
+                &json!({"cell_id": "cell-1", "cell_type": "code", "source": "1 + 1"}),
+            )
+            .unwrap();
+        registry
+            .validate_payload("DocumentCreated", &json!({"title": "Notebook"}))
+            .unwrap();
+        registry
+            .validate_payload(
+                "CellOutputRepositioned",
+                &json!({"output_id": "out-1", "position": 2.5}),
+            )
+            .unwrap();
+        registry
+            .validate_payload(
+                "DocumentCustomFieldSet",
+                &json!({"key": "owner", "value": null}),
+            )
+            .unwrap();
+        // Types without a schema pass through
+        registry
+            .validate_payload("SomethingCustom", &json!(null))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_builtin_schemas_reject_malformed_payloads() {
+        let registry = EventSchemaRegistry::with_builtin_schemas();
+
+        let err = registry
+            .validate_payload("CellCreated", &json!({"cell_type": "code"}))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            EventError::ValidationError("Invalid CellCreated payload: missing cell_id".to_string())
+        );
+
+        assert!(registry
+            .validate_payload(
+                "CellMoved",
+                &json!({"cell_id": "cell-1", "fractional_index": 3})
+            )
+            .is_err());
+        assert!(registry
+            .validate_payload("DocumentCustomFieldSet", &json!({"key": "owner"}))
+            .is_err());
+        let err = registry
+            .validate_payload(
+                "CellCreated",
+                &json!({"cell_id": "cell-1", "cell_type": "code", "fractional_index": "a b"}),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err,
+            EventError::ValidationError(
+                "Invalid CellCreated payload: fractional_index must be a valid fractional index"
+                    .to_string()
+            )
+        );
+        assert!(registry
+            .validate_payload("DocumentTitleUpdated", &json!("just a string"))
+            .is_err());
+        assert!(registry
+            .validate_payload(
+                "CellCreated",
+                &json!({"cell_id": "cell-1", "cell_type": "code", "source_visible": "yes"}),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_custom_validator() {
+        let mut registry = EventSchemaRegistry::new();
+        registry.register_validator("CommentAdded", |payload| {
+            match payload.get("text").and_then(|v| v.as_str()) {
+                Some(text) if !text.trim().is_empty() => Ok(()),
+                _ => Err("text must not be empty".to_string()),
+            }
+        });
+
+        assert!(registry.has_schema("CommentAdded"));
+        registry
+            .validate_payload("CommentAdded", &json!({"text": "Nice plot"}))
+            .unwrap();
+        assert!(registry
+            .validate_payload("CommentAdded", &json!({"text": "  "}))
+            .is_err());
+    }
+}
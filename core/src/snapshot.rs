@@ -0,0 +1,235 @@
+//! Snapshots that let a store drop the history behind its projections
+//!
+//! A `SnapshotCreated` event carries the materialized document and comment
+//! state, plus the latest version of every aggregate at the time. Replaying
+//! it replaces those projections wholesale, so a log that starts with a
+//! snapshot rebuilds to the same state as the history it stands in for, and
+//! writers keep numbering their aggregates from where they were.
+//!
+//! The price is history: once the events before a snapshot are pruned,
+//! anything that replays the log ([`DocumentProjection::rebuild_as_of`],
+//! [`cell_history`], undo through [`invert_transaction`]) can't see past it.
+//! Presence isn't captured either; it's short-lived and refills from the
+//! next round of updates.
+//!
+//! [`DocumentProjection::rebuild_as_of`]: crate::document::DocumentProjection::rebuild_as_of
+//! [`cell_history`]: crate::document::cell_history
+//! [`invert_transaction`]: crate::document::invert_transaction
+
+use crate::comment::CommentProjectionState;
+use crate::document::DocumentProjectionState;
+use crate::{Event, EventError, EventResult};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+
+/// Event type of a snapshot
+pub const SNAPSHOT_EVENT_TYPE: &str = "SnapshotCreated";
+
+/// Build a snapshot of a store's projections
+///
+/// `versions` should be the store's
+/// [`InMemoryEventStore::latest_versions`](crate::InMemoryEventStore::latest_versions)
+/// and `sequence` its
+/// [`InMemoryEventStore::latest_sequence`](crate::InMemoryEventStore::latest_sequence),
+/// so a log reloaded from the snapshot numbers on from there.
+/// `timestamp` should be no earlier than any event the snapshot covers; the
+/// store sorts a snapshot after every event sharing its timestamp. Events
+/// written afterwards need later timestamps to replay after it, see
+/// [`InMemoryEventStore::snapshot_timestamp`](crate::InMemoryEventStore::snapshot_timestamp).
+pub fn snapshot_event(
+    documents: &DocumentProjectionState,
+    comments: &CommentProjectionState,
+    versions: &HashMap<String, i64>,
+    sequence: u64,
+    timestamp: i64,
+) -> EventResult<Event> {
+    use crate::EventBuilder;
+
+    EventBuilder::new()
+        .event_type(SNAPSHOT_EVENT_TYPE)
+        .aggregate_id(format!("snapshot-{}", crate::generate_event_id()))
+        .payload(serde_json::json!({
+            "documents": documents,
+            "comments": comments,
+            "versions": versions,
+            "sequence": sequence,
+        }))?
+        .timestamp(timestamp)
+        .build(1)
+}
+
+/// The aggregate versions a snapshot records
+pub fn snapshot_versions(event: &Event) -> impl Iterator<Item = (&str, i64)> {
+    event
+        .payload
+        .get("versions")
+        .and_then(|v| v.as_object())
+        .into_iter()
+        .flatten()
+        .filter_map(|(aggregate_id, version)| Some((aggregate_id.as_str(), version.as_i64()?)))
+}
+
+/// The store sequence a snapshot stands in for; 0 if it doesn't record one
+pub fn snapshot_sequence(event: &Event) -> u64 {
+    event
+        .payload
+        .get("sequence")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0)
+}
+
+/// Read one projection's state out of a snapshot
+pub(crate) fn restore_state<S: DeserializeOwned>(event: &Event, field: &str) -> EventResult<S> {
+    let state = event
+        .payload
+        .get(field)
+        .cloned()
+        .ok_or_else(|| EventError::ValidationError(format!("Snapshot is missing {}", field)))?;
+    serde_json::from_value(state)
+        .map_err(|e| EventError::ValidationError(format!("Invalid {} snapshot: {}", field, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comment::{create_comment_event, CommentProjection};
+    use crate::document::{
+        create_cell_event, create_document_event, update_cell_source_event, CellType,
+        DocumentMetadata, DocumentProjection,
+    };
+    use crate::{EventStore, InMemoryEventStore, Projection};
+
+    fn state_json(documents: &DocumentProjectionState) -> serde_json::Value {
+        serde_json::json!({
+            "documents": documents.documents,
+            "cells": documents.cells,
+            "outputs": documents.outputs,
+            "collaborators": documents.collaborators,
+        })
+    }
+
+    #[test]
+    fn test_compacted_store_replays_to_same_state() {
+        let doc = || "doc-1".to_string();
+        let mut store = InMemoryEventStore::strict();
+        let mut events = vec![
+            create_document_event(doc(), "Notebook".into(), DocumentMetadata::default(), 1)
+                .unwrap(),
+            create_cell_event(
+                doc(),
+                "cell-1".into(),
+                CellType::Code,
+                "x = 1".into(),
+                Some("a".into()),
+                "alice".into(),
+                2,
+            )
+            .unwrap(),
+            update_cell_source_event(doc(), "cell-1".into(), "x = 2".into(), 3).unwrap(),
+            create_comment_event(
+                doc(),
+                "comment-1".into(),
+                "cell-1".into(),
+                "bob".into(),
+                "Why 2?".into(),
+                None,
+                4,
+            )
+            .unwrap(),
+        ];
+        // Everything lands in the same tick as the snapshot
+        for event in &mut events {
+            event.timestamp = 100;
+        }
+        store.append_events(events).unwrap();
+
+        let mut documents = DocumentProjection::new();
+        let mut comments = CommentProjection::new();
+        let history = store.get_all_events().unwrap();
+        documents.rebuild_from_events(&history).unwrap();
+        comments.rebuild_from_events(&history).unwrap();
+
+        let snapshot = snapshot_event(
+            documents.get_state(),
+            comments.get_state(),
+            &store.latest_versions(),
+            store.latest_sequence(),
+            100,
+        )
+        .unwrap();
+
+        // Kept alongside the history, a replay doesn't apply covered events twice
+        let mut kept = store.clone();
+        kept.append_event(snapshot.clone()).unwrap();
+        let mut replayed = DocumentProjection::new();
+        replayed
+            .rebuild_from_events(&kept.get_all_events().unwrap())
+            .unwrap();
+        assert_eq!(
+            state_json(replayed.get_state()),
+            state_json(documents.get_state())
+        );
+
+        store.compact(snapshot).unwrap();
+        assert_eq!(store.get_event_count(), 1);
+        assert_eq!(store.get_latest_version("doc-1"), 4);
+
+        let compacted = store.get_all_events().unwrap();
+        let mut replayed = DocumentProjection::new();
+        replayed.rebuild_from_events(&compacted).unwrap();
+        assert_eq!(
+            state_json(replayed.get_state()),
+            state_json(documents.get_state())
+        );
+        let mut replayed_comments = CommentProjection::new();
+        replayed_comments.rebuild_from_events(&compacted).unwrap();
+        assert_eq!(
+            replayed_comments.get_comment("comment-1").unwrap().body,
+            "Why 2?"
+        );
+
+        // Writers number on from where they were, before and after a reload
+        let next = update_cell_source_event(doc(), "cell-1".into(), "x = 3".into(), 5).unwrap();
+        let mut reloaded = InMemoryEventStore::strict();
+        reloaded
+            .append_events(vec![compacted[0].clone(), next.clone()])
+            .unwrap();
+        store.append_event(next).unwrap();
+        assert_eq!(reloaded.get_latest_version("doc-1"), 5);
+    }
+
+    #[test]
+    fn test_snapshot_sorts_after_events_sharing_its_timestamp() {
+        let mut store = InMemoryEventStore::new();
+        let mut events = vec![
+            create_document_event(
+                "doc-1".into(),
+                "Notebook".into(),
+                DocumentMetadata::default(),
+                1,
+            )
+            .unwrap(),
+            update_cell_source_event("doc-1".into(), "cell-1".into(), "x".into(), 2).unwrap(),
+        ];
+        for event in &mut events {
+            event.timestamp = 100;
+        }
+        store.append_events(events).unwrap();
+        assert_eq!(store.snapshot_timestamp(), None);
+
+        let snapshot = snapshot_event(
+            &DocumentProjectionState::default(),
+            &CommentProjectionState::default(),
+            &store.latest_versions(),
+            store.latest_sequence(),
+            100,
+        )
+        .unwrap();
+        store.append_event(snapshot.clone()).unwrap();
+
+        assert_eq!(store.iter_events().last().unwrap().id, snapshot.id);
+        assert_eq!(store.snapshot_timestamp(), Some(100));
+        store.truncate(2);
+        assert_eq!(store.snapshot_timestamp(), None);
+    }
+}
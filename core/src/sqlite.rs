@@ -0,0 +1,424 @@
+//! Turso/SQLite-backed event store
+//!
+//! `EventStore` is synchronous while Turso's API is async. Rather than add an
+//! async variant of the trait (which would ripple through every projection,
+//! the server handlers and the WASM client), this store drives Turso's futures
+//! to completion on the calling thread with a minimal executor. Turso performs
+//! local I/O inline and reports a busy database as an error, so its futures
+//! normally finish on the first poll; one that does suspend parks the thread
+//! until its waker fires rather than spinning. Unlike `Runtime::block_on`,
+//! calling from inside a tokio task doesn't panic, though it does block the
+//! worker, so async callers should go through `spawn_blocking`. If we later
+//! move to a remote (network-backed) database that genuinely suspends, an
+//! async `EventStore` becomes worth it.
+
+use crate::{Event, EventError, EventResult, EventStore};
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::Thread;
+use turso::{Connection, Value};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS events (
+    id TEXT PRIMARY KEY,
+    event_type TEXT NOT NULL,
+    aggregate_id TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    version INTEGER NOT NULL,
+    transaction_id TEXT,
+    actor TEXT
+);
+CREATE UNIQUE INDEX IF NOT EXISTS events_aggregate_version ON events (aggregate_id, version);
+";
+
+const SELECT_COLUMNS: &str =
+    "SELECT id, event_type, aggregate_id, payload, timestamp, version, transaction_id, actor FROM events";
+
+/// Columns added after the table was first shipped, for databases created
+/// before them
+const MIGRATIONS: &[&str] = &["ALTER TABLE events ADD COLUMN actor TEXT"];
+
+/// Event store persisted in a Turso (SQLite-compatible) database
+#[derive(Debug, Clone)]
+pub struct SqliteEventStore {
+    conn: Connection,
+}
+
+impl SqliteEventStore {
+    /// Open (or create) a database file and wrap it in a store
+    ///
+    /// Use `":memory:"` for a throwaway database.
+    pub fn open(path: &str) -> EventResult<Self> {
+        let db = block_on(turso::Builder::new_local(path).build()).map_err(storage_error)?;
+        let conn = db.connect().map_err(storage_error)?;
+        Self::new(conn)
+    }
+
+    /// Wrap an existing connection, creating the events table if needed
+    pub fn new(conn: Connection) -> EventResult<Self> {
+        block_on(conn.execute_batch(SCHEMA)).map_err(storage_error)?;
+        for migration in MIGRATIONS {
+            // Fails with a duplicate column error once already applied
+            let _ = block_on(conn.execute_batch(migration));
+        }
+        Ok(Self { conn })
+    }
+
+    fn query_events(&self, sql: &str, params: Vec<Value>) -> EventResult<Vec<Event>> {
+        block_on(async {
+            let mut rows = self.conn.query(sql, params).await?;
+            let mut events = Vec::new();
+            while let Some(row) = rows.next().await? {
+                events.push((
+                    row.get::<String>(0)?,
+                    row.get::<String>(1)?,
+                    row.get::<String>(2)?,
+                    row.get::<String>(3)?,
+                    row.get::<i64>(4)?,
+                    row.get::<i64>(5)?,
+                    row.get::<Option<String>>(6)?,
+                    row.get::<Option<String>>(7)?,
+                ));
+            }
+            Ok::<_, turso::Error>(events)
+        })
+        .map_err(storage_error)?
+        .into_iter()
+        .map(
+            |(id, event_type, aggregate_id, payload, timestamp, version, transaction_id, actor)| {
+                Ok(Event {
+                    id,
+                    event_type,
+                    aggregate_id,
+                    payload: serde_json::from_str(&payload)
+                        .map_err(|e| EventError::SerializationError(e.to_string()))?,
+                    timestamp,
+                    version,
+                    transaction_id,
+                    actor,
+                })
+            },
+        )
+        .collect()
+    }
+
+    fn query_integer(&self, sql: &str, params: Vec<Value>) -> EventResult<i64> {
+        block_on(async {
+            let mut rows = self.conn.query(sql, params).await?;
+            match rows.next().await? {
+                Some(row) => row.get::<Option<i64>>(0),
+                None => Ok(None),
+            }
+        })
+        .map(|value| value.unwrap_or(0))
+        .map_err(storage_error)
+    }
+}
+
+impl EventStore for SqliteEventStore {
+    fn append_event(&mut self, event: Event) -> EventResult<()> {
+        // Check for duplicate event ID
+        let existing = self.query_integer(
+            "SELECT COUNT(*) FROM events WHERE id = ?",
+            vec![Value::Text(event.id.clone())],
+        )?;
+        if existing > 0 {
+            return Err(EventError::DuplicateEventId(event.id));
+        }
+
+        // Check version ordering; the unique index backs this up
+        let expected_version = self.get_latest_version(&event.aggregate_id) + 1;
+        if event.version != expected_version {
+            return Err(EventError::InvalidVersion {
+                expected: expected_version,
+                got: event.version,
+            });
+        }
+
+        let payload = serde_json::to_string(&event.payload)
+            .map_err(|e| EventError::SerializationError(e.to_string()))?;
+        let params = vec![
+            Value::Text(event.id),
+            Value::Text(event.event_type),
+            Value::Text(event.aggregate_id),
+            Value::Text(payload),
+            Value::Integer(event.timestamp),
+            Value::Integer(event.version),
+            event.transaction_id.map(Value::Text).unwrap_or(Value::Null),
+            event.actor.map(Value::Text).unwrap_or(Value::Null),
+        ];
+        block_on(self.conn.execute(
+            "INSERT INTO events (id, event_type, aggregate_id, payload, timestamp, version, transaction_id, actor) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            params,
+        ))
+        .map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn append_events(&mut self, events: Vec<Event>) -> EventResult<()> {
+        // Checks inside the transaction see the batch's earlier rows
+        block_on(self.conn.execute_batch("BEGIN")).map_err(storage_error)?;
+        for event in events {
+            if let Err(e) = self.append_event(event) {
+                block_on(self.conn.execute_batch("ROLLBACK")).map_err(storage_error)?;
+                return Err(e);
+            }
+        }
+        block_on(self.conn.execute_batch("COMMIT")).map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn get_events(&self, aggregate_id: &str) -> EventResult<Vec<Event>> {
+        self.query_events(
+            &format!("{} WHERE aggregate_id = ? ORDER BY version", SELECT_COLUMNS),
+            vec![Value::Text(aggregate_id.to_string())],
+        )
+    }
+
+    fn get_events_since_version(
+        &self,
+        aggregate_id: &str,
+        after_version: i64,
+    ) -> EventResult<Vec<Event>> {
+        self.query_events(
+            &format!(
+                "{} WHERE aggregate_id = ? AND version > ? ORDER BY version",
+                SELECT_COLUMNS
+            ),
+            vec![
+                Value::Text(aggregate_id.to_string()),
+                Value::Integer(after_version),
+            ],
+        )
+    }
+
+    fn get_all_events(&self) -> EventResult<Vec<Event>> {
+        self.query_events(
+            &format!("{} ORDER BY timestamp, version, rowid", SELECT_COLUMNS),
+            Vec::new(),
+        )
+    }
+
+    fn get_events_of_type(&self, event_type: &str) -> EventResult<Vec<Event>> {
+        self.query_events(
+            &format!(
+                "{} WHERE event_type = ? ORDER BY timestamp, version, rowid",
+                SELECT_COLUMNS
+            ),
+            vec![Value::Text(event_type.to_string())],
+        )
+    }
+
+    fn get_events_between(&self, start_ts: i64, end_ts: i64) -> EventResult<Vec<Event>> {
+        self.query_events(
+            &format!(
+                "{} WHERE timestamp BETWEEN ? AND ? ORDER BY timestamp, version, rowid",
+                SELECT_COLUMNS
+            ),
+            vec![Value::Integer(start_ts), Value::Integer(end_ts)],
+        )
+    }
+
+    fn get_latest_version(&self, aggregate_id: &str) -> i64 {
+        self.query_integer(
+            "SELECT MAX(version) FROM events WHERE aggregate_id = ?",
+            vec![Value::Text(aggregate_id.to_string())],
+        )
+        .unwrap_or(0)
+    }
+
+    fn get_event_count(&self) -> usize {
+        self.query_integer("SELECT COUNT(*) FROM events", Vec::new())
+            .unwrap_or(0) as usize
+    }
+
+    fn get_aggregate_ids(&self) -> Vec<String> {
+        block_on(async {
+            let mut rows = self
+                .conn
+                .query(
+                    "SELECT DISTINCT aggregate_id FROM events ORDER BY aggregate_id",
+                    Vec::<Value>::new(),
+                )
+                .await?;
+            let mut aggregate_ids = Vec::new();
+            while let Some(row) = rows.next().await? {
+                aggregate_ids.push(row.get::<String>(0)?);
+            }
+            Ok::<_, turso::Error>(aggregate_ids)
+        })
+        .unwrap_or_default()
+    }
+}
+
+fn storage_error(err: turso::Error) -> EventError {
+    EventError::StorageError(err.to_string())
+}
+
+/// Wakes a thread parked in [`block_on`]
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run a Turso future to completion on the current thread
+///
+/// Between polls the thread parks until the future wakes it. A wake that
+/// lands before the park leaves the thread's token set, so it isn't lost.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        // Spurious wakeups just poll again
+        std::thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EventBuilder;
+
+    fn event(aggregate_id: &str, version: i64, timestamp: i64) -> Event {
+        EventBuilder::new()
+            .event_type("DocumentTitleUpdated")
+            .aggregate_id(aggregate_id)
+            .payload(serde_json::json!({ "title": format!("v{}", version) }))
+            .unwrap()
+            .timestamp(timestamp)
+            .actor("alice")
+            .build(version)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_sqlite_store_round_trip() {
+        let mut store = SqliteEventStore::open(":memory:").unwrap();
+
+        let first = event("doc-1", 1, 200);
+        store.append_event(first.clone()).unwrap();
+        store.append_event(event("doc-2", 1, 100)).unwrap();
+        store.append_event(event("doc-1", 2, 300)).unwrap();
+
+        assert_eq!(store.get_event_count(), 3);
+        assert_eq!(store.get_latest_version("doc-1"), 2);
+        assert_eq!(store.get_latest_version("missing"), 0);
+        assert_eq!(store.get_aggregate_ids(), vec!["doc-1", "doc-2"]);
+
+        let doc_events = store.get_events("doc-1").unwrap();
+        assert_eq!(doc_events[0], first);
+        assert_eq!(doc_events[1].version, 2);
+
+        let timestamps: Vec<_> = store
+            .get_all_events()
+            .unwrap()
+            .iter()
+            .map(|e| e.timestamp)
+            .collect();
+        assert_eq!(timestamps, vec![100, 200, 300]);
+
+        assert_eq!(
+            store
+                .get_events_of_type("DocumentTitleUpdated")
+                .unwrap()
+                .len(),
+            3
+        );
+        assert!(store.get_events_of_type("CellCreated").unwrap().is_empty());
+
+        let between = store.get_events_between(200, 300).unwrap();
+        assert_eq!(between.len(), 2);
+        assert_eq!(between[0], first);
+    }
+
+    #[test]
+    fn test_sqlite_append_events_rolls_back() {
+        let mut store = SqliteEventStore::open(":memory:").unwrap();
+
+        let gapped = vec![event("doc-1", 1, 100), event("doc-1", 3, 200)];
+        assert!(matches!(
+            store.append_events(gapped),
+            Err(EventError::InvalidVersion {
+                expected: 2,
+                got: 3
+            })
+        ));
+        assert_eq!(store.get_event_count(), 0);
+
+        store
+            .append_events(vec![event("doc-1", 1, 100), event("doc-1", 2, 200)])
+            .unwrap();
+        assert_eq!(store.get_latest_version("doc-1"), 2);
+    }
+
+    #[test]
+    fn test_sqlite_store_rejects_bad_appends() {
+        let mut store = SqliteEventStore::open(":memory:").unwrap();
+        let first = event("doc-1", 1, 100);
+        store.append_event(first.clone()).unwrap();
+
+        assert_eq!(
+            store.append_event(first.clone()),
+            Err(EventError::DuplicateEventId(first.id))
+        );
+        assert_eq!(
+            store.append_event(event("doc-1", 3, 200)),
+            Err(EventError::InvalidVersion {
+                expected: 2,
+                got: 3
+            })
+        );
+        assert_eq!(store.get_event_count(), 1);
+    }
+
+    #[test]
+    fn test_block_on_parks_until_woken() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        /// Pending until a helper thread sets the flag and wakes it
+        struct WokenLater {
+            done: Arc<AtomicBool>,
+            started: bool,
+        }
+
+        impl Future for WokenLater {
+            type Output = ();
+
+            fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+                if self.done.load(Ordering::SeqCst) {
+                    return Poll::Ready(());
+                }
+                if !self.started {
+                    self.started = true;
+                    let (done, waker) = (self.done.clone(), cx.waker().clone());
+                    std::thread::spawn(move || {
+                        std::thread::sleep(std::time::Duration::from_millis(20));
+                        done.store(true, Ordering::SeqCst);
+                        waker.wake();
+                    });
+                }
+                Poll::Pending
+            }
+        }
+
+        block_on(WokenLater {
+            done: Arc::new(AtomicBool::new(false)),
+            started: false,
+        });
+    }
+}
@@ -0,0 +1,237 @@
+//! Reading old payload shapes with current materializers
+//!
+//! Stored events are never rewritten, so when a payload changes shape the old
+//! events stay in the log as they were. An [`UpcasterRegistry`] converts them
+//! on the way into the projections instead. Readers get the shared set from
+//! [`UpcasterRegistry::standard`], either through
+//! [`ProjectionRegistry::with_upcasters`](crate::ProjectionRegistry::with_upcasters)
+//! or by upcasting before replaying into a projection directly.
+//!
+//! A payload's shape is numbered by its `schema_version` field. Events
+//! written before the field existed have none and count as version 1, so the
+//! first change to a payload should be stamped version 2 by its writers.
+
+use crate::Event;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+/// Payload field holding the shape version of an event's payload
+pub const SCHEMA_VERSION_FIELD: &str = "schema_version";
+
+/// The shape version of an event's payload, 1 if it isn't stamped
+pub fn schema_version(event: &Event) -> u32 {
+    event
+        .payload
+        .get(SCHEMA_VERSION_FIELD)
+        .and_then(|v| v.as_u64())
+        .and_then(|v| u32::try_from(v).ok())
+        .unwrap_or(1)
+}
+
+/// Converts an event from one payload shape to the next
+pub trait EventUpcaster: Send + Sync {
+    fn upcast(&self, event: Event) -> Event;
+}
+
+impl<F: Fn(Event) -> Event + Send + Sync> EventUpcaster for F {
+    fn upcast(&self, event: Event) -> Event {
+        self(event)
+    }
+}
+
+/// Upcasters keyed by event type and the payload version they read
+///
+/// Each upcaster only has to take its version one step forward: the registry
+/// stamps the result with the next version and keeps going until no upcaster
+/// is registered for where the event has got to.
+#[derive(Clone, Default)]
+pub struct UpcasterRegistry {
+    upcasters: HashMap<(String, u32), Arc<dyn EventUpcaster>>,
+}
+
+impl UpcasterRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The upcasters for payload shapes this crate's materializers no longer read
+    ///
+    /// Register each retired shape here so the server and the browser client
+    /// both pick it up. No payload has changed shape yet, so it's empty.
+    pub fn standard() -> &'static UpcasterRegistry {
+        static STANDARD: OnceLock<UpcasterRegistry> = OnceLock::new();
+        STANDARD.get_or_init(UpcasterRegistry::new)
+    }
+
+    /// Upcast `event_type` payloads at `from_version` to `from_version + 1`
+    ///
+    /// Replaces any upcaster already registered for the pair.
+    pub fn register<S, U>(&mut self, event_type: S, from_version: u32, upcaster: U)
+    where
+        S: Into<String>,
+        U: EventUpcaster + 'static,
+    {
+        self.upcasters
+            .insert((event_type.into(), from_version), Arc::new(upcaster));
+    }
+
+    /// Builder-style [`UpcasterRegistry::register`]
+    pub fn with<S, U>(mut self, event_type: S, from_version: u32, upcaster: U) -> Self
+    where
+        S: Into<String>,
+        U: EventUpcaster + 'static,
+    {
+        self.register(event_type, from_version, upcaster);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.upcasters.is_empty()
+    }
+
+    /// Bring an event's payload up to the newest registered shape
+    pub fn upcast(&self, mut event: Event) -> Event {
+        loop {
+            let version = schema_version(&event);
+            let Some(upcaster) = self.upcasters.get(&(event.event_type.clone(), version)) else {
+                return event;
+            };
+            event = upcaster.upcast(event);
+            match event.payload.as_object_mut() {
+                Some(payload) => {
+                    payload.insert(SCHEMA_VERSION_FIELD.to_string(), (version + 1).into());
+                }
+                // Nowhere to record the step, so stop rather than repeat it
+                None => return event,
+            }
+        }
+    }
+
+    /// Upcast a batch, borrowing it untouched when there's nothing to do
+    pub fn upcast_all<'a>(&self, events: &'a [Event]) -> Cow<'a, [Event]> {
+        if self.is_empty() {
+            return Cow::Borrowed(events);
+        }
+        Cow::Owned(events.iter().cloned().map(|e| self.upcast(e)).collect())
+    }
+}
+
+impl std::fmt::Debug for UpcasterRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut keys: Vec<&(String, u32)> = self.upcasters.keys().collect();
+        keys.sort();
+        f.debug_struct("UpcasterRegistry")
+            .field("upcasters", &keys)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{create_document_event, CellType, DocumentMetadata, DocumentProjection};
+    use crate::{EventBuilder, Projection, ProjectionRegistry};
+    use serde_json::json;
+
+    /// The first `CellCreated` shape: `kind` and `content`, with Python cells
+    /// called `python`
+    fn v1_cell_created(payload: &mut serde_json::Map<String, serde_json::Value>) {
+        let kind = payload.remove("kind").unwrap_or(json!("code"));
+        let cell_type = match kind.as_str() {
+            Some("python") => json!("code"),
+            _ => kind,
+        };
+        payload.insert("cell_type".into(), cell_type);
+        if let Some(content) = payload.remove("content") {
+            payload.insert("source".into(), content);
+        }
+    }
+
+    /// The second shape, before cells recorded who created them
+    fn v2_cell_created(payload: &mut serde_json::Map<String, serde_json::Value>) {
+        payload
+            .entry("created_by")
+            .or_insert_with(|| json!("unknown"));
+    }
+
+    fn upcasters() -> UpcasterRegistry {
+        fn step(f: fn(&mut serde_json::Map<String, serde_json::Value>)) -> impl EventUpcaster {
+            move |mut event: Event| {
+                if let Some(payload) = event.payload.as_object_mut() {
+                    f(payload);
+                }
+                event
+            }
+        }
+        UpcasterRegistry::new()
+            .with("CellCreated", 1, step(v1_cell_created))
+            .with("CellCreated", 2, step(v2_cell_created))
+    }
+
+    fn cell_created(payload: serde_json::Value, version: i64) -> Event {
+        EventBuilder::new()
+            .event_type("CellCreated")
+            .aggregate_id("doc-1")
+            .payload(payload)
+            .unwrap()
+            .build(version)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_old_cell_created_is_upcast_before_materializing() {
+        let old = cell_created(
+            json!({"cell_id": "cell-1", "kind": "python", "content": "x = 1"}),
+            2,
+        );
+        let upcast = upcasters().upcast(old.clone());
+        assert_eq!(schema_version(&old), 1);
+        assert_eq!(schema_version(&upcast), 3);
+        assert_eq!(upcast.id, old.id);
+        assert_eq!(
+            upcast.payload,
+            json!({
+                "cell_id": "cell-1",
+                "cell_type": "code",
+                "source": "x = 1",
+                "created_by": "unknown",
+                "schema_version": 3,
+            })
+        );
+
+        // Current events are stamped, so they pass through untouched
+        let current = cell_created(
+            json!({
+                "cell_id": "cell-2",
+                "cell_type": "markdown",
+                "source": "# Notes",
+                "created_by": "alice",
+                "schema_version": 3,
+            }),
+            3,
+        );
+        assert_eq!(upcasters().upcast(current.clone()).payload, current.payload);
+
+        let events = vec![
+            create_document_event("doc-1".into(), "Old".into(), DocumentMetadata::default(), 1)
+                .unwrap(),
+            old,
+            current,
+        ];
+        let mut registry = ProjectionRegistry::new()
+            .with(DocumentProjection::new())
+            .with_upcasters(upcasters());
+        registry.rebuild_all(&events).unwrap();
+
+        let state = registry.get::<DocumentProjection>().unwrap().get_state();
+        assert_eq!(state.cells["cell-1"].cell_type, CellType::Code);
+        assert_eq!(state.cells["cell-1"].source, "x = 1");
+        assert_eq!(state.cells["cell-1"].created_by, "unknown");
+        assert_eq!(state.cells["cell-2"].created_by, "alice");
+
+        // Without the upcasters the old payload can't be read
+        let mut plain = DocumentProjection::new();
+        assert!(plain.rebuild_from_events(&events).is_err());
+    }
+}
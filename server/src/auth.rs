@@ -0,0 +1,182 @@
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::{header::AUTHORIZATION, request::Parts, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use eventbook_core::presence_document_id;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::{AppState, ErrorResponse};
+
+/// What a caller may do in a store, from least to most privileged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Read events and subscribe to updates
+    Viewer,
+    /// Also submit cell and document edits
+    Editor,
+    /// Also delete documents
+    Owner,
+}
+
+impl Role {
+    /// The least privileged role allowed to submit an event type
+    pub fn required_to_submit(event_type: &str) -> Role {
+        match event_type {
+            "DocumentDeleted" => Role::Owner,
+            _ => Role::Editor,
+        }
+    }
+}
+
+/// Claims granted to a bearer token
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenClaims {
+    /// Identity the token was issued to
+    pub subject: String,
+    /// Aggregates the token may see; `None` grants access to every aggregate
+    pub aggregates: Option<HashSet<String>>,
+    /// Role per store; `None` makes the token an owner everywhere, and stores
+    /// missing from the map are read-only
+    #[serde(default)]
+    pub roles: Option<HashMap<String, Role>>,
+}
+
+impl TokenClaims {
+    /// Check whether these claims allow access to an aggregate
+    ///
+    /// A document's presence aggregate is open to whoever may access the
+    /// document.
+    pub fn can_access_aggregate(&self, aggregate_id: &str) -> bool {
+        self.aggregates
+            .as_ref()
+            .map(|aggregates| {
+                aggregates.contains(aggregate_id)
+                    || presence_document_id(aggregate_id)
+                        .is_some_and(|document_id| aggregates.contains(document_id))
+            })
+            .unwrap_or(true)
+    }
+
+    /// Get the role these claims grant in a store
+    pub fn role_for(&self, store_id: &str) -> Role {
+        self.roles
+            .as_ref()
+            .map(|roles| roles.get(store_id).copied().unwrap_or(Role::Viewer))
+            .unwrap_or(Role::Owner)
+    }
+}
+
+/// Claims of the caller, resolved from the `Authorization: Bearer` header
+///
+/// Requests without a token are anonymous and unrestricted, unless the route
+/// is guarded by [`require_api_key`].
+#[derive(Debug, Clone, Default)]
+pub struct RequestClaims(pub Option<TokenClaims>);
+
+impl RequestClaims {
+    /// Check whether the caller may access an aggregate
+    pub fn can_access_aggregate(&self, aggregate_id: &str) -> bool {
+        self.0
+            .as_ref()
+            .map(|claims| claims.can_access_aggregate(aggregate_id))
+            .unwrap_or(true)
+    }
+
+    /// Check whether the caller is limited to a set of aggregates
+    pub fn is_scoped(&self) -> bool {
+        self.0
+            .as_ref()
+            .is_some_and(|claims| claims.aggregates.is_some())
+    }
+
+    /// Identity of an authenticated caller
+    pub fn subject(&self) -> Option<&str> {
+        self.0.as_ref().map(|claims| claims.subject.as_str())
+    }
+
+    /// Get the caller's role in a store; anonymous callers are owners
+    pub fn role_for(&self, store_id: &str) -> Role {
+        self.0
+            .as_ref()
+            .map(|claims| claims.role_for(store_id))
+            .unwrap_or(Role::Owner)
+    }
+}
+
+impl FromRequestParts<AppState> for RequestClaims {
+    type Rejection = (StatusCode, Json<ErrorResponse>);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(header) = parts.headers.get(AUTHORIZATION) else {
+            return Ok(RequestClaims(None));
+        };
+
+        let token = header
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| unauthorized("Malformed Authorization header"))?;
+
+        let tokens = state.tokens.read().await;
+        let claims = tokens
+            .get(token.trim())
+            .cloned()
+            .ok_or_else(|| unauthorized("Unknown token"))?;
+
+        Ok(RequestClaims(Some(claims)))
+    }
+}
+
+/// Reject anonymous requests when `ServerConfig::require_auth` is set
+///
+/// Layer this onto the routes that must not be anonymous, e.g. with
+/// `Router::route_layer` and `axum::middleware::from_fn_with_state`.
+/// Requests with an unknown or malformed token are rejected either way.
+pub async fn require_api_key(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+    match RequestClaims::from_request_parts(&mut parts, &state).await {
+        Ok(RequestClaims(None)) if state.config.require_auth => {
+            unauthorized("Authentication required").into_response()
+        }
+        Ok(_) => next.run(Request::from_parts(parts, body)).await,
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+/// Parse `EVENTBOOK_API_KEYS`-style configuration into key -> subject
+///
+/// Entries are comma-separated `subject:key` pairs; a bare key is issued to
+/// the subject `api-key`.
+pub fn parse_api_keys(value: &str) -> HashMap<String, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once(':') {
+            Some((subject, key)) => (key.trim().to_string(), subject.trim().to_string()),
+            None => (entry.to_string(), "api-key".to_string()),
+        })
+        .collect()
+}
+
+fn unauthorized(message: &str) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(ErrorResponse {
+            error: message.to_string(),
+            code: "UNAUTHORIZED".to_string(),
+            details: None,
+        }),
+    )
+}
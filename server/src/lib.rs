@@ -1,55 +1,427 @@
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware,
     response::{Html, Json},
     routing::{get, post},
     Router,
 };
 use eventbook_core::{
-    DocumentProjection, Event, EventBuilder, EventError, EventStore, InMemoryEventStore, Projection,
+    reorder_cells, snapshot_event, source_edited_at, validate_timestamp, Cell, CellOutput,
+    CommentProjection, Document, DocumentProjection, DocumentProjectionState, Event, EventBuilder,
+    EventError, EventSchemaRegistry, EventStore, InMemoryEventStore, IntegrityIssue,
+    PresenceProjection, Projection, ProjectionRegistry, UpcasterRegistry, SNAPSHOT_EVENT_TYPE,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
-use tower_http::cors::CorsLayer;
-use tracing::{info, warn};
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{error, info, warn};
 
+mod auth;
+mod metrics;
+mod persistence;
+mod sse;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 mod websocket;
+use auth::parse_api_keys;
+pub use auth::{require_api_key, RequestClaims, Role, TokenClaims};
+use metrics::metrics_handler;
+pub use metrics::Metrics;
+use persistence::DataDir;
+use sse::sse_handler;
 use websocket::{websocket_handler, ConnectionManager};
 
+/// Server configuration
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// How far ahead of server time a client-supplied timestamp may be, in seconds
+    pub max_clock_skew_secs: i64,
+    /// Reject event types the server does not know about
+    pub strict_event_types: bool,
+    /// Event types accepted in strict mode on top of the document events
+    pub extra_event_types: Vec<String>,
+    /// Window in milliseconds over which rapid `CellSourceUpdated` events for
+    /// a cell are coalesced into one projection update and broadcast; 0 disables
+    pub source_update_debounce_ms: u64,
+    /// Create stores on first use; when off, stores must be created with
+    /// `POST /stores/{store_id}` and requests for unknown stores get a 404
+    pub auto_create_stores: bool,
+    /// Origins allowed to make cross-origin requests; any origin is allowed
+    /// when empty
+    pub cors_origins: Vec<String>,
+    /// Allow any origin even when `cors_origins` is set, for local development
+    pub cors_permissive: bool,
+    /// API keys accepted as bearer tokens, mapped to the subject each was
+    /// issued to; every key has full access
+    pub api_keys: HashMap<String, String>,
+    /// Reject anonymous requests to every store route, reads included
+    pub require_auth: bool,
+    /// Directory each store's events are persisted to; stores live only in
+    /// memory when unset
+    pub data_dir: Option<PathBuf>,
+}
+
+impl ServerConfig {
+    /// Read configuration from `EVENTBOOK_*` environment variables, using
+    /// defaults for anything unset or unparseable
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let api_keys = std::env::var("EVENTBOOK_API_KEYS")
+            .map(|v| parse_api_keys(&v))
+            .unwrap_or(defaults.api_keys);
+        Self {
+            max_clock_skew_secs: std::env::var("EVENTBOOK_MAX_CLOCK_SKEW_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.max_clock_skew_secs),
+            strict_event_types: std::env::var("EVENTBOOK_STRICT_EVENT_TYPES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.strict_event_types),
+            extra_event_types: std::env::var("EVENTBOOK_EXTRA_EVENT_TYPES")
+                .map(|v| {
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|t| !t.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or(defaults.extra_event_types),
+            source_update_debounce_ms: std::env::var("EVENTBOOK_SOURCE_UPDATE_DEBOUNCE_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.source_update_debounce_ms),
+            auto_create_stores: std::env::var("EVENTBOOK_AUTO_CREATE_STORES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.auto_create_stores),
+            cors_origins: std::env::var("EVENTBOOK_CORS_ORIGINS")
+                .map(|v| {
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|o| !o.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or(defaults.cors_origins),
+            cors_permissive: std::env::var("EVENTBOOK_CORS_PERMISSIVE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.cors_permissive),
+            // Configuring keys turns authentication on unless explicitly disabled
+            require_auth: std::env::var("EVENTBOOK_REQUIRE_AUTH")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(!api_keys.is_empty()),
+            api_keys,
+            data_dir: std::env::var_os("EVENTBOOK_DATA_DIR")
+                .map(PathBuf::from)
+                .or(defaults.data_dir),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_clock_skew_secs: eventbook_core::DEFAULT_MAX_CLOCK_SKEW_SECS,
+            strict_event_types: false,
+            extra_event_types: Vec::new(),
+            source_update_debounce_ms: 0,
+            auto_create_stores: true,
+            cors_origins: Vec::new(),
+            cors_permissive: false,
+            api_keys: HashMap::new(),
+            require_auth: false,
+            data_dir: None,
+        }
+    }
+}
+
+/// Projections materialized for every store
+fn store_projections() -> ProjectionRegistry {
+    ProjectionRegistry::new()
+        .with(DocumentProjection::new())
+        .with(PresenceProjection::new())
+        .with(CommentProjection::new())
+        .with_upcasters(UpcasterRegistry::standard().clone())
+}
+
+/// The document projection in a store's registry
+fn documents(registry: &ProjectionRegistry) -> &DocumentProjection {
+    registry
+        .get::<DocumentProjection>()
+        .expect("every store registers a DocumentProjection")
+}
+
 /// App state shared across handlers
 #[derive(Clone)]
 pub struct AppState {
     /// Map of store_id -> event store
     pub stores: Arc<RwLock<HashMap<String, InMemoryEventStore>>>,
-    /// Map of store_id -> document projection
-    pub projections: Arc<RwLock<HashMap<String, DocumentProjection>>>,
+    /// Map of store_id -> the projections materialized from its events
+    pub projections: Arc<RwLock<HashMap<String, ProjectionRegistry>>>,
     /// WebSocket connection manager
     pub connection_manager: Arc<ConnectionManager>,
+    /// Map of bearer token -> claims granted to that token
+    pub tokens: Arc<RwLock<HashMap<String, TokenClaims>>>,
+    /// Server configuration
+    pub config: Arc<ServerConfig>,
+    /// Payload schemas events must pass before they're stored
+    pub schemas: Arc<EventSchemaRegistry>,
+    /// Counters exported at `/metrics`
+    pub metrics: Arc<Metrics>,
+    /// Latest debounced source update per (store_id, cell_id), waiting to be
+    /// applied to the projection and broadcast
+    pending_source_updates: Arc<RwLock<HashMap<(String, String), Event>>>,
+    /// Where stores are persisted, if anywhere
+    data_dir: Option<Arc<DataDir>>,
 }
 
 impl AppState {
     pub fn new() -> Self {
+        Self::with_config(ServerConfig::default())
+    }
+
+    /// Create app state that keeps every store in memory only
+    ///
+    /// `config.data_dir` is ignored; use [`AppState::open`] to load and
+    /// persist stores.
+    pub fn with_config(config: ServerConfig) -> Self {
+        let tokens = config
+            .api_keys
+            .iter()
+            .map(|(key, subject)| {
+                let claims = TokenClaims {
+                    subject: subject.clone(),
+                    ..TokenClaims::default()
+                };
+                (key.clone(), claims)
+            })
+            .collect();
         Self {
             stores: Arc::new(RwLock::new(HashMap::new())),
             projections: Arc::new(RwLock::new(HashMap::new())),
             connection_manager: Arc::new(ConnectionManager::new()),
+            tokens: Arc::new(RwLock::new(tokens)),
+            config: Arc::new(config),
+            schemas: Arc::new(EventSchemaRegistry::with_builtin_schemas()),
+            metrics: Arc::new(Metrics::new()),
+            pending_source_updates: Arc::new(RwLock::new(HashMap::new())),
+            data_dir: None,
+        }
+    }
+
+    /// Create app state backed by `config.data_dir`
+    ///
+    /// Stores already in the directory are loaded and their projections
+    /// rebuilt; new events are appended to it. Without a data directory this
+    /// is the same as [`AppState::with_config`].
+    pub fn open(config: ServerConfig) -> std::io::Result<Self> {
+        let Some(path) = config.data_dir.clone() else {
+            return Ok(Self::with_config(config));
+        };
+        let data_dir = DataDir::open(path)?;
+        let mut app_state = Self::with_config(config);
+
+        let mut stores = HashMap::new();
+        let mut projections = HashMap::new();
+        for (store_id, events) in data_dir.load()? {
+            let mut store = app_state.new_store();
+            store.append_events(events).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Failed to load store {}: {}", store_id, e),
+                )
+            })?;
+            let mut registry = store_projections();
+            let events: Vec<Event> = store.iter_events().cloned().collect();
+            if let Err(e) = registry.rebuild_all(&events) {
+                warn!("Failed to rebuild projection for store {}: {}", store_id, e);
+            }
+            stores.insert(store_id.clone(), store);
+            projections.insert(store_id, registry);
         }
+
+        app_state.stores = Arc::new(RwLock::new(stores));
+        app_state.projections = Arc::new(RwLock::new(projections));
+        app_state.data_dir = Some(Arc::new(data_dir));
+        Ok(app_state)
+    }
+
+    /// Register a bearer token with the claims it grants
+    pub async fn issue_token<S: Into<String>>(&self, token: S, claims: TokenClaims) {
+        self.tokens.write().await.insert(token.into(), claims);
     }
 
     /// Ensure a store exists for the given store_id
-    async fn ensure_store_exists(&self, store_id: &str) {
+    ///
+    /// Creates it if `auto_create_stores` is on, otherwise fails with a 404.
+    async fn ensure_store_exists(
+        &self,
+        store_id: &str,
+    ) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+        if self.config.auto_create_stores {
+            self.create_store(store_id).await?;
+            return Ok(());
+        }
+
+        if self.stores.read().await.contains_key(store_id) {
+            Ok(())
+        } else {
+            Err(store_not_found_response(store_id))
+        }
+    }
+
+    /// Create a store and its projection, returning whether it was new
+    async fn create_store(
+        &self,
+        store_id: &str,
+    ) -> Result<bool, (StatusCode, Json<ErrorResponse>)> {
         let mut stores = self.stores.write().await;
         let mut projections = self.projections.write().await;
 
-        stores
-            .entry(store_id.to_string())
-            .or_insert_with(InMemoryEventStore::new);
+        if stores.contains_key(store_id) {
+            return Ok(false);
+        }
+
+        let owned_id = store_id.to_string();
+        self.persist(store_id, move |data_dir| data_dir.create_store(&owned_id))
+            .await?;
+        stores.insert(store_id.to_string(), self.new_store());
+        projections.insert(store_id.to_string(), store_projections());
+        Ok(true)
+    }
+
+    /// An empty event store set up as the config asks
+    fn new_store(&self) -> InMemoryEventStore {
+        if !self.config.strict_event_types {
+            return InMemoryEventStore::new();
+        }
+        let mut store = InMemoryEventStore::strict();
+        for event_type in &self.config.extra_event_types {
+            store.register_event_type(event_type.as_str());
+        }
+        store
+    }
+
+    /// Write a change to the data directory, if there is one
+    ///
+    /// The write runs on the blocking pool. Callers hold the store's write
+    /// lock across it, so changes reach the file in the order they were made,
+    /// and undo their in-memory change if it fails: a log missing an event
+    /// that later ones number on from wouldn't load again.
+    async fn persist<F>(
+        &self,
+        store_id: &str,
+        write: F,
+    ) -> Result<(), (StatusCode, Json<ErrorResponse>)>
+    where
+        F: FnOnce(&DataDir) -> std::io::Result<()> + Send + 'static,
+    {
+        let Some(data_dir) = self.data_dir.clone() else {
+            return Ok(());
+        };
+        tokio::task::spawn_blocking(move || write(&data_dir))
+            .await
+            .unwrap_or_else(|e| Err(std::io::Error::other(e)))
+            .map_err(|e| {
+                error!("Failed to persist store {}: {}", store_id, e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse {
+                        error: format!("Failed to persist store {}: {}", store_id, e),
+                        code: "PERSISTENCE_FAILED".to_string(),
+                        details: None,
+                    }),
+                )
+            })
+    }
+
+    /// Cell whose projection update should be debounced, if any
+    fn debounced_cell(&self, event: &Event) -> Option<String> {
+        if self.config.source_update_debounce_ms == 0 || event.event_type != "CellSourceUpdated" {
+            return None;
+        }
+        event
+            .payload
+            .get("cell_id")
+            .and_then(|v| v.as_str())
+            .map(String::from)
+    }
+
+    /// Hold a source update back until the debounce window closes
+    ///
+    /// A held event is only replaced by one edited no earlier, by
+    /// [`source_edited_at`], so only the source last-writer-wins would keep
+    /// is applied and broadcast. Callers hold the projections lock so a flush
+    /// can't slip in between storing the event and holding it.
+    async fn debounce_source_update(&self, store_id: String, cell_id: String, event: Event) {
+        let key = (store_id, cell_id);
+        let mut pending = self.pending_source_updates.write().await;
+        if let Some(held) = pending.get_mut(&key) {
+            if source_edited_at(&event) >= source_edited_at(held) {
+                *held = event;
+            }
+            // A flush is already scheduled for this cell
+            return;
+        }
+        pending.insert(key.clone(), event);
+        drop(pending);
+
+        let app_state = self.clone();
+        let window = Duration::from_millis(self.config.source_update_debounce_ms);
+        tokio::spawn(async move {
+            tokio::time::sleep(window).await;
+            let (store_id, _) = key;
+            app_state.flush_source_updates(&store_id).await;
+        });
+    }
+
+    /// Apply and broadcast every held source update for a store
+    ///
+    /// Flushing one cell's update alone could apply it ahead of an older
+    /// update held for another cell, which the projection would then skip.
+    async fn flush_source_updates(&self, store_id: &str) {
+        // Lock the projections before taking the events so a concurrent
+        // submit can't apply a later event ahead of them
+        let mut projections = self.projections.write().await;
+        let events = self.take_pending_source_updates(store_id).await;
+        if events.is_empty() {
+            return;
+        }
+        if let Some(registry) = projections.get_mut(store_id) {
+            if let Err(e) = registry.apply_new_events(&events) {
+                warn!("Failed to update projection for store {}: {}", store_id, e);
+            }
+        }
+        drop(projections);
+
+        for event in events {
+            self.connection_manager
+                .broadcast_event(store_id.to_string(), event)
+                .await;
+        }
+    }
 
-        projections
-            .entry(store_id.to_string())
-            .or_insert_with(DocumentProjection::new);
+    /// Take every held source update for a store, oldest first
+    async fn take_pending_source_updates(&self, store_id: &str) -> Vec<Event> {
+        let mut pending = self.pending_source_updates.write().await;
+        let keys: Vec<_> = pending
+            .keys()
+            .filter(|(store, _)| store == store_id)
+            .cloned()
+            .collect();
+        let mut events: Vec<Event> = keys.iter().filter_map(|key| pending.remove(key)).collect();
+        events.sort_by_key(|e| (e.timestamp, e.version));
+        events
     }
 }
 
@@ -58,7 +430,21 @@ impl AppState {
 #[derive(Debug, Deserialize)]
 pub struct SubmitEventRequest {
     pub event_type: String,
+    /// Aggregate (e.g. document) the event belongs to; defaults to the store id
+    #[serde(default)]
+    pub aggregate_id: Option<String>,
     pub payload: serde_json::Value,
+    /// Client-side creation time (Unix epoch seconds); defaults to server
+    /// time, and is moved up to the store's newest timestamp if earlier. A
+    /// `CellSourceUpdated` keeps it as `edited_at` for last-writer-wins
+    #[serde(default)]
+    pub timestamp: Option<i64>,
+    /// Groups this event with others from the same user action
+    #[serde(default)]
+    pub transaction_id: Option<String>,
+    /// Reject with 409 unless the aggregate is still at this version
+    #[serde(default)]
+    pub expected_version: Option<i64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -68,17 +454,113 @@ pub struct SubmitEventResponse {
 }
 
 #[derive(Debug, Deserialize)]
+pub struct BatchSubmitRequest {
+    pub events: Vec<BatchEventRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchEventRequest {
+    pub event_type: String,
+    /// Aggregate the event belongs to; defaults to the store id
+    #[serde(default)]
+    pub aggregate_id: Option<String>,
+    pub payload: serde_json::Value,
+    /// Version the event must get; defaults to the aggregate's next version
+    #[serde(default)]
+    pub version: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchSubmitResponse {
+    /// Assigned ids and versions, in request order
+    pub events: Vec<SubmitEventResponse>,
+}
+
+#[derive(Debug, Default, Deserialize)]
 pub struct GetEventsQuery {
+    /// Maximum number of events to return; unbounded if unset
     pub limit: Option<u32>,
+    /// Number of events to skip; 0 if unset
     pub offset: Option<u32>,
+    /// Cursor mode: return events appended after this global sequence
+    /// number, in append order, instead of paging by offset
+    pub after_seq: Option<u64>,
     pub since_timestamp: Option<i64>,
+    /// Earliest timestamp to return (inclusive)
+    pub from: Option<i64>,
+    /// Latest timestamp to return (inclusive)
+    pub to: Option<i64>,
+    /// Only return events after this version (exclusive)
+    pub since_version: Option<i64>,
+    /// Lowest version to return (inclusive)
+    pub from_version: Option<i64>,
+    /// Highest version to return (inclusive)
+    pub to_version: Option<i64>,
+    /// Only return events for this aggregate
+    pub aggregate_id: Option<String>,
+    /// Only return events of these types, given comma-separated
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub event_types: Option<Vec<String>>,
+    /// Sort direction; pagination walks in this direction
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+/// Split a comma-separated query value, dropping empty entries
+fn comma_separated<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(value.map(|value| {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .map(String::from)
+            .collect()
+    }))
+}
+
+impl GetEventsQuery {
+    /// Whether an event passes the `event_types` filter
+    fn wants_event_type(&self, event_type: &str) -> bool {
+        self.event_types
+            .as_ref()
+            .is_none_or(|types| types.iter().any(|t| t == event_type))
+    }
+
+    /// Inclusive timestamp bounds from `from` and `to`, if either is set
+    fn time_range(&self) -> Option<RangeInclusive<i64>> {
+        if self.from.is_none() && self.to.is_none() {
+            return None;
+        }
+        Some(self.from.unwrap_or(i64::MIN)..=self.to.unwrap_or(i64::MAX))
+    }
+}
+
+/// Direction events are returned in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Oldest first
+    #[default]
+    Asc,
+    /// Newest first
+    Desc,
 }
 
 #[derive(Debug, Serialize)]
 pub struct GetEventsResponse {
     pub events: Vec<Event>,
+    /// Matching events before pagination
     pub total_count: usize,
     pub store_id: String,
+    /// Whether events remain past this page
+    pub has_more: bool,
+    /// Sequence to pass as `after_seq` for the next page, in cursor mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<u64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -90,10 +572,107 @@ pub struct StoreInfoResponse {
     pub last_event_timestamp: Option<i64>,
 }
 
+#[derive(Debug, Serialize)]
+pub struct AggregatesResponse {
+    pub store_id: String,
+    pub aggregate_ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifyStoreResponse {
+    pub store_id: String,
+    /// True when no issues were found
+    pub ok: bool,
+    pub issues: Vec<IntegrityIssue>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateStoreResponse {
+    pub store_id: String,
+    /// False if the store already existed
+    pub created: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CompactQuery {
+    /// Drop every event the snapshot stands in for
+    #[serde(default)]
+    pub prune: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompactStoreResponse {
+    pub store_id: String,
+    /// Id of the `SnapshotCreated` event
+    pub snapshot_event_id: String,
+    /// Events dropped from the log; 0 unless `prune` was set
+    pub pruned_events: usize,
+    /// Events left in the log, including the snapshot
+    pub event_count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncResponse {
+    pub store_id: String,
+    /// Materialized state as of `cursor`
+    pub snapshot: Arc<DocumentProjectionState>,
+    /// Global sequence of the last event reflected in the snapshot
+    pub cursor: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchGetCellsRequest {
+    pub cell_ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchGetCellsResponse {
+    /// Cells that were found, in request order; unknown IDs and cells the
+    /// caller may not access are omitted
+    pub cells: Vec<Cell>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DocumentQuery {
+    /// Show the document as it was at this timestamp
+    pub as_of: Option<i64>,
+    /// Maximum number of cells to return; unbounded if unset
+    pub limit: Option<u32>,
+    /// Number of cells to skip; 0 if unset
+    pub offset: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DocumentResponse {
+    pub document: Document,
+    /// The document's cells, ordered by fractional index
+    pub cells: Vec<Cell>,
+    /// Cells in the document before pagination
+    pub total_cells: usize,
+    /// Whether cells remain past this page
+    pub has_more: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReorderCellsRequest {
+    /// Every live cell of the document, in the order they should appear
+    pub cell_ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CellResponse {
+    pub cell: Cell,
+    /// The cell's outputs, in display order
+    pub outputs: Vec<CellOutput>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
     pub error: String,
     pub code: String,
+    /// Machine-readable context for the error, e.g. the versions in a conflict
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
 }
 
 /// Convert EventError to HTTP status and error response
@@ -101,65 +680,283 @@ fn event_error_to_response(err: EventError) -> (StatusCode, Json<ErrorResponse>)
     let (status, code) = match &err {
         EventError::InvalidVersion { .. } => (StatusCode::CONFLICT, "VERSION_CONFLICT"),
         EventError::DuplicateEventId(_) => (StatusCode::CONFLICT, "DUPLICATE_EVENT"),
+        EventError::StorageError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "STORAGE_ERROR"),
         _ => (StatusCode::BAD_REQUEST, "VALIDATION_ERROR"),
     };
+    let details = match &err {
+        EventError::InvalidVersion { expected, got } => {
+            Some(serde_json::json!({ "expected": expected, "got": got }))
+        }
+        EventError::MultipleValidationErrors(errors) => {
+            Some(serde_json::json!({ "errors": errors }))
+        }
+        _ => None,
+    };
 
     (
         status,
         Json(ErrorResponse {
             error: err.to_string(),
             code: code.to_string(),
+            details,
+        }),
+    )
+}
+
+/// Point an error response at the batch event that caused it
+fn at_batch_index(
+    (status, Json(mut error)): (StatusCode, Json<ErrorResponse>),
+    index: usize,
+) -> (StatusCode, Json<ErrorResponse>) {
+    let mut details = error
+        .details
+        .take()
+        .unwrap_or_else(|| serde_json::json!({}));
+    details["index"] = index.into();
+    error.error = format!("Event {}: {}", index, error.error);
+    error.details = Some(details);
+    (status, Json(error))
+}
+
+/// Build the 403 response for an aggregate outside the caller's claims
+fn forbidden_response(aggregate_id: &str) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::FORBIDDEN,
+        Json(ErrorResponse {
+            error: format!("Not authorized for aggregate {}", aggregate_id),
+            code: "FORBIDDEN".to_string(),
+            details: None,
+        }),
+    )
+}
+
+/// Build the 404 response for a store that doesn't exist
+fn store_not_found_response(store_id: &str) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse {
+            error: format!("Store {} not found", store_id),
+            code: "STORE_NOT_FOUND".to_string(),
+            details: None,
+        }),
+    )
+}
+
+/// Build the 404 response for a document or cell missing from a store
+fn not_found_response(kind: &str, id: &str) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse {
+            error: format!("{} {} not found", kind, id),
+            code: format!("{}_NOT_FOUND", kind.to_uppercase()),
+            details: None,
+        }),
+    )
+}
+
+/// Move a new event's timestamp so it sorts after everything already stored
+///
+/// Projections are updated incrementally and skip events stamped before the
+/// newest one they've applied, so an earlier-stamped event would be stored
+/// and broadcast but never materialized. That happens to backdated client
+/// timestamps and to server time once a client has stamped ahead of it.
+/// Replays also apply a snapshot after everything stamped at or before it,
+/// so events move past the newest snapshot's second too.
+fn stamp_in_order(event_store: &InMemoryEventStore, timestamp: i64) -> i64 {
+    let timestamp = event_store
+        .iter_events()
+        .next_back()
+        .map_or(timestamp, |newest| timestamp.max(newest.timestamp));
+    event_store
+        .snapshot_timestamp()
+        .map_or(timestamp, |snapshot| timestamp.max(snapshot + 1))
+}
+
+/// Build the 400 response for an event type clients may not submit
+///
+/// Snapshots replace the projections wholesale and set the versions writers
+/// number on from, so only [`compact_store`] may write one.
+fn reserved_event_type_response(event_type: &str) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse {
+            error: format!("{} events are written by the server only", event_type),
+            code: "RESERVED_EVENT_TYPE".to_string(),
+            details: None,
+        }),
+    )
+}
+
+/// Build the 403 response for a caller whose role is too low
+fn insufficient_role_response(store_id: &str, required: Role) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::FORBIDDEN,
+        Json(ErrorResponse {
+            error: format!("Requires {:?} role in store {}", required, store_id),
+            code: "INSUFFICIENT_ROLE".to_string(),
+            details: None,
         }),
     )
 }
 
+/// Header carrying the number of events a response describes
+pub const EVENT_COUNT_HEADER: &str = "x-event-count";
+
+/// Headers describing a set of events, shared by GET and HEAD responses
+///
+/// The ETag changes whenever the events do, so clients can poll with HEAD.
+fn event_headers<'a>(events: impl ExactSizeIterator<Item = &'a Event>) -> HeaderMap {
+    let count = events.len();
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::ETAG,
+        HeaderValue::from_str(&format!("\"{:016x}\"", eventbook_core::log_hash(events)))
+            .expect("hex ETag is a valid header value"),
+    );
+    headers.insert(EVENT_COUNT_HEADER, HeaderValue::from(count));
+    headers
+}
+
 /// HTTP handlers
 
 /// Submit an event to a store
 pub async fn submit_event(
     State(app_state): State<AppState>,
     Path(store_id): Path<String>,
+    claims: RequestClaims,
     Json(req): Json<SubmitEventRequest>,
 ) -> Result<Json<SubmitEventResponse>, (StatusCode, Json<ErrorResponse>)> {
-    app_state.ensure_store_exists(&store_id).await;
+    let aggregate_id = req.aggregate_id.unwrap_or_else(|| store_id.clone());
+    if !claims.can_access_aggregate(&aggregate_id) {
+        return Err(forbidden_response(&aggregate_id));
+    }
+
+    if req.event_type == SNAPSHOT_EVENT_TYPE {
+        return Err(reserved_event_type_response(&req.event_type));
+    }
+    let required = Role::required_to_submit(&req.event_type);
+    if claims.role_for(&store_id) < required {
+        return Err(insufficient_role_response(&store_id, required));
+    }
+
+    app_state.ensure_store_exists(&store_id).await?;
 
     let mut stores = app_state.stores.write().await;
     let mut projections = app_state.projections.write().await;
 
     let event_store = stores.get_mut(&store_id).unwrap();
-    let projection = projections.get_mut(&store_id).unwrap();
+    let registry = projections.get_mut(&store_id).unwrap();
 
-    // Get the next version for this store
-    let current_version = event_store.get_latest_version(&store_id);
+    // Versions are counted per aggregate, so documents sharing a store don't
+    // interfere with each other
+    let current_version = event_store.get_latest_version(&aggregate_id);
     let next_version = current_version + 1;
 
+    // Compare-and-swap: the client must have seen the latest version
+    if let Some(expected_version) = req.expected_version {
+        if expected_version != current_version {
+            return Err(event_error_to_response(EventError::InvalidVersion {
+                expected: expected_version,
+                got: current_version,
+            }));
+        }
+    }
+
+    let timestamp = match req.timestamp {
+        Some(timestamp) => {
+            validate_timestamp(
+                timestamp,
+                eventbook_core::current_timestamp(),
+                app_state.config.max_clock_skew_secs,
+            )
+            .map_err(event_error_to_response)?;
+            timestamp
+        }
+        None => eventbook_core::current_timestamp(),
+    };
+
+    // The stored timestamp only records arrival order, so source edits keep
+    // the client's time for last-writer-wins
+    let mut payload = req.payload;
+    if req.event_type == "CellSourceUpdated" && req.timestamp.is_some() {
+        if let Some(fields) = payload.as_object_mut() {
+            fields.entry("edited_at").or_insert(timestamp.into());
+        }
+    }
+
     // Build the event
-    let event = EventBuilder::new()
+    let mut builder = EventBuilder::new()
         .event_type(req.event_type)
-        .aggregate_id(store_id.clone()) // Use store_id as aggregate_id
-        .payload(req.payload)
+        .aggregate_id(aggregate_id)
+        .payload(payload)
         .map_err(event_error_to_response)?
+        .timestamp(stamp_in_order(event_store, timestamp));
+
+    if let Some(transaction_id) = req.transaction_id {
+        builder = builder.transaction(transaction_id);
+    }
+
+    // Authenticated callers are recorded as the actor, whatever the client sent
+    if let Some(subject) = claims.subject() {
+        builder = builder.actor(subject);
+    }
+
+    let event = builder
         .build(next_version)
         .map_err(event_error_to_response)?;
+    app_state
+        .schemas
+        .validate(&event)
+        .map_err(event_error_to_response)?;
 
     let event_id = event.id.clone();
     let version = event.version;
 
     // Store the event
+    let stored_count = event_store.get_event_count();
     event_store
         .append_event(event.clone())
         .map_err(event_error_to_response)?;
-
-    // Update projection
-    if let Err(e) = projection.apply_new_events(&[event.clone()]) {
-        warn!("Failed to update projection for store {}: {}", store_id, e);
+    let (persisted_store, persisted) = (store_id.clone(), event.clone());
+    if let Err(e) = app_state
+        .persist(&store_id, move |data_dir| {
+            data_dir.append(&persisted_store, std::slice::from_ref(&persisted))
+        })
+        .await
+    {
+        event_store.truncate(stored_count);
+        return Err(e);
     }
+    app_state.metrics.record_events_appended(1);
 
-    // Broadcast event to WebSocket connections
-    app_state
-        .connection_manager
-        .broadcast_event(store_id.clone(), event)
-        .await;
+    if let Some(cell_id) = app_state.debounced_cell(&event) {
+        // Persisted above; the projection and subscribers catch up once the
+        // cell goes quiet
+        app_state
+            .debounce_source_update(store_id.clone(), cell_id, event)
+            .await;
+        drop(projections);
+        drop(stores);
+    } else {
+        // Any held source updates go first so the projection sees events in order
+        let mut events = app_state.take_pending_source_updates(&store_id).await;
+        events.push(event);
+
+        // Update projection
+        if let Err(e) = registry.apply_new_events(&events) {
+            warn!("Failed to update projection for store {}: {}", store_id, e);
+        }
+        drop(projections);
+        drop(stores);
+
+        // Broadcast events to WebSocket connections
+        for event in events {
+            app_state
+                .connection_manager
+                .broadcast_event(store_id.clone(), event)
+                .await;
+        }
+    }
 
     info!(
         "Event {} submitted to store {} successfully",
@@ -169,95 +966,843 @@ pub async fn submit_event(
     Ok(Json(SubmitEventResponse { event_id, version }))
 }
 
+/// Submit several events that are committed all together or not at all
+///
+/// The batch is checked as a whole and appended with
+/// [`InMemoryEventStore::append_batch`]; if any event fails its version,
+/// duplicate or validation checks nothing is appended and the error's
+/// `details.index` names the offending event. A batch that can't be
+/// persisted is truncated back off the store. Subscribers get each committed
+/// event, in order.
+pub async fn submit_event_batch(
+    State(app_state): State<AppState>,
+    Path(store_id): Path<String>,
+    claims: RequestClaims,
+    Json(req): Json<BatchSubmitRequest>,
+) -> Result<Json<BatchSubmitResponse>, (StatusCode, Json<ErrorResponse>)> {
+    for (index, event) in req.events.iter().enumerate() {
+        let aggregate_id = event.aggregate_id.as_deref().unwrap_or(&store_id);
+        if !claims.can_access_aggregate(aggregate_id) {
+            return Err(at_batch_index(forbidden_response(aggregate_id), index));
+        }
+        if event.event_type == SNAPSHOT_EVENT_TYPE {
+            return Err(at_batch_index(
+                reserved_event_type_response(&event.event_type),
+                index,
+            ));
+        }
+        let required = Role::required_to_submit(&event.event_type);
+        if claims.role_for(&store_id) < required {
+            return Err(at_batch_index(
+                insufficient_role_response(&store_id, required),
+                index,
+            ));
+        }
+    }
+
+    app_state.ensure_store_exists(&store_id).await?;
+
+    let mut stores = app_state.stores.write().await;
+    let mut projections = app_state.projections.write().await;
+
+    let event_store = stores.get_mut(&store_id).unwrap();
+    let registry = projections.get_mut(&store_id).unwrap();
+
+    // Number the batch up front; the store then takes all of it or none
+    let timestamp = stamp_in_order(event_store, eventbook_core::current_timestamp());
+    let mut batch_versions: HashMap<String, i64> = HashMap::new();
+    let mut events = Vec::with_capacity(req.events.len());
+    for (index, event_req) in req.events.into_iter().enumerate() {
+        let aggregate_id = event_req.aggregate_id.unwrap_or_else(|| store_id.clone());
+        let version = event_req.version.unwrap_or_else(|| {
+            batch_versions
+                .get(&aggregate_id)
+                .copied()
+                .unwrap_or_else(|| event_store.get_latest_version(&aggregate_id))
+                + 1
+        });
+        batch_versions.insert(aggregate_id.clone(), version);
+
+        let event = EventBuilder::new()
+            .event_type(event_req.event_type)
+            .aggregate_id(aggregate_id)
+            .payload(event_req.payload)
+            .map(|builder| builder.timestamp(timestamp))
+            .map(|builder| match claims.subject() {
+                Some(subject) => builder.actor(subject),
+                None => builder,
+            })
+            .and_then(|builder| builder.build(version))
+            .and_then(|event| app_state.schemas.validate(&event).map(|()| event))
+            .map_err(|e| at_batch_index(event_error_to_response(e), index))?;
+        events.push(event);
+    }
+    let stored_count = event_store.get_event_count();
+    event_store
+        .append_batch(events.clone())
+        .map_err(|(index, e)| at_batch_index(event_error_to_response(e), index))?;
+    let (persisted_store, persisted) = (store_id.clone(), events.clone());
+    if let Err(e) = app_state
+        .persist(&store_id, move |data_dir| {
+            data_dir.append(&persisted_store, &persisted)
+        })
+        .await
+    {
+        event_store.truncate(stored_count);
+        return Err(e);
+    }
+    app_state.metrics.record_events_appended(events.len());
+
+    // Held source updates go first so the projection sees events in order
+    let mut applied = app_state.take_pending_source_updates(&store_id).await;
+    applied.extend(events.iter().cloned());
+    if let Err(e) = registry.apply_new_events(&applied) {
+        warn!("Failed to update projection for store {}: {}", store_id, e);
+    }
+    drop(projections);
+    drop(stores);
+
+    for event in applied {
+        app_state
+            .connection_manager
+            .broadcast_event(store_id.clone(), event)
+            .await;
+    }
+
+    info!(
+        "Batch of {} events submitted to store {}",
+        events.len(),
+        store_id
+    );
+
+    Ok(Json(BatchSubmitResponse {
+        events: events
+            .into_iter()
+            .map(|event| SubmitEventResponse {
+                event_id: event.id,
+                version: event.version,
+            })
+            .collect(),
+    }))
+}
+
 /// Get events from a store
 pub async fn get_events(
     State(app_state): State<AppState>,
     Path(store_id): Path<String>,
     Query(query): Query<GetEventsQuery>,
-) -> Result<Json<GetEventsResponse>, (StatusCode, Json<ErrorResponse>)> {
-    app_state.ensure_store_exists(&store_id).await;
+    claims: RequestClaims,
+) -> Result<(HeaderMap, Json<GetEventsResponse>), (StatusCode, Json<ErrorResponse>)> {
+    app_state.ensure_store_exists(&store_id).await?;
 
     let stores = app_state.stores.read().await;
     let event_store = stores.get(&store_id).unwrap();
 
-    let mut events = event_store.get_events(&store_id).map_err(|e| {
+    if let Some(after_seq) = query.after_seq {
+        return get_events_page(event_store, store_id, after_seq, &query, &claims);
+    }
+
+    let aggregate_id = query.aggregate_id.as_deref();
+    let time_range = query.time_range();
+    let events = match (query.since_version, query.from_version, query.to_version) {
+        (None, None, None) => match (aggregate_id, &time_range) {
+            (None, None) => event_store.get_all_events(),
+            (None, Some(range)) => event_store.get_events_between(*range.start(), *range.end()),
+            (Some(aggregate_id), _) => event_store.get_events(aggregate_id),
+        },
+        (Some(after), None, None) => {
+            event_store.get_events_since_version(aggregate_id.unwrap_or(&store_id), after)
+        }
+        (after, from, to) => event_store.get_events_in_version_range(
+            aggregate_id.unwrap_or(&store_id),
+            from.unwrap_or(1).max(after.map_or(1, |v| v + 1)),
+            to.unwrap_or(i64::MAX),
+        ),
+    };
+    let mut events = events.map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
                 error: e.to_string(),
                 code: "EVENT_RETRIEVAL_FAILED".to_string(),
+                details: None,
             }),
         )
     })?;
 
+    // Hide events from aggregates the caller isn't authorized to see
+    events.retain(|e| claims.can_access_aggregate(&e.aggregate_id));
+
+    events.retain(|e| query.wants_event_type(&e.event_type));
+
     // Filter by timestamp if requested
     if let Some(since) = query.since_timestamp {
         events.retain(|e| e.timestamp > since);
     }
+    if let Some(range) = time_range {
+        events.retain(|e| range.contains(&e.timestamp));
+    }
+
+    if query.order == SortOrder::Desc {
+        events.reverse();
+    }
 
     let total_count = events.len();
+    let headers = event_headers(events.iter());
 
     // Apply pagination if requested
-    if let (Some(limit), Some(offset)) = (query.limit, query.offset) {
+    let offset = query.offset.unwrap_or(0) as usize;
+    if query.limit.is_some() || offset > 0 {
         events = events
             .into_iter()
-            .skip(offset as usize)
-            .take(limit as usize)
+            .skip(offset)
+            .take(query.limit.map_or(usize::MAX, |limit| limit as usize))
             .collect();
     }
+    let has_more = offset + events.len() < total_count;
 
-    Ok(Json(GetEventsResponse {
-        events,
-        total_count,
-        store_id,
-    }))
+    Ok((
+        headers,
+        Json(GetEventsResponse {
+            events,
+            total_count,
+            store_id,
+            has_more,
+            next_cursor: None,
+        }),
+    ))
+}
+
+/// Page through a store in append order, starting after `after_seq`
+///
+/// Filters apply within the page, so a page may hold fewer than `limit`
+/// events even when more remain; keep following `next_cursor` until
+/// `has_more` is false.
+fn get_events_page(
+    event_store: &InMemoryEventStore,
+    store_id: String,
+    after_seq: u64,
+    query: &GetEventsQuery,
+    claims: &RequestClaims,
+) -> Result<(HeaderMap, Json<GetEventsResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let limit = query.limit.map_or(usize::MAX, |limit| limit as usize);
+    let page: Vec<(u64, &Event)> = event_store
+        .iter_events_after_sequence(after_seq)
+        .take(limit)
+        .collect();
+
+    let latest_sequence = event_store.latest_sequence();
+    // A cursor from before a compaction jumps ahead to the snapshot
+    let next_cursor = page.last().map_or(after_seq, |&(seq, _)| seq);
+
+    let time_range = query.time_range();
+    let matches = |e: &Event| {
+        claims.can_access_aggregate(&e.aggregate_id)
+            && query
+                .aggregate_id
+                .as_ref()
+                .is_none_or(|aggregate_id| &e.aggregate_id == aggregate_id)
+            && query.wants_event_type(&e.event_type)
+            && query
+                .since_timestamp
+                .is_none_or(|since| e.timestamp > since)
+            && time_range
+                .as_ref()
+                .is_none_or(|range| range.contains(&e.timestamp))
+    };
+    let events: Vec<Event> = page
+        .into_iter()
+        .filter(|(_, e)| matches(e))
+        .map(|(_, e)| e.clone())
+        .collect();
+    // Counted over the whole store, like offset mode, not just this page
+    let total_count = event_store.iter_events().filter(|e| matches(e)).count();
+
+    let headers = event_headers(events.iter());
+    Ok((
+        headers,
+        Json(GetEventsResponse {
+            events,
+            total_count,
+            store_id,
+            has_more: next_cursor < latest_sequence,
+            next_cursor: Some(next_cursor),
+        }),
+    ))
 }
 
 /// Get store information
+///
+/// Counts, versions and the ETag cover only the events the caller may see,
+/// so a scoped token learns nothing about other aggregates.
 pub async fn get_store_info(
     State(app_state): State<AppState>,
     Path(store_id): Path<String>,
-) -> Result<Json<StoreInfoResponse>, (StatusCode, Json<ErrorResponse>)> {
-    app_state.ensure_store_exists(&store_id).await;
+    claims: RequestClaims,
+) -> Result<(HeaderMap, Json<StoreInfoResponse>), (StatusCode, Json<ErrorResponse>)> {
+    app_state.ensure_store_exists(&store_id).await?;
 
     let stores = app_state.stores.read().await;
     let event_store = stores.get(&store_id).unwrap();
 
-    let events = event_store.get_events(&store_id).map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-                code: "EVENT_RETRIEVAL_FAILED".to_string(),
-            }),
-        )
-    })?;
+    // Everything here can be read off the store without cloning its log
+    let visible: Vec<&Event> = event_store
+        .iter_events()
+        .filter(|e| claims.can_access_aggregate(&e.aggregate_id))
+        .collect();
+    let latest_version = visible.iter().map(|e| e.version).max().unwrap_or(0);
+    let first_event_timestamp = visible.first().map(|e| e.timestamp);
+    let last_event_timestamp = visible.last().map(|e| e.timestamp);
 
-    let latest_version = event_store.get_latest_version(&store_id);
+    Ok((
+        event_headers(visible.iter().copied()),
+        Json(StoreInfoResponse {
+            store_id,
+            event_count: visible.len(),
+            latest_version,
+            first_event_timestamp,
+            last_event_timestamp,
+        }),
+    ))
+}
+
+/// List the aggregates with events in a store
+///
+/// Aggregates the caller isn't authorized to see are left out, as they are
+/// from the event listing.
+pub async fn list_aggregates(
+    State(app_state): State<AppState>,
+    Path(store_id): Path<String>,
+    claims: RequestClaims,
+) -> Result<Json<AggregatesResponse>, (StatusCode, Json<ErrorResponse>)> {
+    app_state.ensure_store_exists(&store_id).await?;
 
-    Ok(Json(StoreInfoResponse {
+    let mut aggregate_ids = app_state.stores.read().await[&store_id].get_aggregate_ids();
+    aggregate_ids.retain(|id| claims.can_access_aggregate(id));
+    Ok(Json(AggregatesResponse {
         store_id,
-        event_count: events.len(),
-        latest_version,
-        first_event_timestamp: events.first().map(|e| e.timestamp),
-        last_event_timestamp: events.last().map(|e| e.timestamp),
+        aggregate_ids,
     }))
 }
 
-/// List all stores
-pub async fn list_stores(
+/// Check a store's log for version gaps, duplicates and out-of-order timestamps
+pub async fn verify_store(
     State(app_state): State<AppState>,
-) -> Result<Json<Vec<String>>, (StatusCode, Json<ErrorResponse>)> {
-    let stores = app_state.stores.read().await;
-    let store_ids: Vec<String> = stores.keys().cloned().collect();
+    Path(store_id): Path<String>,
+    claims: RequestClaims,
+) -> Result<Json<VerifyStoreResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if !claims.can_access_aggregate(&store_id) {
+        return Err(forbidden_response(&store_id));
+    }
+
+    app_state.ensure_store_exists(&store_id).await?;
+
+    let issues = app_state.stores.read().await[&store_id].verify_integrity();
+    Ok(Json(VerifyStoreResponse {
+        store_id,
+        ok: issues.is_empty(),
+        issues,
+    }))
+}
+
+/// Get the materialized state of a store plus the sequence it reflects
+///
+/// A fresh client renders the snapshot immediately and then follows the
+/// store from `cursor` onward. Scoped tokens only see the documents they may
+/// access.
+pub async fn sync_store(
+    State(app_state): State<AppState>,
+    Path(store_id): Path<String>,
+    claims: RequestClaims,
+) -> Result<Json<SyncResponse>, (StatusCode, Json<ErrorResponse>)> {
+    app_state.ensure_store_exists(&store_id).await?;
+
+    // Hold both locks so the snapshot and cursor describe the same point in the log
+    let (snapshot, cursor, flushed) = {
+        let stores = app_state.stores.read().await;
+        let mut projections = app_state.projections.write().await;
+        let registry = projections.get_mut(&store_id).unwrap();
+        // Held source updates are already under the cursor, so the snapshot
+        // has to include them or the client never sees those edits
+        let flushed = app_state.take_pending_source_updates(&store_id).await;
+        if let Err(e) = registry.apply_new_events(&flushed) {
+            warn!("Failed to update projection for store {}: {}", store_id, e);
+        }
+        (
+            documents(registry).snapshot(),
+            stores.get(&store_id).unwrap().latest_sequence(),
+            flushed,
+        )
+    };
+    for event in flushed {
+        app_state
+            .connection_manager
+            .broadcast_event(store_id.clone(), event)
+            .await;
+    }
+    let snapshot = if claims.is_scoped() {
+        Arc::new(snapshot.retain_documents(|document_id| claims.can_access_aggregate(document_id)))
+    } else {
+        snapshot
+    };
+
+    Ok(Json(SyncResponse {
+        store_id,
+        snapshot,
+        cursor,
+    }))
+}
+
+/// Get several materialized cells from a store in one request
+pub async fn batch_get_cells(
+    State(app_state): State<AppState>,
+    Path(store_id): Path<String>,
+    claims: RequestClaims,
+    Json(req): Json<BatchGetCellsRequest>,
+) -> Result<Json<BatchGetCellsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    app_state.ensure_store_exists(&store_id).await?;
+
+    let snapshot = documents(&app_state.projections.read().await[&store_id]).snapshot();
+
+    let cells = req
+        .cell_ids
+        .iter()
+        .filter_map(|cell_id| snapshot.get_live_cell(cell_id))
+        .filter(|cell| claims.can_access_aggregate(&cell.document_id))
+        .cloned()
+        .collect();
+
+    Ok(Json(BatchGetCellsResponse { cells }))
+}
+
+/// Delete a store, its projection and any held source updates
+///
+/// Subscribers are sent `store_deleted` and disconnected. With
+/// `auto_create_stores` on, the next request for the store recreates it
+/// empty; otherwise it 404s until created again.
+pub async fn delete_store(
+    State(app_state): State<AppState>,
+    Path(store_id): Path<String>,
+    claims: RequestClaims,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    if !claims.can_access_aggregate(&store_id) {
+        return Err(forbidden_response(&store_id));
+    }
+    if claims.role_for(&store_id) < Role::Owner {
+        return Err(insufficient_role_response(&store_id, Role::Owner));
+    }
+
+    {
+        let mut stores = app_state.stores.write().await;
+        let mut projections = app_state.projections.write().await;
+        if !stores.contains_key(&store_id) {
+            return Err(store_not_found_response(&store_id));
+        }
+        let removed_store = store_id.clone();
+        app_state
+            .persist(&store_id, move |data_dir| {
+                data_dir.remove_store(&removed_store)
+            })
+            .await?;
+        stores.remove(&store_id);
+        projections.remove(&store_id);
+        app_state
+            .pending_source_updates
+            .write()
+            .await
+            .retain(|(store, _), _| store != &store_id);
+    }
+
+    app_state.connection_manager.close_store(&store_id).await;
+    info!("Store {} deleted", store_id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Snapshot a store's projections, optionally dropping the history behind them
+///
+/// Appends a `SnapshotCreated` event holding the materialized documents and
+/// comments; replaying a log from it gives the same projections as replaying
+/// everything before it. With `prune=true` every earlier event is dropped.
+/// Aggregate versions and sequence numbers carry on where they were, and a
+/// cursor from before the prune resumes at the snapshot, but `as_of` reads,
+/// cell history and undo can no longer reach past it. Subscribers get a
+/// `refresh` either way. Events submitted
+/// afterwards are stamped after the snapshot, so replays don't lose them.
+///
+/// The snapshot lives on its own `snapshot-*` aggregate and holds every
+/// document, so tokens scoped to particular aggregates can't read it. After
+/// pruning, such tokens see no events from before the snapshot; they should
+/// read current state from the document and sync endpoints instead.
+pub async fn compact_store(
+    State(app_state): State<AppState>,
+    Path(store_id): Path<String>,
+    Query(query): Query<CompactQuery>,
+    claims: RequestClaims,
+) -> Result<Json<CompactStoreResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if !claims.can_access_aggregate(&store_id) {
+        return Err(forbidden_response(&store_id));
+    }
+    if claims.role_for(&store_id) < Role::Owner {
+        return Err(insufficient_role_response(&store_id, Role::Owner));
+    }
+
+    let mut stores = app_state.stores.write().await;
+    let mut projections = app_state.projections.write().await;
+    let Some(event_store) = stores.get_mut(&store_id) else {
+        return Err(store_not_found_response(&store_id));
+    };
+    let registry = projections.get_mut(&store_id).unwrap();
+
+    // Source updates still held back are already stored, so the snapshot
+    // has to include them
+    let pending = app_state.take_pending_source_updates(&store_id).await;
+    if let Err(e) = registry.apply_new_events(&pending) {
+        warn!("Failed to update projection for store {}: {}", store_id, e);
+    }
+
+    // Stamped no earlier than anything it covers, so replays apply it after
+    // all of it; later submits are stamped after it by `stamp_in_order`
+    let now = eventbook_core::current_timestamp();
+    let timestamp = event_store
+        .iter_events()
+        .next_back()
+        .map_or(now, |newest| newest.timestamp.max(now));
+    let comments = registry
+        .get::<CommentProjection>()
+        .expect("every store registers a CommentProjection")
+        .get_state();
+    let snapshot = snapshot_event(
+        &documents(registry).snapshot(),
+        comments,
+        &event_store.latest_versions(),
+        event_store.latest_sequence(),
+        timestamp,
+    )
+    .map_err(event_error_to_response)?;
+
+    let events_before = event_store.get_event_count();
+    // Pruning can't be undone by truncating, so keep what it replaces
+    let uncompacted = query.prune.then(|| event_store.clone());
+    if query.prune {
+        event_store.compact(snapshot.clone())
+    } else {
+        event_store.append_event(snapshot.clone())
+    }
+    .map_err(event_error_to_response)?;
+    let event_count = event_store.get_event_count();
+
+    let (persisted_store, persisted) = (store_id.clone(), snapshot.clone());
+    if let Err(e) = app_state
+        .persist(&store_id, move |data_dir| {
+            let events = std::slice::from_ref(&persisted);
+            if query.prune {
+                data_dir.replace(&persisted_store, events)
+            } else {
+                data_dir.append(&persisted_store, events)
+            }
+        })
+        .await
+    {
+        match uncompacted {
+            Some(uncompacted) => *event_store = uncompacted,
+            None => event_store.truncate(events_before),
+        }
+        return Err(e);
+    }
+
+    // The held source updates and the snapshot reach subscribers as one refresh
+    app_state
+        .connection_manager
+        .pause_broadcasts(&store_id)
+        .await;
+    if let Err(e) = registry.apply_new_events(std::slice::from_ref(&snapshot)) {
+        warn!("Failed to update projection for store {}: {}", store_id, e);
+    }
+    app_state.metrics.record_events_appended(1);
+    drop(projections);
+    drop(stores);
+    app_state
+        .connection_manager
+        .resume_broadcasts(&store_id)
+        .await;
+    let pruned_events = (events_before + 1).saturating_sub(event_count);
+    info!(
+        "Store {} compacted ({} events pruned)",
+        store_id, pruned_events
+    );
+
+    Ok(Json(CompactStoreResponse {
+        store_id,
+        snapshot_event_id: snapshot.id,
+        pruned_events,
+        event_count,
+    }))
+}
+
+/// Get a materialized document and its ordered cells
+///
+/// With `as_of`, the document is rebuilt from the events stamped at or before
+/// that time instead of read from the live projection.
+pub async fn get_document(
+    State(app_state): State<AppState>,
+    Path((store_id, document_id)): Path<(String, String)>,
+    Query(query): Query<DocumentQuery>,
+    claims: RequestClaims,
+) -> Result<Json<DocumentResponse>, (StatusCode, Json<ErrorResponse>)> {
+    // Documents outside the token's scope look missing, so ids don't leak
+    if !claims.can_access_aggregate(&document_id) {
+        return Err(not_found_response("Document", &document_id));
+    }
+
+    app_state.ensure_store_exists(&store_id).await?;
+
+    if let Some(as_of) = query.as_of {
+        let events = {
+            let stores = app_state.stores.read().await;
+            stores
+                .get(&store_id)
+                .unwrap()
+                .get_all_events()
+                .map_err(event_error_to_response)?
+        };
+        // Replay into a throwaway projection so the live one is untouched
+        let mut projection = DocumentProjection::new();
+        projection
+            .rebuild_as_of(&UpcasterRegistry::standard().upcast_all(&events), as_of)
+            .map_err(event_error_to_response)?;
+        return document_response(&projection.snapshot(), &document_id, &query).map(Json);
+    }
+
+    let snapshot = documents(&app_state.projections.read().await[&store_id]).snapshot();
+    document_response(&snapshot, &document_id, &query).map(Json)
+}
+
+fn document_response(
+    state: &DocumentProjectionState,
+    document_id: &str,
+    query: &DocumentQuery,
+) -> Result<DocumentResponse, (StatusCode, Json<ErrorResponse>)> {
+    let document = state
+        .documents
+        .get(document_id)
+        .cloned()
+        .ok_or_else(|| not_found_response("Document", document_id))?;
+    let offset = query.offset.unwrap_or(0) as usize;
+    let limit = query.limit.map_or(usize::MAX, |limit| limit as usize);
+    let cells: Vec<Cell> = state
+        .get_document_cells_range(document_id, offset, limit)
+        .into_iter()
+        .cloned()
+        .collect();
+    let total_cells = state.document_cell_count(document_id);
+    let has_more = offset + cells.len() < total_cells;
+
+    Ok(DocumentResponse {
+        document,
+        cells,
+        total_cells,
+        has_more,
+    })
+}
+
+/// Put a document's cells in a new order
+///
+/// The server picks the fractional indices, moving as few cells as it can;
+/// responds with the `CellMoved` events it stored, none if the order is
+/// unchanged. Subscribers get a `refresh` rather than the individual moves.
+pub async fn reorder_document_cells(
+    State(app_state): State<AppState>,
+    Path((store_id, document_id)): Path<(String, String)>,
+    claims: RequestClaims,
+    Json(req): Json<ReorderCellsRequest>,
+) -> Result<Json<BatchSubmitResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if !claims.can_access_aggregate(&document_id) {
+        return Err(forbidden_response(&document_id));
+    }
+    let required = Role::required_to_submit("CellMoved");
+    if claims.role_for(&store_id) < required {
+        return Err(insufficient_role_response(&store_id, required));
+    }
+
+    app_state.ensure_store_exists(&store_id).await?;
+
+    let mut stores = app_state.stores.write().await;
+    let mut projections = app_state.projections.write().await;
+
+    let event_store = stores.get_mut(&store_id).unwrap();
+    let registry = projections.get_mut(&store_id).unwrap();
+
+    let mut events = {
+        let projection = documents(registry);
+        if projection.get_document(&document_id).is_none() {
+            return Err(not_found_response("Document", &document_id));
+        }
+        let cells = projection.get_document_cells(&document_id);
+        let current: Vec<_> = cells
+            .iter()
+            .map(|cell| (cell.id.as_str(), cell.fractional_index.as_ref()))
+            .collect();
+        let desired: Vec<&str> = req.cell_ids.iter().map(String::as_str).collect();
+        reorder_cells(
+            &document_id,
+            &current,
+            &desired,
+            event_store.get_latest_version(&document_id) + 1,
+        )
+        .map_err(event_error_to_response)?
+    };
+    for event in &mut events {
+        event.actor = claims.subject().map(String::from);
+    }
+
+    let stored_count = event_store.get_event_count();
+    event_store
+        .append_events(events.clone())
+        .map_err(event_error_to_response)?;
+    let (persisted_store, persisted) = (store_id.clone(), events.clone());
+    if let Err(e) = app_state
+        .persist(&store_id, move |data_dir| {
+            data_dir.append(&persisted_store, &persisted)
+        })
+        .await
+    {
+        event_store.truncate(stored_count);
+        return Err(e);
+    }
+    app_state.metrics.record_events_appended(events.len());
+
+    // Held source updates go first so the projection sees events in order
+    let mut applied = app_state.take_pending_source_updates(&store_id).await;
+    applied.extend(events.iter().cloned());
+
+    // Subscribers get one refresh for the new order instead of a frame per move
+    if !applied.is_empty() {
+        app_state
+            .connection_manager
+            .pause_broadcasts(&store_id)
+            .await;
+    }
+    if let Err(e) = registry.apply_new_events(&applied) {
+        warn!("Failed to update projection for store {}: {}", store_id, e);
+    }
+    drop(projections);
+    drop(stores);
+    app_state
+        .connection_manager
+        .resume_broadcasts(&store_id)
+        .await;
+
+    info!(
+        "Document {} in store {} reordered with {} moves",
+        document_id,
+        store_id,
+        events.len()
+    );
+
+    Ok(Json(BatchSubmitResponse {
+        events: events
+            .into_iter()
+            .map(|event| SubmitEventResponse {
+                event_id: event.id,
+                version: event.version,
+            })
+            .collect(),
+    }))
+}
+
+/// Get a materialized cell and its outputs
+pub async fn get_cell(
+    State(app_state): State<AppState>,
+    Path((store_id, cell_id)): Path<(String, String)>,
+    claims: RequestClaims,
+) -> Result<Json<CellResponse>, (StatusCode, Json<ErrorResponse>)> {
+    app_state.ensure_store_exists(&store_id).await?;
+
+    let snapshot = documents(&app_state.projections.read().await[&store_id]).snapshot();
+
+    // Cells in documents outside the token's scope look missing
+    let cell = snapshot
+        .get_live_cell(&cell_id)
+        .filter(|cell| claims.can_access_aggregate(&cell.document_id))
+        .cloned()
+        .ok_or_else(|| not_found_response("Cell", &cell_id))?;
+    let outputs = snapshot
+        .get_cell_outputs(&cell_id)
+        .into_iter()
+        .cloned()
+        .collect();
+
+    Ok(Json(CellResponse { cell, outputs }))
+}
+
+/// Explicitly create a store
+///
+/// Responds 201 if the store was created and 200 if it already existed.
+pub async fn create_store(
+    State(app_state): State<AppState>,
+    Path(store_id): Path<String>,
+    claims: RequestClaims,
+) -> Result<(StatusCode, Json<CreateStoreResponse>), (StatusCode, Json<ErrorResponse>)> {
+    if !claims.can_access_aggregate(&store_id) {
+        return Err(forbidden_response(&store_id));
+    }
+    if claims.role_for(&store_id) < Role::Editor {
+        return Err(insufficient_role_response(&store_id, Role::Editor));
+    }
+
+    let created = app_state.create_store(&store_id).await?;
+    if created {
+        info!("Store {} created", store_id);
+    }
+
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Json(CreateStoreResponse { store_id, created })))
+}
+
+/// List the stores the caller can see into
+///
+/// A scoped token only sees stores named after one of its aggregates or
+/// holding events for one.
+pub async fn list_stores(
+    State(app_state): State<AppState>,
+    claims: RequestClaims,
+) -> Result<Json<Vec<String>>, (StatusCode, Json<ErrorResponse>)> {
+    let stores = app_state.stores.read().await;
+    let store_ids: Vec<String> = stores
+        .iter()
+        .filter(|(store_id, store)| {
+            claims.can_access_aggregate(store_id)
+                || store
+                    .get_aggregate_ids()
+                    .iter()
+                    .any(|id| claims.can_access_aggregate(id))
+        })
+        .map(|(store_id, _)| store_id.clone())
+        .collect();
     Ok(Json(store_ids))
 }
 
-/// Health check
-pub async fn health_check() -> Json<serde_json::Value> {
+/// Health check, with enough counts to serve as a readiness probe
+///
+/// Probes shouldn't queue behind writers, so the locks are only tried: a
+/// count whose lock is held comes back as `null` instead of blocking.
+pub async fn health_check(State(app_state): State<AppState>) -> Json<serde_json::Value> {
+    let store_counts = app_state.stores.try_read().ok().map(|stores| {
+        let total_events: usize = stores.values().map(|store| store.get_event_count()).sum();
+        (stores.len(), total_events)
+    });
     Json(serde_json::json!({
         "status": "healthy",
-        "timestamp": eventbook_core::current_timestamp()
+        "timestamp": eventbook_core::current_timestamp(),
+        "uptime_seconds": app_state.metrics.uptime().as_secs(),
+        "stores": store_counts.map(|(stores, _)| stores),
+        "total_events": store_counts.map(|(_, events)| events),
+        "websocket_connections": app_state.connection_manager.try_total_connections(),
     }))
 }
 
@@ -266,17 +1811,81 @@ pub async fn serve_client() -> Html<&'static str> {
     Html(include_str!("../../client.html"))
 }
 
+/// CORS policy for the configured origins
+///
+/// Only the methods and headers the API uses are allowed, and only the
+/// response headers clients read are exposed.
+fn cors_layer(config: &ServerConfig) -> CorsLayer {
+    if config.cors_permissive || config.cors_origins.is_empty() {
+        return CorsLayer::permissive();
+    }
+
+    let origins: Vec<HeaderValue> = config
+        .cors_origins
+        .iter()
+        .filter_map(|origin| match HeaderValue::from_str(origin) {
+            Ok(origin) => Some(origin),
+            Err(_) => {
+                warn!("Ignoring invalid CORS origin {:?}", origin);
+                None
+            }
+        })
+        .collect();
+
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([
+            Method::GET,
+            Method::HEAD,
+            Method::POST,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+        .expose_headers([header::ETAG, HeaderName::from_static(EVENT_COUNT_HEADER)])
+}
+
 /// Create the application router
 pub fn create_app(app_state: AppState) -> Router {
+    let auth = middleware::from_fn_with_state(app_state.clone(), require_api_key);
     Router::new()
-        .route("/", get(serve_client))
-        .route("/health", get(health_check))
         .route("/stores", get(list_stores))
-        .route("/stores/{store_id}/events", post(submit_event))
-        .route("/stores/{store_id}/events", get(get_events))
-        .route("/stores/{store_id}", get(get_store_info))
+        .route(
+            "/stores/{store_id}/events",
+            get(get_events).post(submit_event),
+        )
+        .route("/stores/{store_id}/events/batch", post(submit_event_batch))
+        .route("/stores/{store_id}/compact", post(compact_store))
+        .route(
+            "/stores/{store_id}/documents/{document_id}/reorder",
+            post(reorder_document_cells),
+        )
+        // GET routes also answer HEAD with the same headers and no body
+        .route(
+            "/stores/{store_id}",
+            get(get_store_info).post(create_store).delete(delete_store),
+        )
+        .route("/stores/{store_id}/sync", get(sync_store))
+        .route("/stores/{store_id}/verify", get(verify_store))
+        .route("/stores/{store_id}/aggregates", get(list_aggregates))
+        .route("/stores/{store_id}/cells/batch-get", post(batch_get_cells))
+        .route(
+            "/stores/{store_id}/documents/{document_id}",
+            get(get_document),
+        )
+        .route("/stores/{store_id}/cells/{cell_id}", get(get_cell))
+        // Compress JSON bodies for clients that send Accept-Encoding; the
+        // streaming routes below are added after so they stay untouched
+        .layer(CompressionLayer::new())
         .route("/stores/{store_id}/ws", get(websocket_handler))
-        .layer(CorsLayer::permissive())
+        .route("/stores/{store_id}/sse", get(sse_handler))
+        // Every store route, reads and subscriptions included, needs a known
+        // bearer token when `require_auth` is set
+        .route_layer(auth)
+        .route("/", get(serve_client))
+        .route("/health", get(health_check))
+        .route("/metrics", get(metrics_handler))
+        .layer(cors_layer(&app_state.config))
         .with_state(app_state)
 }
 
@@ -289,10 +1898,17 @@ pub async fn start_server(port: u16) -> anyhow::Result<()> {
 
     info!("Initializing EventBook server...");
 
-    // Create the app state
-    let app_state = AppState::new();
+    // Create the app state, loading any persisted stores
+    let app_state = AppState::open(ServerConfig::from_env())?;
 
-    info!("Event stores initialized (in-memory)");
+    match &app_state.config.data_dir {
+        Some(path) => info!(
+            "Event stores loaded from {} ({} stores)",
+            path.display(),
+            app_state.stores.read().await.len()
+        ),
+        None => info!("Event stores initialized (in-memory)"),
+    }
 
     // Create the app
     let app = create_app(app_state);
@@ -305,3 +1921,2885 @@ pub async fn start_server(port: u16) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use tokio::sync::broadcast;
+    use websocket::{Connection, WsMessage};
+
+    /// Submit an event through the handler, unwrapping the JSON envelope
+    async fn submit(
+        app_state: &AppState,
+        store_id: &str,
+        claims: RequestClaims,
+        event_type: &str,
+        payload: serde_json::Value,
+    ) -> Result<SubmitEventResponse, StatusCode> {
+        submit_event(
+            State(app_state.clone()),
+            Path(store_id.to_string()),
+            claims,
+            Json(SubmitEventRequest {
+                event_type: event_type.to_string(),
+                aggregate_id: None,
+                payload,
+                timestamp: None,
+                transaction_id: None,
+                expected_version: None,
+            }),
+        )
+        .await
+        .map(|Json(response)| response)
+        .map_err(|(status, _)| status)
+    }
+
+    /// Give a store two documents, `doc-a` and `doc-b`, with one cell each
+    async fn two_document_store(app_state: &AppState, store_id: &str) {
+        for document_id in ["doc-a", "doc-b"] {
+            for (event_type, payload) in [
+                ("DocumentCreated", serde_json::json!({"title": document_id})),
+                (
+                    "CellCreated",
+                    serde_json::json!({
+                        "cell_id": format!("cell-{}", &document_id[4..]),
+                        "cell_type": "code",
+                    }),
+                ),
+            ] {
+                let Json(_) = submit_event(
+                    State(app_state.clone()),
+                    Path(store_id.to_string()),
+                    RequestClaims::default(),
+                    Json(SubmitEventRequest {
+                        event_type: event_type.to_string(),
+                        aggregate_id: Some(document_id.to_string()),
+                        payload,
+                        timestamp: None,
+                        transaction_id: None,
+                        expected_version: None,
+                    }),
+                )
+                .await
+                .unwrap();
+            }
+        }
+    }
+
+    fn scoped_claims(aggregate_id: &str) -> RequestClaims {
+        RequestClaims(Some(TokenClaims {
+            subject: "alice".to_string(),
+            aggregates: Some(HashSet::from([aggregate_id.to_string()])),
+            roles: None,
+        }))
+    }
+
+    fn role_claims(store_id: &str, role: Role) -> RequestClaims {
+        RequestClaims(Some(TokenClaims {
+            subject: format!("{:?}", role).to_lowercase(),
+            aggregates: None,
+            roles: Some(HashMap::from([(store_id.to_string(), role)])),
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_scoped_token_hides_other_aggregates() {
+        let app_state = AppState::new();
+        let payload = serde_json::json!({"title": "Test"});
+
+        let response = submit(
+            &app_state,
+            "doc-b",
+            RequestClaims::default(),
+            "DocumentCreated",
+            payload.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.version, 1);
+
+        let claims = scoped_claims("doc-a");
+        let response = submit(
+            &app_state,
+            "doc-a",
+            claims.clone(),
+            "DocumentCreated",
+            payload.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.version, 1);
+
+        let status = submit(
+            &app_state,
+            "doc-b",
+            claims.clone(),
+            "DocumentTitleUpdated",
+            payload,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (_, Json(visible)) = get_events(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            Query(GetEventsQuery::default()),
+            claims.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(visible.events.len(), 1);
+
+        let (_, Json(hidden)) = get_events(
+            State(app_state.clone()),
+            Path("doc-b".to_string()),
+            Query(GetEventsQuery::default()),
+            claims,
+        )
+        .await
+        .unwrap();
+        assert!(hidden.events.is_empty());
+        assert_eq!(hidden.total_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_scoped_store_info_covers_visible_events() {
+        let app_state = AppState::new();
+        two_document_store(&app_state, "nb").await;
+        app_state.create_store("other").await.unwrap();
+        let claims = scoped_claims("doc-a");
+        let info = |claims: RequestClaims| {
+            get_store_info(State(app_state.clone()), Path("nb".to_string()), claims)
+        };
+
+        let (headers, Json(visible)) = info(claims.clone()).await.unwrap();
+        assert_eq!(visible.event_count, 2);
+        assert_eq!(visible.latest_version, 2);
+        let (_, Json(full)) = info(RequestClaims::default()).await.unwrap();
+        assert_eq!(full.event_count, 4);
+
+        // Writes to other aggregates leave the scoped ETag alone
+        let Json(_) = submit_event(
+            State(app_state.clone()),
+            Path("nb".to_string()),
+            RequestClaims::default(),
+            Json(SubmitEventRequest {
+                event_type: "DocumentTitleUpdated".to_string(),
+                aggregate_id: Some("doc-b".to_string()),
+                payload: serde_json::json!({"title": "Renamed"}),
+                timestamp: None,
+                transaction_id: None,
+                expected_version: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let (after, _) = info(claims.clone()).await.unwrap();
+        assert_eq!(after[header::ETAG], headers[header::ETAG]);
+
+        let Json(store_ids) = list_stores(State(app_state.clone()), claims).await.unwrap();
+        assert_eq!(store_ids, vec!["nb"]);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_skips_unauthorized_connections() {
+        let manager = ConnectionManager::new();
+        let (scoped_tx, mut scoped_rx) = broadcast::channel(10);
+        let (open_tx, mut open_rx) = broadcast::channel(10);
+
+        manager
+            .subscribe(
+                "doc-b".to_string(),
+                Connection {
+                    id: "scoped".to_string(),
+                    sender: scoped_tx,
+                    claims: scoped_claims("doc-a"),
+                },
+            )
+            .await;
+        manager
+            .subscribe(
+                "doc-b".to_string(),
+                Connection {
+                    id: "open".to_string(),
+                    sender: open_tx,
+                    claims: RequestClaims::default(),
+                },
+            )
+            .await;
+
+        let event = EventBuilder::new()
+            .event_type("DocumentCreated")
+            .aggregate_id("doc-b")
+            .build(1)
+            .unwrap();
+        manager.broadcast_event("doc-b".to_string(), event).await;
+
+        assert!(matches!(open_rx.try_recv(), Ok(WsMessage::Event { .. })));
+        assert!(scoped_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sync_returns_snapshot_and_cursor() {
+        let app_state = AppState::new();
+        let claims = RequestClaims::default();
+
+        submit(
+            &app_state,
+            "doc-a",
+            claims.clone(),
+            "DocumentCreated",
+            serde_json::json!({"title": "Synced"}),
+        )
+        .await
+        .unwrap();
+        submit(
+            &app_state,
+            "doc-a",
+            claims.clone(),
+            "CellCreated",
+            serde_json::json!({"cell_id": "cell-1", "cell_type": "code", "source": "1 + 1"}),
+        )
+        .await
+        .unwrap();
+
+        let sync = sync_store(State(app_state.clone()), Path("doc-a".to_string()), claims)
+            .await
+            .unwrap();
+
+        assert_eq!(sync.snapshot.documents["doc-a"].title, "Synced");
+
+        let stores = app_state.stores.read().await;
+        assert_eq!(sync.cursor, stores["doc-a"].latest_sequence());
+        assert_eq!(sync.cursor, 2);
+    }
+
+    #[tokio::test]
+    async fn test_far_future_timestamp_rejected() {
+        let app_state = AppState::new();
+
+        let (status, Json(error)) = submit_event(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            RequestClaims::default(),
+            Json(SubmitEventRequest {
+                event_type: "DocumentCreated".to_string(),
+                aggregate_id: None,
+                payload: serde_json::json!({"title": "From the future"}),
+                // 3000-01-01T00:00:00Z
+                timestamp: Some(32_503_680_000),
+                transaction_id: None,
+                expected_version: None,
+            }),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.code, "VALIDATION_ERROR");
+        assert_eq!(app_state.stores.read().await["doc-a"].get_event_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_client_timestamps_keep_the_projection_in_order() {
+        let app_state = AppState::new();
+        let create_cell = |cell_id: &str, timestamp: Option<i64>| {
+            submit_event(
+                State(app_state.clone()),
+                Path("doc-a".to_string()),
+                RequestClaims::default(),
+                Json(SubmitEventRequest {
+                    event_type: "CellCreated".to_string(),
+                    aggregate_id: None,
+                    payload: serde_json::json!({"cell_id": cell_id, "cell_type": "code"}),
+                    timestamp,
+                    transaction_id: None,
+                    expected_version: None,
+                }),
+            )
+        };
+
+        let now = eventbook_core::current_timestamp();
+        let _ = create_cell("cell-future", Some(now + 200)).await.unwrap();
+        let _ = create_cell("cell-now", None).await.unwrap();
+        let _ = create_cell("cell-backdated", Some(1_000)).await.unwrap();
+
+        let stores = app_state.stores.read().await;
+        let timestamps: Vec<i64> = stores["doc-a"].iter_events().map(|e| e.timestamp).collect();
+        assert_eq!(timestamps, vec![now + 200; 3]);
+
+        let projections = app_state.projections.read().await;
+        let documents = documents(&projections["doc-a"]);
+        for cell_id in ["cell-future", "cell-now", "cell-backdated"] {
+            assert!(documents.get_cell(cell_id).is_some(), "{} missing", cell_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stale_offline_source_edit_loses_to_newer_one() {
+        let app_state = AppState::new();
+        submit(
+            &app_state,
+            "doc-a",
+            RequestClaims::default(),
+            "CellCreated",
+            serde_json::json!({"cell_id": "cell-1", "cell_type": "code", "source": ""}),
+        )
+        .await
+        .unwrap();
+        let edit = |source: &str, timestamp: i64| {
+            submit_event(
+                State(app_state.clone()),
+                Path("doc-a".to_string()),
+                RequestClaims::default(),
+                Json(SubmitEventRequest {
+                    event_type: "CellSourceUpdated".to_string(),
+                    aggregate_id: None,
+                    payload: serde_json::json!({"cell_id": "cell-1", "source": source}),
+                    timestamp: Some(timestamp),
+                    transaction_id: None,
+                    expected_version: None,
+                }),
+            )
+        };
+
+        // The offline edit was made first but reaches the server last
+        let now = eventbook_core::current_timestamp();
+        let _ = edit("online", now).await.unwrap();
+        let _ = edit("offline", now - 600).await.unwrap();
+
+        // Stored in arrival order, with the edit time kept alongside
+        let stores = app_state.stores.read().await;
+        let stale = stores["doc-a"].iter_events().next_back().unwrap();
+        assert!(stale.timestamp >= now);
+        assert_eq!(stale.payload["edited_at"], now - 600);
+        drop(stores);
+
+        let projections = app_state.projections.read().await;
+        let cell = documents(&projections["doc-a"]).get_cell("cell-1").unwrap();
+        assert_eq!(cell.source, "online");
+        assert_eq!(cell.source_updated_at, now);
+    }
+
+    #[tokio::test]
+    async fn test_paused_broadcasts_send_refresh_on_resume() {
+        let manager = ConnectionManager::new();
+        let (tx, mut rx) = broadcast::channel(10);
+        manager
+            .subscribe(
+                "doc-a".to_string(),
+                Connection {
+                    id: "conn".to_string(),
+                    sender: tx,
+                    claims: RequestClaims::default(),
+                },
+            )
+            .await;
+
+        manager.pause_broadcasts("doc-a").await;
+        for version in 1..=3 {
+            let event = EventBuilder::new()
+                .event_type("DocumentTitleUpdated")
+                .aggregate_id("doc-a")
+                .build(version)
+                .unwrap();
+            manager.broadcast_event("doc-a".to_string(), event).await;
+        }
+        assert!(rx.try_recv().is_err());
+
+        manager.resume_broadcasts("doc-a").await;
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(WsMessage::Refresh { store_id }) if store_id == "doc-a"
+        ));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_output_event_broadcasts_output_delta() {
+        let manager = ConnectionManager::new();
+        let (tx, mut rx) = broadcast::channel(10);
+        manager
+            .subscribe(
+                "doc-a".to_string(),
+                Connection {
+                    id: "client".to_string(),
+                    sender: tx,
+                    claims: RequestClaims::default(),
+                },
+            )
+            .await;
+
+        let event = EventBuilder::new()
+            .event_type("CellOutputCreated")
+            .aggregate_id("doc-a")
+            .payload(serde_json::json!({
+                "output_id": "out-1",
+                "cell_id": "cell-1",
+                "output_type": "terminal",
+                "stream_name": "stdout",
+                "data": "hello\n",
+            }))
+            .unwrap()
+            .build(1)
+            .unwrap();
+        manager.broadcast_event("doc-a".to_string(), event).await;
+
+        assert!(matches!(rx.try_recv(), Ok(WsMessage::Event { .. })));
+        match rx.try_recv() {
+            Ok(WsMessage::OutputDelta {
+                store_id,
+                cell_id,
+                output,
+            }) => {
+                assert_eq!(store_id, "doc-a");
+                assert_eq!(cell_id, "cell-1");
+                assert_eq!(output.id, "out-1");
+                assert_eq!(output.data.as_deref(), Some("hello\n"));
+            }
+            other => panic!("expected output delta, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_roles_gate_submits_but_not_reads() {
+        let app_state = AppState::new();
+        let viewer = role_claims("doc-a", Role::Viewer);
+        let editor = role_claims("doc-a", Role::Editor);
+        let title = serde_json::json!({"title": "Shared"});
+
+        assert_eq!(
+            submit(
+                &app_state,
+                "doc-a",
+                viewer.clone(),
+                "DocumentCreated",
+                title.clone()
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        submit(
+            &app_state,
+            "doc-a",
+            editor.clone(),
+            "DocumentCreated",
+            title,
+        )
+        .await
+        .unwrap();
+
+        // Deleting a document is reserved for owners
+        assert_eq!(
+            submit(
+                &app_state,
+                "doc-a",
+                editor,
+                "DocumentDeleted",
+                serde_json::json!({})
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+
+        let (_, Json(response)) = get_events(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            Query(GetEventsQuery::default()),
+            viewer,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.total_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_events_descending_pages() {
+        let app_state = AppState::new();
+        for i in 1..=5 {
+            submit(
+                &app_state,
+                "doc-a",
+                RequestClaims::default(),
+                "CellSourceUpdated",
+                serde_json::json!({"cell_id": "cell-1", "source": i.to_string()}),
+            )
+            .await
+            .unwrap();
+        }
+
+        let page = |offset| {
+            let app_state = app_state.clone();
+            async move {
+                let (_, Json(response)) = get_events(
+                    State(app_state),
+                    Path("doc-a".to_string()),
+                    Query(GetEventsQuery {
+                        limit: Some(2),
+                        offset: Some(offset),
+                        order: SortOrder::Desc,
+                        ..Default::default()
+                    }),
+                    RequestClaims::default(),
+                )
+                .await
+                .unwrap();
+                assert_eq!(response.total_count, 5);
+                response
+                    .events
+                    .iter()
+                    .map(|e| e.version)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(page(0).await, vec![5, 4]);
+        assert_eq!(page(2).await, vec![3, 2]);
+        assert_eq!(page(4).await, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_expected_version_conflict() {
+        let app_state = AppState::new();
+        let submit_expecting = |expected_version: Option<i64>| {
+            submit_event(
+                State(app_state.clone()),
+                Path("doc-a".to_string()),
+                RequestClaims::default(),
+                Json(SubmitEventRequest {
+                    event_type: "DocumentTitleUpdated".to_string(),
+                    aggregate_id: None,
+                    payload: serde_json::json!({"title": "Renamed"}),
+                    timestamp: None,
+                    transaction_id: None,
+                    expected_version,
+                }),
+            )
+        };
+
+        let Json(first) = submit_expecting(Some(0)).await.unwrap();
+        assert_eq!(first.version, 1);
+
+        // A second writer that also read version 0 loses the race
+        let (status, Json(error)) = submit_expecting(Some(0)).await.unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(error.code, "VERSION_CONFLICT");
+        assert_eq!(
+            error.details,
+            Some(serde_json::json!({"expected": 0, "got": 1}))
+        );
+
+        let Json(retry) = submit_expecting(Some(1)).await.unwrap();
+        assert_eq!(retry.version, 2);
+    }
+
+    #[tokio::test]
+    async fn test_documents_share_store_with_separate_versions() {
+        let app_state = AppState::new();
+        let submit_to = |aggregate_id: &str| {
+            submit_event(
+                State(app_state.clone()),
+                Path("workspace".to_string()),
+                RequestClaims::default(),
+                Json(SubmitEventRequest {
+                    event_type: "DocumentCreated".to_string(),
+                    aggregate_id: Some(aggregate_id.to_string()),
+                    payload: serde_json::json!({"title": aggregate_id}),
+                    timestamp: None,
+                    transaction_id: None,
+                    expected_version: None,
+                }),
+            )
+        };
+
+        let Json(a1) = submit_to("doc-a").await.unwrap();
+        let Json(b1) = submit_to("doc-b").await.unwrap();
+        let Json(a2) = submit_to("doc-a").await.unwrap();
+        assert_eq!((a1.version, b1.version, a2.version), (1, 1, 2));
+
+        let events_for = |aggregate_id: Option<&str>| {
+            get_events(
+                State(app_state.clone()),
+                Path("workspace".to_string()),
+                Query(GetEventsQuery {
+                    aggregate_id: aggregate_id.map(str::to_string),
+                    ..Default::default()
+                }),
+                RequestClaims::default(),
+            )
+        };
+
+        let (_, Json(all)) = events_for(None).await.unwrap();
+        assert_eq!(all.total_count, 3);
+
+        let (_, Json(doc_b)) = events_for(Some("doc-b")).await.unwrap();
+        assert_eq!(doc_b.total_count, 1);
+        assert_eq!(doc_b.events[0].aggregate_id, "doc-b");
+    }
+
+    #[tokio::test]
+    async fn test_head_returns_headers_without_body() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let app_state = AppState::new();
+        for title in ["One", "Two"] {
+            submit(
+                &app_state,
+                "doc-a",
+                RequestClaims::default(),
+                "DocumentTitleUpdated",
+                serde_json::json!({ "title": title }),
+            )
+            .await
+            .unwrap();
+        }
+        let (addr, handle) = test_support::spawn_test_server_with_state(app_state.clone()).await;
+
+        let request = |method: &'static str, path: &'static str| async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let request = format!(
+                "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                method, path
+            );
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            (head.to_lowercase(), body.to_string())
+        };
+        let header = |head: &str, name: &str| {
+            head.lines()
+                .find_map(|line| line.strip_prefix(&format!("{}: ", name)))
+                .map(str::to_string)
+        };
+
+        for path in ["/stores/doc-a", "/stores/doc-a/events"] {
+            let (get_head, get_body) = request("GET", path).await;
+            let (head_head, head_body) = request("HEAD", path).await;
+
+            assert!(head_head.starts_with("http/1.1 200"));
+            assert!(head_body.is_empty());
+            assert!(!get_body.is_empty());
+            assert_eq!(header(&head_head, EVENT_COUNT_HEADER).as_deref(), Some("2"));
+            assert!(header(&head_head, "etag").is_some());
+            assert_eq!(header(&head_head, "etag"), header(&get_head, "etag"));
+        }
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_rapid_source_updates_are_coalesced() {
+        let app_state = AppState::with_config(ServerConfig {
+            source_update_debounce_ms: 50,
+            ..ServerConfig::default()
+        });
+        let (tx, mut rx) = broadcast::channel(10);
+        app_state
+            .connection_manager
+            .subscribe(
+                "doc-a".to_string(),
+                Connection {
+                    id: "client".to_string(),
+                    sender: tx,
+                    claims: RequestClaims::default(),
+                },
+            )
+            .await;
+
+        let claims = RequestClaims::default();
+        submit(
+            &app_state,
+            "doc-a",
+            claims.clone(),
+            "CellCreated",
+            serde_json::json!({"cell_id": "cell-1", "cell_type": "code", "source": ""}),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(rx.try_recv(), Ok(WsMessage::Event { .. })));
+
+        for source in ["p", "pr", "print(1)"] {
+            submit(
+                &app_state,
+                "doc-a",
+                claims.clone(),
+                "CellSourceUpdated",
+                serde_json::json!({"cell_id": "cell-1", "source": source}),
+            )
+            .await
+            .unwrap();
+        }
+
+        // Every event is stored straight away, but nothing is broadcast yet
+        let stores = app_state.stores.read().await;
+        assert_eq!(stores["doc-a"].get_event_count(), 4);
+        drop(stores);
+        assert!(rx.try_recv().is_err());
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        match rx.try_recv() {
+            Ok(WsMessage::Event { event, .. }) => {
+                assert_eq!(event.version, 4);
+                assert_eq!(event.payload["source"], "print(1)");
+            }
+            other => panic!("expected coalesced event, got {:?}", other),
+        }
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sync_includes_held_source_updates() {
+        let app_state = AppState::with_config(ServerConfig {
+            source_update_debounce_ms: 50,
+            ..ServerConfig::default()
+        });
+        let claims = RequestClaims::default();
+        for (event_type, payload) in [
+            (
+                "CellCreated",
+                serde_json::json!({"cell_id": "cell-1", "cell_type": "code", "source": ""}),
+            ),
+            (
+                "CellSourceUpdated",
+                serde_json::json!({"cell_id": "cell-1", "source": "print(1)"}),
+            ),
+        ] {
+            submit(&app_state, "doc-a", claims.clone(), event_type, payload)
+                .await
+                .unwrap();
+        }
+
+        // The cursor already counts the held update, so the snapshot must
+        // reflect it
+        let Json(sync) = sync_store(State(app_state.clone()), Path("doc-a".to_string()), claims)
+            .await
+            .unwrap();
+        assert_eq!(sync.cursor, 2);
+        assert_eq!(sync.snapshot.cells["cell-1"].source, "print(1)");
+    }
+
+    #[tokio::test]
+    async fn test_auto_create_stores_on_by_default() {
+        let app_state = AppState::new();
+        let (_, Json(info)) = get_store_info(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            RequestClaims::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(info.event_count, 0);
+
+        let Json(stores) = list_stores(State(app_state.clone()), RequestClaims::default())
+            .await
+            .unwrap();
+        assert_eq!(stores, vec!["doc-a".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_missing_store_404s_without_auto_create() {
+        let app_state = AppState::with_config(ServerConfig {
+            auto_create_stores: false,
+            ..ServerConfig::default()
+        });
+        let claims = RequestClaims::default();
+
+        let err = get_events(
+            State(app_state.clone()),
+            Path("typo".to_string()),
+            Query(GetEventsQuery::default()),
+            claims.clone(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.0, StatusCode::NOT_FOUND);
+        assert_eq!(err.1.code, "STORE_NOT_FOUND");
+
+        let status = submit(
+            &app_state,
+            "typo",
+            claims.clone(),
+            "DocumentTitleUpdated",
+            serde_json::json!({"title": "Lost"}),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // No phantom store was left behind
+        let Json(stores) = list_stores(State(app_state.clone()), RequestClaims::default())
+            .await
+            .unwrap();
+        assert!(stores.is_empty());
+
+        let (status, Json(created)) = create_store(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            claims.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(created.created);
+
+        let (status, _) = create_store(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            claims.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+
+        submit(
+            &app_state,
+            "doc-a",
+            claims,
+            "DocumentTitleUpdated",
+            serde_json::json!({"title": "Found"}),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_events_since_version() {
+        let app_state = AppState::new();
+        for title in ["one", "two", "three"] {
+            submit(
+                &app_state,
+                "doc-a",
+                RequestClaims::default(),
+                "DocumentTitleUpdated",
+                serde_json::json!({"title": title}),
+            )
+            .await
+            .unwrap();
+        }
+
+        let since = |since_version| {
+            let app_state = app_state.clone();
+            async move {
+                let (_, Json(response)) = get_events(
+                    State(app_state),
+                    Path("doc-a".to_string()),
+                    Query(GetEventsQuery {
+                        since_version: Some(since_version),
+                        ..Default::default()
+                    }),
+                    RequestClaims::default(),
+                )
+                .await
+                .unwrap();
+                response
+                    .events
+                    .iter()
+                    .map(|e| e.version)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(since(0).await, vec![1, 2, 3]);
+        assert_eq!(since(2).await, vec![3]);
+        assert!(since(3).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_websocket_catch_up_replays_history() {
+        let app_state = AppState::new();
+        for title in ["one", "two"] {
+            submit(
+                &app_state,
+                "doc-a",
+                RequestClaims::default(),
+                "DocumentTitleUpdated",
+                serde_json::json!({"title": title}),
+            )
+            .await
+            .unwrap();
+        }
+
+        let messages = websocket::catch_up_messages(
+            &app_state.stores,
+            "doc-a",
+            "conn-1",
+            &RequestClaims::default(),
+        )
+        .await;
+        assert_eq!(messages.len(), 3);
+        assert!(matches!(
+            &messages[0],
+            WsMessage::Subscribed { latest_version: 2, connection_id, .. } if connection_id == "conn-1"
+        ));
+        let versions: Vec<i64> = messages[1..]
+            .iter()
+            .map(|message| match message {
+                WsMessage::Event { event, .. } => event.version,
+                other => panic!("expected an event, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(versions, vec![1, 2]);
+
+        // Events the client may not see are left out of the replay
+        let hidden = websocket::catch_up_messages(
+            &app_state.stores,
+            "doc-a",
+            "conn-2",
+            &scoped_claims("doc-b"),
+        )
+        .await;
+        assert!(matches!(
+            hidden.as_slice(),
+            [WsMessage::Subscribed {
+                latest_version: 0,
+                ..
+            }]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_connection_subscribes_to_multiple_stores() {
+        let manager = ConnectionManager::new();
+        let (tx, mut rx) = broadcast::channel(10);
+        manager
+            .subscribe(
+                "doc-a".to_string(),
+                Connection {
+                    id: "conn".to_string(),
+                    sender: tx,
+                    claims: RequestClaims::default(),
+                },
+            )
+            .await;
+
+        assert!(manager.add_subscription("doc-b", "conn").await);
+        assert!(!manager.add_subscription("doc-b", "conn").await);
+        assert!(!manager.add_subscription("doc-b", "unknown").await);
+        assert_eq!(manager.get_connection_count("doc-b").await, 1);
+        assert_eq!(manager.get_total_connections().await, 1);
+
+        let title_event = |store_id: &str| {
+            EventBuilder::new()
+                .event_type("DocumentTitleUpdated")
+                .aggregate_id(store_id)
+                .build(1)
+                .unwrap()
+        };
+        manager
+            .broadcast_event("doc-b".to_string(), title_event("doc-b"))
+            .await;
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(WsMessage::Event { store_id, .. }) if store_id == "doc-b"
+        ));
+
+        // Leaving the URL's store keeps the other subscription alive
+        manager.unsubscribe("doc-a", "conn").await;
+        manager
+            .broadcast_event("doc-a".to_string(), title_event("doc-a"))
+            .await;
+        assert!(rx.try_recv().is_err());
+        manager
+            .broadcast_event("doc-b".to_string(), title_event("doc-b"))
+            .await;
+        assert!(rx.try_recv().is_ok());
+
+        manager.disconnect("conn").await;
+        assert_eq!(manager.get_connection_count("doc-b").await, 0);
+        assert_eq!(manager.get_total_connections().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_websocket_ping_gets_pong() {
+        let (tx, _rx) = broadcast::channel(10);
+        let connection = Connection {
+            id: "conn".to_string(),
+            sender: tx,
+            claims: RequestClaims::default(),
+        };
+
+        let replies = websocket::handle_client_message(
+            r#"{"type":"ping"}"#,
+            &ConnectionManager::new(),
+            &RwLock::new(HashMap::new()),
+            &connection,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(replies.as_slice(), [WsMessage::Pong]));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_subscribe_replays_long_history() {
+        let app_state = AppState::new();
+        for i in 0..150 {
+            let event_type = if i == 0 {
+                "DocumentCreated"
+            } else {
+                "DocumentTitleUpdated"
+            };
+            submit(
+                &app_state,
+                "doc-b",
+                RequestClaims::default(),
+                event_type,
+                serde_json::json!({"title": format!("Title {}", i)}),
+            )
+            .await
+            .unwrap();
+        }
+
+        // Far more history than the connection's broadcast channel holds
+        let (tx, mut rx) = broadcast::channel(100);
+        let connection = Connection {
+            id: "conn".to_string(),
+            sender: tx,
+            claims: RequestClaims::default(),
+        };
+        let manager = ConnectionManager::new();
+        manager
+            .subscribe("doc-a".to_string(), connection.clone())
+            .await;
+
+        let replies = websocket::handle_client_message(
+            r#"{"type":"subscribe","store_id":"doc-b"}"#,
+            &manager,
+            &app_state.stores,
+            &connection,
+        )
+        .await
+        .unwrap();
+        assert_eq!(replies.len(), 151);
+        assert!(matches!(
+            replies.first(),
+            Some(WsMessage::Subscribed {
+                latest_version: 150,
+                ..
+            })
+        ));
+        assert!(rx.try_recv().is_err());
+        let mut stores = manager.subscribed_stores("conn").await;
+        stores.sort();
+        assert_eq!(stores, vec!["doc-a", "doc-b"]);
+    }
+
+    #[tokio::test]
+    async fn test_sse_stream_replays_then_follows() {
+        use futures_util::StreamExt;
+
+        let app_state = AppState::new();
+        submit(
+            &app_state,
+            "doc-a",
+            RequestClaims::default(),
+            "DocumentCreated",
+            serde_json::json!({"title": "Test"}),
+        )
+        .await
+        .unwrap();
+
+        let stream = sse::subscribe_stream(
+            app_state.connection_manager.clone(),
+            &app_state.stores,
+            "doc-a".to_string(),
+            RequestClaims::default(),
+        )
+        .await;
+        let mut stream = Box::pin(stream);
+        assert!(matches!(
+            stream.next().await,
+            Some(WsMessage::Subscribed {
+                latest_version: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            stream.next().await,
+            Some(WsMessage::Event { event, .. }) if event.version == 1
+        ));
+
+        submit(
+            &app_state,
+            "doc-a",
+            RequestClaims::default(),
+            "DocumentTitleUpdated",
+            serde_json::json!({"title": "Renamed"}),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            stream.next().await,
+            Some(WsMessage::Event { event, .. }) if event.version == 2
+        ));
+        assert_eq!(
+            app_state.connection_manager.get_total_connections().await,
+            1
+        );
+
+        drop(stream);
+        tokio::task::yield_now().await;
+        assert_eq!(
+            app_state.connection_manager.get_total_connections().await,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_materialized_document_and_cell() {
+        let app_state = AppState::new();
+        let claims = RequestClaims::default();
+        submit(
+            &app_state,
+            "doc-a",
+            claims.clone(),
+            "DocumentCreated",
+            serde_json::json!({"title": "Notebook"}),
+        )
+        .await
+        .unwrap();
+        for (cell_id, index) in [("cell-2", "b"), ("cell-1", "a")] {
+            submit(
+                &app_state,
+                "doc-a",
+                claims.clone(),
+                "CellCreated",
+                serde_json::json!({
+                    "cell_id": cell_id,
+                    "cell_type": "code",
+                    "fractional_index": index,
+                }),
+            )
+            .await
+            .unwrap();
+        }
+        submit(
+            &app_state,
+            "doc-a",
+            claims.clone(),
+            "CellOutputCreated",
+            serde_json::json!({
+                "output_id": "out-1",
+                "cell_id": "cell-1",
+                "output_type": "terminal",
+                "stream_name": "stdout",
+                "data": "hello\n",
+            }),
+        )
+        .await
+        .unwrap();
+
+        let Json(document) = get_document(
+            State(app_state.clone()),
+            Path(("doc-a".to_string(), "doc-a".to_string())),
+            Query(DocumentQuery::default()),
+            claims.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(document.document.title, "Notebook");
+        let cell_ids: Vec<&str> = document.cells.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(cell_ids, vec!["cell-1", "cell-2"]);
+
+        let Json(cell) = get_cell(
+            State(app_state.clone()),
+            Path(("doc-a".to_string(), "cell-1".to_string())),
+            claims.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(cell.cell.id, "cell-1");
+        assert_eq!(cell.outputs.len(), 1);
+        assert_eq!(cell.outputs[0].id, "out-1");
+
+        let (status, Json(error)) = get_cell(
+            State(app_state.clone()),
+            Path(("doc-a".to_string(), "missing".to_string())),
+            claims.clone(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(error.code, "CELL_NOT_FOUND");
+
+        let (status, _) = get_document(
+            State(app_state),
+            Path(("doc-a".to_string(), "missing".to_string())),
+            Query(DocumentQuery::default()),
+            claims,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    fn batch_event(event_type: &str, payload: serde_json::Value) -> BatchEventRequest {
+        BatchEventRequest {
+            event_type: event_type.to_string(),
+            aggregate_id: None,
+            payload,
+            version: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch_submit_commits_in_order() {
+        let app_state = AppState::new();
+        let (tx, mut rx) = broadcast::channel(10);
+        app_state
+            .connection_manager
+            .subscribe(
+                "doc-a".to_string(),
+                Connection {
+                    id: "conn".to_string(),
+                    sender: tx,
+                    claims: RequestClaims::default(),
+                },
+            )
+            .await;
+
+        let Json(response) = submit_event_batch(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            RequestClaims::default(),
+            Json(BatchSubmitRequest {
+                events: vec![
+                    batch_event("DocumentCreated", serde_json::json!({"title": "Batch"})),
+                    batch_event(
+                        "CellCreated",
+                        serde_json::json!({"cell_id": "cell-1", "cell_type": "code"}),
+                    ),
+                    batch_event(
+                        "CellCreated",
+                        serde_json::json!({"cell_id": "cell-2", "cell_type": "markdown"}),
+                    ),
+                ],
+            }),
+        )
+        .await
+        .unwrap();
+
+        let versions: Vec<i64> = response.events.iter().map(|e| e.version).collect();
+        assert_eq!(versions, vec![1, 2, 3]);
+        // Subscribers get each committed event, in order
+        for version in versions {
+            assert!(matches!(
+                rx.try_recv(),
+                Ok(WsMessage::Event { event, .. }) if event.version == version
+            ));
+        }
+        assert!(rx.try_recv().is_err());
+        let projections = app_state.projections.read().await;
+        assert_eq!(
+            documents(&projections["doc-a"])
+                .get_document_cells("doc-a")
+                .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_submit_is_all_or_nothing() {
+        let app_state = AppState::new();
+        submit(
+            &app_state,
+            "doc-a",
+            RequestClaims::default(),
+            "DocumentCreated",
+            serde_json::json!({"title": "Test"}),
+        )
+        .await
+        .unwrap();
+
+        let mut stale = batch_event("DocumentTitleUpdated", serde_json::json!({"title": "Late"}));
+        stale.version = Some(2);
+        let (status, Json(error)) = submit_event_batch(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            RequestClaims::default(),
+            Json(BatchSubmitRequest {
+                events: vec![
+                    batch_event("DocumentTitleUpdated", serde_json::json!({"title": "New"})),
+                    stale,
+                ],
+            }),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(error.code, "VERSION_CONFLICT");
+        assert_eq!(error.details.unwrap()["index"], 1);
+        assert_eq!(app_state.stores.read().await["doc-a"].get_event_count(), 1);
+        let projections = app_state.projections.read().await;
+        assert_eq!(
+            documents(&projections["doc-a"])
+                .get_document("doc-a")
+                .unwrap()
+                .title,
+            "Test"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_store() {
+        let app_state = AppState::new();
+        submit(
+            &app_state,
+            "doc-a",
+            RequestClaims::default(),
+            "DocumentCreated",
+            serde_json::json!({"title": "Doomed"}),
+        )
+        .await
+        .unwrap();
+        let (tx, mut rx) = broadcast::channel(10);
+        app_state
+            .connection_manager
+            .subscribe(
+                "doc-a".to_string(),
+                Connection {
+                    id: "conn".to_string(),
+                    sender: tx,
+                    claims: RequestClaims::default(),
+                },
+            )
+            .await;
+
+        let (status, _) = delete_store(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            role_claims("doc-a", Role::Editor),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let status = delete_store(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            RequestClaims::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(WsMessage::StoreDeleted { store_id }) if store_id == "doc-a"
+        ));
+        assert_eq!(
+            app_state.connection_manager.get_total_connections().await,
+            0
+        );
+
+        let Json(store_ids) = list_stores(State(app_state.clone()), RequestClaims::default())
+            .await
+            .unwrap();
+        assert!(!store_ids.contains(&"doc-a".to_string()));
+
+        // Stores are auto-created by default, so the store comes back empty
+        let (_, Json(info)) = get_store_info(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            RequestClaims::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(info.event_count, 0);
+
+        let (status, Json(error)) = delete_store(
+            State(app_state),
+            Path("missing".to_string()),
+            RequestClaims::default(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(error.code, "STORE_NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn test_get_events_partial_pagination() {
+        let app_state = AppState::new();
+        for i in 1..=5 {
+            submit(
+                &app_state,
+                "doc-a",
+                RequestClaims::default(),
+                "CellSourceUpdated",
+                serde_json::json!({"cell_id": "cell-1", "source": i.to_string()}),
+            )
+            .await
+            .unwrap();
+        }
+
+        let page = |limit, offset| {
+            let app_state = app_state.clone();
+            async move {
+                let (_, Json(response)) = get_events(
+                    State(app_state),
+                    Path("doc-a".to_string()),
+                    Query(GetEventsQuery {
+                        limit,
+                        offset,
+                        ..Default::default()
+                    }),
+                    RequestClaims::default(),
+                )
+                .await
+                .unwrap();
+                assert_eq!(response.total_count, 5);
+                let versions: Vec<i64> = response.events.iter().map(|e| e.version).collect();
+                (versions, response.has_more)
+            }
+        };
+
+        // Limit only starts from the beginning
+        assert_eq!(page(Some(2), None).await, (vec![1, 2], true));
+        // Offset only runs to the end
+        assert_eq!(page(None, Some(3)).await, (vec![4, 5], false));
+        assert_eq!(page(Some(2), Some(2)).await, (vec![3, 4], true));
+        assert_eq!(page(Some(2), Some(3)).await, (vec![4, 5], false));
+        assert_eq!(page(None, None).await, (vec![1, 2, 3, 4, 5], false));
+    }
+
+    #[tokio::test]
+    async fn test_cursor_pagination_is_stable_across_appends() {
+        let app_state = AppState::new();
+        let append = |source: &'static str| {
+            let app_state = app_state.clone();
+            async move {
+                submit(
+                    &app_state,
+                    "doc-a",
+                    RequestClaims::default(),
+                    "CellSourceUpdated",
+                    serde_json::json!({"cell_id": "cell-1", "source": source}),
+                )
+                .await
+                .unwrap();
+            }
+        };
+        for source in ["1", "2", "3"] {
+            append(source).await;
+        }
+
+        let page = |after_seq| {
+            let app_state = app_state.clone();
+            async move {
+                let (_, Json(response)) = get_events(
+                    State(app_state),
+                    Path("doc-a".to_string()),
+                    Query(GetEventsQuery {
+                        after_seq: Some(after_seq),
+                        limit: Some(2),
+                        ..Default::default()
+                    }),
+                    RequestClaims::default(),
+                )
+                .await
+                .unwrap();
+                response
+            }
+        };
+
+        let first = page(0).await;
+        let mut versions: Vec<i64> = first.events.iter().map(|e| e.version).collect();
+        assert_eq!(first.next_cursor, Some(2));
+        assert!(first.has_more);
+
+        // New events arriving mid-iteration neither shift nor duplicate pages
+        append("4").await;
+        append("5").await;
+
+        let mut cursor = first.next_cursor.unwrap();
+        loop {
+            let response = page(cursor).await;
+            versions.extend(response.events.iter().map(|e| e.version));
+            cursor = response.next_cursor.unwrap();
+            if !response.has_more {
+                break;
+            }
+        }
+        assert_eq!(versions, vec![1, 2, 3, 4, 5]);
+        assert_eq!(cursor, 5);
+    }
+
+    #[tokio::test]
+    async fn test_cursor_page_counts_matching_events() {
+        let app_state = AppState::new();
+        two_document_store(&app_state, "nb").await;
+        let page = |query: GetEventsQuery| {
+            get_events(
+                State(app_state.clone()),
+                Path("nb".to_string()),
+                Query(GetEventsQuery {
+                    after_seq: Some(0),
+                    limit: Some(1),
+                    ..query
+                }),
+                scoped_claims("doc-a"),
+            )
+        };
+
+        // Only doc-a's events count, not the store's four
+        let (_, Json(response)) = page(GetEventsQuery::default()).await.unwrap();
+        assert_eq!(response.total_count, 2);
+        let (_, Json(response)) = page(GetEventsQuery {
+            event_types: Some(vec!["CellCreated".to_string()]),
+            ..GetEventsQuery::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(response.total_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_payload_rejected_before_storing() {
+        let app_state = AppState::new();
+
+        let status = submit(
+            &app_state,
+            "doc-a",
+            RequestClaims::default(),
+            "CellCreated",
+            serde_json::json!({"cell_type": "code"}),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(app_state.stores.read().await["doc-a"].get_event_count(), 0);
+
+        submit(
+            &app_state,
+            "doc-a",
+            RequestClaims::default(),
+            "CellCreated",
+            serde_json::json!({"cell_id": "cell-1", "cell_type": "code"}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(app_state.stores.read().await["doc-a"].get_event_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_document_as_of() {
+        let app_state = AppState::new();
+        let claims = RequestClaims::default();
+        let events = [
+            (
+                "DocumentCreated",
+                serde_json::json!({"title": "Draft"}),
+                1_000,
+            ),
+            (
+                "CellCreated",
+                serde_json::json!({"cell_id": "cell-1", "cell_type": "code", "source": "x = 1"}),
+                1_010,
+            ),
+            (
+                "DocumentTitleUpdated",
+                serde_json::json!({"title": "Final"}),
+                1_020,
+            ),
+            (
+                "CellCreated",
+                serde_json::json!({"cell_id": "cell-2", "cell_type": "markdown"}),
+                1_030,
+            ),
+        ];
+        for (event_type, payload, timestamp) in events {
+            let _ = submit_event(
+                State(app_state.clone()),
+                Path("doc-a".to_string()),
+                claims.clone(),
+                Json(SubmitEventRequest {
+                    event_type: event_type.to_string(),
+                    aggregate_id: None,
+                    payload,
+                    timestamp: Some(timestamp),
+                    transaction_id: None,
+                    expected_version: None,
+                }),
+            )
+            .await
+            .unwrap();
+        }
+
+        let as_of = |timestamp: Option<i64>| {
+            get_document(
+                State(app_state.clone()),
+                Path(("doc-a".to_string(), "doc-a".to_string())),
+                Query(DocumentQuery {
+                    as_of: timestamp,
+                    ..DocumentQuery::default()
+                }),
+                claims.clone(),
+            )
+        };
+
+        let Json(past) = as_of(Some(1_015)).await.unwrap();
+        assert_eq!(past.document.title, "Draft");
+        let cell_ids: Vec<&str> = past.cells.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(cell_ids, vec!["cell-1"]);
+
+        let Json(current) = as_of(None).await.unwrap();
+        assert_eq!(current.document.title, "Final");
+        assert_eq!(current.cells.len(), 2);
+
+        // Before the document was created it doesn't exist yet
+        let (status, _) = as_of(Some(999)).await.unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_events_between_timestamps() {
+        let app_state = AppState::new();
+        let claims = RequestClaims::default();
+        for (title, timestamp) in [("a", 1_000), ("b", 1_010), ("c", 1_020)] {
+            let _ = submit_event(
+                State(app_state.clone()),
+                Path("doc-a".to_string()),
+                claims.clone(),
+                Json(SubmitEventRequest {
+                    event_type: "DocumentTitleUpdated".to_string(),
+                    aggregate_id: None,
+                    payload: serde_json::json!({ "title": title }),
+                    timestamp: Some(timestamp),
+                    transaction_id: None,
+                    expected_version: None,
+                }),
+            )
+            .await
+            .unwrap();
+        }
+
+        let between = |from: Option<i64>, to: Option<i64>| {
+            let app_state = app_state.clone();
+            async move {
+                let (_, Json(response)) = get_events(
+                    State(app_state),
+                    Path("doc-a".to_string()),
+                    Query(GetEventsQuery {
+                        from,
+                        to,
+                        ..Default::default()
+                    }),
+                    RequestClaims::default(),
+                )
+                .await
+                .unwrap();
+                response
+                    .events
+                    .iter()
+                    .map(|e| e.timestamp)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(between(Some(1_010), Some(1_020)).await, vec![1_010, 1_020]);
+        assert_eq!(between(Some(1_010), None).await, vec![1_010, 1_020]);
+        assert_eq!(between(None, Some(1_000)).await, vec![1_000]);
+        assert!(between(Some(1_011), Some(1_019)).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_events_filtered_by_type() {
+        let app_state = AppState::new();
+        let claims = RequestClaims::default();
+        let events = [
+            (
+                "CellCreated",
+                serde_json::json!({"cell_id": "cell-1", "cell_type": "code"}),
+                1_000,
+            ),
+            (
+                "CellSourceUpdated",
+                serde_json::json!({"cell_id": "cell-1", "source": "x = 1"}),
+                1_010,
+            ),
+            (
+                "CellExecutionStateChanged",
+                serde_json::json!({"cell_id": "cell-1", "execution_state": "running"}),
+                1_020,
+            ),
+            (
+                "CellCreated",
+                serde_json::json!({"cell_id": "cell-2", "cell_type": "code"}),
+                1_030,
+            ),
+        ];
+        for (event_type, payload, timestamp) in events {
+            let _ = submit_event(
+                State(app_state.clone()),
+                Path("doc-a".to_string()),
+                claims.clone(),
+                Json(SubmitEventRequest {
+                    event_type: event_type.to_string(),
+                    aggregate_id: None,
+                    payload,
+                    timestamp: Some(timestamp),
+                    transaction_id: None,
+                    expected_version: None,
+                }),
+            )
+            .await
+            .unwrap();
+        }
+
+        let fetch = |query: &str| {
+            let app_state = app_state.clone();
+            let Query(query) = Query::<GetEventsQuery>::try_from_uri(
+                &format!("/stores/doc-a/events?{}", query).parse().unwrap(),
+            )
+            .unwrap();
+            async move {
+                let (_, Json(response)) = get_events(
+                    State(app_state),
+                    Path("doc-a".to_string()),
+                    Query(query),
+                    RequestClaims::default(),
+                )
+                .await
+                .unwrap();
+                response
+            }
+        };
+
+        let response = fetch("event_types=CellCreated,CellSourceUpdated").await;
+        assert_eq!(response.total_count, 3);
+        assert!(response
+            .events
+            .iter()
+            .all(|e| e.event_type != "CellExecutionStateChanged"));
+
+        // Composes with the timestamp filter
+        let response = fetch("event_types=CellCreated&since_timestamp=1000").await;
+        assert_eq!(response.total_count, 1);
+        assert_eq!(response.events[0].payload["cell_id"], "cell-2");
+
+        let response = fetch("event_types=CellDeleted").await;
+        assert_eq!(response.total_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let app_state = AppState::new();
+        let claims = RequestClaims::default();
+        for title in ["One", "Two"] {
+            submit(
+                &app_state,
+                "doc-a",
+                claims.clone(),
+                "DocumentTitleUpdated",
+                serde_json::json!({ "title": title }),
+            )
+            .await
+            .unwrap();
+        }
+        submit(
+            &app_state,
+            "doc-b",
+            claims,
+            "DocumentTitleUpdated",
+            serde_json::json!({"title": "Three"}),
+        )
+        .await
+        .unwrap();
+
+        let response =
+            axum::response::IntoResponse::into_response(metrics_handler(State(app_state)).await);
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        for line in [
+            "# TYPE eventbook_events_appended_total counter",
+            "eventbook_events_appended_total 3",
+            "eventbook_stores 2",
+            "eventbook_store_events{store=\"doc-a\"} 2",
+            "eventbook_store_events{store=\"doc-b\"} 1",
+            "eventbook_websocket_connections 0",
+            "eventbook_websocket_connections_opened_total 0",
+            "eventbook_websocket_connections_closed_total 0",
+        ] {
+            assert!(
+                body.lines().any(|l| l == line),
+                "missing {:?} in\n{}",
+                line,
+                body
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight_respects_allowed_origins() {
+        use tower::ServiceExt;
+
+        let app = create_app(AppState::with_config(ServerConfig {
+            cors_origins: vec!["https://notebooks.example.com".to_string()],
+            ..ServerConfig::default()
+        }));
+        let preflight = |origin: &str| {
+            axum::http::Request::builder()
+                .method(Method::OPTIONS)
+                .uri("/stores/doc-a/events")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(preflight("https://notebooks.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://notebooks.example.com"
+        );
+        assert!(response.headers()[header::ACCESS_CONTROL_ALLOW_METHODS]
+            .to_str()
+            .unwrap()
+            .contains("POST"));
+
+        let response = app
+            .oneshot(preflight("https://evil.example.com"))
+            .await
+            .unwrap();
+        assert!(response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_api_key_required_when_auth_is_on() {
+        use tower::ServiceExt;
+
+        let app_state = AppState::with_config(ServerConfig {
+            api_keys: parse_api_keys("alice:secret-a, secret-b"),
+            require_auth: true,
+            ..ServerConfig::default()
+        });
+        let app = create_app(app_state.clone());
+        let submit_with = |authorization: Option<&str>| {
+            let mut request = axum::http::Request::builder()
+                .method(Method::POST)
+                .uri("/stores/doc-a/events")
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(authorization) = authorization {
+                request = request.header(header::AUTHORIZATION, authorization);
+            }
+            request
+                .body(axum::body::Body::from(
+                    serde_json::json!({
+                        "event_type": "DocumentCreated",
+                        "payload": {"title": "Notebook", "actor": "mallory"},
+                    })
+                    .to_string(),
+                ))
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(submit_with(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app
+            .clone()
+            .oneshot(submit_with(Some("Bearer wrong")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(app_state.stores.read().await.len(), 0);
+
+        let response = app
+            .clone()
+            .oneshot(submit_with(Some("Bearer secret-a")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The event is attributed to the authenticated subject, not the actor
+        // the client claimed in the payload
+        let events = app_state.stores.read().await["doc-a"]
+            .get_all_events()
+            .unwrap();
+        assert_eq!(events[0].actor.as_deref(), Some("alice"));
+
+        // Anonymous reads and subscriptions are turned away too; only the
+        // health check stays open
+        let get_with = |uri: &str, authorization: Option<&str>| {
+            let mut request = axum::http::Request::get(uri);
+            if let Some(authorization) = authorization {
+                request = request.header(header::AUTHORIZATION, authorization);
+            }
+            request.body(axum::body::Body::empty()).unwrap()
+        };
+        for uri in [
+            "/stores",
+            "/stores/doc-a",
+            "/stores/doc-a/events",
+            "/stores/doc-a/events?after_seq=0",
+            "/stores/doc-a/sync",
+            "/stores/doc-a/ws",
+            "/stores/doc-a/sse",
+        ] {
+            let response = app.clone().oneshot(get_with(uri, None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", uri);
+        }
+        for (uri, authorization) in [
+            ("/health", None),
+            ("/stores/doc-a/events", Some("Bearer secret-b")),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(get_with(uri, authorization))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        }
+
+        let response = app
+            .oneshot(
+                axum::http::Request::builder()
+                    .method(Method::DELETE)
+                    .uri("/stores/doc-a")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_stores_survive_restart_with_data_dir() {
+        let dir = std::env::temp_dir().join(format!("eventbook-test-{}", uuid::Uuid::new_v4()));
+        let config = || ServerConfig {
+            data_dir: Some(dir.clone()),
+            ..ServerConfig::default()
+        };
+
+        let app_state = AppState::open(config()).unwrap();
+        // Not a safe file name as-is
+        let store_id = "notes/v1";
+        submit(
+            &app_state,
+            store_id,
+            RequestClaims::default(),
+            "DocumentCreated",
+            serde_json::json!({"title": "Persisted"}),
+        )
+        .await
+        .unwrap();
+        submit(
+            &app_state,
+            store_id,
+            RequestClaims::default(),
+            "CellCreated",
+            serde_json::json!({"cell_id": "cell-1", "cell_type": "code", "source": "1 + 1"}),
+        )
+        .await
+        .unwrap();
+        app_state.create_store("empty").await.unwrap();
+        app_state.create_store("gone").await.unwrap();
+        delete_store(
+            State(app_state.clone()),
+            Path("gone".to_string()),
+            RequestClaims::default(),
+        )
+        .await
+        .unwrap();
+        drop(app_state);
+
+        let reopened = AppState::open(config()).unwrap();
+        let stores = reopened.stores.read().await;
+        let mut store_ids: Vec<&String> = stores.keys().collect();
+        store_ids.sort();
+        assert_eq!(store_ids, vec!["empty", "notes/v1"]);
+        assert_eq!(stores[store_id].get_event_count(), 2);
+        assert_eq!(stores["empty"].get_event_count(), 0);
+
+        let projections = reopened.projections.read().await;
+        let documents = documents(&projections[store_id]);
+        assert_eq!(documents.get_document(store_id).unwrap().title, "Persisted");
+        assert_eq!(documents.get_document_cells(store_id).len(), 1);
+        drop((stores, projections));
+
+        // Appends continue from the reloaded versions
+        let response = submit(
+            &reopened,
+            store_id,
+            RequestClaims::default(),
+            "DocumentTitleUpdated",
+            serde_json::json!({"title": "Renamed"}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.version, 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_large_event_responses_are_compressed() {
+        use std::io::Read;
+        use tower::ServiceExt;
+
+        let app_state = AppState::new();
+        for i in 0..50 {
+            submit(
+                &app_state,
+                "doc-a",
+                RequestClaims::default(),
+                "DocumentTitleUpdated",
+                serde_json::json!({ "title": format!("Title number {}", i) }),
+            )
+            .await
+            .unwrap();
+        }
+        let app = create_app(app_state);
+        let get_events = |encoding: Option<&str>| {
+            let mut request = axum::http::Request::get("/stores/doc-a/events");
+            if let Some(encoding) = encoding {
+                request = request.header(header::ACCEPT_ENCODING, encoding);
+            }
+            request.body(axum::body::Body::empty()).unwrap()
+        };
+
+        let plain = app.clone().oneshot(get_events(None)).await.unwrap();
+        assert!(plain.headers().get(header::CONTENT_ENCODING).is_none());
+        let plain = axum::body::to_bytes(plain.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        let response = app.oneshot(get_events(Some("gzip"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        let compressed = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(compressed.len() < plain.len());
+
+        let mut body = Vec::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_end(&mut body)
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::from_slice::<serde_json::Value>(&plain).unwrap()
+        );
+        assert_eq!(body["events"].as_array().unwrap().len(), 50);
+    }
+
+    #[tokio::test]
+    async fn test_compact_store_keeps_projection() {
+        let app_state = AppState::new();
+        let claims = RequestClaims::default();
+        submit(
+            &app_state,
+            "doc-a",
+            claims.clone(),
+            "DocumentCreated",
+            serde_json::json!({"title": "Notebook"}),
+        )
+        .await
+        .unwrap();
+        for (cell_id, index) in [("cell-1", "a"), ("cell-2", "b")] {
+            submit(
+                &app_state,
+                "doc-a",
+                claims.clone(),
+                "CellCreated",
+                serde_json::json!({
+                    "cell_id": cell_id,
+                    "cell_type": "code",
+                    "fractional_index": index,
+                }),
+            )
+            .await
+            .unwrap();
+        }
+        submit(
+            &app_state,
+            "doc-a",
+            claims.clone(),
+            "CellOutputCreated",
+            serde_json::json!({
+                "output_id": "out-1",
+                "cell_id": "cell-1",
+                "output_type": "terminal",
+                "stream_name": "stdout",
+                "data": "hello\n",
+            }),
+        )
+        .await
+        .unwrap();
+
+        let projection_json = |app_state: AppState| async move {
+            let projections = app_state.projections.read().await;
+            let state = documents(&projections["doc-a"]).snapshot();
+            serde_json::json!({
+                "documents": state.documents,
+                "cells": state.cells,
+                "outputs": state.outputs,
+            })
+        };
+        let before = projection_json(app_state.clone()).await;
+
+        // Compacting is for owners only
+        let (status, _) = compact_store(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            Query(CompactQuery { prune: true }),
+            role_claims("doc-a", Role::Editor),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let Json(compacted) = compact_store(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            Query(CompactQuery { prune: true }),
+            claims.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(compacted.pruned_events, 4);
+        assert_eq!(compacted.event_count, 1);
+        assert_eq!(projection_json(app_state.clone()).await, before);
+
+        // Rebuilding from the compacted log gives the same projection
+        let events = app_state.stores.read().await["doc-a"]
+            .get_all_events()
+            .unwrap();
+        assert_eq!(events[0].id, compacted.snapshot_event_id);
+        let mut rebuilt = DocumentProjection::new();
+        rebuilt.rebuild_from_events(&events).unwrap();
+        assert_eq!(
+            serde_json::to_value(rebuilt.get_cell("cell-1")).unwrap(),
+            before["cells"]["cell-1"]
+        );
+
+        // Writes carry on from the version before compaction
+        let response = submit(
+            &app_state,
+            "doc-a",
+            claims.clone(),
+            "DocumentTitleUpdated",
+            serde_json::json!({"title": "Renamed"}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.version, 5);
+
+        // So do sequence numbers; a cursor from before resumes at the snapshot
+        let (_, Json(page)) = get_events(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            Query(GetEventsQuery {
+                after_seq: Some(2),
+                ..GetEventsQuery::default()
+            }),
+            claims,
+        )
+        .await
+        .unwrap();
+        let ids: Vec<&str> = page.events.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids[0], compacted.snapshot_event_id);
+        assert_eq!(ids.len(), 2);
+        assert_eq!(page.next_cursor, Some(6));
+        assert!(!page.has_more);
+    }
+
+    #[tokio::test]
+    async fn test_every_invalid_field_is_reported() {
+        let app_state = AppState::new();
+        let (status, Json(error)) = submit_event(
+            State(app_state),
+            Path("doc-a".to_string()),
+            RequestClaims::default(),
+            Json(SubmitEventRequest {
+                event_type: " ".to_string(),
+                aggregate_id: Some(String::new()),
+                payload: serde_json::json!({}),
+                timestamp: None,
+                transaction_id: None,
+                expected_version: None,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.code, "VALIDATION_ERROR");
+        assert_eq!(
+            error.details.unwrap()["errors"],
+            serde_json::json!(["Invalid event type:  ", "Invalid aggregate ID: "])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_document_pages_cells() {
+        let app_state = AppState::new();
+        let claims = RequestClaims::default();
+        submit(
+            &app_state,
+            "doc-a",
+            claims.clone(),
+            "DocumentCreated",
+            serde_json::json!({"title": "Notebook"}),
+        )
+        .await
+        .unwrap();
+        for (cell_id, index) in [("cell-3", "c"), ("cell-1", "a"), ("cell-2", "b")] {
+            submit(
+                &app_state,
+                "doc-a",
+                claims.clone(),
+                "CellCreated",
+                serde_json::json!({
+                    "cell_id": cell_id,
+                    "cell_type": "code",
+                    "fractional_index": index,
+                }),
+            )
+            .await
+            .unwrap();
+        }
+
+        let page = |offset, limit| {
+            let app_state = app_state.clone();
+            let claims = claims.clone();
+            async move {
+                let Json(response) = get_document(
+                    State(app_state),
+                    Path(("doc-a".to_string(), "doc-a".to_string())),
+                    Query(DocumentQuery {
+                        offset: Some(offset),
+                        limit: Some(limit),
+                        ..DocumentQuery::default()
+                    }),
+                    claims,
+                )
+                .await
+                .unwrap();
+                let ids: Vec<String> = response.cells.iter().map(|c| c.id.clone()).collect();
+                (ids, response.total_cells, response.has_more)
+            }
+        };
+
+        assert_eq!(
+            page(0, 2).await,
+            (vec!["cell-1".to_string(), "cell-2".to_string()], 3, true)
+        );
+        assert_eq!(page(2, 2).await, (vec!["cell-3".to_string()], 3, false));
+        assert_eq!(page(3, 2).await, (vec![], 3, false));
+    }
+
+    #[tokio::test]
+    async fn test_reorder_document_cells() {
+        let app_state = AppState::new();
+        let claims = RequestClaims::default();
+        submit(
+            &app_state,
+            "doc-a",
+            claims.clone(),
+            "DocumentCreated",
+            serde_json::json!({"title": "Notebook"}),
+        )
+        .await
+        .unwrap();
+        for (cell_id, index) in [("cell-1", "a"), ("cell-2", "b"), ("cell-3", "c")] {
+            submit(
+                &app_state,
+                "doc-a",
+                claims.clone(),
+                "CellCreated",
+                serde_json::json!({
+                    "cell_id": cell_id,
+                    "cell_type": "code",
+                    "fractional_index": index,
+                }),
+            )
+            .await
+            .unwrap();
+        }
+
+        let reorder = |cell_ids: &[&str]| {
+            reorder_document_cells(
+                State(app_state.clone()),
+                Path(("doc-a".to_string(), "doc-a".to_string())),
+                claims.clone(),
+                Json(ReorderCellsRequest {
+                    cell_ids: cell_ids.iter().map(|id| id.to_string()).collect(),
+                }),
+            )
+        };
+
+        let Json(response) = reorder(&["cell-3", "cell-1", "cell-2"]).await.unwrap();
+        assert_eq!(response.events.len(), 1);
+        assert_eq!(response.events[0].version, 5);
+
+        let projections = app_state.projections.read().await;
+        let order: Vec<&str> = documents(&projections["doc-a"])
+            .get_document_cells("doc-a")
+            .iter()
+            .map(|cell| cell.id.as_str())
+            .collect();
+        assert_eq!(order, vec!["cell-3", "cell-1", "cell-2"]);
+        drop(projections);
+
+        // Leaving a cell out is rejected without storing anything
+        let (status, _) = reorder(&["cell-1", "cell-2"]).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            app_state.stores.read().await["doc-a"].get_latest_version("doc-a"),
+            5
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_store_endpoint() {
+        use tower::ServiceExt;
+
+        let app_state = AppState::new();
+        app_state.create_store("doc-a").await.unwrap();
+        // Submits are stamped in order, but a log written elsewhere may not be
+        let mut timestamp = eventbook_core::current_timestamp();
+        for (version, title) in [(1, "One"), (2, "Two")] {
+            let event = EventBuilder::new()
+                .event_type("DocumentTitleUpdated")
+                .aggregate_id("doc-a")
+                .payload(serde_json::json!({ "title": title }))
+                .unwrap()
+                .timestamp(timestamp)
+                .build(version)
+                .unwrap();
+            app_state
+                .stores
+                .write()
+                .await
+                .get_mut("doc-a")
+                .unwrap()
+                .append_event(event)
+                .unwrap();
+            timestamp -= 5;
+        }
+
+        let response = create_app(app_state)
+            .oneshot(
+                axum::http::Request::get("/stores/doc-a/verify")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["ok"], false);
+        assert_eq!(report["issues"][0]["kind"], "timestamp_out_of_order");
+        assert_eq!(report["issues"][0]["aggregate_id"], "doc-a");
+        assert_eq!(report["issues"][0]["version"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_list_aggregates_endpoint() {
+        use tower::ServiceExt;
+
+        let app_state = AppState::new();
+        for aggregate_id in ["doc-c", "doc-a", "doc-b", "doc-a"] {
+            let Json(_) = submit_event(
+                State(app_state.clone()),
+                Path("store-1".to_string()),
+                RequestClaims::default(),
+                Json(SubmitEventRequest {
+                    event_type: "DocumentTitleUpdated".to_string(),
+                    aggregate_id: Some(aggregate_id.to_string()),
+                    payload: serde_json::json!({ "title": aggregate_id }),
+                    timestamp: None,
+                    transaction_id: None,
+                    expected_version: None,
+                }),
+            )
+            .await
+            .unwrap();
+        }
+
+        let response = create_app(app_state.clone())
+            .oneshot(
+                axum::http::Request::get("/stores/store-1/aggregates")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let listing: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            listing["aggregate_ids"],
+            serde_json::json!(["doc-a", "doc-b", "doc-c"])
+        );
+
+        let Json(scoped) = list_aggregates(
+            State(app_state),
+            Path("store-1".to_string()),
+            scoped_claims("doc-b"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(scoped.aggregate_ids, vec!["doc-b"]);
+    }
+
+    #[tokio::test]
+    async fn test_health_check_reports_counts() {
+        let app_state = AppState::new();
+        for store_id in ["doc-a", "doc-b", "doc-a"] {
+            submit(
+                &app_state,
+                store_id,
+                RequestClaims::default(),
+                "DocumentTitleUpdated",
+                serde_json::json!({ "title": store_id }),
+            )
+            .await
+            .unwrap();
+        }
+
+        let Json(health) = health_check(State(app_state.clone())).await;
+        assert_eq!(health["status"], "healthy");
+        assert_eq!(health["stores"], 2);
+        assert_eq!(health["total_events"], 3);
+        assert_eq!(health["websocket_connections"], 0);
+        assert!(health["uptime_seconds"].is_u64());
+
+        // A held write lock doesn't stall the probe
+        let stores = app_state.stores.write().await;
+        let Json(health) = health_check(State(app_state.clone())).await;
+        assert!(health["stores"].is_null());
+        assert!(health["total_events"].is_null());
+        assert_eq!(health["websocket_connections"], 0);
+        drop(stores);
+    }
+
+    #[tokio::test]
+    async fn test_clients_cannot_submit_snapshots() {
+        let app_state = AppState::new();
+        let forged = serde_json::json!({
+            "documents": {},
+            "comments": {},
+            "versions": { "doc-a": i64::MAX },
+        });
+
+        let (status, Json(error)) = submit_event(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            RequestClaims::default(),
+            Json(SubmitEventRequest {
+                event_type: SNAPSHOT_EVENT_TYPE.to_string(),
+                aggregate_id: Some("snapshot-forged".to_string()),
+                payload: forged.clone(),
+                timestamp: None,
+                transaction_id: None,
+                expected_version: None,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.code, "RESERVED_EVENT_TYPE");
+
+        let (status, Json(error)) = submit_event_batch(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            RequestClaims::default(),
+            Json(BatchSubmitRequest {
+                events: vec![BatchEventRequest {
+                    event_type: SNAPSHOT_EVENT_TYPE.to_string(),
+                    aggregate_id: Some("snapshot-forged".to_string()),
+                    payload: forged,
+                    version: None,
+                }],
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.details.unwrap()["index"], 0);
+
+        // The store still numbers from where it was
+        let response = submit(
+            &app_state,
+            "doc-a",
+            RequestClaims::default(),
+            "DocumentCreated",
+            serde_json::json!({"title": "Notebook"}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.version, 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_persist_rolls_back_append() {
+        let dir = std::env::temp_dir().join(format!("eventbook-test-{}", uuid::Uuid::new_v4()));
+        let config = || ServerConfig {
+            data_dir: Some(dir.clone()),
+            ..ServerConfig::default()
+        };
+        let app_state = AppState::open(config()).unwrap();
+        let title = |title: &str| serde_json::json!({ "title": title });
+        submit(
+            &app_state,
+            "doc-a",
+            RequestClaims::default(),
+            "DocumentCreated",
+            title("Kept"),
+        )
+        .await
+        .unwrap();
+
+        // Take the data directory away so the next write fails
+        let log = dir.join("doc-a.jsonl");
+        let contents = std::fs::read(&log).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let status = submit(
+            &app_state,
+            "doc-a",
+            RequestClaims::default(),
+            "DocumentTitleUpdated",
+            title("Lost"),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            app_state.stores.read().await["doc-a"].get_latest_version("doc-a"),
+            1
+        );
+
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&log, contents).unwrap();
+        let response = submit(
+            &app_state,
+            "doc-a",
+            RequestClaims::default(),
+            "DocumentTitleUpdated",
+            title("Renamed"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.version, 2);
+        drop(app_state);
+
+        // The log has no gap, so it loads again
+        let reopened = AppState::open(config()).unwrap();
+        let projections = reopened.projections.read().await;
+        assert_eq!(
+            documents(&projections["doc-a"])
+                .get_document("doc-a")
+                .unwrap()
+                .title,
+            "Renamed"
+        );
+        drop(projections);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_scoped_token_reaches_document_presence() {
+        let claims = scoped_claims("doc-a");
+        assert!(claims.can_access_aggregate(&eventbook_core::presence_aggregate_id("doc-a")));
+        assert!(!claims.can_access_aggregate(&eventbook_core::presence_aggregate_id("doc-b")));
+        assert!(!claims.can_access_aggregate("doc-b"));
+    }
+
+    #[tokio::test]
+    async fn test_scoped_token_cannot_read_other_documents() {
+        let app_state = AppState::new();
+        two_document_store(&app_state, "workspace").await;
+        let claims = scoped_claims("doc-a");
+
+        let read_document = |document_id: &str| {
+            get_document(
+                State(app_state.clone()),
+                Path(("workspace".to_string(), document_id.to_string())),
+                Query(DocumentQuery::default()),
+                claims.clone(),
+            )
+        };
+        let Json(visible) = read_document("doc-a").await.unwrap();
+        assert_eq!(visible.cells[0].id, "cell-a");
+
+        let (hidden_status, Json(hidden)) = read_document("doc-b").await.unwrap_err();
+        let (missing_status, Json(missing)) = read_document("doc-c").await.unwrap_err();
+        assert_eq!(hidden_status, StatusCode::NOT_FOUND);
+        assert_eq!(hidden_status, missing_status);
+        assert_eq!(hidden.code, missing.code);
+    }
+
+    #[tokio::test]
+    async fn test_scoped_token_cannot_read_other_documents_cells() {
+        let app_state = AppState::new();
+        two_document_store(&app_state, "workspace").await;
+        let claims = scoped_claims("doc-a");
+
+        let read_cell = |cell_id: &str| {
+            get_cell(
+                State(app_state.clone()),
+                Path(("workspace".to_string(), cell_id.to_string())),
+                claims.clone(),
+            )
+        };
+        let Json(visible) = read_cell("cell-a").await.unwrap();
+        assert_eq!(visible.cell.document_id, "doc-a");
+
+        let (hidden_status, Json(hidden)) = read_cell("cell-b").await.unwrap_err();
+        let (missing_status, Json(missing)) = read_cell("cell-c").await.unwrap_err();
+        assert_eq!(hidden_status, StatusCode::NOT_FOUND);
+        assert_eq!(hidden_status, missing_status);
+        assert_eq!(hidden.code, missing.code);
+    }
+
+    #[tokio::test]
+    async fn test_scoped_sync_leaves_out_other_documents() {
+        let app_state = AppState::new();
+        two_document_store(&app_state, "workspace").await;
+
+        let Json(sync) = sync_store(
+            State(app_state.clone()),
+            Path("workspace".to_string()),
+            scoped_claims("doc-a"),
+        )
+        .await
+        .unwrap();
+
+        let document_ids: Vec<&String> = sync.snapshot.documents.keys().collect();
+        assert_eq!(document_ids, vec!["doc-a"]);
+        let cell_ids: Vec<&String> = sync.snapshot.cells.keys().collect();
+        assert_eq!(cell_ids, vec!["cell-a"]);
+        assert!(!sync.snapshot.collaborators.contains_key("doc-b"));
+        assert_eq!(sync.cursor, 4);
+    }
+
+    #[tokio::test]
+    async fn test_scoped_batch_get_cells_omits_other_documents() {
+        let app_state = AppState::new();
+        two_document_store(&app_state, "workspace").await;
+
+        let Json(response) = batch_get_cells(
+            State(app_state.clone()),
+            Path("workspace".to_string()),
+            scoped_claims("doc-a"),
+            Json(BatchGetCellsRequest {
+                cell_ids: vec!["cell-b".to_string(), "cell-a".to_string()],
+            }),
+        )
+        .await
+        .unwrap();
+
+        let cell_ids: Vec<&str> = response.cells.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(cell_ids, vec!["cell-a"]);
+    }
+
+    #[tokio::test]
+    async fn test_held_source_update_keeps_the_latest() {
+        let app_state = AppState::with_config(ServerConfig {
+            source_update_debounce_ms: 50,
+            ..ServerConfig::default()
+        });
+        submit(
+            &app_state,
+            "doc-a",
+            RequestClaims::default(),
+            "CellCreated",
+            serde_json::json!({"cell_id": "cell-1", "cell_type": "code", "source": ""}),
+        )
+        .await
+        .unwrap();
+
+        let now = eventbook_core::current_timestamp();
+        let update = |source: &str, timestamp: i64| {
+            submit_event(
+                State(app_state.clone()),
+                Path("doc-a".to_string()),
+                RequestClaims::default(),
+                Json(SubmitEventRequest {
+                    event_type: "CellSourceUpdated".to_string(),
+                    aggregate_id: None,
+                    payload: serde_json::json!({"cell_id": "cell-1", "source": source}),
+                    timestamp: Some(timestamp),
+                    transaction_id: None,
+                    expected_version: None,
+                }),
+            )
+        };
+        // A backdated edit is stored after the newer one but doesn't replace
+        // it, as last-writer-wins goes by edit time
+        let _ = update("newer", now).await.unwrap();
+        let _ = update("older", now - 600).await.unwrap();
+
+        let pending = app_state.pending_source_updates.read().await;
+        let held = &pending[&("doc-a".to_string(), "cell-1".to_string())];
+        assert_eq!(held.payload["source"], "newer");
+        drop(pending);
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let projections = app_state.projections.read().await;
+        assert_eq!(
+            documents(&projections["doc-a"])
+                .get_cell("cell-1")
+                .unwrap()
+                .source,
+            "newer"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_interleaved_source_updates_flush_together() {
+        let app_state = AppState::with_config(ServerConfig {
+            source_update_debounce_ms: 50,
+            ..ServerConfig::default()
+        });
+        for cell_id in ["cell-a", "cell-b"] {
+            submit(
+                &app_state,
+                "doc-a",
+                RequestClaims::default(),
+                "CellCreated",
+                serde_json::json!({"cell_id": cell_id, "cell_type": "code", "source": ""}),
+            )
+            .await
+            .unwrap();
+        }
+
+        let now = eventbook_core::current_timestamp();
+        let update = |cell_id: &str, source: &str, timestamp: i64| {
+            submit_event(
+                State(app_state.clone()),
+                Path("doc-a".to_string()),
+                RequestClaims::default(),
+                Json(SubmitEventRequest {
+                    event_type: "CellSourceUpdated".to_string(),
+                    aggregate_id: None,
+                    payload: serde_json::json!({"cell_id": cell_id, "source": source}),
+                    timestamp: Some(timestamp),
+                    transaction_id: None,
+                    expected_version: None,
+                }),
+            )
+        };
+        // cell-a's window closes first, holding a newer update than cell-b's
+        let _ = update("cell-a", "a1", now).await.unwrap();
+        let _ = update("cell-b", "b1", now).await.unwrap();
+        let _ = update("cell-a", "a2", now + 1).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(app_state.pending_source_updates.read().await.is_empty());
+        let projections = app_state.projections.read().await;
+        let documents = documents(&projections["doc-a"]);
+        assert_eq!(documents.get_cell("cell-a").unwrap().source, "a2");
+        assert_eq!(documents.get_cell("cell-b").unwrap().source, "b1");
+    }
+
+    #[tokio::test]
+    async fn test_replay_after_compaction_keeps_later_events() {
+        let app_state = AppState::new();
+        let claims = RequestClaims::default();
+        submit(
+            &app_state,
+            "doc-a",
+            claims.clone(),
+            "DocumentCreated",
+            serde_json::json!({"title": "Notebook"}),
+        )
+        .await
+        .unwrap();
+        submit(
+            &app_state,
+            "doc-a",
+            claims.clone(),
+            "CellCreated",
+            serde_json::json!({"cell_id": "cell-1", "cell_type": "code"}),
+        )
+        .await
+        .unwrap();
+
+        // Keep the history so the snapshot shares its second with what it covers
+        let Json(compacted) = compact_store(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            Query(CompactQuery { prune: false }),
+            claims.clone(),
+        )
+        .await
+        .unwrap();
+
+        let Json(backdated) = submit_event(
+            State(app_state.clone()),
+            Path("doc-a".to_string()),
+            claims.clone(),
+            Json(SubmitEventRequest {
+                event_type: "DocumentTitleUpdated".to_string(),
+                aggregate_id: None,
+                payload: serde_json::json!({"title": "Renamed"}),
+                timestamp: Some(eventbook_core::current_timestamp() - 60),
+                transaction_id: None,
+                expected_version: None,
+            }),
+        )
+        .await
+        .unwrap();
+        submit(
+            &app_state,
+            "doc-a",
+            claims,
+            "CellCreated",
+            serde_json::json!({"cell_id": "cell-2", "cell_type": "code"}),
+        )
+        .await
+        .unwrap();
+
+        let stores = app_state.stores.read().await;
+        let event_ids: Vec<&str> = stores["doc-a"]
+            .iter_events()
+            .map(|e| e.id.as_str())
+            .collect();
+        assert_eq!(event_ids.len(), 5);
+        assert_eq!(event_ids[2], compacted.snapshot_event_id);
+        assert_eq!(event_ids[3], backdated.event_id);
+
+        let mut rebuilt = DocumentProjection::new();
+        rebuilt
+            .rebuild_from_events(&stores["doc-a"].get_all_events().unwrap())
+            .unwrap();
+        let projections = app_state.projections.read().await;
+        let live = documents(&projections["doc-a"]);
+        assert_eq!(rebuilt.get_document("doc-a").unwrap().title, "Renamed");
+        assert_eq!(
+            serde_json::to_value(rebuilt.get_document_cells("doc-a")).unwrap(),
+            serde_json::to_value(live.get_document_cells("doc-a")).unwrap()
+        );
+        assert_eq!(
+            rebuilt.get_document("doc-a").unwrap().title,
+            live.get_document("doc-a").unwrap().title
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Prometheus metrics in the text exposition format
+//!
+//! Counters are plain atomics bumped on the hot paths; gauges are read from
+//! the stores and connection manager when scraped. The output is formatted by
+//! hand to avoid pulling in a metrics client.
+
+use axum::{extract::State, http::header, response::IntoResponse};
+use eventbook_core::EventStore;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Counters shared across handlers
+#[derive(Debug)]
+pub struct Metrics {
+    events_appended: AtomicU64,
+    websocket_connections_opened: AtomicU64,
+    websocket_connections_closed: AtomicU64,
+    started_at: Instant,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            events_appended: AtomicU64::default(),
+            websocket_connections_opened: AtomicU64::default(),
+            websocket_connections_closed: AtomicU64::default(),
+            started_at: Instant::now(),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_events_appended(&self, count: usize) {
+        self.events_appended
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_websocket_opened(&self) {
+        self.websocket_connections_opened
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_websocket_closed(&self) {
+        self.websocket_connections_closed
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Total events appended since the server started
+    pub fn events_appended(&self) -> u64 {
+        self.events_appended.load(Ordering::Relaxed)
+    }
+
+    /// Time since the server started
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+}
+
+/// Serve metrics for Prometheus to scrape
+pub async fn metrics_handler(State(app_state): State<crate::AppState>) -> impl IntoResponse {
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        render_metrics(&app_state).await,
+    )
+}
+
+/// Render every metric in the text exposition format
+pub async fn render_metrics(app_state: &crate::AppState) -> String {
+    let metrics = &app_state.metrics;
+    let mut out = String::new();
+
+    write_metric(
+        &mut out,
+        "eventbook_events_appended_total",
+        "counter",
+        "Events appended since the server started",
+        [(None, metrics.events_appended())],
+    );
+
+    let mut store_events: Vec<(String, usize)> = app_state
+        .stores
+        .read()
+        .await
+        .iter()
+        .map(|(store_id, store)| (store_id.clone(), store.get_event_count()))
+        .collect();
+    store_events.sort();
+
+    write_metric(
+        &mut out,
+        "eventbook_stores",
+        "gauge",
+        "Stores currently held",
+        [(None, store_events.len() as u64)],
+    );
+    write_metric(
+        &mut out,
+        "eventbook_store_events",
+        "gauge",
+        "Events held per store",
+        store_events.iter().map(|(store_id, count)| {
+            (
+                Some(format!("store=\"{}\"", escape_label(store_id))),
+                *count as u64,
+            )
+        }),
+    );
+
+    let connections = app_state.connection_manager.get_total_connections().await;
+    write_metric(
+        &mut out,
+        "eventbook_websocket_connections",
+        "gauge",
+        "Connections currently subscribed to a store, over WebSocket or SSE",
+        [(None, connections as u64)],
+    );
+    write_metric(
+        &mut out,
+        "eventbook_websocket_connections_opened_total",
+        "counter",
+        "WebSocket connections opened since the server started",
+        [(
+            None,
+            metrics.websocket_connections_opened.load(Ordering::Relaxed),
+        )],
+    );
+    write_metric(
+        &mut out,
+        "eventbook_websocket_connections_closed_total",
+        "counter",
+        "WebSocket connections closed since the server started",
+        [(
+            None,
+            metrics.websocket_connections_closed.load(Ordering::Relaxed),
+        )],
+    );
+
+    out
+}
+
+/// Write one metric's HELP and TYPE lines followed by its samples
+fn write_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (Option<String>, u64)>,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        match labels {
+            Some(labels) => {
+                let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+            }
+            None => {
+                let _ = writeln!(out, "{} {}", name, value);
+            }
+        }
+    }
+}
+
+/// Escape a label value as the exposition format requires
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
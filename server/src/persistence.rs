@@ -0,0 +1,151 @@
+//! Append-only JSONL files backing the in-memory stores
+//!
+//! Each store gets one file in the data directory holding its events, one JSON
+//! object per line, in append order. Memory stays the source of truth while
+//! the server runs; the files are only read back on startup.
+
+use eventbook_core::Event;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+const EXTENSION: &str = "jsonl";
+
+/// Directory holding one event log per store
+#[derive(Debug, Clone)]
+pub struct DataDir {
+    path: PathBuf,
+}
+
+impl DataDir {
+    /// Use `path` as the data directory, creating it if needed
+    pub fn open<P: Into<PathBuf>>(path: P) -> io::Result<Self> {
+        let path = path.into();
+        fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+
+    /// Record that a store exists, so an empty store survives a restart
+    pub fn create_store(&self, store_id: &str) -> io::Result<()> {
+        self.open_log(store_id).map(drop)
+    }
+
+    /// Append events to a store's log
+    pub fn append(&self, store_id: &str, events: &[Event]) -> io::Result<()> {
+        let lines = to_lines(events)?;
+        // A single write keeps a batch's lines together in the file
+        let mut file = self.open_log(store_id)?;
+        file.write_all(&lines)?;
+        file.sync_data()
+    }
+
+    /// Replace a store's log with `events`, e.g. after compacting it
+    ///
+    /// The new log is written beside the old one and renamed over it, so a
+    /// crash part way leaves one or the other intact.
+    pub fn replace(&self, store_id: &str, events: &[Event]) -> io::Result<()> {
+        let path = self.log_path(store_id);
+        let staged = path.with_extension(format!("{}.tmp", EXTENSION));
+        let mut file = File::create(&staged)?;
+        file.write_all(&to_lines(events)?)?;
+        file.sync_data()?;
+        fs::rename(staged, path)
+    }
+
+    /// Delete a store's log
+    pub fn remove_store(&self, store_id: &str) -> io::Result<()> {
+        match fs::remove_file(self.log_path(store_id)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Read every store's events back, in append order
+    pub fn load(&self) -> io::Result<Vec<(String, Vec<Event>)>> {
+        let mut stores = Vec::new();
+        for entry in fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+            let Some(store_id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(decode_store_id)
+            else {
+                continue;
+            };
+
+            let mut events = Vec::new();
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                events.push(serde_json::from_str(&line).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}: {}", path.display(), e),
+                    )
+                })?);
+            }
+            stores.push((store_id, events));
+        }
+        stores.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(stores)
+    }
+
+    fn open_log(&self, store_id: &str) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.log_path(store_id))
+    }
+
+    fn log_path(&self, store_id: &str) -> PathBuf {
+        self.path
+            .join(format!("{}.{}", encode_store_id(store_id), EXTENSION))
+    }
+}
+
+/// Serialize events as JSON lines
+fn to_lines(events: &[Event]) -> io::Result<Vec<u8>> {
+    let mut lines = Vec::new();
+    for event in events {
+        serde_json::to_writer(&mut lines, event)?;
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+/// Turn a store id into a safe file name
+///
+/// Store ids come from request paths, so anything but ASCII letters, digits,
+/// `-` and `_` is percent-encoded; `../` can't escape the data directory.
+fn encode_store_id(store_id: &str) -> String {
+    let mut name = String::with_capacity(store_id.len());
+    for byte in store_id.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    name
+}
+
+fn decode_store_id(name: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
@@ -0,0 +1,114 @@
+//! Server-Sent Events, for clients that can't or needn't use WebSockets
+//!
+//! Each SSE frame's `data:` is a `WsMessage` in the same JSON shape the
+//! WebSocket sends, so a plain `EventSource` can follow a store read-only.
+
+use crate::websocket::{catch_up_messages, Connection, ConnectionManager, WsMessage};
+use crate::RequestClaims;
+use axum::{
+    extract::{Path, State},
+    response::sse::{Event as SseEvent, KeepAlive, Sse},
+};
+use eventbook_core::InMemoryEventStore;
+use futures_util::stream::{self, Stream, StreamExt};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::{broadcast, broadcast::error::RecvError, RwLock};
+use tracing::warn;
+use uuid::Uuid;
+
+/// Stream a store's events as `text/event-stream`
+///
+/// Opens with a comment, then the `subscribed` confirmation and a replay of
+/// existing events, then live broadcasts.
+pub async fn sse_handler(
+    State(app_state): State<crate::AppState>,
+    Path(store_id): Path<String>,
+    claims: RequestClaims,
+) -> Sse<impl Stream<Item = Result<SseEvent, axum::Error>>> {
+    let messages = subscribe_stream(
+        app_state.connection_manager.clone(),
+        &app_state.stores,
+        store_id,
+        claims,
+    )
+    .await;
+
+    let opening = stream::once(async { Ok(SseEvent::default().comment("connected")) });
+    let frames = messages.map(|message| SseEvent::default().json_data(message));
+    Sse::new(opening.chain(frames)).keep_alive(KeepAlive::default())
+}
+
+/// Subscribe a new connection to a store and stream what it should receive
+///
+/// The connection is removed from the manager when the stream is dropped.
+pub async fn subscribe_stream(
+    manager: Arc<ConnectionManager>,
+    stores: &RwLock<HashMap<String, InMemoryEventStore>>,
+    store_id: String,
+    claims: RequestClaims,
+) -> impl Stream<Item = WsMessage> {
+    let connection_id = Uuid::new_v4().to_string();
+    let (tx, rx) = broadcast::channel::<WsMessage>(100);
+
+    // Subscribe before reading history so no event falls between the two
+    manager
+        .subscribe(
+            store_id.clone(),
+            Connection {
+                id: connection_id.clone(),
+                sender: tx,
+                claims: claims.clone(),
+            },
+        )
+        .await;
+    let catch_up = catch_up_messages(stores, &store_id, &connection_id, &claims).await;
+
+    let subscription = Subscription {
+        manager,
+        connection_id,
+    };
+    let live = stream::unfold(
+        (rx, subscription, store_id, false),
+        |(mut rx, subscription, store_id, finished)| async move {
+            if finished {
+                return None;
+            }
+            let message = match rx.recv().await {
+                Ok(message) => message,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(
+                        "SSE connection {} lagged by {} messages",
+                        subscription.connection_id, skipped
+                    );
+                    // The client can't be caught up frame by frame; have it refetch
+                    WsMessage::Refresh {
+                        store_id: store_id.clone(),
+                    }
+                }
+                Err(RecvError::Closed) => return None,
+            };
+            // Nothing follows a deleted store, so end the stream after telling the client
+            let finished = matches!(message, WsMessage::StoreDeleted { .. });
+            Some((message, (rx, subscription, store_id, finished)))
+        },
+    );
+
+    stream::iter(catch_up).chain(live)
+}
+
+/// Removes an SSE connection from the manager once its stream is dropped
+struct Subscription {
+    manager: Arc<ConnectionManager>,
+    connection_id: String,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let manager = Arc::clone(&self.manager);
+        let connection_id = std::mem::take(&mut self.connection_id);
+        runtime.spawn(async move { manager.disconnect(&connection_id).await });
+    }
+}
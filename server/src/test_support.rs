@@ -0,0 +1,57 @@
+//! In-process server for integration tests
+//!
+//! Enabled by the `test-support` feature so other crates can test against a
+//! real HTTP server without duplicating the app setup.
+
+use std::net::SocketAddr;
+use tokio::task::JoinHandle;
+
+use crate::{create_app, AppState};
+
+/// Serve a fresh app on an ephemeral localhost port
+///
+/// Returns the bound address and the server task; abort the handle to stop
+/// the server.
+pub async fn spawn_test_server() -> (SocketAddr, JoinHandle<()>) {
+    spawn_test_server_with_state(AppState::new()).await
+}
+
+/// Serve the app with pre-populated state on an ephemeral localhost port
+pub async fn spawn_test_server_with_state(app_state: AppState) -> (SocketAddr, JoinHandle<()>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("failed to bind test server");
+    let addr = listener.local_addr().expect("test server has no address");
+
+    let handle = tokio::spawn(async move {
+        axum::serve(listener, create_app(app_state))
+            .await
+            .expect("test server failed");
+    });
+
+    (addr, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_health_smoke() {
+        let (addr, handle) = spawn_test_server().await;
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("\"status\":\"healthy\""));
+
+        handle.abort();
+    }
+}
@@ -1,3 +1,4 @@
+use crate::{Metrics, RequestClaims};
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
@@ -5,14 +6,29 @@ use axum::{
     },
     response::Response,
 };
-use eventbook_core::Event;
+use eventbook_core::{CellOutput, Event, EventStore, InMemoryEventStore};
 use futures_util::{sink::SinkExt, stream::StreamExt};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::{broadcast, RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, RwLock,
+    },
+    time::Instant,
+};
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
+/// How often the server pings each connection
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+/// How long a connection may stay silent before it's closed
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(75);
+
 /// Message types sent over WebSocket
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -28,11 +44,30 @@ pub enum WsMessage {
         latest_version: i64,
     },
     /// Client successfully subscribed to a store
+    ///
+    /// Followed by an `event` frame for each stored event up to
+    /// `latest_version`; live events may overlap these, so clients should
+    /// dedupe by event id.
     #[serde(rename = "subscribed")]
     Subscribed {
         store_id: String,
         connection_id: String,
+        latest_version: i64,
     },
+    /// A new output for a cell, so clients can append it without refetching
+    #[serde(rename = "output_delta")]
+    OutputDelta {
+        store_id: String,
+        cell_id: String,
+        output: CellOutput,
+    },
+    /// Events were withheld while broadcasting was paused, or the store was
+    /// compacted; refetch the store
+    #[serde(rename = "refresh")]
+    Refresh { store_id: String },
+    /// The store was deleted; the server closes the connection after this
+    #[serde(rename = "store_deleted")]
+    StoreDeleted { store_id: String },
     /// Error message
     #[serde(rename = "error")]
     Error { message: String },
@@ -57,6 +92,9 @@ pub enum ClientMessage {
     /// Heartbeat ping
     #[serde(rename = "ping")]
     Ping,
+    /// Reply to a server heartbeat ping
+    #[serde(rename = "pong")]
+    Pong,
 }
 
 /// Connection information
@@ -64,43 +102,105 @@ pub enum ClientMessage {
 pub struct Connection {
     pub id: String,
     pub sender: broadcast::Sender<WsMessage>,
+    /// Claims of the client, used to withhold events it may not see
+    pub claims: RequestClaims,
 }
 
 /// WebSocket connection manager
+///
+/// A connection can be subscribed to any number of stores at once.
 #[derive(Debug, Clone)]
 pub struct ConnectionManager {
-    /// Map of store_id -> list of connections subscribed to that store
-    connections: Arc<RwLock<HashMap<String, Vec<Connection>>>>,
+    /// Map of connection id -> connection
+    connections: Arc<RwLock<HashMap<String, Connection>>>,
+    /// Map of store_id -> ids of connections subscribed to that store
+    subscriptions: Arc<RwLock<HashMap<String, HashSet<String>>>>,
+    /// Stores whose event broadcasts are currently paused
+    paused: Arc<RwLock<HashSet<String>>>,
 }
 
 impl ConnectionManager {
     pub fn new() -> Self {
         Self {
             connections: Arc::new(RwLock::new(HashMap::new())),
+            subscriptions: Arc::new(RwLock::new(HashMap::new())),
+            paused: Arc::new(RwLock::new(HashSet::new())),
         }
     }
 
-    /// Add a connection to a store
+    /// Stop broadcasting individual events for a store, e.g. during a bulk import
+    pub async fn pause_broadcasts(&self, store_id: &str) {
+        self.paused.write().await.insert(store_id.to_string());
+        info!("Broadcasts paused for store {}", store_id);
+    }
+
+    /// Resume broadcasting for a store
+    ///
+    /// Subscribers get a single `refresh` hint covering everything withheld
+    /// while paused. Does nothing if the store wasn't paused.
+    pub async fn resume_broadcasts(&self, store_id: &str) {
+        if !self.paused.write().await.remove(store_id) {
+            return;
+        }
+
+        self.send_refresh(store_id).await;
+        info!("Broadcasts resumed for store {}", store_id);
+    }
+
+    /// Tell a store's subscribers to refetch it instead of waiting for events
+    pub async fn send_refresh(&self, store_id: &str) {
+        let message = WsMessage::Refresh {
+            store_id: store_id.to_string(),
+        };
+        for connection in self.subscribers(store_id).await {
+            let _ = connection.sender.send(message.clone());
+        }
+    }
+
+    /// Register a connection and subscribe it to a store
     pub async fn subscribe(&self, store_id: String, connection: Connection) {
-        let mut connections = self.connections.write().await;
-        connections
-            .entry(store_id.clone())
-            .or_insert_with(Vec::new)
-            .push(connection.clone());
+        let connection_id = connection.id.clone();
+        self.connections
+            .write()
+            .await
+            .insert(connection_id.clone(), connection);
+        self.add_subscription(&store_id, &connection_id).await;
+    }
+
+    /// Subscribe an already registered connection to another store
+    ///
+    /// Returns false if the connection is unknown or already subscribed.
+    pub async fn add_subscription(&self, store_id: &str, connection_id: &str) -> bool {
+        if !self.connections.read().await.contains_key(connection_id) {
+            return false;
+        }
+        let added = self
+            .subscriptions
+            .write()
+            .await
+            .entry(store_id.to_string())
+            .or_default()
+            .insert(connection_id.to_string());
+        if !added {
+            return false;
+        }
 
         info!(
             "Connection {} subscribed to store {}",
-            connection.id, store_id
+            connection_id, store_id
         );
+        true
     }
 
     /// Remove a connection from a store
+    ///
+    /// The connection stays registered and can subscribe again.
     pub async fn unsubscribe(&self, store_id: &str, connection_id: &str) {
-        let mut connections = self.connections.write().await;
-        if let Some(store_connections) = connections.get_mut(store_id) {
-            store_connections.retain(|conn| conn.id != connection_id);
-            if store_connections.is_empty() {
-                connections.remove(store_id);
+        let mut subscriptions = self.subscriptions.write().await;
+        if let Some(subscribers) = subscriptions.get_mut(store_id) {
+            subscribers.remove(connection_id);
+            if subscribers.is_empty() {
+                subscriptions.remove(store_id);
             }
         }
 
@@ -110,73 +210,141 @@ impl ConnectionManager {
         );
     }
 
+    /// Close every connection subscribed to a deleted store
+    ///
+    /// Each gets a `store_deleted` message followed by a close frame, even
+    /// if it was also subscribed to other stores.
+    pub async fn close_store(&self, store_id: &str) {
+        let subscribers = self.subscribers(store_id).await;
+        for connection in &subscribers {
+            let _ = connection.sender.send(WsMessage::StoreDeleted {
+                store_id: store_id.to_string(),
+            });
+        }
+        for connection in &subscribers {
+            self.disconnect(&connection.id).await;
+        }
+        self.paused.write().await.remove(store_id);
+
+        info!(
+            "Closed {} connections to deleted store {}",
+            subscribers.len(),
+            store_id
+        );
+    }
+
     /// Remove a connection from all stores
     pub async fn disconnect(&self, connection_id: &str) {
-        let mut connections = self.connections.write().await;
-        let mut stores_to_remove = Vec::new();
+        self.connections.write().await.remove(connection_id);
 
-        for (store_id, store_connections) in connections.iter_mut() {
-            store_connections.retain(|conn| conn.id != connection_id);
-            if store_connections.is_empty() {
-                stores_to_remove.push(store_id.clone());
-            }
-        }
-
-        for store_id in stores_to_remove {
-            connections.remove(&store_id);
-        }
+        let mut subscriptions = self.subscriptions.write().await;
+        subscriptions.retain(|_, subscribers| {
+            subscribers.remove(connection_id);
+            !subscribers.is_empty()
+        });
 
         info!("Connection {} disconnected from all stores", connection_id);
     }
 
+    /// Stores a connection is currently subscribed to
+    pub async fn subscribed_stores(&self, connection_id: &str) -> Vec<String> {
+        self.subscriptions
+            .read()
+            .await
+            .iter()
+            .filter(|(_, subscribers)| subscribers.contains(connection_id))
+            .map(|(store_id, _)| store_id.clone())
+            .collect()
+    }
+
+    /// Connections currently subscribed to a store
+    async fn subscribers(&self, store_id: &str) -> Vec<Connection> {
+        let subscriptions = self.subscriptions.read().await;
+        let Some(subscribers) = subscriptions.get(store_id) else {
+            return Vec::new();
+        };
+        let connections = self.connections.read().await;
+        subscribers
+            .iter()
+            .filter_map(|id| connections.get(id).cloned())
+            .collect()
+    }
+
     /// Broadcast an event to all connections subscribed to a store
+    ///
+    /// Output events are followed by an `output_delta` frame carrying just
+    /// the new output.
     pub async fn broadcast_event(&self, store_id: String, event: Event) {
-        let message = WsMessage::Event {
+        if self.paused.read().await.contains(&store_id) {
+            return;
+        }
+
+        let aggregate_id = event.aggregate_id.clone();
+        let output_delta = match event.event_type.as_str() {
+            "CellOutputCreated" => CellOutput::from_event(&event)
+                .map(|output| WsMessage::OutputDelta {
+                    store_id: store_id.clone(),
+                    cell_id: output.cell_id.clone(),
+                    output,
+                })
+                .map_err(|e| warn!("Skipping output delta for event {}: {}", event.id, e))
+                .ok(),
+            _ => None,
+        };
+        let messages: Vec<WsMessage> = std::iter::once(WsMessage::Event {
             store_id: store_id.clone(),
             event,
-        };
+        })
+        .chain(output_delta)
+        .collect();
 
         let mut disconnected = Vec::new();
-        let mut connection_count = 0;
-
-        // Limit scope of read lock
-        {
-            let connections = self.connections.read().await;
-            if let Some(store_connections) = connections.get(&store_id) {
-                connection_count = store_connections.len();
-                for connection in store_connections {
-                    if let Err(_) = connection.sender.send(message.clone()) {
-                        // Connection is closed, mark for removal
-                        disconnected.push(connection.id.clone());
-                    }
+        let store_connections = self.subscribers(&store_id).await;
+        for connection in &store_connections {
+            if !connection.claims.can_access_aggregate(&aggregate_id) {
+                continue;
+            }
+            for message in &messages {
+                if connection.sender.send(message.clone()).is_err() {
+                    // Connection is closed, mark for removal
+                    disconnected.push(connection.id.clone());
+                    break;
                 }
             }
         }
 
-        // Clean up disconnected connections (lock is dropped here)
         for connection_id in disconnected {
-            self.unsubscribe(&store_id, &connection_id).await;
+            self.disconnect(&connection_id).await;
         }
 
         info!(
             "Broadcasted event to {} connections for store {}",
-            connection_count, store_id
+            store_connections.len(),
+            store_id
         );
     }
 
     /// Get connection count for a store
     pub async fn get_connection_count(&self, store_id: &str) -> usize {
-        let connections = self.connections.read().await;
-        connections
+        let subscriptions = self.subscriptions.read().await;
+        subscriptions
             .get(store_id)
-            .map(|conns| conns.len())
+            .map(|subscribers| subscribers.len())
             .unwrap_or(0)
     }
 
-    /// Get total connection count across all stores
+    /// Get the number of open connections, however many stores each watches
     pub async fn get_total_connections(&self) -> usize {
-        let connections = self.connections.read().await;
-        connections.values().map(|conns| conns.len()).sum()
+        self.connections.read().await.len()
+    }
+
+    /// [`ConnectionManager::get_total_connections`] without waiting, or
+    /// `None` if the connection map is being written
+    pub fn try_total_connections(&self) -> Option<usize> {
+        self.connections
+            .try_read()
+            .ok()
+            .map(|connections| connections.len())
     }
 }
 
@@ -191,13 +359,57 @@ pub async fn websocket_handler(
     ws: WebSocketUpgrade,
     Path(store_id): Path<String>,
     State(app_state): State<crate::AppState>,
+    claims: RequestClaims,
 ) -> Response {
     let manager = app_state.connection_manager.clone();
-    ws.on_upgrade(move |socket| handle_socket(socket, store_id, manager))
+    let stores = app_state.stores.clone();
+    let metrics = app_state.metrics.clone();
+    ws.on_upgrade(move |socket| handle_socket(socket, store_id, manager, stores, metrics, claims))
+}
+
+/// The subscription confirmation followed by the store's existing events
+///
+/// Only events the client may see are included.
+pub async fn catch_up_messages(
+    stores: &RwLock<HashMap<String, InMemoryEventStore>>,
+    store_id: &str,
+    connection_id: &str,
+    claims: &RequestClaims,
+) -> Vec<WsMessage> {
+    let events = match stores.read().await.get(store_id) {
+        Some(store) => store.get_all_events().unwrap_or_else(|e| {
+            error!("Failed to read events for catch-up on {}: {}", store_id, e);
+            Vec::new()
+        }),
+        None => Vec::new(),
+    };
+    let events: Vec<Event> = events
+        .into_iter()
+        .filter(|e| claims.can_access_aggregate(&e.aggregate_id))
+        .collect();
+
+    let confirm_msg = WsMessage::Subscribed {
+        store_id: store_id.to_string(),
+        connection_id: connection_id.to_string(),
+        latest_version: events.iter().map(|e| e.version).max().unwrap_or(0),
+    };
+    std::iter::once(confirm_msg)
+        .chain(events.into_iter().map(|event| WsMessage::Event {
+            store_id: store_id.to_string(),
+            event,
+        }))
+        .collect()
 }
 
 /// Handle individual WebSocket connection
-async fn handle_socket(socket: WebSocket, store_id: String, manager: Arc<ConnectionManager>) {
+async fn handle_socket(
+    socket: WebSocket,
+    store_id: String,
+    manager: Arc<ConnectionManager>,
+    stores: Arc<RwLock<HashMap<String, InMemoryEventStore>>>,
+    metrics: Arc<Metrics>,
+    claims: RequestClaims,
+) {
     let connection_id = Uuid::new_v4().to_string();
     let (mut sender, mut receiver) = socket.split();
 
@@ -208,20 +420,25 @@ async fn handle_socket(socket: WebSocket, store_id: String, manager: Arc<Connect
     let connection = Connection {
         id: connection_id.clone(),
         sender: tx,
+        claims: claims.clone(),
     };
 
-    // Subscribe to the store
-    manager.subscribe(store_id.clone(), connection).await;
-
-    // Send subscription confirmation
-    let confirm_msg = WsMessage::Subscribed {
-        store_id: store_id.clone(),
-        connection_id: connection_id.clone(),
-    };
-
-    if let Ok(msg_json) = serde_json::to_string(&confirm_msg) {
+    // Subscribe before reading history so no event falls between the two;
+    // live events queue up in `rx` until the catch-up has been sent
+    manager
+        .subscribe(store_id.clone(), connection.clone())
+        .await;
+    metrics.record_websocket_opened();
+
+    // Send subscription confirmation and replay existing events
+    for message in catch_up_messages(&stores, &store_id, &connection_id, &claims).await {
+        let Ok(msg_json) = serde_json::to_string(&message) else {
+            continue;
+        };
         if sender.send(Message::Text(msg_json.into())).await.is_err() {
-            error!("Failed to send subscription confirmation");
+            error!("Failed to send catch-up to connection {}", connection_id);
+            manager.disconnect(&connection_id).await;
+            metrics.record_websocket_closed();
             return;
         }
     }
@@ -231,21 +448,82 @@ async fn handle_socket(socket: WebSocket, store_id: String, manager: Arc<Connect
         connection_id, store_id
     );
 
-    // Spawn task to handle outgoing messages
+    // When the client was last heard from, for the heartbeat
+    let last_seen = Arc::new(Mutex::new(Instant::now()));
+
+    // Replies to the client's own messages, e.g. the catch-up for a new
+    // subscription, bypass the bounded broadcast channel so a long history
+    // can't overflow it
+    let (reply_tx, mut reply_rx) = mpsc::unbounded_channel::<WsMessage>();
+
+    // Spawn task to handle outgoing messages and heartbeat pings
     let mut send_task = {
+        let manager = Arc::clone(&manager);
         let connection_id = connection_id.clone();
+        let last_seen = Arc::clone(&last_seen);
         tokio::spawn(async move {
-            while let Ok(msg) = rx.recv().await {
-                if let Ok(msg_json) = serde_json::to_string(&msg) {
-                    if sender.send(Message::Text(msg_json.into())).await.is_err() {
-                        error!("Failed to send message to connection {}", connection_id);
-                        break;
+            let mut heartbeat =
+                tokio::time::interval_at(Instant::now() + HEARTBEAT_INTERVAL, HEARTBEAT_INTERVAL);
+            'send: loop {
+                let messages = tokio::select! {
+                    msg = rx.recv() => match msg {
+                        Ok(msg) => vec![msg],
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!(
+                                "WebSocket connection {} lagged by {} messages",
+                                connection_id, skipped
+                            );
+                            // The client can't be caught up frame by frame; have it refetch
+                            manager
+                                .subscribed_stores(&connection_id)
+                                .await
+                                .into_iter()
+                                .map(|store_id| WsMessage::Refresh { store_id })
+                                .collect()
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    reply = reply_rx.recv() => match reply {
+                        Some(reply) => vec![reply],
+                        None => break,
+                    },
+                    _ = heartbeat.tick() => {
+                        let silent_for = last_seen.lock().unwrap().elapsed();
+                        if silent_for > HEARTBEAT_TIMEOUT {
+                            warn!(
+                                "Connection {} silent for {:?}, closing",
+                                connection_id, silent_for
+                            );
+                            let _ = sender.send(Message::Close(None)).await;
+                            break;
+                        }
+                        // A protocol-level ping, which browsers answer on their own,
+                        // so liveness doesn't depend on the client handling a JSON ping
+                        if sender.send(Message::Ping(Default::default())).await.is_err() {
+                            error!("Failed to ping connection {}", connection_id);
+                            break;
+                        }
+                        continue;
+                    }
+                };
+
+                for msg in messages {
+                    if let Ok(msg_json) = serde_json::to_string(&msg) {
+                        if sender.send(Message::Text(msg_json.into())).await.is_err() {
+                            error!("Failed to send message to connection {}", connection_id);
+                            break 'send;
+                        }
+                    } else {
+                        error!(
+                            "Failed to serialize message for connection {}",
+                            connection_id
+                        );
+                    }
+
+                    if matches!(msg, WsMessage::StoreDeleted { .. }) {
+                        let _ = sender.send(Message::Close(None)).await;
+                        break 'send;
                     }
-                } else {
-                    error!(
-                        "Failed to serialize message for connection {}",
-                        connection_id
-                    );
                 }
             }
         })
@@ -254,17 +532,23 @@ async fn handle_socket(socket: WebSocket, store_id: String, manager: Arc<Connect
     // Spawn task to handle incoming messages
     let mut recv_task = {
         let manager = Arc::clone(&manager);
-        let store_id = store_id.clone();
         let connection_id = connection_id.clone();
 
         tokio::spawn(async move {
             while let Some(msg) = receiver.next().await {
+                // Any frame, including protocol-level pongs, shows the client is alive
+                *last_seen.lock().unwrap() = Instant::now();
                 match msg {
                     Ok(Message::Text(text)) => {
-                        if let Err(e) =
-                            handle_client_message(&text, &manager, &store_id, &connection_id).await
-                        {
-                            warn!("Error handling client message: {}", e);
+                        match handle_client_message(&text, &manager, &stores, &connection).await {
+                            Ok(replies) => {
+                                for reply in replies {
+                                    if reply_tx.send(reply).is_err() {
+                                        break;
+                                    }
+                                }
+                            }
+                            Err(e) => warn!("Error handling client message: {}", e),
                         }
                     }
                     Ok(Message::Close(_)) => {
@@ -293,37 +577,38 @@ async fn handle_socket(socket: WebSocket, store_id: String, manager: Arc<Connect
 
     // Clean up connection
     manager.disconnect(&connection_id).await;
+    metrics.record_websocket_closed();
     info!("WebSocket connection {} cleaned up", connection_id);
 }
 
 /// Handle client messages
-async fn handle_client_message(
+///
+/// Returns the replies to send back to the client.
+pub(crate) async fn handle_client_message(
     text: &str,
     manager: &ConnectionManager,
-    current_store_id: &str,
-    connection_id: &str,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    stores: &RwLock<HashMap<String, InMemoryEventStore>>,
+    connection: &Connection,
+) -> Result<Vec<WsMessage>, Box<dyn std::error::Error + Send + Sync>> {
     let client_msg: ClientMessage = serde_json::from_str(text)?;
 
-    match client_msg {
+    let replies = match client_msg {
         ClientMessage::Subscribe { store_id } => {
-            // For now, we only support subscribing to the store specified in the URL
-            if store_id != current_store_id {
-                warn!(
-                    "Connection {} tried to subscribe to {} but is connected to {}",
-                    connection_id, store_id, current_store_id
-                );
+            // Re-subscribing, e.g. to the store in the URL, is a no-op
+            if manager.add_subscription(&store_id, &connection.id).await {
+                catch_up_messages(stores, &store_id, &connection.id, &connection.claims).await
+            } else {
+                Vec::new()
             }
-            // Already subscribed during connection setup
         }
         ClientMessage::Unsubscribe { store_id } => {
-            manager.unsubscribe(&store_id, connection_id).await;
-        }
-        ClientMessage::Ping => {
-            // Pong will be sent automatically by the broadcast system
-            // if we had the connection's sender here
+            manager.unsubscribe(&store_id, &connection.id).await;
+            Vec::new()
         }
-    }
+        ClientMessage::Ping => vec![WsMessage::Pong],
+        // Liveness is tracked by the receive loop
+        ClientMessage::Pong => Vec::new(),
+    };
 
-    Ok(())
+    Ok(replies)
 }
@@ -0,0 +1,240 @@
+use eventbook_core::{content_event_id, Event};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+/// How `push_events` handles a server that has moved past our last push
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictStrategy {
+    /// Refuse to push and report the conflict
+    #[default]
+    Abort,
+    /// Renumber local events to follow the server's latest version
+    Rebase,
+}
+
+impl FromStr for ConflictStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(ConflictStrategy::Abort),
+            "rebase" => Ok(ConflictStrategy::Rebase),
+            other => Err(format!("Unknown conflict strategy: {}", other)),
+        }
+    }
+}
+
+/// Decide which events to push for one aggregate
+///
+/// `server_base` is the server version our last push was based on and
+/// `server_latest` is the version the server is at now; anything in between
+/// was written by someone else. The events are returned in the order to post
+/// them, each expecting the server at the version the one before left it, so
+/// the first expects `server_latest`.
+pub fn resolve_push(
+    strategy: ConflictStrategy,
+    pending: Vec<Event>,
+    server_base: i64,
+    server_latest: i64,
+) -> Result<Vec<Event>, String> {
+    if pending.is_empty() || server_latest <= server_base {
+        return Ok(pending);
+    }
+
+    match strategy {
+        ConflictStrategy::Abort => Err(format!(
+            "Version conflict: server is at version {}, local events are based on version {}",
+            server_latest, server_base
+        )),
+        ConflictStrategy::Rebase => Ok(pending
+            .into_iter()
+            .zip(server_latest + 1..)
+            .map(|(mut event, version)| {
+                event.version = version;
+                event
+            })
+            .collect()),
+    }
+}
+
+/// Renumber unsynced local events to follow events pulled from the server
+///
+/// Both sides numbered from the same base, so for every aggregate `remote`
+/// touches, the local events are given the versions after the newest remote
+/// one, in their original order. Their timestamps are raised to at least the
+/// newest remote event's, so replay applies them after the remote events;
+/// random ids are kept, while content-derived ids (see
+/// [`EventBuilder::deterministic_id`](eventbook_core::EventBuilder::deterministic_id))
+/// are derived again for the new version. Local events the server already
+/// has are dropped, and aggregates `remote` doesn't mention are left alone.
+///
+/// This is a rebase, not a merge: payloads are replayed as written, which is
+/// only sound for edits that commute or where the last writer should win. Two
+/// edits to the same cell source resolve to the local one, with the remote
+/// edit lost from the projection though not from the log.
+pub fn rebase_local_events(remote: &[Event], local: &[Event]) -> Vec<Event> {
+    let remote_ids: HashSet<&str> = remote.iter().map(|e| e.id.as_str()).collect();
+    let mut remote_heads: HashMap<&str, (i64, i64)> = HashMap::new();
+    for event in remote {
+        let head = remote_heads
+            .entry(event.aggregate_id.as_str())
+            .or_insert((event.version, event.timestamp));
+        *head = (head.0.max(event.version), head.1.max(event.timestamp));
+    }
+
+    let mut pending: Vec<&Event> = local
+        .iter()
+        .filter(|e| !remote_ids.contains(e.id.as_str()))
+        .collect();
+    // Within an aggregate, version order is the order the events were written
+    pending.sort_by_key(|e| e.version);
+
+    let mut next_versions = HashMap::new();
+    let mut rebased = Vec::with_capacity(pending.len());
+    for event in pending {
+        let mut event = event.clone();
+        if let Some(&(latest_version, latest_timestamp)) =
+            remote_heads.get(event.aggregate_id.as_str())
+        {
+            let version = next_versions
+                .entry(event.aggregate_id.clone())
+                .or_insert(latest_version + 1);
+            let was_content_id = event.id
+                == content_event_id(
+                    &event.aggregate_id,
+                    event.version,
+                    &event.event_type,
+                    &event.payload,
+                );
+            event.version = *version;
+            *version += 1;
+            event.timestamp = event.timestamp.max(latest_timestamp);
+            if was_content_id {
+                event.id = content_event_id(
+                    &event.aggregate_id,
+                    event.version,
+                    &event.event_type,
+                    &event.payload,
+                );
+            }
+        }
+        rebased.push(event);
+    }
+    rebased
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn local_event(version: i64) -> Event {
+        Event {
+            id: format!("local-{}", version),
+            event_type: "CellSourceUpdated".to_string(),
+            aggregate_id: "doc-1".to_string(),
+            payload: json!({}),
+            timestamp: 1_700_000_000_000,
+            version,
+            transaction_id: None,
+            actor: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_strategy() {
+        assert_eq!("abort".parse(), Ok(ConflictStrategy::Abort));
+        assert_eq!("rebase".parse(), Ok(ConflictStrategy::Rebase));
+        assert!("keep-both".parse::<ConflictStrategy>().is_err());
+        assert!("merge".parse::<ConflictStrategy>().is_err());
+    }
+
+    #[test]
+    fn test_no_conflict_pushes_unchanged() {
+        let pending = vec![local_event(3), local_event(4)];
+        let events = resolve_push(ConflictStrategy::Abort, pending.clone(), 2, 2).unwrap();
+        assert_eq!(events, pending);
+    }
+
+    #[test]
+    fn test_abort_on_conflict() {
+        let pending = vec![local_event(3), local_event(4)];
+        let result = resolve_push(ConflictStrategy::Abort, pending, 2, 5);
+        assert!(result.unwrap_err().contains("server is at version 5"));
+    }
+
+    #[test]
+    fn test_rebase_on_conflict() {
+        let pending = vec![local_event(3), local_event(4)];
+        let events = resolve_push(ConflictStrategy::Rebase, pending, 2, 5).unwrap();
+
+        let ids: Vec<_> = events.iter().map(|e| e.id.as_str()).collect();
+        let versions: Vec<_> = events.iter().map(|e| e.version).collect();
+        assert_eq!(ids, vec!["local-3", "local-4"]);
+        assert_eq!(versions, vec![6, 7]);
+    }
+
+    #[test]
+    fn test_rebase_local_events_after_remote() {
+        let remote_event = |id: &str, version: i64, timestamp: i64| Event {
+            id: id.to_string(),
+            timestamp,
+            ..local_event(version)
+        };
+        let remote = vec![remote_event("remote-3", 3, 1_700_000_000_500)];
+
+        let mut deterministic = eventbook_core::EventBuilder::new()
+            .event_type("CellSourceUpdated")
+            .aggregate_id("doc-1")
+            .payload(json!({"cell_id": "cell-1", "source": "x = 2"}))
+            .unwrap()
+            .timestamp(1_700_000_000_000)
+            .deterministic_id()
+            .build(4)
+            .unwrap();
+        let other_aggregate = Event {
+            id: "other-3".to_string(),
+            aggregate_id: "doc-2".to_string(),
+            ..local_event(3)
+        };
+        let local = vec![
+            local_event(3),
+            deterministic.clone(),
+            other_aggregate.clone(),
+        ];
+
+        let rebased = rebase_local_events(&remote, &local);
+        assert_eq!(rebased.len(), 3);
+
+        // Both sides appended version 3; ours now follows theirs
+        assert_eq!(rebased[0].id, "local-3");
+        assert_eq!(rebased[0].version, 4);
+        assert_eq!(rebased[0].timestamp, 1_700_000_000_500);
+
+        // A content-derived id follows the new version
+        deterministic.version = 5;
+        assert_eq!(rebased[2].version, 5);
+        assert_eq!(
+            rebased[2].id,
+            content_event_id("doc-1", 5, "CellSourceUpdated", &deterministic.payload)
+        );
+        assert_ne!(rebased[2].id, local[1].id);
+
+        // Aggregates the server didn't touch keep their numbering
+        assert_eq!(rebased[1], other_aggregate);
+
+        // Local events the server already has aren't re-submitted
+        let echoed = vec![
+            remote[0].clone(),
+            Event {
+                version: 4,
+                ..local_event(3)
+            },
+        ];
+        let rebased = rebase_local_events(&echoed, &local);
+        assert_eq!(
+            rebased.iter().map(|e| e.version).collect::<Vec<_>>(),
+            vec![3, 5]
+        );
+    }
+}
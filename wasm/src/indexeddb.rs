@@ -0,0 +1,107 @@
+//! IndexedDB persistence for the browser event store
+//!
+//! Events are stored as JSON objects keyed by id in a single object store.
+//! IndexedDB's API is callback based, so each request is bridged to a
+//! `Promise` and awaited.
+
+use eventbook_core::Event;
+use js_sys::{Promise, JSON};
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{IdbDatabase, IdbObjectStoreParameters, IdbRequest, IdbTransactionMode};
+
+const EVENTS_STORE: &str = "events";
+const SCHEMA_VERSION: u32 = 1;
+
+/// Open (or create) the named database
+pub async fn open_database(name: &str) -> Result<IdbDatabase, JsValue> {
+    let window = web_sys::window().ok_or("No global window object")?;
+    let factory = window
+        .indexed_db()?
+        .ok_or("IndexedDB is not available in this browser")?;
+
+    let request = factory.open_with_u32(name, SCHEMA_VERSION)?;
+    let upgrade_request = request.clone();
+    let on_upgrade = Closure::once_into_js(move || {
+        let Ok(result) = upgrade_request.result() else {
+            return;
+        };
+        let db: IdbDatabase = result.unchecked_into();
+        let params = IdbObjectStoreParameters::new();
+        params.set_key_path(&JsValue::from_str("id"));
+        if let Err(e) = db.create_object_store_with_optional_parameters(EVENTS_STORE, &params) {
+            web_sys::console::error_1(&e);
+        }
+    });
+    request.set_onupgradeneeded(Some(on_upgrade.unchecked_ref()));
+
+    Ok(request_result(&request).await?.unchecked_into())
+}
+
+/// Write an event, replacing any stored event with the same id
+pub async fn put_event(db: &IdbDatabase, event: &Event) -> Result<(), JsValue> {
+    let json = serde_json::to_string(event).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let value = JSON::parse(&json)?;
+
+    let transaction =
+        db.transaction_with_str_and_mode(EVENTS_STORE, IdbTransactionMode::Readwrite)?;
+    let request = transaction.object_store(EVENTS_STORE)?.put(&value)?;
+    request_result(&request).await?;
+    Ok(())
+}
+
+/// Write events in the background, if a database has been loaded
+///
+/// Shared by every path that adds events to the local store, so events that
+/// arrive from the server survive a reload as well as locally submitted ones.
+pub fn persist_events(database: &RefCell<Option<IdbDatabase>>, events: Vec<Event>) {
+    let Some(db) = database.borrow().clone() else {
+        return;
+    };
+    if events.is_empty() {
+        return;
+    }
+    wasm_bindgen_futures::spawn_local(async move {
+        for event in events {
+            if let Err(e) = put_event(&db, &event).await {
+                log!("Failed to persist event {}: {:?}", event.id, e);
+            }
+        }
+    });
+}
+
+/// Read every stored event, ordered by `(timestamp, version)`
+pub async fn load_events(db: &IdbDatabase) -> Result<Vec<Event>, JsValue> {
+    let transaction = db.transaction_with_str(EVENTS_STORE)?;
+    let request = transaction.object_store(EVENTS_STORE)?.get_all()?;
+    let values = request_result(&request).await?;
+
+    let json = JSON::stringify(&values)?
+        .as_string()
+        .unwrap_or_else(|| "[]".to_string());
+    let mut events: Vec<Event> = serde_json::from_str(&json)
+        .map_err(|e| JsValue::from_str(&format!("Corrupt event in IndexedDB: {}", e)))?;
+    events.sort_by_key(|e| (e.timestamp, e.version));
+    Ok(events)
+}
+
+/// Wait for a request to finish, resolving to its result
+async fn request_result(request: &IdbRequest) -> Result<JsValue, JsValue> {
+    let promise = Promise::new(&mut |resolve, reject| {
+        let success_request = request.clone();
+        let on_success = Closure::once_into_js(move || {
+            let result = success_request.result().unwrap_or(JsValue::UNDEFINED);
+            let _ = resolve.call1(&JsValue::NULL, &result);
+        });
+        let on_error = Closure::once_into_js(move || {
+            let _ = reject.call1(
+                &JsValue::NULL,
+                &JsValue::from_str("IndexedDB request failed"),
+            );
+        });
+        request.set_onsuccess(Some(on_success.unchecked_ref()));
+        request.set_onerror(Some(on_error.unchecked_ref()));
+    });
+    JsFuture::from(promise).await
+}
@@ -1,10 +1,35 @@
-use eventbook_core::{Cell, CellType, Document, DocumentProjection, ExecutionState};
-use eventbook_core::{Event, EventStore, InMemoryEventStore, Projection};
+// Console logging macro for debugging
+macro_rules! log {
+    ( $( $t:tt )* ) => {
+        web_sys::console::log_1(&format!( $( $t )* ).into());
+    }
+}
+
+mod conflict;
+#[cfg(feature = "indexeddb")]
+mod indexeddb;
+mod sync;
+mod websocket;
+
+use conflict::{resolve_push, ConflictStrategy};
+use eventbook_core::{
+    Cell, CellOutput, CellType, Document, DocumentProjection, ExecutionState, OutputType,
+};
+use eventbook_core::{
+    Event, EventBuilder, EventStore, InMemoryEventStore, Projection, UpcasterRegistry,
+};
 use js_sys::{Date, Promise};
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use sync::{
+    merge_remote_events, record_pulled_events, unpushed_events, PushState, MILLIS_PER_SECOND,
+};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{console, Request, RequestInit, Response};
+use web_sys::{Request, RequestInit, Response};
+use websocket::LiveConnection;
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
 // allocator.
@@ -19,13 +44,6 @@ pub fn main() {
     console_error_panic_hook::set_once();
 }
 
-// Console logging macro for debugging
-macro_rules! log {
-    ( $( $t:tt )* ) => {
-        console::log_1(&format!( $( $t )* ).into());
-    }
-}
-
 /// JavaScript-compatible Event type
 #[wasm_bindgen]
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,6 +54,7 @@ pub struct JsEvent {
     payload: String, // JSON string for JS compatibility
     timestamp: f64,  // JS numbers are f64
     version: f64,
+    actor: Option<String>,
 }
 
 #[wasm_bindgen]
@@ -56,6 +75,7 @@ impl JsEvent {
             payload,
             timestamp,
             version,
+            actor: None,
         }
     }
 
@@ -88,6 +108,12 @@ impl JsEvent {
     pub fn version(&self) -> f64 {
         self.version
     }
+
+    /// Who created the event, if the server recorded it
+    #[wasm_bindgen(getter)]
+    pub fn actor(&self) -> Option<String> {
+        self.actor.clone()
+    }
 }
 
 impl From<Event> for JsEvent {
@@ -99,6 +125,7 @@ impl From<Event> for JsEvent {
             payload: serde_json::to_string(&event.payload).unwrap_or_default(),
             timestamp: event.timestamp as f64,
             version: event.version as f64,
+            actor: event.actor,
         }
     }
 }
@@ -117,10 +144,104 @@ impl TryFrom<JsEvent> for Event {
             payload,
             timestamp: js_event.timestamp as i64,
             version: js_event.version as i64,
+            transaction_id: None,
+            actor: js_event.actor,
         })
     }
 }
 
+/// Chainable event construction for JavaScript, mirroring core's `EventBuilder`
+///
+/// ```js
+/// const event = new JsEventBuilder()
+///   .event_type("CellCreated")
+///   .aggregate_id(documentId)
+///   .payload(JSON.stringify({ cell_id: "cell-1", cell_type: "code" }))
+///   .build();
+/// ```
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct JsEventBuilder {
+    inner: EventBuilder,
+    version: i64,
+    timestamp: Option<i64>,
+}
+
+#[wasm_bindgen]
+impl JsEventBuilder {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> JsEventBuilder {
+        JsEventBuilder {
+            inner: EventBuilder::new(),
+            version: 1,
+            timestamp: None,
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn event_type(mut self, event_type: String) -> JsEventBuilder {
+        self.inner = self.inner.event_type(event_type);
+        self
+    }
+
+    #[wasm_bindgen]
+    pub fn aggregate_id(mut self, aggregate_id: String) -> JsEventBuilder {
+        self.inner = self.inner.aggregate_id(aggregate_id);
+        self
+    }
+
+    /// Set the payload from a JSON string
+    #[wasm_bindgen]
+    pub fn payload(mut self, payload: String) -> Result<JsEventBuilder, JsError> {
+        let value: serde_json::Value = serde_json::from_str(&payload)
+            .map_err(|e| JsError::new(&format!("Invalid JSON payload: {}", e)))?;
+        self.inner = self
+            .inner
+            .payload(value)
+            .map_err(|e| JsError::new(&e.to_string()))?;
+        Ok(self)
+    }
+
+    /// Version to build with; defaults to 1
+    #[wasm_bindgen]
+    pub fn version(mut self, version: f64) -> JsEventBuilder {
+        self.version = version as i64;
+        self
+    }
+
+    /// Timestamp in milliseconds; defaults to now
+    #[wasm_bindgen]
+    pub fn timestamp(mut self, timestamp: f64) -> JsEventBuilder {
+        self.timestamp = Some(timestamp as i64);
+        self
+    }
+
+    /// Derive the id from the event's content, so rebuilding the same event
+    /// for a retry gives the same id
+    #[wasm_bindgen]
+    pub fn deterministic_id(mut self) -> JsEventBuilder {
+        self.inner = self.inner.deterministic_id();
+        self
+    }
+
+    /// Build the event, applying core's validation
+    #[wasm_bindgen]
+    pub fn build(self) -> Result<JsEvent, JsError> {
+        let timestamp = self.timestamp.unwrap_or_else(|| Date::now() as i64);
+        self.inner
+            .timestamp(timestamp)
+            .build(self.version)
+            .map(JsEvent::from)
+            .map_err(|e| JsError::new(&e.to_string()))
+    }
+}
+
+impl Default for JsEventBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// JavaScript-compatible Cell type
 #[wasm_bindgen]
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -135,6 +256,9 @@ pub struct JsCell {
     last_execution_duration_ms: Option<u32>,
     source_visible: bool,
     output_visible: bool,
+    ai_provider: Option<String>,
+    ai_model: Option<String>,
+    ai_settings_json: Option<String>,
     created_by: String,
     document_id: String,
     created_at: f64,
@@ -168,6 +292,22 @@ impl JsCell {
         self.execution_state.clone()
     }
 
+    #[wasm_bindgen(getter)]
+    pub fn ai_provider(&self) -> Option<String> {
+        self.ai_provider.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn ai_model(&self) -> Option<String> {
+        self.ai_model.clone()
+    }
+
+    /// AI settings as a JSON string, for `JSON.parse` on the JS side
+    #[wasm_bindgen(getter)]
+    pub fn ai_settings_json(&self) -> Option<String> {
+        self.ai_settings_json.clone()
+    }
+
     #[wasm_bindgen(getter)]
     pub fn created_by(&self) -> String {
         self.created_by.clone()
@@ -201,7 +341,7 @@ impl From<Cell> for JsCell {
                 CellType::Raw => "raw".to_string(),
             },
             source: cell.source,
-            fractional_index: cell.fractional_index,
+            fractional_index: cell.fractional_index.map(String::from),
             execution_count: cell.execution_count.map(|v| v as u32),
             execution_state: match cell.execution_state {
                 ExecutionState::Idle => "idle".to_string(),
@@ -214,6 +354,12 @@ impl From<Cell> for JsCell {
             last_execution_duration_ms: cell.last_execution_duration_ms.map(|v| v as u32),
             source_visible: cell.source_visible,
             output_visible: cell.output_visible,
+            ai_provider: cell.ai_provider,
+            ai_model: cell.ai_model,
+            ai_settings_json: cell
+                .ai_settings
+                .as_ref()
+                .and_then(|settings| serde_json::to_string(settings).ok()),
             created_by: cell.created_by,
             document_id: cell.document_id,
             created_at: cell.created_at as f64,
@@ -273,6 +419,124 @@ impl From<Document> for JsDocument {
     }
 }
 
+/// JavaScript-compatible CellOutput type
+#[wasm_bindgen]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsCellOutput {
+    id: String,
+    cell_id: String,
+    output_type: String,
+    position: f64,
+    stream_name: Option<String>,
+    execution_count: Option<u32>,
+    display_id: Option<String>,
+    data: Option<String>,
+    artifact_id: Option<String>,
+    mime_type: Option<String>,
+    metadata_json: Option<String>,
+    representations_json: Option<String>,
+    created_at: f64,
+}
+
+#[wasm_bindgen]
+impl JsCellOutput {
+    #[wasm_bindgen(getter)]
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn cell_id(&self) -> String {
+        self.cell_id.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn output_type(&self) -> String {
+        self.output_type.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn position(&self) -> f64 {
+        self.position
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn stream_name(&self) -> Option<String> {
+        self.stream_name.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn execution_count(&self) -> Option<u32> {
+        self.execution_count
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn display_id(&self) -> Option<String> {
+        self.display_id.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn data(&self) -> Option<String> {
+        self.data.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn artifact_id(&self) -> Option<String> {
+        self.artifact_id.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn mime_type(&self) -> Option<String> {
+        self.mime_type.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn metadata_json(&self) -> Option<String> {
+        self.metadata_json.clone()
+    }
+
+    /// Representations keyed by mime type, as a JSON string
+    #[wasm_bindgen(getter)]
+    pub fn representations_json(&self) -> Option<String> {
+        self.representations_json.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn created_at(&self) -> f64 {
+        self.created_at
+    }
+}
+
+impl From<CellOutput> for JsCellOutput {
+    fn from(output: CellOutput) -> Self {
+        JsCellOutput {
+            id: output.id,
+            cell_id: output.cell_id,
+            output_type: match output.output_type {
+                OutputType::MultimediaDisplay => "multimedia_display".to_string(),
+                OutputType::MultimediaResult => "multimedia_result".to_string(),
+                OutputType::Terminal => "terminal".to_string(),
+                OutputType::Markdown => "markdown".to_string(),
+                OutputType::Error => "error".to_string(),
+            },
+            position: output.position,
+            stream_name: output.stream_name,
+            execution_count: output.execution_count.map(|v| v as u32),
+            display_id: output.display_id,
+            data: output.data,
+            artifact_id: output.artifact_id,
+            mime_type: output.mime_type,
+            metadata_json: output
+                .metadata
+                .map(|metadata| serde_json::to_string(&metadata).unwrap_or_default()),
+            representations_json: output
+                .representations
+                .map(|representations| serde_json::to_string(&representations).unwrap_or_default()),
+            created_at: output.created_at as f64,
+        }
+    }
+}
+
 /// Sync result for JavaScript
 #[wasm_bindgen]
 #[derive(Debug, Serialize, Deserialize)]
@@ -300,12 +564,80 @@ impl SyncResult {
     }
 }
 
+/// Push result for JavaScript
+#[wasm_bindgen]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PushResult {
+    events_pushed: u32,
+    success: bool,
+    error_message: Option<String>,
+}
+
+#[wasm_bindgen]
+impl PushResult {
+    #[wasm_bindgen(getter)]
+    pub fn events_pushed(&self) -> u32 {
+        self.events_pushed
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn success(&self) -> bool {
+        self.success
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn error_message(&self) -> Option<String> {
+        self.error_message.clone()
+    }
+}
+
+/// Result of pushing every aggregate's unpushed events
+#[wasm_bindgen]
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LocalPushResult {
+    events_pushed: u32,
+    conflicts: u32,
+    errors: Vec<String>,
+}
+
+#[wasm_bindgen]
+impl LocalPushResult {
+    #[wasm_bindgen(getter)]
+    pub fn events_pushed(&self) -> u32 {
+        self.events_pushed
+    }
+
+    /// Number of aggregates the server rejected with a version conflict
+    #[wasm_bindgen(getter)]
+    pub fn conflicts(&self) -> u32 {
+        self.conflicts
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn success(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// One message per event the server rejected
+    #[wasm_bindgen(getter)]
+    pub fn errors(&self) -> js_sys::Array {
+        self.errors.iter().map(|e| JsValue::from_str(e)).collect()
+    }
+}
+
 /// Main EventBook client for browser
 #[wasm_bindgen]
 pub struct EventBookClient {
-    local_store: InMemoryEventStore,
-    document_projection: DocumentProjection,
+    local_store: Rc<RefCell<InMemoryEventStore>>,
+    document_projection: Rc<RefCell<DocumentProjection>>,
     server_url: String,
+    conflict_strategy: ConflictStrategy,
+    push_state: Rc<RefCell<HashMap<String, PushState>>>,
+    /// Database that local and pulled events are persisted to, once loaded
+    #[cfg(feature = "indexeddb")]
+    database: Rc<RefCell<Option<web_sys::IdbDatabase>>>,
+    /// WebSocket streaming server events into the local store
+    live: LiveConnection,
 }
 
 #[wasm_bindgen]
@@ -314,10 +646,28 @@ impl EventBookClient {
     pub fn new(server_url: String) -> EventBookClient {
         log!("Creating EventBook client with server: {}", server_url);
 
+        let local_store = Rc::new(RefCell::new(InMemoryEventStore::new()));
+        let document_projection = Rc::new(RefCell::new(DocumentProjection::new()));
+        #[cfg(feature = "indexeddb")]
+        let database = Rc::new(RefCell::new(None));
+        let push_state = Rc::new(RefCell::new(HashMap::new()));
+        let live = LiveConnection::new(
+            local_store.clone(),
+            document_projection.clone(),
+            push_state.clone(),
+            #[cfg(feature = "indexeddb")]
+            database.clone(),
+        );
+
         EventBookClient {
-            local_store: InMemoryEventStore::new(),
-            document_projection: DocumentProjection::new(),
+            local_store,
+            document_projection,
             server_url,
+            conflict_strategy: ConflictStrategy::default(),
+            push_state,
+            #[cfg(feature = "indexeddb")]
+            database,
+            live,
         }
     }
 
@@ -334,36 +684,40 @@ impl EventBookClient {
             .map_err(|e| JsError::new(&format!("Invalid JSON payload: {}", e)))?;
 
         // Get next version (immutable borrow)
-        let current_version = self.local_store.get_latest_version(&aggregate_id);
+        let current_version = self.local_store.borrow().get_latest_version(&aggregate_id);
         let next_version = current_version + 1;
 
         // Build the event with browser-compatible timestamp
         let timestamp = Date::now() as i64;
-        let event_id = format!("event-{}", timestamp);
+        let event_id = eventbook_core::generate_event_id();
 
         let event = Event {
-            id: event_id.clone(),
+            id: event_id,
             event_type,
             aggregate_id,
             payload: payload_value,
             timestamp,
             version: next_version,
+            transaction_id: None,
+            actor: None,
         };
 
-        // Store locally (first mutable operation)
-        match self.local_store.append_event(event.clone()) {
-            Ok(_) => {}
-            Err(e) => return Err(JsError::new(&format!("Store error: {}", e))),
-        }
-
-        // Update projection (second mutable operation)
-        match self.document_projection.apply_new_events(&[event.clone()]) {
-            Ok(_) => {}
-            Err(e) => return Err(JsError::new(&format!("Projection error: {}", e))),
-        }
+        self.store_local_event(event)
+    }
 
-        log!("Event {} submitted locally", event_id);
-        Ok(event.into())
+    /// Submit an event made with `JsEventBuilder`
+    ///
+    /// The event keeps its id and timestamp but takes the aggregate's next
+    /// local version, whatever version it was built with.
+    #[wasm_bindgen]
+    pub fn submit_built_event(&mut self, event: JsEvent) -> Result<JsEvent, JsError> {
+        let mut event = Event::try_from(event)?;
+        event.version = self
+            .local_store
+            .borrow()
+            .get_latest_version(&event.aggregate_id)
+            + 1;
+        self.store_local_event(event)
     }
 
     /// Get all local events
@@ -371,6 +725,7 @@ impl EventBookClient {
     pub fn get_events(&self) -> Result<js_sys::Array, JsError> {
         let events = self
             .local_store
+            .borrow()
             .get_all_events()
             .map_err(|e| JsError::new(&format!("Get events error: {}", e)))?;
 
@@ -388,6 +743,7 @@ impl EventBookClient {
     pub fn get_events_for_aggregate(&self, aggregate_id: String) -> Result<js_sys::Array, JsError> {
         let events = self
             .local_store
+            .borrow()
             .get_events(&aggregate_id)
             .map_err(|e| JsError::new(&format!("Get events error: {}", e)))?;
 
@@ -400,10 +756,14 @@ impl EventBookClient {
         Ok(js_array)
     }
 
-    /// Get materialized cells for a document
+    /// Get a document's cells in the order they were created
+    ///
+    /// Moving a cell doesn't change its place here; use `get_ordered_cells`
+    /// for the order the notebook displays them in.
     #[wasm_bindgen]
     pub fn get_document_cells(&self, document_id: String) -> js_sys::Array {
-        let cells = self.document_projection.get_document_cells(&document_id);
+        let projection = self.document_projection.borrow();
+        let cells = projection.get_document_cells_in_creation_order(&document_id);
         let js_array = js_sys::Array::new();
 
         for cell in cells {
@@ -414,10 +774,31 @@ impl EventBookClient {
         js_array
     }
 
-    /// Get ordered cells for a document
+    /// Get up to `limit` of a document's cells in display order, skipping the
+    /// first `offset`, so large notebooks can be rendered a window at a time
+    ///
+    /// The windows tile `get_ordered_cells`, not `get_document_cells`, whose
+    /// creation order ignores moves.
+    #[wasm_bindgen]
+    pub fn get_document_cells_range(
+        &self,
+        document_id: String,
+        offset: u32,
+        limit: u32,
+    ) -> js_sys::Array {
+        let projection = self.document_projection.borrow();
+        projection
+            .get_document_cells_range(&document_id, offset as usize, limit as usize)
+            .into_iter()
+            .map(|cell| JsValue::from(JsCell::from(cell.clone())))
+            .collect()
+    }
+
+    /// Get a document's cells in display order, by fractional index
     #[wasm_bindgen]
     pub fn get_ordered_cells(&self, document_id: String) -> js_sys::Array {
-        let cells = self.document_projection.get_document_cells(&document_id);
+        let projection = self.document_projection.borrow();
+        let cells = projection.get_document_cells(&document_id);
         let js_array = js_sys::Array::new();
 
         for cell in cells {
@@ -432,14 +813,31 @@ impl EventBookClient {
     #[wasm_bindgen]
     pub fn get_cell(&self, cell_id: String) -> Option<JsCell> {
         self.document_projection
+            .borrow()
             .get_cell(&cell_id)
             .map(|c| JsCell::from(c.clone()))
     }
 
+    /// Get a cell's outputs, ordered by position
+    #[wasm_bindgen]
+    pub fn get_cell_outputs(&self, cell_id: String) -> js_sys::Array {
+        let projection = self.document_projection.borrow();
+        let outputs = projection.get_cell_outputs(&cell_id);
+        let js_array = js_sys::Array::new();
+
+        for output in outputs {
+            let js_output = JsCellOutput::from(output.clone());
+            js_array.push(&JsValue::from(js_output));
+        }
+
+        js_array
+    }
+
     /// Get document by ID
     #[wasm_bindgen]
     pub fn get_document(&self, document_id: String) -> Option<JsDocument> {
         self.document_projection
+            .borrow()
             .get_document(&document_id)
             .map(|d| JsDocument::from(d.clone()))
     }
@@ -448,6 +846,7 @@ impl EventBookClient {
     #[wasm_bindgen]
     pub fn get_cell_count(&self, document_id: String) -> u32 {
         self.document_projection
+            .borrow()
             .get_document_cells(&document_id)
             .len() as u32
     }
@@ -455,14 +854,15 @@ impl EventBookClient {
     /// Get total event count
     #[wasm_bindgen]
     pub fn get_event_count(&self) -> u32 {
-        self.local_store.get_event_count() as u32
+        self.local_store.borrow().get_event_count() as u32
     }
 
     /// Clear local store
     #[wasm_bindgen]
     pub fn clear_local_store(&mut self) {
-        self.local_store = InMemoryEventStore::new();
-        self.document_projection = DocumentProjection::new();
+        *self.local_store.borrow_mut() = InMemoryEventStore::new();
+        *self.document_projection.borrow_mut() = DocumentProjection::new();
+        self.push_state.borrow_mut().clear();
         log!("Local store cleared");
     }
 
@@ -471,27 +871,106 @@ impl EventBookClient {
     pub fn rebuild_projections(&mut self) -> Result<u32, JsError> {
         let events = self
             .local_store
+            .borrow()
             .get_all_events()
             .map_err(|e| JsError::new(&format!("Failed to get events: {}", e)))?;
 
         self.document_projection
-            .rebuild_from_events(&events)
+            .borrow_mut()
+            .rebuild_from_events(&UpcasterRegistry::standard().upcast_all(&events))
             .map_err(|e| JsError::new(&format!("Failed to rebuild projections: {}", e)))?;
 
         log!("Rebuilt projections from {} events", events.len());
         Ok(events.len() as u32)
     }
 
-    /// Sync event log from server
+    /// Open the IndexedDB database `name` and load its events
+    ///
+    /// Resolves to the number of events loaded; projections are rebuilt from
+    /// the full local log. Events submitted afterwards are persisted to the
+    /// same database.
+    #[cfg(feature = "indexeddb")]
+    #[wasm_bindgen]
+    pub fn load_from_indexeddb(&self, name: String) -> Promise {
+        let local_store = self.local_store.clone();
+        let document_projection = self.document_projection.clone();
+        let database = self.database.clone();
+
+        wasm_bindgen_futures::future_to_promise(async move {
+            let load_error = |e: JsValue| {
+                JsValue::from(JsError::new(&format!("IndexedDB load failed: {:?}", e)))
+            };
+
+            let db = indexeddb::open_database(&name).await.map_err(load_error)?;
+            let events = indexeddb::load_events(&db).await.map_err(load_error)?;
+
+            let mut store = local_store.borrow_mut();
+            let mut loaded = 0;
+            for event in events {
+                match store.append_event(event) {
+                    Ok(()) => loaded += 1,
+                    // Already in memory, e.g. submitted before loading
+                    Err(eventbook_core::EventError::DuplicateEventId(_)) => {}
+                    Err(e) => {
+                        return Err(JsError::new(&format!("IndexedDB load failed: {}", e)).into())
+                    }
+                }
+            }
+
+            let all_events = store
+                .get_all_events()
+                .map_err(|e| JsError::new(&format!("Failed to get events: {}", e)))?;
+            document_projection
+                .borrow_mut()
+                .rebuild_from_events(&UpcasterRegistry::standard().upcast_all(&all_events))
+                .map_err(|e| JsError::new(&format!("Failed to rebuild projections: {}", e)))?;
+
+            *database.borrow_mut() = Some(db);
+            log!("Loaded {} events from IndexedDB {}", loaded, name);
+            Ok(JsValue::from(loaded))
+        })
+    }
+
+    /// Pull the server's event log into the local store
+    ///
+    /// Events already held locally or out of sequence are skipped; the
+    /// result's `events_pulled` counts the events actually merged.
     #[wasm_bindgen]
     pub fn sync_event_log(&mut self) -> Promise {
         let server_url = self.server_url.clone();
+        let local_store = self.local_store.clone();
+        let document_projection = self.document_projection.clone();
+        let push_state = self.push_state.clone();
+        #[cfg(feature = "indexeddb")]
+        let database = self.database.clone();
 
         wasm_bindgen_futures::future_to_promise(async move {
-            match fetch_events_from_server(&server_url).await {
-                Ok(events) => {
+            let merged = fetch_events_from_server(&server_url)
+                .await
+                .and_then(|events| {
+                    merge_remote_events(
+                        &mut local_store.borrow_mut(),
+                        &mut document_projection.borrow_mut(),
+                        events,
+                    )
+                    .map_err(|e| format!("Failed to merge events: {}", e))
+                });
+
+            match merged {
+                Ok(outcome) => {
+                    log!(
+                        "Merged {} events ({} duplicates, {} out of sequence{})",
+                        outcome.merged.len(),
+                        outcome.duplicates,
+                        outcome.rejected,
+                        if outcome.rebuilt { ", rebuilt" } else { "" }
+                    );
+                    let events_pulled = outcome.merged.len() as u32;
+                    record_pulled_events(&mut push_state.borrow_mut(), &outcome.merged);
+                    #[cfg(feature = "indexeddb")]
+                    indexeddb::persist_events(&database, outcome.merged);
                     let sync_result = SyncResult {
-                        events_pulled: events.len() as u32,
+                        events_pulled,
                         success: true,
                         error_message: None,
                     };
@@ -508,6 +987,351 @@ impl EventBookClient {
             }
         })
     }
+
+    /// Stream events for `store_id` from the server's WebSocket
+    ///
+    /// New events are merged into the local store and projection, then
+    /// passed to `on_event` as a `JsEvent`. The socket reconnects with
+    /// backoff until `disconnect` is called.
+    #[wasm_bindgen]
+    pub fn connect_websocket(
+        &self,
+        store_id: String,
+        on_event: Option<js_sys::Function>,
+    ) -> Result<(), JsError> {
+        self.live
+            .connect(&self.server_url, &store_id, on_event)
+            .map_err(|e| JsError::new(&format!("Failed to open WebSocket: {:?}", e)))
+    }
+
+    /// Close the WebSocket and stop reconnecting
+    #[wasm_bindgen]
+    pub fn disconnect(&self) {
+        self.live.disconnect();
+    }
+
+    /// `disconnected`, `connecting`, `connected` or `reconnecting`
+    #[wasm_bindgen(getter)]
+    pub fn connection_state(&self) -> String {
+        self.live.state().as_str().to_string()
+    }
+
+    /// Choose how `push_events` handles version conflicts:
+    /// `abort` (default) or `rebase`
+    #[wasm_bindgen]
+    pub fn set_conflict_strategy(&mut self, strategy: String) -> Result<(), JsError> {
+        self.conflict_strategy = strategy.parse().map_err(|e: String| JsError::new(&e))?;
+        Ok(())
+    }
+
+    /// Push local events for an aggregate that the server has not seen yet
+    #[wasm_bindgen]
+    pub fn push_events(&self, aggregate_id: String) -> Promise {
+        let server_url = self.server_url.clone();
+        let strategy = self.conflict_strategy;
+        let push_state = self.push_state.clone();
+        let state = push_state
+            .borrow()
+            .get(&aggregate_id)
+            .copied()
+            .unwrap_or_default();
+        let pending: Vec<Event> = self
+            .local_store
+            .borrow()
+            .get_events(&aggregate_id)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|e| e.version > state.local_version)
+            .collect();
+
+        wasm_bindgen_futures::future_to_promise(async move {
+            let result = push_pending_events(
+                &server_url,
+                &aggregate_id,
+                strategy,
+                pending,
+                state,
+                &push_state,
+            )
+            .await;
+
+            let push_result = match result {
+                Ok(events_pushed) => PushResult {
+                    events_pushed,
+                    success: true,
+                    error_message: None,
+                },
+                Err(e) => PushResult {
+                    events_pushed: 0,
+                    success: false,
+                    error_message: Some(e),
+                },
+            };
+            Ok(JsValue::from(push_result))
+        })
+    }
+
+    /// Push every local event the server hasn't seen, across all aggregates
+    ///
+    /// Each event is sent with the server version we last saw, so writes by
+    /// other clients surface as conflicts instead of being interleaved. An
+    /// aggregate stops at its first rejected event; events before it stay
+    /// pushed and the rest are retried on the next call.
+    #[wasm_bindgen]
+    pub fn push_local_events(&self) -> Promise {
+        let server_url = self.server_url.clone();
+        let push_state = self.push_state.clone();
+        let pending = unpushed_events(&self.local_store.borrow(), &push_state.borrow());
+
+        wasm_bindgen_futures::future_to_promise(async move {
+            let mut result = LocalPushResult::default();
+            for (aggregate_id, events) in pending {
+                push_aggregate_events(&server_url, &aggregate_id, events, &push_state, &mut result)
+                    .await;
+            }
+            Ok(JsValue::from(result))
+        })
+    }
+}
+
+/// Post one aggregate's unpushed events in order, advancing its high-water mark
+async fn push_aggregate_events(
+    server_url: &str,
+    aggregate_id: &str,
+    events: Vec<Event>,
+    push_state: &RefCell<HashMap<String, PushState>>,
+    result: &mut LocalPushResult,
+) {
+    for event in events {
+        let state = push_state
+            .borrow()
+            .get(aggregate_id)
+            .copied()
+            .unwrap_or_default();
+
+        match post_event(server_url, aggregate_id, &event, Some(state.server_version)).await {
+            Ok(server_version) => {
+                push_state.borrow_mut().insert(
+                    aggregate_id.to_string(),
+                    PushState {
+                        local_version: event.version,
+                        server_version,
+                    },
+                );
+                result.events_pushed += 1;
+            }
+            Err(e) => {
+                if e.status == Some(409) {
+                    result.conflicts += 1;
+                    result.errors.push(format!(
+                        "Version conflict pushing event {} for {}: {}",
+                        event.id, aggregate_id, e.message
+                    ));
+                } else {
+                    result.errors.push(format!(
+                        "Failed to push event {} for {}: {}",
+                        event.id, aggregate_id, e.message
+                    ));
+                }
+                return;
+            }
+        }
+    }
+}
+
+impl EventBookClient {
+    /// Append an event to the local store and projection
+    fn store_local_event(&mut self, event: Event) -> Result<JsEvent, JsError> {
+        // Store locally (first mutable operation)
+        match self.local_store.borrow_mut().append_event(event.clone()) {
+            Ok(_) => {}
+            Err(e) => return Err(JsError::new(&format!("Store error: {}", e))),
+        }
+
+        // Update projection (second mutable operation)
+        match self.document_projection.borrow_mut().apply_new_events(
+            &UpcasterRegistry::standard().upcast_all(std::slice::from_ref(&event)),
+        ) {
+            Ok(_) => {}
+            Err(e) => return Err(JsError::new(&format!("Projection error: {}", e))),
+        }
+
+        #[cfg(feature = "indexeddb")]
+        indexeddb::persist_events(&self.database, vec![event.clone()]);
+
+        log!("Event {} submitted locally", event.id);
+        Ok(event.into())
+    }
+}
+
+/// Resolve conflicts for pending events and post them to the server
+///
+/// The push state advances after every accepted event, so a failure partway
+/// through only leaves the rest to retry.
+async fn push_pending_events(
+    server_url: &str,
+    aggregate_id: &str,
+    strategy: ConflictStrategy,
+    pending: Vec<Event>,
+    state: PushState,
+    push_state: &RefCell<HashMap<String, PushState>>,
+) -> Result<u32, String> {
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    // Rebasing renumbers the events, so remember which local versions they were
+    let local_versions: Vec<i64> = pending.iter().map(|e| e.version).collect();
+    let server_latest = fetch_latest_version(server_url, aggregate_id).await?;
+    let events = resolve_push(strategy, pending, state.server_version, server_latest)?;
+
+    // Each post expects the version the previous one left the server at, so a
+    // write by someone else in between is rejected rather than interleaved
+    let mut server_version = server_latest;
+    for (event, local_version) in events.iter().zip(local_versions) {
+        server_version = post_event(server_url, aggregate_id, event, Some(server_version)).await?;
+        push_state.borrow_mut().insert(
+            aggregate_id.to_string(),
+            PushState {
+                local_version,
+                server_version,
+            },
+        );
+    }
+
+    log!(
+        "Pushed {} events for {} (server now at version {})",
+        events.len(),
+        aggregate_id,
+        server_version
+    );
+    Ok(events.len() as u32)
+}
+
+/// Fetch the server's latest version for an aggregate
+///
+/// Read from the store's info rather than its event log, which may be large.
+async fn fetch_latest_version(server_url: &str, aggregate_id: &str) -> Result<i64, String> {
+    let url = format!("{}/stores/{}", server_url, aggregate_id);
+
+    let opts = RequestInit::new();
+    opts.set_method("GET");
+
+    let response_text = send_request(&url, &opts).await?;
+
+    #[derive(Deserialize)]
+    struct ServerResponse {
+        latest_version: i64,
+    }
+
+    let server_response: ServerResponse = serde_json::from_str(&response_text)
+        .map_err(|e| format!("Failed to parse server response: {}", e))?;
+
+    Ok(server_response.latest_version)
+}
+
+/// Post a single event to the server, returning the version it was stored at
+///
+/// With `expected_version` the server rejects the event (409) unless it is
+/// still at that version.
+async fn post_event(
+    server_url: &str,
+    aggregate_id: &str,
+    event: &Event,
+    expected_version: Option<i64>,
+) -> Result<i64, RequestError> {
+    let url = format!("{}/stores/{}/events", server_url, aggregate_id);
+
+    // The server expects epoch seconds; browser timestamps are milliseconds
+    let mut body = serde_json::json!({
+        "event_type": event.event_type,
+        "payload": event.payload,
+        "timestamp": event.timestamp / MILLIS_PER_SECOND,
+    });
+    if let Some(expected_version) = expected_version {
+        body["expected_version"] = serde_json::Value::from(expected_version);
+    }
+
+    let opts = RequestInit::new();
+    opts.set_method("POST");
+    opts.set_body(&JsValue::from_str(&body.to_string()));
+
+    let response_text = send_request(&url, &opts).await?;
+
+    #[derive(Deserialize)]
+    struct ServerResponse {
+        version: i64,
+    }
+
+    let server_response: ServerResponse = serde_json::from_str(&response_text)
+        .map_err(|e| RequestError::from(format!("Failed to parse server response: {}", e)))?;
+
+    Ok(server_response.version)
+}
+
+/// A failed request, with the HTTP status if the server answered
+#[derive(Debug)]
+struct RequestError {
+    status: Option<u16>,
+    message: String,
+}
+
+impl From<&str> for RequestError {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+impl From<String> for RequestError {
+    fn from(message: String) -> Self {
+        RequestError {
+            status: None,
+            message,
+        }
+    }
+}
+
+impl From<RequestError> for String {
+    fn from(err: RequestError) -> Self {
+        err.message
+    }
+}
+
+/// Send a JSON request and return the response body
+async fn send_request(url: &str, opts: &RequestInit) -> Result<String, RequestError> {
+    let window = web_sys::window().ok_or("No global window object")?;
+
+    let request =
+        Request::new_with_str_and_init(url, opts).map_err(|_| "Failed to create request")?;
+
+    let headers = request.headers();
+    headers
+        .set("Accept", "application/json")
+        .map_err(|_| "Failed to set headers")?;
+    headers
+        .set("Content-Type", "application/json")
+        .map_err(|_| "Failed to set headers")?;
+
+    let resp_value = JsFuture::from(window.fetch_with_request(&request))
+        .await
+        .map_err(|_| "Fetch request failed")?;
+
+    let resp: Response = resp_value
+        .dyn_into()
+        .map_err(|_| "Response conversion failed")?;
+
+    if !resp.ok() {
+        return Err(RequestError {
+            status: Some(resp.status()),
+            message: format!("HTTP error: {} for URL: {}", resp.status(), url),
+        });
+    }
+
+    let text = JsFuture::from(resp.text().map_err(|_| "Failed to get response text")?)
+        .await
+        .map_err(|_| "Failed to read response text")?;
+
+    Ok(text.as_string().unwrap_or_default())
 }
 
 /// Fetch events from server via HTTP
@@ -568,6 +1392,8 @@ async fn fetch_events_from_server(server_url: &str) -> Result<Vec<Event>, String
         payload: serde_json::Value,
         timestamp: i64,
         version: i64,
+        #[serde(default)]
+        actor: Option<String>,
     }
 
     let server_response: ServerResponse = serde_json::from_str(&response_text)
@@ -583,6 +1409,8 @@ async fn fetch_events_from_server(server_url: &str) -> Result<Vec<Event>, String
             payload: se.payload,
             timestamp: se.timestamp,
             version: se.version,
+            transaction_id: None,
+            actor: se.actor,
         })
         .collect();
 
@@ -599,7 +1427,22 @@ pub fn current_timestamp() -> f64 {
 
 #[wasm_bindgen]
 pub fn generate_event_id() -> String {
-    format!("event-{}", Date::now() as i64)
+    eventbook_core::generate_event_id()
+}
+
+/// Renumber unsynced local events to follow events pulled from the server
+///
+/// Takes and returns JSON arrays of events; see the notes on
+/// last-writer-wins in the Rust `rebase_local_events`.
+#[wasm_bindgen]
+pub fn rebase_local_events(remote: String, local: String) -> Result<String, JsError> {
+    let parse = |json: &str| {
+        serde_json::from_str::<Vec<Event>>(json)
+            .map_err(|e| JsError::new(&format!("Invalid events JSON: {}", e)))
+    };
+    let rebased = conflict::rebase_local_events(&parse(&remote)?, &parse(&local)?);
+    serde_json::to_string(&rebased)
+        .map_err(|e| JsError::new(&format!("Failed to serialize events: {}", e)))
 }
 
 #[wasm_bindgen]
@@ -662,6 +1505,8 @@ pub fn test_document_materializer() -> js_sys::Array {
             }),
             timestamp,
             version: 1,
+            transaction_id: None,
+            actor: None,
         },
         Event {
             id: format!("event-{}", timestamp + 1),
@@ -675,12 +1520,14 @@ pub fn test_document_materializer() -> js_sys::Array {
             }),
             timestamp: timestamp + 1000,
             version: 2,
+            transaction_id: None,
+            actor: None,
         },
     ];
 
     // Create projection and materialize
     let mut projection = DocumentProjection::new();
-    let _ = projection.rebuild_from_events(&events);
+    let _ = projection.rebuild_from_events(&UpcasterRegistry::standard().upcast_all(&events));
 
     // Return materialized cells
     let cells = projection.get_document_cells(&document_id);
@@ -0,0 +1,284 @@
+use eventbook_core::{
+    DocumentProjection, Event, EventError, EventResult, EventStore, InMemoryEventStore, Projection,
+    UpcasterRegistry,
+};
+use std::collections::HashMap;
+
+/// The server stamps events in epoch seconds, while local events carry
+/// `Date::now()` milliseconds
+pub const MILLIS_PER_SECOND: i64 = 1000;
+
+/// What happened to a batch of events pulled from the server
+#[derive(Debug, Default, PartialEq)]
+pub struct MergeOutcome {
+    /// Events appended to the local store, oldest first
+    pub merged: Vec<Event>,
+    /// Events the local store already had
+    pub duplicates: usize,
+    /// Events skipped because their version didn't follow the local log
+    pub rejected: usize,
+    /// Whether the projection had to be rebuilt from the whole log
+    pub rebuilt: bool,
+}
+
+/// Append pulled events to the local store and bring the projection up to date
+///
+/// Server timestamps are converted to milliseconds first, so pulled and local
+/// events order against each other and last-writer-wins rules compare like
+/// with like. Duplicates and version gaps or clashes are skipped rather than
+/// aborting the merge. Events are applied incrementally unless one is older
+/// than what the projection has already seen, in which case it is rebuilt
+/// from the store so the older event isn't lost. Old payload shapes are
+/// upcast on the way into the projection; the store keeps them as the server
+/// sent them.
+pub fn merge_remote_events(
+    store: &mut InMemoryEventStore,
+    projection: &mut DocumentProjection,
+    mut events: Vec<Event>,
+) -> EventResult<MergeOutcome> {
+    for event in &mut events {
+        event.timestamp = event.timestamp.saturating_mul(MILLIS_PER_SECOND);
+    }
+    // Versions are per aggregate, so appending in version order avoids
+    // spurious gaps when the server interleaves aggregates
+    events.sort_by(|a, b| {
+        a.aggregate_id
+            .cmp(&b.aggregate_id)
+            .then(a.version.cmp(&b.version))
+    });
+
+    let mut outcome = MergeOutcome::default();
+    for event in events {
+        match store.append_event(event.clone()) {
+            Ok(()) => outcome.merged.push(event),
+            Err(EventError::DuplicateEventId(_)) => outcome.duplicates += 1,
+            Err(EventError::InvalidVersion { .. }) => outcome.rejected += 1,
+            Err(e) => return Err(e),
+        }
+    }
+
+    outcome.merged.sort_by_key(|e| e.timestamp);
+    let needs_rebuild = outcome
+        .merged
+        .first()
+        .is_some_and(|e| e.timestamp < projection.last_processed_timestamp());
+    let upcasters = UpcasterRegistry::standard();
+    if needs_rebuild {
+        projection.rebuild_from_events(&upcasters.upcast_all(&store.get_all_events()?))?;
+        outcome.rebuilt = true;
+    } else {
+        projection.apply_new_events(&upcasters.upcast_all(&outcome.merged))?;
+    }
+
+    Ok(outcome)
+}
+
+/// Per-aggregate record of what the server already has
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PushState {
+    /// Highest local version pushed to or pulled from the server
+    pub local_version: i64,
+    /// Server version after our last push or pull
+    pub server_version: i64,
+}
+
+/// Count events merged from the server as already pushed
+///
+/// They're on the server at the versions they carry, so pushing them back
+/// would only conflict, or with `rebase` post the server's events again.
+pub fn record_pulled_events(push_state: &mut HashMap<String, PushState>, events: &[Event]) {
+    for event in events {
+        let state = push_state.entry(event.aggregate_id.clone()).or_default();
+        state.local_version = state.local_version.max(event.version);
+        state.server_version = state.server_version.max(event.version);
+    }
+}
+
+/// Local events the server hasn't seen, grouped by aggregate in version order
+pub fn unpushed_events(
+    store: &InMemoryEventStore,
+    push_state: &HashMap<String, PushState>,
+) -> Vec<(String, Vec<Event>)> {
+    let mut pending: Vec<(String, Vec<Event>)> = Vec::new();
+    for event in store.iter_events() {
+        let pushed = push_state
+            .get(&event.aggregate_id)
+            .map_or(0, |state| state.local_version);
+        if event.version <= pushed {
+            continue;
+        }
+        match pending
+            .iter_mut()
+            .find(|(aggregate_id, _)| *aggregate_id == event.aggregate_id)
+        {
+            Some((_, events)) => events.push(event.clone()),
+            None => pending.push((event.aggregate_id.clone(), vec![event.clone()])),
+        }
+    }
+    for (_, events) in &mut pending {
+        events.sort_by_key(|e| e.version);
+    }
+    pending
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn title_event(id: &str, aggregate_id: &str, version: i64, timestamp: i64) -> Event {
+        Event {
+            id: id.to_string(),
+            event_type: "DocumentTitleUpdated".to_string(),
+            aggregate_id: aggregate_id.to_string(),
+            payload: json!({ "title": id }),
+            timestamp,
+            version,
+            transaction_id: None,
+            actor: None,
+        }
+    }
+
+    fn document_event(aggregate_id: &str, timestamp: i64) -> Event {
+        Event {
+            id: format!("{}-created", aggregate_id),
+            event_type: "DocumentCreated".to_string(),
+            aggregate_id: aggregate_id.to_string(),
+            payload: json!({ "title": "Untitled" }),
+            timestamp,
+            version: 1,
+            transaction_id: None,
+            actor: None,
+        }
+    }
+
+    #[test]
+    fn test_merge_skips_duplicates_and_gaps() {
+        let mut store = InMemoryEventStore::new();
+        let mut projection = DocumentProjection::new();
+
+        let pulled = vec![
+            document_event("doc-1", 100),
+            title_event("t-2", "doc-1", 2, 200),
+            // Gap: version 3 never arrived
+            title_event("t-4", "doc-1", 4, 400),
+        ];
+        let outcome = merge_remote_events(&mut store, &mut projection, pulled.clone()).unwrap();
+        let in_millis = |event: &Event| Event {
+            timestamp: event.timestamp * MILLIS_PER_SECOND,
+            ..event.clone()
+        };
+        assert_eq!(
+            outcome,
+            MergeOutcome {
+                merged: pulled[..2].iter().map(in_millis).collect(),
+                duplicates: 0,
+                rejected: 1,
+                rebuilt: false,
+            }
+        );
+        assert_eq!(projection.get_document("doc-1").unwrap().title, "t-2");
+
+        // Pulling the same events again merges nothing new
+        let outcome = merge_remote_events(&mut store, &mut projection, pulled).unwrap();
+        assert!(outcome.merged.is_empty());
+        assert_eq!(outcome.duplicates, 2);
+        assert_eq!(store.get_event_count(), 2);
+    }
+
+    #[test]
+    fn test_merge_rebuilds_for_older_events() {
+        let mut store = InMemoryEventStore::new();
+        let mut projection = DocumentProjection::new();
+        merge_remote_events(
+            &mut store,
+            &mut projection,
+            vec![document_event("doc-2", 500)],
+        )
+        .unwrap();
+
+        // An older document shows up after the projection has moved past it
+        let outcome = merge_remote_events(
+            &mut store,
+            &mut projection,
+            vec![document_event("doc-1", 100)],
+        )
+        .unwrap();
+        assert_eq!(outcome.merged.len(), 1);
+        assert!(outcome.rebuilt);
+        assert!(projection.get_document("doc-1").is_some());
+        assert!(projection.get_document("doc-2").is_some());
+    }
+
+    #[test]
+    fn test_remote_edits_order_against_local_ones() {
+        let mut store = InMemoryEventStore::new();
+        let mut projection = DocumentProjection::new();
+
+        // Written locally, stamped in milliseconds
+        let local = vec![
+            document_event("doc-1", 1_700_000_000_000),
+            title_event("local", "doc-1", 2, 1_700_000_000_500),
+        ];
+        for event in &local {
+            store.append_event(event.clone()).unwrap();
+        }
+        projection.apply_new_events(&local).unwrap();
+
+        // A later edit from the server, stamped in seconds
+        let outcome = merge_remote_events(
+            &mut store,
+            &mut projection,
+            vec![title_event("remote", "doc-1", 3, 1_700_000_001)],
+        )
+        .unwrap();
+        assert_eq!(outcome.merged[0].timestamp, 1_700_000_001_000);
+        assert!(!outcome.rebuilt);
+        assert_eq!(projection.get_document("doc-1").unwrap().title, "remote");
+
+        // One from earlier in the same second as the document sorts before
+        // both edits
+        let outcome = merge_remote_events(
+            &mut store,
+            &mut projection,
+            vec![title_event("stale", "doc-1", 4, 1_700_000_000)],
+        )
+        .unwrap();
+        assert!(outcome.rebuilt);
+        assert_eq!(projection.get_document("doc-1").unwrap().title, "remote");
+    }
+
+    #[test]
+    fn test_pulled_events_are_not_pushed_back() {
+        let mut store = InMemoryEventStore::new();
+        let mut projection = DocumentProjection::new();
+        let mut push_state = HashMap::new();
+
+        let outcome = merge_remote_events(
+            &mut store,
+            &mut projection,
+            vec![
+                document_event("doc-1", 100),
+                title_event("t-2", "doc-1", 2, 200),
+            ],
+        )
+        .unwrap();
+        record_pulled_events(&mut push_state, &outcome.merged);
+        assert_eq!(
+            push_state["doc-1"],
+            PushState {
+                local_version: 2,
+                server_version: 2,
+            }
+        );
+        assert!(unpushed_events(&store, &push_state).is_empty());
+
+        // Only what was written locally after the sync is pushed
+        let local = title_event("local", "doc-1", 3, 300_500);
+        store.append_event(local.clone()).unwrap();
+        assert_eq!(
+            unpushed_events(&store, &push_state),
+            vec![("doc-1".to_string(), vec![local])]
+        );
+    }
+}
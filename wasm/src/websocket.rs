@@ -0,0 +1,462 @@
+//! Live updates over the server's `/stores/{id}/ws` socket
+//!
+//! Incoming events are merged into the client's local store and projection
+//! exactly like pulled events, with their timestamps converted to
+//! milliseconds. Dropped sockets are reopened with exponential backoff until
+//! `disconnect` is called.
+
+use crate::sync::{merge_remote_events, record_pulled_events, PushState};
+use crate::JsEvent;
+use eventbook_core::{DocumentProjection, Event, InMemoryEventStore};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use web_sys::{CloseEvent, MessageEvent, WebSocket};
+
+const INITIAL_RECONNECT_DELAY_MS: u32 = 500;
+const MAX_RECONNECT_DELAY_MS: u32 = 30_000;
+
+/// Where the live connection is at, as shown to the page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Disconnected,
+    Connecting,
+    Connected,
+    Reconnecting,
+}
+
+impl ConnectionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionState::Disconnected => "disconnected",
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Reconnecting => "reconnecting",
+        }
+    }
+}
+
+/// Messages the server sends; mirrors the server's `WsMessage`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum ServerMessage {
+    #[serde(rename = "event")]
+    Event { store_id: String, event: Event },
+    #[serde(rename = "error")]
+    Error { message: String },
+    #[serde(rename = "ping")]
+    Ping,
+    #[serde(rename = "store_deleted")]
+    StoreDeleted { store_id: String },
+    /// Events were sent as one hint rather than one by one, e.g. for a batch
+    #[serde(rename = "refresh")]
+    Refresh { store_id: String },
+    /// Anything this client doesn't act on yet
+    #[serde(other)]
+    Other,
+}
+
+/// Messages the client sends; mirrors the server's `ClientMessage`
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+enum ClientMessage {
+    #[serde(rename = "subscribe")]
+    Subscribe { store_id: String },
+    #[serde(rename = "pong")]
+    Pong,
+}
+
+/// Turn an `http(s)://` server URL into the store's `ws(s)://` endpoint
+pub fn websocket_url(server_url: &str, store_id: &str) -> String {
+    let base = server_url.trim_end_matches('/');
+    let base = if let Some(rest) = base.strip_prefix("https://") {
+        format!("wss://{}", rest)
+    } else if let Some(rest) = base.strip_prefix("http://") {
+        format!("ws://{}", rest)
+    } else {
+        base.to_string()
+    };
+    format!("{}/stores/{}/ws", base, store_id)
+}
+
+/// Delay before reconnect attempt `attempt` (0-based)
+pub fn reconnect_delay_ms(attempt: u32) -> u32 {
+    INITIAL_RECONNECT_DELAY_MS
+        .saturating_mul(1 << attempt.min(16))
+        .min(MAX_RECONNECT_DELAY_MS)
+}
+
+/// What to connect to and who to tell about new events
+struct Target {
+    url: String,
+    store_id: String,
+    on_event: Option<js_sys::Function>,
+}
+
+/// Socket callbacks, kept alive for as long as the socket they're attached to
+struct Handlers {
+    _on_open: Closure<dyn FnMut()>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_error: Closure<dyn FnMut(JsValue)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+}
+
+struct Inner {
+    local_store: Rc<RefCell<InMemoryEventStore>>,
+    document_projection: Rc<RefCell<DocumentProjection>>,
+    /// What the server already has, shared with the client's pushes
+    push_state: Rc<RefCell<HashMap<String, PushState>>>,
+    /// Where merged events are persisted, shared with the client
+    #[cfg(feature = "indexeddb")]
+    database: Rc<RefCell<Option<web_sys::IdbDatabase>>>,
+    state: ConnectionState,
+    target: Option<Target>,
+    socket: Option<WebSocket>,
+    handlers: Option<Handlers>,
+    attempts: u32,
+    /// Bumped on every connect/disconnect so stale reconnect timers give up
+    generation: u32,
+}
+
+/// A reconnecting WebSocket feeding the client's local store
+pub struct LiveConnection {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl LiveConnection {
+    pub fn new(
+        local_store: Rc<RefCell<InMemoryEventStore>>,
+        document_projection: Rc<RefCell<DocumentProjection>>,
+        push_state: Rc<RefCell<HashMap<String, PushState>>>,
+        #[cfg(feature = "indexeddb")] database: Rc<RefCell<Option<web_sys::IdbDatabase>>>,
+    ) -> Self {
+        LiveConnection {
+            inner: Rc::new(RefCell::new(Inner {
+                local_store,
+                document_projection,
+                push_state,
+                #[cfg(feature = "indexeddb")]
+                database,
+                state: ConnectionState::Disconnected,
+                target: None,
+                socket: None,
+                handlers: None,
+                attempts: 0,
+                generation: 0,
+            })),
+        }
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.inner.borrow().state
+    }
+
+    /// Connect to `store_id`, replacing any existing connection
+    pub fn connect(
+        &self,
+        server_url: &str,
+        store_id: &str,
+        on_event: Option<js_sys::Function>,
+    ) -> Result<(), JsValue> {
+        self.disconnect();
+        {
+            let mut inner = self.inner.borrow_mut();
+            inner.target = Some(Target {
+                url: websocket_url(server_url, store_id),
+                store_id: store_id.to_string(),
+                on_event,
+            });
+            inner.state = ConnectionState::Connecting;
+        }
+        open(&self.inner)
+    }
+
+    /// Close the socket and stop reconnecting
+    pub fn disconnect(&self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.generation = inner.generation.wrapping_add(1);
+        inner.target = None;
+        inner.attempts = 0;
+        inner.state = ConnectionState::Disconnected;
+        if let Some(socket) = inner.socket.take() {
+            detach(&socket);
+            let _ = socket.close();
+        }
+        inner.handlers = None;
+    }
+}
+
+impl Drop for LiveConnection {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
+
+/// Clear a socket's callbacks before the closures behind them are dropped
+fn detach(socket: &WebSocket) {
+    socket.set_onopen(None);
+    socket.set_onmessage(None);
+    socket.set_onerror(None);
+    socket.set_onclose(None);
+}
+
+/// Open a socket to the current target and wire up its callbacks
+fn open(inner_rc: &Rc<RefCell<Inner>>) -> Result<(), JsValue> {
+    let (url, store_id) = match &inner_rc.borrow().target {
+        Some(target) => (target.url.clone(), target.store_id.clone()),
+        None => return Ok(()),
+    };
+
+    let socket = WebSocket::new(&url)?;
+
+    let on_open = {
+        let inner_weak = Rc::downgrade(inner_rc);
+        let socket = socket.clone();
+        Closure::<dyn FnMut()>::new(move || {
+            let Some(inner_rc) = inner_weak.upgrade() else {
+                return;
+            };
+            {
+                let mut inner = inner_rc.borrow_mut();
+                inner.state = ConnectionState::Connected;
+                inner.attempts = 0;
+            }
+            let subscribe = ClientMessage::Subscribe {
+                store_id: store_id.clone(),
+            };
+            if let Ok(json) = serde_json::to_string(&subscribe) {
+                if let Err(e) = socket.send_with_str(&json) {
+                    log!("Failed to subscribe: {:?}", e);
+                }
+            }
+            log!("WebSocket connected to {}", store_id);
+        })
+    };
+
+    let on_message = {
+        let inner_weak = Rc::downgrade(inner_rc);
+        Closure::<dyn FnMut(MessageEvent)>::new(move |message: MessageEvent| {
+            if let (Some(inner_rc), Some(text)) = (inner_weak.upgrade(), message.data().as_string())
+            {
+                handle_message(&inner_rc, &text);
+            }
+        })
+    };
+
+    let on_error = Closure::<dyn FnMut(JsValue)>::new(move |_| {
+        log!("WebSocket error");
+    });
+
+    let on_close = {
+        let inner_weak = Rc::downgrade(inner_rc);
+        Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| {
+            log!("WebSocket closed (code {})", event.code());
+            if let Some(inner_rc) = inner_weak.upgrade() {
+                schedule_reconnect(&inner_rc);
+            }
+        })
+    };
+
+    socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+    socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+    socket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+    socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+    let mut inner = inner_rc.borrow_mut();
+    if let Some(old) = inner.socket.replace(socket) {
+        detach(&old);
+    }
+    inner.handlers = Some(Handlers {
+        _on_open: on_open,
+        _on_message: on_message,
+        _on_error: on_error,
+        _on_close: on_close,
+    });
+    Ok(())
+}
+
+/// Merge an event from the server and notify the page if it was new
+fn handle_message(inner_rc: &Rc<RefCell<Inner>>, text: &str) {
+    let message: ServerMessage = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(e) => {
+            log!("Ignoring malformed WebSocket message: {}", e);
+            return;
+        }
+    };
+
+    match message {
+        ServerMessage::Event { store_id, event } => {
+            // Release the borrow before calling into JS, which may disconnect
+            let (local_store, document_projection, push_state, on_event) = {
+                let inner = inner_rc.borrow();
+                let Some(target) = &inner.target else {
+                    return;
+                };
+                if target.store_id != store_id {
+                    return;
+                }
+                (
+                    Rc::clone(&inner.local_store),
+                    Rc::clone(&inner.document_projection),
+                    Rc::clone(&inner.push_state),
+                    target.on_event.clone(),
+                )
+            };
+            #[cfg(feature = "indexeddb")]
+            let database = Rc::clone(&inner_rc.borrow().database);
+
+            let merged = merge_remote_events(
+                &mut local_store.borrow_mut(),
+                &mut document_projection.borrow_mut(),
+                vec![event.clone()],
+            );
+            match merged {
+                Ok(outcome) if !outcome.merged.is_empty() => {
+                    // Pass on the merged copy, stamped in the client's milliseconds
+                    let merged_event = outcome.merged[0].clone();
+                    record_pulled_events(&mut push_state.borrow_mut(), &outcome.merged);
+                    #[cfg(feature = "indexeddb")]
+                    crate::indexeddb::persist_events(&database, outcome.merged);
+                    if let Some(callback) = on_event {
+                        let js_event = JsValue::from(JsEvent::from(merged_event));
+                        if let Err(e) = callback.call1(&JsValue::NULL, &js_event) {
+                            log!("Event callback failed: {:?}", e);
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    log!("Failed to merge event {}: {}", event.id, e);
+                }
+            }
+        }
+        ServerMessage::Error { message } => {
+            log!("Server error: {}", message);
+        }
+        ServerMessage::Ping => {
+            // The server closes connections that stop answering
+            let socket = inner_rc.borrow().socket.clone();
+            if let (Some(socket), Ok(json)) = (socket, serde_json::to_string(&ClientMessage::Pong))
+            {
+                if let Err(e) = socket.send_with_str(&json) {
+                    log!("Failed to answer ping: {:?}", e);
+                }
+            }
+        }
+        ServerMessage::StoreDeleted { store_id } => {
+            // The server is about to close the socket; don't reconnect to
+            // an empty store. Handlers are left in place since one is running.
+            let mut inner = inner_rc.borrow_mut();
+            if inner
+                .target
+                .as_ref()
+                .is_some_and(|t| t.store_id == store_id)
+            {
+                log!("Store {} was deleted", store_id);
+                inner.target = None;
+                inner.state = ConnectionState::Disconnected;
+            }
+        }
+        ServerMessage::Refresh { store_id } => {
+            // Reconnecting replays the store, and events already held are skipped
+            let inner = inner_rc.borrow();
+            if inner
+                .target
+                .as_ref()
+                .is_some_and(|t| t.store_id == store_id)
+            {
+                if let Some(socket) = &inner.socket {
+                    log!("Refreshing store {}", store_id);
+                    let _ = socket.close();
+                }
+            }
+        }
+        ServerMessage::Other => {}
+    }
+}
+
+/// Reopen the socket after a backoff delay, unless disconnected meanwhile
+fn schedule_reconnect(inner_rc: &Rc<RefCell<Inner>>) {
+    let (generation, delay) = {
+        let mut inner = inner_rc.borrow_mut();
+        if inner.target.is_none() {
+            return;
+        }
+        let delay = reconnect_delay_ms(inner.attempts);
+        inner.attempts = inner.attempts.saturating_add(1);
+        inner.state = ConnectionState::Reconnecting;
+        (inner.generation, delay)
+    };
+
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    let inner_weak = Rc::downgrade(inner_rc);
+    let retry = Closure::once_into_js(move || {
+        let Some(inner_rc) = inner_weak.upgrade() else {
+            return;
+        };
+        if inner_rc.borrow().generation != generation {
+            return;
+        }
+        if let Err(e) = open(&inner_rc) {
+            log!("Reconnect failed: {:?}", e);
+            schedule_reconnect(&inner_rc);
+        }
+    });
+
+    log!("Reconnecting in {}ms", delay);
+    if let Err(e) = window
+        .set_timeout_with_callback_and_timeout_and_arguments_0(retry.unchecked_ref(), delay as i32)
+    {
+        log!("Failed to schedule reconnect: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_websocket_url() {
+        assert_eq!(
+            websocket_url("http://localhost:3000", "doc-1"),
+            "ws://localhost:3000/stores/doc-1/ws"
+        );
+        assert_eq!(
+            websocket_url("https://example.com/", "doc-1"),
+            "wss://example.com/stores/doc-1/ws"
+        );
+    }
+
+    #[test]
+    fn test_reconnect_delay_backs_off_to_a_cap() {
+        assert_eq!(reconnect_delay_ms(0), 500);
+        assert_eq!(reconnect_delay_ms(1), 1_000);
+        assert_eq!(reconnect_delay_ms(3), 4_000);
+        assert_eq!(reconnect_delay_ms(10), MAX_RECONNECT_DELAY_MS);
+        assert_eq!(reconnect_delay_ms(u32::MAX), MAX_RECONNECT_DELAY_MS);
+    }
+
+    #[test]
+    fn test_parse_server_messages() {
+        let message: ServerMessage = serde_json::from_str(
+            r#"{"type":"subscribed","store_id":"doc-1","connection_id":"c-1"}"#,
+        )
+        .unwrap();
+        assert!(matches!(message, ServerMessage::Other));
+
+        let message: ServerMessage = serde_json::from_str(
+            r#"{"type":"event","store_id":"doc-1","event":{"id":"e-1","event_type":"DocumentCreated","aggregate_id":"doc-1","payload":{},"timestamp":1,"version":1}}"#,
+        )
+        .unwrap();
+        assert!(matches!(message, ServerMessage::Event { event, .. } if event.id == "e-1"));
+
+        let message: ServerMessage =
+            serde_json::from_str(r#"{"type":"refresh","store_id":"doc-1"}"#).unwrap();
+        assert!(matches!(message, ServerMessage::Refresh { store_id } if store_id == "doc-1"));
+    }
+}